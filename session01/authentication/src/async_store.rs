@@ -0,0 +1,387 @@
+use anyhow::{Result, anyhow};
+use sqlx::{
+    SqlitePool,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+};
+use std::str::FromStr;
+use util::{
+    auth::{User, UserRole},
+    error::RmxError,
+};
+use uuid::Uuid;
+
+use crate::{check_cost, hash_password_with_cost, password_cost, verify_password};
+
+type UserRow = (String, String, String, String, String);
+
+const SELECT_USER: &str = "SELECT id, username, password, name, role FROM users";
+
+/// Condition that keeps the statement from touching the only admin, with `?` the role the
+/// row is left with. It is part of the statement, so two removals can't both pass it.
+const KEEPS_AN_ADMIN: &str = "NOT (role = 'Admin' AND ? <> 'Admin' \
+     AND (SELECT COUNT(*) FROM users WHERE role = 'Admin') = 1)";
+
+/// A user store kept in a sqlite `users` table, so several processes can share it. It
+/// follows the rules of [`crate::UserStore`]: usernames are unique (here regardless of
+/// case), updates keep an empty password or a `None` role, the last admin can't be
+/// removed or demoted, and `login` upgrades hashes below the store's cost. bcrypt runs on
+/// the blocking pool.
+#[derive(Debug, Clone)]
+pub struct AsyncUserStore {
+    pool: SqlitePool,
+    cost: u32,
+}
+
+impl AsyncUserStore {
+    /// Opens (or creates) the database at `url`, e.g. `sqlite://users.db`, and applies
+    /// the migrations.
+    pub async fn connect(url: &str) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+        Self::new(pool).await
+    }
+
+    /// Uses `pool`, applying the migrations first.
+    pub async fn new(pool: SqlitePool) -> Result<Self> {
+        sqlx::migrate!("./migrations").run(&pool).await?;
+        Ok(Self {
+            pool,
+            cost: bcrypt::DEFAULT_COST,
+        })
+    }
+
+    pub fn cost(&self) -> u32 {
+        self.cost
+    }
+
+    /// Sets the bcrypt cost new hashes use, see [`crate::UserStore::with_cost`].
+    pub fn with_cost(mut self, cost: u32) -> Result<Self> {
+        self.cost = check_cost(cost)?;
+        Ok(self)
+    }
+
+    pub async fn hash_password(&self, password: &str) -> Result<String> {
+        let password = password.to_owned();
+        let cost = self.cost;
+        Ok(tokio::task::spawn_blocking(move || hash_password_with_cost(&password, cost)).await?)
+    }
+
+    pub async fn verify_password(&self, password: &str, password_hash: &str) -> Result<bool> {
+        let password = password.to_owned();
+        let password_hash = password_hash.to_owned();
+        Ok(tokio::task::spawn_blocking(move || verify_password(&password, &password_hash)).await?)
+    }
+
+    pub fn needs_rehash(&self, user: &User) -> bool {
+        password_cost(user.password()).is_some_and(|cost| cost < self.cost)
+    }
+
+    pub async fn add(&self, user: &User) -> Result<()> {
+        if !user.is_valid() {
+            return Err(anyhow!("Invalid user data"));
+        }
+
+        sqlx::query(
+            "INSERT INTO users (id, username, password, name, role) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(user.id().to_string())
+        .bind(user.username())
+        .bind(user.password())
+        .bind(user.name())
+        .bind(role_name(user.role()))
+        .execute(&self.pool)
+        .await
+        .map_err(|e| unique_violation(e, "User already exists"))?;
+        Ok(())
+    }
+
+    /// Hashes `password` with the store's cost and adds the user with it.
+    pub async fn add_with_password(&self, user: User, password: &str) -> Result<()> {
+        let mut user = user;
+        user.set_password(&self.hash_password(password).await?);
+        self.add(&user).await
+    }
+
+    /// Replaces the user's fields, keeping the stored password if `user`'s is empty and
+    /// the stored role if it's `None`. Unknown users are added.
+    pub async fn update(&self, user: &User) -> Result<()> {
+        if !user.is_valid_for_update() {
+            return Err(anyhow!("Invalid user data"));
+        }
+
+        let Some(existing) = self.get(user.id()).await? else {
+            return self.add(user).await;
+        };
+        let mut user = user.clone();
+
+        if user.password().is_empty() {
+            user.set_password(existing.password());
+        }
+
+        if user.role() == UserRole::None {
+            user.set_role(existing.role());
+        }
+
+        let result = sqlx::query(&format!(
+            "UPDATE users SET username = ?, password = ?, name = ?, role = ? \
+             WHERE id = ? AND {KEEPS_AN_ADMIN}"
+        ))
+        .bind(user.username())
+        .bind(user.password())
+        .bind(user.name())
+        .bind(role_name(user.role()))
+        .bind(user.id().to_string())
+        .bind(role_name(user.role()))
+        .execute(&self.pool)
+        .await
+        .map_err(|e| unique_violation(e, "Username already exists"))?;
+
+        if result.rows_affected() == 0 {
+            return Err(self.not_changed(user.id()).await);
+        }
+
+        Ok(())
+    }
+
+    /// Fails with [`RmxError::LastAdmin`] instead of removing the only admin.
+    pub async fn remove(&self, id: &Uuid) -> Result<()> {
+        let result = sqlx::query(&format!(
+            "DELETE FROM users WHERE id = ? AND {KEEPS_AN_ADMIN}"
+        ))
+        .bind(id.to_string())
+        .bind(role_name(UserRole::None))
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(self.not_changed(id).await);
+        }
+
+        Ok(())
+    }
+
+    /// Why a statement guarded by [`KEEPS_AN_ADMIN`] changed nothing: the user is gone, or
+    /// is the only admin.
+    async fn not_changed(&self, id: &Uuid) -> anyhow::Error {
+        match self.get(id).await {
+            Ok(Some(_)) => RmxError::LastAdmin.into(),
+            Ok(None) => anyhow!("User not found"),
+            Err(e) => e,
+        }
+    }
+
+    pub async fn get(&self, id: &Uuid) -> Result<Option<User>> {
+        if id.is_nil() {
+            return Ok(None);
+        }
+
+        let row = sqlx::query_as::<_, UserRow>(&format!("{SELECT_USER} WHERE id = ?"))
+            .bind(id.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(user_from_row).transpose()
+    }
+
+    pub async fn get_by_username(&self, username: &str) -> Result<Option<User>> {
+        if username.is_empty() {
+            return Ok(None);
+        }
+
+        let row = sqlx::query_as::<_, UserRow>(&format!("{SELECT_USER} WHERE username = ?"))
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+        row.map(user_from_row).transpose()
+    }
+
+    /// Verifies the credentials. If the stored hash is below the current cost,
+    /// it is upgraded using the verified password.
+    pub async fn login(&self, username: &str, password: &str) -> Result<User> {
+        if username.is_empty() || password.is_empty() {
+            return Err(anyhow!("Username or password cannot be empty"));
+        }
+
+        let mut user = self
+            .get_by_username(username.trim())
+            .await?
+            .ok_or_else(|| anyhow!("User not found"))?;
+
+        if !self.verify_password(password, user.password()).await? {
+            return Err(anyhow!("Invalid credentials"));
+        }
+
+        if self.needs_rehash(&user) {
+            user.set_password(&self.hash_password(password).await?);
+            self.update(&user).await?;
+        }
+
+        Ok(user)
+    }
+}
+
+fn role_name(role: UserRole) -> &'static str {
+    match role {
+        UserRole::None => "None",
+        UserRole::User => "User",
+        UserRole::Admin => "Admin",
+    }
+}
+
+fn user_from_row((id, username, password, name, role): UserRow) -> Result<User> {
+    let role = match role.as_str() {
+        "User" => UserRole::User,
+        "Admin" => UserRole::Admin,
+        _ => return Err(anyhow!("Unknown role '{role}' for user {username}")),
+    };
+    Ok(User::build().with(&Uuid::parse_str(&id)?, &name, &username, &password, role))
+}
+
+fn unique_violation(error: sqlx::Error, message: &str) -> anyhow::Error {
+    match error.as_database_error() {
+        Some(e) if e.is_unique_violation() => anyhow!("{message}"),
+        _ => error.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn store() -> AsyncUserStore {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        AsyncUserStore::new(pool)
+            .await
+            .unwrap()
+            .with_cost(4)
+            .unwrap()
+    }
+
+    fn user(username: &str) -> User {
+        User::build()
+            .with_id(&Uuid::new_v4())
+            .with_name("Test")
+            .with_username(username)
+            .with_role(UserRole::User)
+    }
+
+    #[tokio::test]
+    async fn login_checks_the_password_and_upgrades_the_hash() {
+        let store = store().await;
+        let test = user("test").with_password(&hash_password_with_cost("Passw0rd", 4));
+        store.add(&test).await.unwrap();
+
+        let logged_in = store.login("TEST", "Passw0rd").await.unwrap();
+        assert_eq!(logged_in.id(), test.id());
+        assert_eq!(logged_in.password(), test.password());
+        let error = store.login("test", "wrong").await.unwrap_err();
+        assert_eq!(error.to_string(), "Invalid credentials");
+        let error = store.login("nobody", "Passw0rd").await.unwrap_err();
+        assert_eq!(error.to_string(), "User not found");
+
+        let store = store.with_cost(5).unwrap();
+        assert!(store.needs_rehash(&test));
+        let upgraded = store.login("test", "Passw0rd").await.unwrap();
+        assert_eq!(password_cost(upgraded.password()), Some(5));
+        let stored = store.get_by_username("test").await.unwrap().unwrap();
+        assert!(!store.needs_rehash(&stored));
+        assert!(store.login("test", "Passw0rd").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn usernames_are_unique_regardless_of_case() {
+        let store = store().await;
+        let alice = user("alice");
+        store
+            .add_with_password(alice.clone(), "secret")
+            .await
+            .unwrap();
+
+        let error = store
+            .add_with_password(user("Alice"), "secret")
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "User already exists");
+
+        let bob = user("bob");
+        store
+            .add_with_password(bob.clone(), "secret")
+            .await
+            .unwrap();
+        let error = store
+            .update(&bob.clone().with_username("ALICE"))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Username already exists");
+    }
+
+    #[tokio::test]
+    async fn update_keeps_the_password_and_role_unless_given() {
+        let store = store().await;
+        let alice = user("alice").with_role(UserRole::Admin);
+        store
+            .add_with_password(alice.clone(), "secret")
+            .await
+            .unwrap();
+
+        let renamed = alice
+            .clone()
+            .with_password("")
+            .with_role(UserRole::None)
+            .with_name("Alice");
+        store.update(&renamed).await.unwrap();
+        let stored = store.get(alice.id()).await.unwrap().unwrap();
+        assert_eq!(stored.name(), "Alice");
+        assert_eq!(stored.role(), UserRole::Admin);
+        assert!(store.login("alice", "secret").await.is_ok());
+
+        let error = store.remove(alice.id()).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RmxError>(),
+            Some(RmxError::LastAdmin)
+        ));
+        let demoted = alice.clone().with_password("").with_role(UserRole::User);
+        assert!(store.update(&demoted).await.is_err());
+
+        let bob = user("bob").with_role(UserRole::Admin);
+        store.add_with_password(bob, "secret").await.unwrap();
+        store.remove(alice.id()).await.unwrap();
+        assert!(store.get(alice.id()).await.unwrap().is_none());
+        assert!(store.remove(alice.id()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn two_admins_cant_remove_each_other_at_once() {
+        let store = store().await;
+        let admins = [user("alice"), user("bob")].map(|u| u.with_role(UserRole::Admin));
+
+        for admin in admins.iter() {
+            store
+                .add_with_password(admin.clone(), "secret")
+                .await
+                .unwrap();
+        }
+
+        let demoted = admins[1]
+            .clone()
+            .with_password("")
+            .with_role(UserRole::User);
+        let (removed, demoted) = tokio::join!(store.remove(admins[0].id()), store.update(&demoted));
+        let error = match (removed, demoted) {
+            (Ok(()), Err(e)) | (Err(e), Ok(())) => e,
+            results => panic!("Expected exactly one to fail: {results:?}"),
+        };
+        assert!(matches!(
+            error.downcast_ref::<RmxError>(),
+            Some(RmxError::LastAdmin)
+        ));
+        let admins = store
+            .get_by_username("alice")
+            .await
+            .unwrap()
+            .into_iter()
+            .chain(store.get_by_username("bob").await.unwrap())
+            .filter(User::is_admin)
+            .count();
+        assert_eq!(admins, 1);
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use util::auth::User;
+use uuid::Uuid;
+
+/// Environment variable overriding where the CLIs keep their users.
+pub const USERS_FILE_VAR: &str = "USERS_FILE";
+/// The users file when `USERS_FILE` is not set, relative to the working directory.
+pub const DEFAULT_USERS_FILE: &str = "../users.json";
+
+/// The users file named by `USERS_FILE`, or [`DEFAULT_USERS_FILE`].
+pub fn users_file() -> PathBuf {
+    users_file_from(std::env::var(USERS_FILE_VAR).ok())
+}
+
+fn users_file_from(value: Option<String>) -> PathBuf {
+    value
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| DEFAULT_USERS_FILE.to_owned())
+        .into()
+}
+
+/// Where a [`crate::UserStore`] loads its users from and saves them to.
+pub trait UserBackend {
+    /// Returns `None` when nothing was saved yet.
+    fn load(&self) -> Result<Option<HashMap<Uuid, User>>>;
+    fn save(&self, users: &HashMap<Uuid, User>) -> Result<()>;
+}
+
+/// Keeps the users in a JSON file.
+#[derive(Debug, Clone)]
+pub struct FileBackend {
+    path: PathBuf,
+}
+
+impl FileBackend {
+    pub fn new<T: AsRef<Path>>(path: T) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl UserBackend for FileBackend {
+    fn load(&self) -> Result<Option<HashMap<Uuid, User>>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let data = std::fs::read_to_string(&self.path)?;
+        Ok(Some(serde_json::from_str(&data)?))
+    }
+
+    fn save(&self, users: &HashMap<Uuid, User>) -> Result<()> {
+        let json = serde_json::to_string(users)?;
+        std::fs::write(&self.path, json)?;
+        Ok(())
+    }
+}
+
+/// Keeps the users in memory, so tests don't touch the file system.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    users: Mutex<Option<HashMap<Uuid, User>>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A backend that already holds `users`, as if they had been saved.
+    pub fn with_users(users: HashMap<Uuid, User>) -> Self {
+        Self {
+            users: Mutex::new(Some(users)),
+        }
+    }
+}
+
+impl UserBackend for MemoryBackend {
+    fn load(&self) -> Result<Option<HashMap<Uuid, User>>> {
+        Ok(self.users.lock().unwrap().clone())
+    }
+
+    fn save(&self, users: &HashMap<Uuid, User>) -> Result<()> {
+        *self.users.lock().unwrap() = Some(users.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn users_file_can_be_overridden() {
+        assert_eq!(users_file_from(None), Path::new(DEFAULT_USERS_FILE));
+        assert_eq!(
+            users_file_from(Some(" ".into())),
+            Path::new(DEFAULT_USERS_FILE)
+        );
+        assert_eq!(
+            users_file_from(Some("/tmp/users.json".into())),
+            Path::new("/tmp/users.json")
+        );
+    }
+}
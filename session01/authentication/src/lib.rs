@@ -3,13 +3,50 @@ use bimap::BiMap;
 use std::{
     collections::{HashMap, HashSet},
     path::Path,
+    time::Duration,
+};
+use util::{
+    auth::{User, UserRole},
+    datetime::unix,
+    error::RmxError,
 };
-use util::auth::{User, UserRole};
 use uuid::Uuid;
 
+mod async_store;
+mod backend;
+mod policy;
+mod token;
+pub use async_store::*;
+pub use backend::*;
+pub use policy::*;
+pub use token::DEFAULT_TOKEN_TTL;
+
+/// Controls the built-in users added when a store is loaded. By default `admin/root` and
+/// `user/password` are added when missing, which is only meant for development; turn
+/// `seed_defaults` off or supply `default_admin_password` anywhere else.
+#[derive(Debug, Clone)]
+pub struct UserStoreOptions {
+    pub seed_defaults: bool,
+    /// Password for the seeded `admin` user instead of `root`.
+    pub default_admin_password: Option<String>,
+}
+
+impl Default for UserStoreOptions {
+    fn default() -> Self {
+        Self {
+            seed_defaults: true,
+            default_admin_password: None,
+        }
+    }
+}
+
 pub struct UserStore {
     users: HashMap<Uuid, User>,
     username_map: BiMap<String, Uuid>,
+    policy: PasswordPolicy,
+    cost: u32,
+    token_secret: Vec<u8>,
+    token_ttl: Duration,
 }
 
 impl UserStore {
@@ -19,6 +56,10 @@ impl UserStore {
         Self {
             users,
             username_map,
+            policy: PasswordPolicy::default(),
+            cost: bcrypt::DEFAULT_COST,
+            token_secret: random_secret(),
+            token_ttl: DEFAULT_TOKEN_TTL,
         }
     }
 
@@ -32,38 +73,181 @@ impl UserStore {
         Self {
             users,
             username_map,
+            policy: PasswordPolicy::default(),
+            cost: bcrypt::DEFAULT_COST,
+            token_secret: random_secret(),
+            token_ttl: DEFAULT_TOKEN_TTL,
         }
     }
 
-    pub fn load_from_file<T: AsRef<Path>>(path: T) -> Result<Self> {
-        let path = path.as_ref();
-        let users: HashMap<Uuid, User> = {
-            if !path.exists() {
+    /// Like [`UserStore::from`], but adds the default users first as `options` allow.
+    pub fn from_with_options(mut users: HashMap<Uuid, User>, options: &UserStoreOptions) -> Self {
+        add_default_users(&mut users, options);
+        Self::from(users)
+    }
+
+    pub fn load(backend: &impl UserBackend) -> Result<Self> {
+        Self::load_with_options(backend, &UserStoreOptions::default())
+    }
+
+    /// Loads the users from `backend`, saving the initial users when it has none yet. The
+    /// default users are added as `options` allow; without seeding a new store starts out
+    /// empty.
+    pub fn load_with_options(
+        backend: &impl UserBackend,
+        options: &UserStoreOptions,
+    ) -> Result<Self> {
+        let users: HashMap<Uuid, User> = match backend.load()? {
+            None => {
                 let mut map: HashMap<Uuid, User> = HashMap::new();
-                add_default_users(&mut map);
-                let json = serde_json::to_string(&map)?;
-                std::fs::write(path, json).expect("Unable to write users file");
+                add_default_users(&mut map, options);
+                backend.save(&map)?;
                 map
-            } else {
-                let data = std::fs::read_to_string(path)?;
-                let mut map: HashMap<Uuid, User> = serde_json::from_str(&data)?;
+            }
+            Some(mut map) => {
                 map.retain(|_, user| user.is_valid());
-                add_default_users(&mut map);
+                add_default_users(&mut map, options);
                 map
             }
         };
         Ok(Self::from(users))
     }
 
+    pub fn save(&self, backend: &impl UserBackend) -> Result<()> {
+        backend.save(&self.users)
+    }
+
+    pub fn load_from_file<T: AsRef<Path>>(path: T) -> Result<Self> {
+        Self::load(&FileBackend::new(path))
+    }
+
+    /// [`UserStore::load_with_options`] from a JSON file, created when it doesn't exist.
+    pub fn load_from_file_with_options<T: AsRef<Path>>(
+        path: T,
+        options: &UserStoreOptions,
+    ) -> Result<Self> {
+        Self::load_with_options(&FileBackend::new(path), options)
+    }
+
     pub fn save_to_file<T: AsRef<Path>>(&self, path: T) -> Result<()> {
-        let path = path.as_ref();
-        let json = serde_json::to_string(&self.users)?;
-        std::fs::write(path, json)?;
+        self.save(&FileBackend::new(path))
+    }
+
+    pub fn policy(&self) -> &PasswordPolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: PasswordPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn cost(&self) -> u32 {
+        self.cost
+    }
+
+    /// Sets the bcrypt cost [`UserStore::hash_password`] uses. Fails outside
+    /// [`MIN_COST`]..=[`MAX_COST`], leaving the cost unchanged.
+    pub fn set_cost(&mut self, cost: u32) -> Result<()> {
+        self.cost = check_cost(cost)?;
         Ok(())
     }
 
+    /// Builder form of [`UserStore::set_cost`], e.g. a low cost to keep tests fast.
+    pub fn with_cost(mut self, cost: u32) -> Result<Self> {
+        self.set_cost(cost)?;
+        Ok(self)
+    }
+
     pub fn hash_password(&self, password: &str) -> String {
-        hash_password(password)
+        hash_password_with_cost(password, self.cost)
+    }
+
+    /// Sets the key session tokens are signed with. Every store starts with a random one, so
+    /// its tokens are only accepted by other stores, or after a restart, once they share a
+    /// configured secret. Fails for an empty secret.
+    pub fn set_token_secret(&mut self, secret: &[u8]) -> Result<()> {
+        if secret.is_empty() {
+            return Err(anyhow!("The token secret cannot be empty."));
+        }
+
+        self.token_secret = secret.to_vec();
+        Ok(())
+    }
+
+    /// Builder form of [`UserStore::set_token_secret`].
+    pub fn with_token_secret(mut self, secret: &[u8]) -> Result<Self> {
+        self.set_token_secret(secret)?;
+        Ok(self)
+    }
+
+    pub fn token_ttl(&self) -> Duration {
+        self.token_ttl
+    }
+
+    /// Sets how long tokens issued from now on are accepted.
+    pub fn set_token_ttl(&mut self, ttl: Duration) {
+        self.token_ttl = ttl;
+    }
+
+    /// Issues a session token for `user`, e.g. after [`UserStore::login`], so later requests
+    /// can be checked with [`UserStore::verify_token`] instead of the password. The token is
+    /// the user's id, role and expiry signed with HMAC-SHA256.
+    pub fn issue_token(&self, user: &User) -> String {
+        let expires = unix::now().saturating_add(self.token_ttl.as_secs());
+        token::sign_token(&self.token_secret, user, expires)
+    }
+
+    /// Returns the user a token from [`UserStore::issue_token`] was issued for. Fails if the
+    /// token was tampered with or signed with another secret, has expired, or its user was
+    /// removed or has changed role since.
+    pub fn verify_token(&self, token: &str) -> Result<User> {
+        let claims = token::read_token(&self.token_secret, token, unix::now())?;
+        let user = self
+            .get(&claims.id)
+            .ok_or_else(|| anyhow!("User not found"))?;
+
+        if user.role().to_string() != claims.role {
+            return Err(anyhow!("User role has changed"));
+        }
+
+        Ok(user.clone())
+    }
+
+    /// Returns true if the user's password hash was created with a lower bcrypt cost
+    /// than the store's current cost.
+    pub fn needs_rehash(&self, user: &User) -> bool {
+        password_cost(user.password()).is_some_and(|cost| cost < self.cost)
+    }
+
+    /// Re-hashes the passwords of the users in `passwords` (username -> plain text password)
+    /// that are below the current cost. Hashes can't be upgraded without the plain text, so
+    /// this is only meant for offline migrations; `login` upgrades hashes as users sign in.
+    /// Entries whose password doesn't match the stored hash are skipped.
+    /// Returns the number of upgraded users.
+    pub fn rehash_all(&mut self, passwords: &HashMap<String, String>) -> usize {
+        let mut count = 0;
+
+        for (username, password) in passwords {
+            let Some(id) = self.username_map.get_by_left(username) else {
+                continue;
+            };
+            let Some(user) = self.users.get(id) else {
+                continue;
+            };
+
+            if !self.needs_rehash(user) || !self.verify_password(password, user.password()) {
+                continue;
+            }
+
+            let password_hash = self.hash_password(password);
+
+            if let Some(user) = self.users.get_mut(id) {
+                user.set_password(&password_hash);
+                count += 1;
+            }
+        }
+
+        count
     }
 
     pub fn verify_password(&self, password: &str, password_hash: &str) -> bool {
@@ -85,12 +269,34 @@ impl UserStore {
         Ok(())
     }
 
+    /// Adds the user after checking the plain text password against the store's policy.
+    pub fn add_with_password(&mut self, user: User, password: &str) -> Result<()> {
+        self.policy.check(password).map_err(|violations| {
+            anyhow!(
+                "{}",
+                violations
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })?;
+
+        let mut user = user;
+        user.set_password(&self.hash_password(password));
+        self.add(user)
+    }
+
     pub fn update(&mut self, user: User) -> Result<()> {
         if !user.is_valid_for_update() {
             return Err(anyhow!("Invalid user data"));
         }
 
         if let Some(existing_user) = self.users.get(user.id()) {
+            if existing_user.is_admin() && user.is_user() {
+                self.check_not_last_admin(user.id())?;
+            }
+
             if existing_user.username() != user.username()
                 && self.username_map.contains_left(user.username())
             {
@@ -128,7 +334,10 @@ impl UserStore {
         Ok(())
     }
 
+    /// Fails with [`RmxError::LastAdmin`] instead of removing the only admin.
     pub fn remove(&mut self, id: &Uuid) -> Result<()> {
+        self.check_not_last_admin(id)?;
+
         if let Some(user) = self.users.remove(id) {
             self.username_map.remove_by_right(user.id());
             Ok(())
@@ -144,6 +353,18 @@ impl UserStore {
         }
     }
 
+    /// Fails with [`RmxError::LastAdmin`] if `id` is the only admin left, who can't be
+    /// removed or demoted without locking everyone out of user management.
+    pub fn check_not_last_admin(&self, id: &Uuid) -> Result<()> {
+        let is_admin = self.users.get(id).is_some_and(User::is_admin);
+
+        if is_admin && self.users.values().filter(|u| u.is_admin()).count() == 1 {
+            return Err(RmxError::LastAdmin.into());
+        }
+
+        Ok(())
+    }
+
     pub fn clear(&mut self) {
         self.users.clear();
         self.username_map.clear();
@@ -179,7 +400,9 @@ impl UserStore {
             .and_then(|id| self.users.get(id))
     }
 
-    pub fn login(&self, username: &str, password: &str) -> Result<User> {
+    /// Verifies the credentials. If the stored hash is below the current cost,
+    /// it is upgraded using the verified password.
+    pub fn login(&mut self, username: &str, password: &str) -> Result<User> {
         if username.is_empty() || password.is_empty() {
             return Err(anyhow!("Username or password cannot be empty"));
         }
@@ -189,11 +412,18 @@ impl UserStore {
             .get_by_username(&username)
             .ok_or_else(|| anyhow!("User not found"))?;
 
-        if self.verify_password(password, user.password()) {
-            Ok(user.clone())
-        } else {
-            Err(anyhow!("Invalid credentials"))
+        if !self.verify_password(password, user.password()) {
+            return Err(anyhow!("Invalid credentials"));
         }
+
+        if !self.needs_rehash(user) {
+            return Ok(user.clone());
+        }
+
+        let mut user = user.clone();
+        user.set_password(&self.hash_password(password));
+        self.users.insert(*user.id(), user.clone());
+        Ok(user)
     }
 
     pub fn great_user(&self, name: &str) -> String {
@@ -201,7 +431,18 @@ impl UserStore {
     }
 }
 
-fn add_default_users(users: &mut HashMap<Uuid, User>) {
+fn random_secret() -> Vec<u8> {
+    [Uuid::new_v4(), Uuid::new_v4()]
+        .iter()
+        .flat_map(|id| id.into_bytes())
+        .collect()
+}
+
+fn add_default_users(users: &mut HashMap<Uuid, User>, options: &UserStoreOptions) {
+    if !options.seed_defaults {
+        return;
+    }
+
     let usernames = users
         .values()
         .map(|u| u.username().to_owned())
@@ -212,7 +453,7 @@ fn add_default_users(users: &mut HashMap<Uuid, User>) {
             &Uuid::new_v4(),
             "administrator",
             "admin",
-            &hash_password("root"),
+            &hash_password(options.default_admin_password.as_deref().unwrap_or("root")),
             UserRole::Admin,
         );
         users.insert(user.id().clone(), user);
@@ -230,12 +471,44 @@ fn add_default_users(users: &mut HashMap<Uuid, User>) {
     }
 }
 
+/// bcrypt cost used by [`hash_password`] and new stores.
+pub const DEFAULT_COST: u32 = bcrypt::DEFAULT_COST;
+/// Lowest bcrypt cost that can be hashed with.
+pub const MIN_COST: u32 = 4;
+/// Highest bcrypt cost that can be hashed with.
+pub const MAX_COST: u32 = 31;
+
+/// Returns `cost` if bcrypt accepts it.
+pub fn check_cost(cost: u32) -> Result<u32> {
+    if !(MIN_COST..=MAX_COST).contains(&cost) {
+        return Err(anyhow!(
+            "bcrypt cost {} is outside {}..={}.",
+            cost,
+            MIN_COST,
+            MAX_COST
+        ));
+    }
+
+    Ok(cost)
+}
+
 pub fn hash_password(password: &str) -> String {
+    hash_password_with_cost(password, bcrypt::DEFAULT_COST)
+}
+
+pub fn hash_password_with_cost(password: &str, cost: u32) -> String {
     if password.is_empty() {
         return String::new();
     }
 
-    bcrypt::hash(password, bcrypt::DEFAULT_COST).unwrap_or_default()
+    bcrypt::hash(password, cost).unwrap_or_default()
+}
+
+fn password_cost(password_hash: &str) -> Option<u32> {
+    password_hash
+        .parse::<bcrypt::HashParts>()
+        .ok()
+        .map(|parts| parts.get_cost())
 }
 
 pub fn verify_password(password: &str, password_hash: &str) -> bool {
@@ -245,3 +518,240 @@ pub fn verify_password(password: &str, password_hash: &str) -> bool {
 
     bcrypt::verify(password, password_hash).unwrap_or(false)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn low_cost_hash_is_upgraded_on_login() {
+        let mut store = UserStore::new();
+        store.set_cost(5).unwrap();
+        let user = User::build().with(
+            &Uuid::new_v4(),
+            "Test",
+            "test",
+            &hash_password_with_cost("Passw0rd", 4),
+            UserRole::User,
+        );
+        store.add(user).unwrap();
+        assert!(store.needs_rehash(store.get_by_username("test").unwrap()));
+
+        let user = store.login("test", "Passw0rd").unwrap();
+        assert_eq!(password_cost(user.password()), Some(5));
+        assert!(!store.needs_rehash(store.get_by_username("test").unwrap()));
+        assert!(store.login("test", "Passw0rd").is_ok());
+    }
+
+    fn temp_users_file() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("users-{}.json", Uuid::new_v4()))
+    }
+
+    #[test]
+    fn default_users_follow_the_options() {
+        let path = temp_users_file();
+        let options = UserStoreOptions {
+            seed_defaults: false,
+            default_admin_password: None,
+        };
+        let store = UserStore::load_from_file_with_options(&path, &options).unwrap();
+        assert!(store.users().is_empty());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{}");
+        std::fs::remove_file(&path).unwrap();
+
+        let options = UserStoreOptions {
+            default_admin_password: Some("S3cret!pass".to_string()),
+            ..Default::default()
+        };
+        let mut store = UserStore::from_with_options(HashMap::new(), &options);
+        assert_eq!(store.users().len(), 2);
+        assert!(store.login("admin", "root").is_err());
+        assert!(store.login("admin", "S3cret!pass").is_ok());
+        assert!(store.login("user", "password").is_ok());
+    }
+
+    fn test_user(username: &str) -> User {
+        User::build().with(
+            &Uuid::new_v4(),
+            username,
+            username,
+            &hash_password_with_cost("Passw0rd", 4),
+            UserRole::User,
+        )
+    }
+
+    #[test]
+    fn users_are_managed_in_memory() {
+        let backend = MemoryBackend::new();
+        let options = UserStoreOptions {
+            seed_defaults: false,
+            default_admin_password: None,
+        };
+        let mut store = UserStore::load_with_options(&backend, &options).unwrap();
+        assert!(store.users().is_empty());
+
+        let alice = test_user("alice");
+        store.add(alice.clone()).unwrap();
+        store.add(test_user("bob")).unwrap();
+        assert!(store.add(test_user("alice")).is_err());
+
+        let mut renamed = alice.clone();
+        renamed.set_username("alicia");
+        renamed.set_password("");
+        store.update(renamed).unwrap();
+        assert!(store.get_by_username("alice").is_none());
+        assert_eq!(
+            store.get_by_username("alicia").unwrap().password(),
+            alice.password()
+        );
+
+        store.remove_by_username("bob").unwrap();
+        assert!(store.remove_by_username("bob").is_err());
+        store.save(&backend).unwrap();
+
+        let store = UserStore::load_with_options(&backend, &options).unwrap();
+        let usernames = store
+            .users()
+            .iter()
+            .map(|u| u.username().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(usernames, ["alicia"]);
+    }
+
+    #[test]
+    fn cost_is_validated() {
+        let store = UserStore::new().with_cost(MIN_COST).unwrap();
+        assert_eq!(store.cost(), MIN_COST);
+        assert_eq!(
+            password_cost(&store.hash_password("Passw0rd")),
+            Some(MIN_COST)
+        );
+
+        assert!(UserStore::new().with_cost(MIN_COST - 1).is_err());
+
+        let mut store = UserStore::new();
+        assert!(store.set_cost(MAX_COST + 1).is_err());
+        assert_eq!(store.cost(), DEFAULT_COST);
+    }
+
+    #[test]
+    fn rehash_all_skips_wrong_passwords() {
+        let mut store = UserStore::new();
+        store.set_cost(5).unwrap();
+
+        for username in ["a", "b"] {
+            let user = User::build().with(
+                &Uuid::new_v4(),
+                username,
+                username,
+                &hash_password_with_cost("Passw0rd", 4),
+                UserRole::User,
+            );
+            store.add(user).unwrap();
+        }
+
+        let passwords = HashMap::from([
+            ("a".to_string(), "Passw0rd".to_string()),
+            ("b".to_string(), "wrong".to_string()),
+        ]);
+        assert_eq!(store.rehash_all(&passwords), 1);
+        assert!(!store.needs_rehash(store.get_by_username("a").unwrap()));
+        assert!(store.needs_rehash(store.get_by_username("b").unwrap()));
+    }
+
+    #[test]
+    fn tokens_cant_be_forged() {
+        let mut store = UserStore::new().with_token_secret(b"s3cret").unwrap();
+        let bob = test_user("bob");
+        store.add(bob.clone()).unwrap();
+        store
+            .add(test_user("root").with_role(UserRole::Admin))
+            .unwrap();
+
+        let token = store.issue_token(&bob);
+        assert_eq!(store.verify_token(&token).unwrap().id(), bob.id());
+
+        // Promoting oneself in the payload breaks the signature
+        let promoted = token.replacen(".User.", ".Admin.", 1);
+        assert_ne!(promoted, token);
+        assert!(store.verify_token(&promoted).is_err());
+        let (payload, signature) = token.rsplit_once('.').unwrap();
+        let flipped = if signature.starts_with('0') { "1" } else { "0" };
+        let tampered = format!("{payload}.{flipped}{}", &signature[1..]);
+        assert!(store.verify_token(&tampered).is_err());
+        assert!(store.verify_token("").is_err());
+        assert!(store.verify_token(payload).is_err());
+
+        // Signed with another secret
+        let other = UserStore::new();
+        assert!(store.verify_token(&other.issue_token(&bob)).is_err());
+
+        // Only valid for the user as it was when the token was issued
+        store
+            .update(bob.clone().with_role(UserRole::Admin))
+            .unwrap();
+        assert!(store.verify_token(&token).is_err());
+        assert!(
+            store
+                .verify_token(&store.issue_token(store.get(bob.id()).unwrap()))
+                .is_ok()
+        );
+        store.remove(bob.id()).unwrap();
+        assert!(store.verify_token(&token).is_err());
+
+        assert!(UserStore::new().with_token_secret(b"").is_err());
+    }
+
+    #[test]
+    fn tokens_expire() {
+        let mut store = UserStore::new();
+        let bob = test_user("bob");
+        store.add(bob.clone()).unwrap();
+        assert_eq!(store.token_ttl(), DEFAULT_TOKEN_TTL);
+        assert!(store.verify_token(&store.issue_token(&bob)).is_ok());
+
+        store.set_token_ttl(Duration::ZERO);
+        let error = store.verify_token(&store.issue_token(&bob)).unwrap_err();
+        assert_eq!(error.to_string(), "Token expired");
+
+        let expired = token::sign_token(&store.token_secret, &bob, unix::now() - 1);
+        assert!(store.verify_token(&expired).is_err());
+        let valid = token::sign_token(&store.token_secret, &bob, unix::now() + 60);
+        assert!(store.verify_token(&valid).is_ok());
+    }
+
+    #[test]
+    fn the_last_admin_is_kept() {
+        let mut store = UserStore::new();
+        let admin = test_user("root").with_role(UserRole::Admin);
+        store.add(admin.clone()).unwrap();
+        store.add(test_user("bob")).unwrap();
+
+        let error = store.remove_by_username("root").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RmxError>(),
+            Some(RmxError::LastAdmin)
+        ));
+
+        let demoted = admin.clone().with_role(UserRole::User);
+        let error = store.update(demoted.clone()).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RmxError>(),
+            Some(RmxError::LastAdmin)
+        ));
+        assert!(store.get(admin.id()).unwrap().is_admin());
+
+        // Keeping the role or renaming is fine
+        store
+            .update(admin.clone().with_role(UserRole::None).with_name("Root"))
+            .unwrap();
+
+        // With a second admin either one can go
+        store
+            .add(test_user("alice").with_role(UserRole::Admin))
+            .unwrap();
+        store.update(demoted).unwrap();
+        store.remove_by_username("alice").unwrap_err();
+        store.remove_by_username("root").unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyViolation {
+    TooShort(usize),
+    MissingUppercase,
+    MissingLowercase,
+    MissingDigit,
+    MissingSymbol,
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyViolation::TooShort(min) => {
+                write!(f, "Password must be at least {} characters long", min)
+            }
+            PolicyViolation::MissingUppercase => {
+                write!(f, "Password must contain an uppercase letter")
+            }
+            PolicyViolation::MissingLowercase => {
+                write!(f, "Password must contain a lowercase letter")
+            }
+            PolicyViolation::MissingDigit => write!(f, "Password must contain a digit"),
+            PolicyViolation::MissingSymbol => write!(f, "Password must contain a symbol"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_symbol: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Validates the password against every rule and reports all the violations,
+    /// so callers can show per-rule feedback instead of one error at a time.
+    pub fn check(&self, password: &str) -> Result<(), Vec<PolicyViolation>> {
+        let mut violations = vec![];
+
+        if password.chars().count() < self.min_length {
+            violations.push(PolicyViolation::TooShort(self.min_length));
+        }
+
+        if self.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+            violations.push(PolicyViolation::MissingUppercase);
+        }
+
+        if self.require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
+            violations.push(PolicyViolation::MissingLowercase);
+        }
+
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            violations.push(PolicyViolation::MissingDigit);
+        }
+
+        if self.require_symbol
+            && !password
+                .chars()
+                .any(|c| !c.is_alphanumeric() && !c.is_whitespace())
+        {
+            violations.push(PolicyViolation::MissingSymbol);
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_reports_every_violation() {
+        let policy = PasswordPolicy {
+            require_symbol: true,
+            ..Default::default()
+        };
+        let violations = policy.check("abc").unwrap_err();
+        assert_eq!(
+            violations,
+            vec![
+                PolicyViolation::TooShort(8),
+                PolicyViolation::MissingUppercase,
+                PolicyViolation::MissingDigit,
+                PolicyViolation::MissingSymbol,
+            ]
+        );
+    }
+
+    #[test]
+    fn check_accepts_valid_password() {
+        assert!(PasswordPolicy::default().check("Passw0rd").is_ok());
+    }
+}
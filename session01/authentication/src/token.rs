@@ -0,0 +1,61 @@
+use anyhow::{Result, anyhow};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::Duration;
+use util::auth::User;
+use uuid::Uuid;
+
+/// How long a token from `UserStore::issue_token` is accepted unless the store says otherwise.
+pub const DEFAULT_TOKEN_TTL: Duration = Duration::from_secs(60 * 60);
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What a verified token vouches for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TokenClaims {
+    pub id: Uuid,
+    pub role: String,
+    pub expires: u64,
+}
+
+/// `{id}.{role}.{expires}` followed by the hex HMAC-SHA256 of that part under `secret`.
+pub(crate) fn sign_token(secret: &[u8], user: &User, expires: u64) -> String {
+    let payload = format!("{}.{}.{}", user.id(), user.role(), expires);
+    let signature = hex::encode(mac(secret, &payload).finalize().into_bytes());
+    format!("{payload}.{signature}")
+}
+
+/// Reads the claims of a token signed with `secret` that is still valid at `now`.
+pub(crate) fn read_token(secret: &[u8], token: &str, now: u64) -> Result<TokenClaims> {
+    let invalid = || anyhow!("Invalid token");
+    let (payload, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+    let signature = hex::decode(signature).map_err(|_| invalid())?;
+    // Compared in constant time, so the signature can't be guessed byte by byte
+    mac(secret, payload)
+        .verify_slice(&signature)
+        .map_err(|_| invalid())?;
+
+    let mut parts = payload.split('.');
+    let (Some(id), Some(role), Some(expires), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    let claims = TokenClaims {
+        id: id.parse().map_err(|_| invalid())?,
+        role: role.to_owned(),
+        expires: expires.parse().map_err(|_| invalid())?,
+    };
+
+    if now >= claims.expires {
+        return Err(anyhow!("Token expired"));
+    }
+
+    Ok(claims)
+}
+
+fn mac(secret: &[u8], payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(payload.as_bytes());
+    mac
+}
@@ -3,16 +3,20 @@ use authentication::*;
 use std::path::Path;
 use util::{
     auth::{User, UserFormatter, UserRole},
-    io::{clear_screen, display_menu, get, get_password, get_password_str, get_str, pause},
+    io::{
+        clear_screen, confirm, display_menu_interactive, get, get_password, get_password_str,
+        get_str, pause,
+    },
 };
 use uuid::Uuid;
 
 fn main() {
-    let mut user_store =
-        UserStore::load_from_file(Path::new("../users.json")).unwrap_or_else(|ex| {
-            eprintln!("{}", ex);
-            std::process::exit(1);
-        });
+    let users_file = users_file();
+    eprintln!("Users file: {}", users_file.display());
+    let mut user_store = UserStore::load_from_file(&users_file).unwrap_or_else(|ex| {
+        eprintln!("{}", ex);
+        std::process::exit(1);
+    });
     let items = vec![
         "Login",
         "List users",
@@ -25,20 +29,20 @@ fn main() {
     ];
 
     loop {
-        let choice: usize = display_menu(&items, Some("Welcome to the Login System!"))
+        let choice: usize = display_menu_interactive(&items, Some("Welcome to the Login System!"))
             .unwrap_or_else(|ex| {
                 eprintln!("{}", ex);
                 10
             });
 
         let result = match choice {
-            1 => login(&user_store),
+            1 => login(&mut user_store, &users_file),
             2 => list_users(&user_store),
             3 => list_users_by_role(&user_store),
             4 => add_user(&mut user_store),
             5 => update_user(&mut user_store),
             6 => remove_user(&mut user_store),
-            7 => save_users(&user_store),
+            7 => save_users(&user_store, &users_file),
             _ => {
                 if choice == 0 {
                     println!("Exiting the application.");
@@ -56,14 +60,23 @@ fn main() {
     }
 }
 
-fn login(user_store: &UserStore) -> Result<()> {
+fn login(user_store: &mut UserStore, users_file: &Path) -> Result<()> {
     let mut tries = 0;
 
     loop {
         let username = get_str(Some("Enter your username: "))?;
         let password = get_password(Some("Enter your password: "))?;
 
+        let needs_rehash = user_store
+            .get_by_username(&username)
+            .is_some_and(|user| user_store.needs_rehash(user));
+
         if let Ok(user) = user_store.login(&username, &password) {
+            if needs_rehash {
+                // The password hash was upgraded to the current cost
+                user_store.save_to_file(users_file)?;
+            }
+
             println!("{}", user_store.great_user(&user.username()));
             match user.role() {
                 UserRole::Admin => println!("You are logged in as an Admin."),
@@ -132,14 +145,8 @@ fn add_user(user_store: &mut UserStore) -> Result<()> {
     let role: UserRole = get_str(Some("Enter role (leave empty for default): "))
         .unwrap_or("user".to_string())
         .into();
-    let user = User::build().with(
-        &Uuid::new_v4(),
-        &name,
-        &username,
-        &user_store.hash_password(&password),
-        role,
-    );
-    user_store.add(user)?;
+    let user = User::build().with(&Uuid::new_v4(), &name, &username, "", role);
+    user_store.add_with_password(user, &password)?;
     println!("User '{}' added successfully.", username);
     pause();
     Ok(())
@@ -180,21 +187,30 @@ fn update_user(user_store: &mut UserStore) -> Result<()> {
 
 fn remove_user(user_store: &mut UserStore) -> Result<()> {
     let username = get_str(Some("Enter username to remove: "))?;
-
-    if user_store.remove_by_username(&username).is_ok() {
-        println!("User '{}' removed successfully.", username);
-    } else {
+    let Some(user) = user_store.get_by_username(&username) else {
         eprintln!("User '{}' not found.", username);
+        pause();
+        return Ok(());
+    };
+
+    println!("Id: {}\nRole: {}", user.id(), user.role());
+
+    if !confirm(&format!("Remove user '{}'?", username))? {
+        println!("User '{}' was not removed.", username);
+        pause();
+        return Ok(());
     }
 
+    user_store.remove_by_username(&username)?;
+    println!("User '{}' removed successfully.", username);
     pause();
     Ok(())
 }
 
-fn save_users(user_store: &UserStore) -> Result<()> {
+fn save_users(user_store: &UserStore, users_file: &Path) -> Result<()> {
     clear_screen()?;
 
-    if user_store.save_to_file(Path::new("../users.json")).is_ok() {
+    if user_store.save_to_file(users_file).is_ok() {
         println!("Users saved successfully.");
     } else {
         eprintln!("Failed to save users.");
@@ -1,33 +1,45 @@
 use anyhow::{Result, anyhow};
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use crossterm::{
     ExecutableCommand, cursor,
     terminal::{Clear, ClearType},
 };
+use serde::Serialize;
+use serde_json::json;
 use std::{io::stdout, path::Path};
 use uuid::Uuid;
 
 use authentication::*;
 use util::{
     auth::{User, UserFormatter, UserRole},
-    io::pause,
+    io::{get_password_str, pause},
 };
 
 #[derive(Parser)]
 #[command()]
 struct Args {
+    /// `json` prints the results, or `{ "error": ... }`, as JSON on stdout
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Login to the system
     Login {
         #[arg(short, long)]
         username: String,
+        /// Prompted for with masked input when omitted
         #[arg(short, long)]
-        password: String,
+        password: Option<String>,
     },
     /// List all users
     List,
@@ -42,8 +54,9 @@ enum Commands {
         name: String,
         #[arg(short, long)]
         username: String,
+        /// Prompted for with masked input when omitted
         #[arg(short, long)]
-        password: String,
+        password: Option<String>,
         #[arg(short, long)]
         role: UserRole,
     },
@@ -64,75 +77,278 @@ enum Commands {
     Remove {
         #[arg(short, long)]
         username: String,
+        /// Report the user that would be removed without saving
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print the bcrypt hash of a password, without touching the users file
+    Hash {
+        /// Read from stdin with --password-stdin, or prompted for when both are omitted
+        #[arg(short, long)]
+        password: Option<String>,
+        /// Read the password from the first line of stdin
+        #[arg(long, conflicts_with = "password")]
+        password_stdin: bool,
+        /// bcrypt cost, `BCRYPT_COST` or the library default when omitted
+        #[arg(short, long)]
+        cost: Option<u32>,
+    },
+    /// Check a password against a bcrypt hash; exits with 1 if it doesn't match
+    Verify {
+        /// Read from stdin with --password-stdin, or prompted for when both are omitted
+        #[arg(short, long)]
+        password: Option<String>,
+        /// Read the password from the first line of stdin
+        #[arg(long, conflicts_with = "password")]
+        password_stdin: bool,
+        #[arg(long)]
+        hash: String,
     },
 }
 
-fn main() {
-    clear_screen().unwrap();
-    println!("Welcome to the Login System!");
+impl Commands {
+    /// Password tools that don't need the users file or the interactive screen.
+    fn is_tool(&self) -> bool {
+        matches!(self, Commands::Hash { .. } | Commands::Verify { .. })
+    }
+}
 
+fn main() {
     let cli = Args::parse();
-    let mut user_store =
-        UserStore::load_from_file(Path::new("../users.json")).unwrap_or_else(|ex| {
-            eprintln!("{}", ex);
+    let format = cli.format;
+
+    let interactive =
+        format == OutputFormat::Text && !cli.command.as_ref().is_some_and(Commands::is_tool);
+
+    if interactive {
+        clear_screen().unwrap();
+        println!("Welcome to the Login System!");
+    }
+
+    let Some(command) = cli.command else {
+        let mut cmd = Args::command();
+        cmd.print_help().unwrap_or_else(|e| {
+            eprintln!("Error displaying help: {}", e);
             std::process::exit(1);
         });
-    match cli.command {
-        Some(Commands::Login { username, password }) => {
-            if let Err(ex) = login(&user_store, &username, &password) {
-                eprintln!("{}", ex);
-            }
+        return;
+    };
+    let users_file = users_file();
+
+    if !command.is_tool() {
+        eprintln!("Users file: {}", users_file.display());
+    }
+
+    let result = run(command, &users_file).and_then(|outcome| {
+        let success = !matches!(outcome, Outcome::Verified(false));
+
+        match format {
+            OutputFormat::Text => print_text(outcome)?,
+            OutputFormat::Json => print_json(outcome)?,
         }
-        Some(Commands::List) => {
-            if let Err(ex) = list_users(&user_store) {
-                eprintln!("{}", ex);
-            }
+
+        Ok(success)
+    });
+
+    match result {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(ex) => {
+            print_error(format, &ex);
+            std::process::exit(1);
         }
-        Some(Commands::ListByRole { role }) => {
-            if let Err(ex) = list_users_by_role(&user_store, role) {
-                eprintln!("{}", ex);
-            }
+    }
+}
+
+/// What a command did, printed as text or JSON.
+enum Outcome {
+    LoggedIn(User),
+    Users {
+        users: Vec<User>,
+        role: Option<UserRole>,
+    },
+    Added(User),
+    Updated {
+        username: String,
+        user: User,
+    },
+    Removed {
+        user: User,
+        dry_run: bool,
+    },
+    Hashed(String),
+    Verified(bool),
+}
+
+/// The JSON form of a user, without the password hash.
+#[derive(Serialize)]
+struct UserJson<'a> {
+    id: &'a Uuid,
+    name: &'a str,
+    username: &'a str,
+    role: UserRole,
+}
+
+impl<'a> From<&'a User> for UserJson<'a> {
+    fn from(user: &'a User) -> Self {
+        Self {
+            id: user.id(),
+            name: user.name(),
+            username: user.username(),
+            role: user.role(),
+        }
+    }
+}
+
+/// Loads `users_file` only for the commands that need it.
+fn run(command: Commands, users_file: &Path) -> Result<Outcome> {
+    let load_store = || UserStore::load_from_file(users_file);
+
+    match command {
+        Commands::Login { username, password } => {
+            let password = password_or_prompt(password)?;
+            login(&mut load_store()?, users_file, &username, &password)
         }
-        Some(Commands::Add {
+        Commands::List => Ok(Outcome::Users {
+            users: load_store()?.users(),
+            role: None,
+        }),
+        Commands::ListByRole { role } => Ok(Outcome::Users {
+            users: load_store()?.users_by_role(role),
+            role: Some(role),
+        }),
+        Commands::Add {
             name,
             username,
             password,
             role,
-        }) => {
-            if let Err(ex) = add_user(&mut user_store, &name, &username, &password, role) {
-                eprintln!("{}", ex);
-            }
+        } => {
+            let password = password_or_prompt(password)?;
+            add_user(
+                &mut load_store()?,
+                users_file,
+                &name,
+                &username,
+                &password,
+                role,
+            )
         }
-        Some(Commands::Update {
+        Commands::Update {
             username,
             new_name,
             new_username,
             new_password,
             new_role,
-        }) => {
-            if let Err(ex) = update_user(
-                &mut user_store,
-                &username,
-                new_name.as_deref(),
-                new_username.as_deref(),
-                new_password.as_deref(),
-                new_role.unwrap_or(UserRole::None),
-            ) {
-                eprintln!("{}", ex);
+        } => update_user(
+            &mut load_store()?,
+            users_file,
+            &username,
+            new_name.as_deref(),
+            new_username.as_deref(),
+            new_password.as_deref(),
+            new_role.unwrap_or(UserRole::None),
+        ),
+        Commands::Remove { username, dry_run } => {
+            remove_user(&mut load_store()?, users_file, &username, dry_run)
+        }
+        Commands::Hash {
+            password,
+            password_stdin,
+            cost,
+        } => hash(&read_password(password, password_stdin)?, cost),
+        Commands::Verify {
+            password,
+            password_stdin,
+            hash,
+        } => {
+            let password = read_password(password, password_stdin)?;
+            Ok(Outcome::Verified(verify_password(&password, &hash)))
+        }
+    }
+}
+
+fn print_text(outcome: Outcome) -> Result<()> {
+    match outcome {
+        Outcome::LoggedIn(user) => {
+            println!("Hello, {}!", user.username());
+            match user.role() {
+                UserRole::Admin => println!("You are logged in as an Admin."),
+                UserRole::User => println!("You are logged in as a User."),
+                UserRole::None => println!("You are logged in with no role."),
             }
         }
-        Some(Commands::Remove { username }) => {
-            if let Err(ex) = remove_user(&mut user_store, &username) {
-                eprintln!("{}", ex);
+        Outcome::Users { users, role } if users.is_empty() => match role {
+            Some(role) => eprintln!("No users found with role '{}'.", role),
+            None => eprintln!("No users found."),
+        },
+        Outcome::Users { users, .. } => {
+            clear_screen()?;
+            UserFormatter::default().print_users(&users);
+        }
+        Outcome::Added(user) => {
+            clear_screen()?;
+            println!("User '{}' added successfully.", user.username());
+        }
+        Outcome::Updated { username, .. } => {
+            println!("User '{}' updated successfully.", username)
+        }
+        Outcome::Removed { user, dry_run } => {
+            let verb = if dry_run {
+                "would be removed"
+            } else {
+                "removed"
+            };
+            println!(
+                "User '{}' ({}, {}) {}.",
+                user.username(),
+                user.role(),
+                user.id(),
+                verb
+            )
+        }
+        // Password tool output is meant for scripts, so it skips the pause
+        Outcome::Hashed(hash) => {
+            println!("{}", hash);
+            return Ok(());
+        }
+        Outcome::Verified(valid) => {
+            match valid {
+                true => println!("Password matches."),
+                false => println!("Password does not match."),
             }
+            return Ok(());
+        }
+    }
+
+    pause();
+    Ok(())
+}
+
+fn print_json(outcome: Outcome) -> Result<()> {
+    let value = match outcome {
+        Outcome::LoggedIn(user) | Outcome::Added(user) | Outcome::Updated { user, .. } => {
+            serde_json::to_value(UserJson::from(&user))?
         }
-        None => {
-            let mut cmd = Args::command();
-            cmd.print_help().unwrap_or_else(|e| {
-                eprintln!("Error displaying help: {}", e);
-                std::process::exit(1);
-            });
+        Outcome::Users { users, .. } => {
+            serde_json::to_value(users.iter().map(UserJson::from).collect::<Vec<_>>())?
         }
+        Outcome::Removed { user, dry_run } => json!({
+            "removed": user.username(),
+            "id": user.id(),
+            "role": user.role(),
+            "dry_run": dry_run,
+        }),
+        Outcome::Hashed(hash) => json!({ "hash": hash }),
+        Outcome::Verified(valid) => json!({ "valid": valid }),
+    };
+    println!("{}", value);
+    Ok(())
+}
+
+fn print_error(format: OutputFormat, error: &anyhow::Error) {
+    match format {
+        OutputFormat::Text => eprintln!("{}", error),
+        OutputFormat::Json => println!("{}", json!({ "error": error.to_string() })),
     }
 }
 
@@ -144,87 +360,96 @@ fn clear_screen() -> Result<()> {
     Ok(())
 }
 
-fn login(user_store: &UserStore, username: &str, password: &str) -> Result<()> {
-    if let Ok(user) = user_store.login(&username, &password) {
-        println!("{}", user_store.great_user(&user.username()));
-        match user.role() {
-            UserRole::Admin => println!("You are logged in as an Admin."),
-            UserRole::User => println!("You are logged in as a User."),
-            UserRole::None => println!("You are logged in with no role."),
-        }
-        pause();
-    } else {
-        return Err(anyhow!("Invalid credentials. Please try again."));
+/// The password from the argument, the first line of stdin or a masked prompt, in that
+/// order. Reading stdin keeps the password out of the shell history.
+fn read_password(password: Option<String>, from_stdin: bool) -> Result<String> {
+    if !from_stdin {
+        return password_or_prompt(password);
     }
 
-    Ok(())
-}
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    let password = line.trim_end_matches(['\r', '\n']);
 
-fn list_users(user_store: &UserStore) -> Result<()> {
-    let users = user_store.users();
+    if password.is_empty() {
+        return Err(anyhow!("No password on stdin."));
+    }
 
-    if users.is_empty() {
-        eprintln!("No users found.");
-        pause();
-        return Ok(());
+    Ok(password.to_owned())
+}
+
+/// `cost` defaults to `BCRYPT_COST`, then to the library default.
+fn hash(password: &str, cost: Option<u32>) -> Result<Outcome> {
+    // The library hashes an empty password to an empty string, which no login accepts
+    if password.is_empty() {
+        return Err(anyhow!("The password cannot be empty."));
     }
 
-    clear_screen()?;
+    let cost = match cost {
+        Some(cost) => cost,
+        None => match std::env::var("BCRYPT_COST") {
+            Ok(value) => value
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("BCRYPT_COST '{}' is not a number.", value))?,
+            Err(_) => DEFAULT_COST,
+        },
+    };
+    let cost = check_cost(cost)?;
+    Ok(Outcome::Hashed(hash_password_with_cost(password, cost)))
+}
 
-    let formatter = UserFormatter::default();
-    formatter.print_users(&users);
-    pause();
-    Ok(())
+fn password_or_prompt(password: Option<String>) -> Result<String> {
+    match password {
+        Some(password) => Ok(password),
+        None => Ok(get_password_str(Some("Password:"))?),
+    }
 }
 
-fn list_users_by_role(user_store: &UserStore, role: UserRole) -> Result<()> {
-    let users = user_store.users_by_role(role);
+fn login(
+    user_store: &mut UserStore,
+    users_file: &Path,
+    username: &str,
+    password: &str,
+) -> Result<Outcome> {
+    let needs_rehash = user_store
+        .get_by_username(username)
+        .is_some_and(|user| user_store.needs_rehash(user));
+    let user = user_store
+        .login(username, password)
+        .map_err(|_| anyhow!("Invalid credentials. Please try again."))?;
 
-    if users.is_empty() {
-        eprintln!("No users found with role '{}'.", role);
-        pause();
-        return Ok(());
+    if needs_rehash {
+        // The password hash was upgraded to the current cost
+        user_store.save_to_file(users_file)?;
     }
 
-    clear_screen()?;
-
-    let formatter = UserFormatter::default();
-    formatter.print_users(&users);
-    pause();
-    Ok(())
+    Ok(Outcome::LoggedIn(user))
 }
 
 fn add_user(
     user_store: &mut UserStore,
+    users_file: &Path,
     name: &str,
     username: &str,
     password: &str,
     role: UserRole,
-) -> Result<()> {
-    clear_screen()?;
-
-    let user = User::build().with(
-        &Uuid::new_v4(),
-        name,
-        username,
-        &user_store.hash_password(password),
-        role,
-    );
-    user_store.add(user)?;
-    user_store.save_to_file(Path::new("../users.json"))?;
-    println!("User '{}' added successfully.", username);
-    pause();
-    Ok(())
+) -> Result<Outcome> {
+    let user = User::build().with(&Uuid::new_v4(), name, username, "", role);
+    user_store.add_with_password(user.clone(), password)?;
+    user_store.save_to_file(users_file)?;
+    Ok(Outcome::Added(user))
 }
 
 fn update_user(
     user_store: &mut UserStore,
+    users_file: &Path,
     username: &str,
     new_name: Option<&str>,
     new_username: Option<&str>,
     new_password: Option<&str>,
     nw_role: UserRole,
-) -> Result<()> {
+) -> Result<Outcome> {
     let mut user = user_store
         .get_by_username(&username)
         .cloned()
@@ -246,21 +471,31 @@ fn update_user(
         user.set_role(nw_role);
     }
 
-    user_store.update(user)?;
-    user_store.save_to_file(Path::new("../users.json"))?;
-    println!("User '{}' updated successfully.", username);
-    pause();
-    Ok(())
+    user_store.update(user.clone())?;
+    user_store.save_to_file(users_file)?;
+    Ok(Outcome::Updated {
+        username: username.to_owned(),
+        user,
+    })
 }
 
-fn remove_user(user_store: &mut UserStore, username: &str) -> Result<()> {
-    if user_store.remove_by_username(&username).is_ok() {
-        user_store.save_to_file(Path::new("../users.json"))?;
-        println!("User '{}' removed successfully.", username);
-    } else {
-        println!("User '{}' not found.", username);
+fn remove_user(
+    user_store: &mut UserStore,
+    users_file: &Path,
+    username: &str,
+    dry_run: bool,
+) -> Result<Outcome> {
+    let user = user_store
+        .get_by_username(username)
+        .cloned()
+        .ok_or_else(|| anyhow!("User '{}' not found.", username))?;
+    // A dry run must fail the way the removal would
+    user_store.check_not_last_admin(user.id())?;
+
+    if !dry_run {
+        user_store.remove_by_username(username)?;
+        user_store.save_to_file(users_file)?;
     }
 
-    pause();
-    Ok(())
+    Ok(Outcome::Removed { user, dry_run })
 }
@@ -1,22 +1,65 @@
 use dashmap::DashMap;
-use once_cell::sync::Lazy;
-use std::thread;
-
-static SHARED: Lazy<DashMap<u32, u32>> = Lazy::new(DashMap::new);
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
 
 fn main() {
-    for n in 0..100 {
-        thread::spawn(move || {
-            loop {
-                if let Some(mut entry) = SHARED.get_mut(&n) {
-                    *entry += 1;
-                } else {
-                    SHARED.insert(n, 1);
-                }
-            }
-        });
+    let counts = run_counters(100, Duration::from_secs(2));
+    let mut keys = counts.keys().copied().collect::<Vec<_>>();
+    keys.sort_unstable();
+
+    for n in keys {
+        println!("{n:>3}: {}", counts[&n]);
     }
+}
+
+/// Spawns `n_threads` threads that each keep incrementing their own key in a shared
+/// `DashMap` until `duration` has passed, then stops and joins them and returns the counts.
+/// Every thread increments at least once before it checks the stop flag.
+fn run_counters(n_threads: u32, duration: Duration) -> HashMap<u32, u32> {
+    let shared = DashMap::new();
+    let stop = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        for n in 0..n_threads {
+            let shared = &shared;
+            let stop = &stop;
+            scope.spawn(move || {
+                loop {
+                    if let Some(mut entry) = shared.get_mut(&n) {
+                        *entry += 1;
+                    } else {
+                        shared.insert(n, 1);
+                    }
+
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                }
+            });
+        }
+
+        thread::sleep(duration);
+        stop.store(true, Ordering::Relaxed);
+    });
+
+    shared.into_iter().collect()
+}
 
-    thread::sleep(std::time::Duration::from_secs(2));
-    println!("{SHARED:#?}");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_thread_counts_and_stops() {
+        let counts = run_counters(8, Duration::from_millis(50));
+        assert_eq!(counts.len(), 8);
+        assert!((0..8).all(|n| counts[&n] >= 1));
+
+        let counts = run_counters(4, Duration::ZERO);
+        assert_eq!(counts.len(), 4);
+    }
 }
@@ -1,6 +1,7 @@
-use std::{mem, sync::Mutex, thread, time::Duration};
+use std::{mem, thread, time::Duration};
+use util::threading::RecoverableMutex;
 
-static SHARED: Mutex<u32> = Mutex::new(0);
+static SHARED: RecoverableMutex<u32> = RecoverableMutex::new(0);
 
 fn poinsoner() {
     let mut shared = SHARED.lock().unwrap();
@@ -52,19 +53,10 @@ fn main() {
         }
     }
 
-    match SHARED.try_lock() {
-        Ok(shared) => {
-            println!("Final value: {}", *shared);
-        }
-        Err(std::sync::TryLockError::Poisoned(e)) => {
-            eprintln!("{}", e);
-            // Attempt to recover from the poison
-            let recovered = e.into_inner();
-            println!("Recovered value: {}", *recovered);
-        }
-        Err(std::sync::TryLockError::WouldBlock) => {
-            eprintln!("Mutex is still locked, cannot access shared data.");
-        }
+    // The poisoner panicked while holding the lock, but the value is still usable
+    match SHARED.try_lock_recover() {
+        Some(shared) => println!("Final value: {}", *shared),
+        None => eprintln!("Mutex is still locked, cannot access shared data."),
     }
 
     println!("All threads finished.");
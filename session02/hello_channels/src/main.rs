@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
-use std::{fmt, sync::mpsc};
-use util::{io::get, threading::Signal};
+use std::fmt;
+use util::{io::get, threading::Actor};
 
 #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 enum Command {
@@ -22,59 +22,99 @@ impl fmt::Display for Command {
     }
 }
 
-impl From<String> for Command {
-    fn from(s: String) -> Self {
-        if s.is_empty() {
-            return Command::None;
-        }
+impl Command {
+    /// Parses `hello <name>`, `say <message>` or `quit`. The keyword is matched in any
+    /// case and the surrounding whitespace is ignored; anything else is `None`.
+    fn parse(input: &str) -> Command {
+        let input = input.trim();
+        // Split on the keyword itself, lowercasing the whole line could shift the byte
+        // offsets of the rest
+        let (keyword, rest) = input
+            .split_once(char::is_whitespace)
+            .map_or((input, ""), |(keyword, rest)| (keyword, rest.trim()));
 
-        let l = s.to_lowercase();
-        match l.as_str() {
-            _ if l.starts_with("hello ") => Command::Hello(s[6..].to_string()),
-            _ if l.starts_with("say ") => Command::Say(s[4..].to_string()),
-            "quit" => Command::Quit,
+        match keyword.to_lowercase().as_str() {
+            "hello" if !rest.is_empty() => Command::Hello(rest.to_string()),
+            "say" if !rest.is_empty() => Command::Say(rest.to_string()),
+            "quit" if rest.is_empty() => Command::Quit,
             _ => Command::None,
         }
     }
 }
 
+impl From<String> for Command {
+    fn from(s: String) -> Self {
+        Command::parse(&s)
+    }
+}
+
 impl From<&str> for Command {
     fn from(s: &str) -> Self {
-        s.to_string().into()
+        Command::parse(s)
     }
 }
 
 fn main() {
-    let (tx, rx) = mpsc::channel();
-    let signal = Signal::new();
-    let signal2 = signal.clone();
-    let handle = std::thread::spawn(move || {
-        while let Ok(command) = rx.recv() {
-            println!("Bot: {}", command);
-            signal2.set();
-            if command == Command::Quit {
-                break;
-            }
-        }
-    });
+    let bot = Actor::new(|command: Command| format!("Bot: {}", command));
 
     loop {
         let input = get(Some(">")).unwrap();
         let command = Command::from(input);
 
-        if let Err(ex) = tx.send(command.clone()) {
-            eprintln!("{}", ex);
-            break;
+        match bot.call(command.clone()) {
+            Ok(reply) => println!("{}", reply),
+            Err(ex) => {
+                eprintln!("{}", ex);
+                break;
+            }
         }
 
         if command == Command::Quit {
             break;
         }
+    }
+
+    bot.shutdown();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        signal.wait();
+    #[test]
+    fn keywords_match_in_any_case() {
+        assert_eq!(
+            Command::parse("HELLO Bob"),
+            Command::Hello("Bob".to_string())
+        );
+        assert_eq!(
+            Command::parse("hello Bob"),
+            Command::Hello("Bob".to_string())
+        );
+        assert_eq!(
+            Command::parse("Say Hi there"),
+            Command::Say("Hi there".to_string())
+        );
+        assert_eq!(Command::parse("QuIt"), Command::Quit);
     }
 
-    if let Err(ex) = handle.join() {
-        eprintln!("Error joining thread: {:?}", ex);
+    #[test]
+    fn extra_whitespace_is_ignored() {
+        assert_eq!(
+            Command::parse("  hello \t Bob  "),
+            Command::Hello("Bob".to_string())
+        );
+        assert_eq!(
+            Command::parse("say   two  spaces "),
+            Command::Say("two  spaces".to_string())
+        );
+        assert_eq!(Command::parse(" quit\n"), Command::Quit);
+    }
+
+    #[test]
+    fn unknown_commands_are_none() {
+        for input in ["", "   ", "hello", "say ", "quit now", "helloBob", "bye"] {
+            assert_eq!(Command::parse(input), Command::None, "{input:?}");
+        }
     }
 }
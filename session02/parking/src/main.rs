@@ -1,45 +1,98 @@
-use std::thread;
-use util::{io::get_numeric, threading::Signal};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+};
+use util::{io::get_numeric_in_range, threading::Signal};
 
-fn parkable(n: usize, signal: Signal) {
+fn parkable(n: usize, signal: Signal, shutdown: Arc<AtomicBool>) {
     loop {
         thread::park();
+
+        // Parking can also end spuriously, only the flag means it's time to leave
+        if shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+
         println!("{n}>>> unparked briefly.");
         signal.set();
     }
 }
 
-fn main() {
-    let mut threads = vec![];
-
-    for i in 0..10 {
-        let signal = Signal::new();
-        let signal2 = signal.clone();
-        threads.push((
-            thread::spawn(move || {
-                parkable(i + 1, signal2);
-            }),
-            signal,
-        ));
+fn spawn_threads(count: usize, shutdown: &Arc<AtomicBool>) -> Vec<(JoinHandle<()>, Signal)> {
+    (0..count)
+        .map(|i| {
+            let signal = Signal::new();
+            let signal2 = signal.clone();
+            let shutdown = shutdown.clone();
+            (
+                thread::spawn(move || {
+                    parkable(i + 1, signal2, shutdown);
+                }),
+                signal,
+            )
+        })
+        .collect()
+}
+
+/// Sets the flag and unparks every thread so it sees it, then joins them. Returns the
+/// number of threads that finished cleanly.
+fn shutdown_threads(threads: Vec<(JoinHandle<()>, Signal)>, shutdown: &AtomicBool) -> usize {
+    shutdown.store(true, Ordering::SeqCst);
+
+    for (handle, _) in &threads {
+        handle.thread().unpark();
     }
 
+    threads
+        .into_iter()
+        .filter_map(|(handle, _)| handle.join().ok())
+        .count()
+}
+
+fn main() {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let threads = spawn_threads(10, &shutdown);
+
     loop {
-        let input = get_numeric::<usize>(Some("Enter a number to unpark a thread (0 to exit): "))
-            .unwrap_or(0);
+        let input = get_numeric_in_range(
+            Some("Enter a number to unpark a thread (0 to exit): "),
+            0,
+            threads.len(),
+        )
+        .unwrap_or(0);
 
         if input == 0 {
             break;
         }
 
-        let index = input - 1;
+        let (handle, signal) = &threads[input - 1];
+        println!("Unparking thread {input}.");
+        handle.thread().unpark();
+        signal.wait();
+    }
 
-        if index < threads.len() {
-            let (handle, signal) = &threads[index];
-            println!("Unparking thread {input}.");
-            handle.thread().unpark();
-            signal.wait();
-        } else {
-            println!("Invalid thread number: {input}. Please try again.");
-        }
+    let count = threads.len();
+    let joined = shutdown_threads(threads, &shutdown);
+    println!("{joined} of {count} threads stopped.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_thread_joins_after_shutdown() {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let threads = spawn_threads(10, &shutdown);
+
+        let (handle, signal) = &threads[3];
+        handle.thread().unpark();
+        signal.wait();
+        assert!(threads.iter().all(|(handle, _)| !handle.is_finished()));
+
+        assert_eq!(shutdown_threads(threads, &shutdown), 10);
     }
 }
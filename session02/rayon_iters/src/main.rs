@@ -1,13 +1,26 @@
 use anyhow::{Result, anyhow};
 use rayon::prelude::*;
-use std::time::Instant;
-use util::io::{display_menu, get_numeric, pause};
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+use util::{
+    event::KeyCode,
+    io::{KeyListener, display_menu_interactive, get_numeric, pause},
+};
 
 fn main() {
-    let items = vec!["Sum", "Is prime", "Sum of prime numbers", "Exit"];
+    let items = vec![
+        "Sum",
+        "Is prime",
+        "Sum of prime numbers",
+        "Sum of prime numbers (sieve)",
+        "Exit",
+    ];
 
     loop {
-        let choice: usize = display_menu(&items, None).unwrap_or_else(|ex| {
+        let choice: usize = display_menu_interactive(&items, None).unwrap_or_else(|ex| {
             eprintln!("{}", ex);
             10
         });
@@ -15,6 +28,7 @@ fn main() {
             1 => do_sum(),
             2 => do_prime(),
             3 => do_sum_prime(),
+            4 => do_sum_prime_sieve(),
             _ => {
                 if choice == 0 {
                     println!("Exiting the application.");
@@ -78,10 +92,11 @@ fn do_sum_prime() -> Result<()> {
     }
 
     let start = Instant::now();
-    let sum = (0..=input)
-        .into_par_iter()
-        .filter(|x| is_prime(*x))
-        .sum::<u64>();
+    let Some(sum) = cancellable(|cancelled| sum_primes(input, cancelled))? else {
+        println!("Cancelled.");
+        pause();
+        return Ok(());
+    };
     let ellapsed = start.elapsed();
     println!(
         "Sum of prime numbers between 0 and {input}: {sum}. took {:.4} seconds",
@@ -91,6 +106,184 @@ fn do_sum_prime() -> Result<()> {
     Ok(())
 }
 
+fn do_sum_prime_sieve() -> Result<()> {
+    let input: u64 = get_numeric(Some("Enter a number (Leave empty to exit): ")).unwrap_or(0);
+
+    if input < 1 {
+        println!("Sum: 0. took 0 seconds");
+        return Ok(());
+    }
+
+    let start = Instant::now();
+    let Some(sum) = cancellable(|cancelled| sum_primes_sieve(input, cancelled))? else {
+        println!("Cancelled.");
+        pause();
+        return Ok(());
+    };
+    let ellapsed = start.elapsed();
+    println!(
+        "Sum of prime numbers between 0 and {input}: {sum}. took {:.4} seconds",
+        ellapsed.as_secs_f64()
+    );
+    pause();
+    Ok(())
+}
+
+/// Runs `f` on another thread while listening for Esc, which sets the flag `f` is given.
+/// `f` returns `None` once it notices the flag, in which case its partial result is dropped.
+fn cancellable<T, F>(f: F) -> Result<Option<T>>
+where
+    T: Send,
+    F: FnOnce(&AtomicBool) -> Option<T> + Send,
+{
+    let cancelled = AtomicBool::new(false);
+    let mut key_listener = KeyListener::new()?;
+    println!("Press ESC to cancel...");
+
+    let result = thread::scope(|scope| {
+        let handle = scope.spawn(|| f(&cancelled));
+
+        while !handle.is_finished() {
+            match key_listener.try_recv() {
+                Ok(key) if key.code == KeyCode::Esc => cancelled.store(true, Ordering::Relaxed),
+                _ => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+
+        handle.join()
+    });
+
+    // Leave raw mode before printing the result
+    drop(key_listener);
+    result.map_err(|_| anyhow!("The computation panicked."))
+}
+
 fn is_prime(n: u64) -> bool {
-    (2..=n / 2).into_par_iter().all(|x| n % x != 0)
+    n >= 2 && (2..=n / 2).into_par_iter().all(|x| !n.is_multiple_of(x))
+}
+
+/// Sums the primes up to `limit` by trial division, kept to compare with the sieve.
+/// Returns `None` if `cancelled` gets set before it finishes.
+fn sum_primes(limit: u64, cancelled: &AtomicBool) -> Option<u64> {
+    let sum = AtomicU64::new(0);
+    (0..=limit)
+        .into_par_iter()
+        .try_for_each(|x| {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(());
+            }
+
+            if is_prime(x) {
+                sum.fetch_add(x, Ordering::Relaxed);
+            }
+
+            Ok(())
+        })
+        .ok()?;
+    Some(sum.into_inner())
+}
+
+/// Numbers each segment of the sieve covers, small enough to stay in the cache.
+const SEGMENT_SIZE: u64 = 1 << 15;
+
+/// Sums the primes up to `limit` with a segmented Sieve of Eratosthenes. The primes up to
+/// √limit are sieved first, then each segment is crossed out by them in parallel.
+/// Returns `None` if `cancelled` gets set before it finishes.
+fn sum_primes_sieve(limit: u64, cancelled: &AtomicBool) -> Option<u64> {
+    if limit < 2 {
+        return Some(0);
+    }
+
+    let base_primes = primes_up_to(limit.isqrt());
+    let segments = limit / SEGMENT_SIZE + 1;
+    let sum = AtomicU64::new(0);
+
+    (0..segments)
+        .into_par_iter()
+        .try_for_each(|segment| {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(());
+            }
+
+            let low = segment * SEGMENT_SIZE;
+            let high = (low + SEGMENT_SIZE - 1).min(limit);
+            let mut composite = vec![false; (high - low + 1) as usize];
+
+            for &p in &base_primes {
+                if p * p > high {
+                    break;
+                }
+
+                // Smaller multiples were crossed out by smaller primes
+                let first = (p * p).max(low.div_ceil(p) * p);
+
+                for multiple in (first..=high).step_by(p as usize) {
+                    composite[(multiple - low) as usize] = true;
+                }
+            }
+
+            let primes = composite
+                .iter()
+                .enumerate()
+                .filter(|&(_, &c)| !c)
+                .map(|(i, _)| low + i as u64)
+                .filter(|&n| n >= 2)
+                .sum::<u64>();
+            sum.fetch_add(primes, Ordering::Relaxed);
+            Ok(())
+        })
+        .ok()?;
+    Some(sum.into_inner())
+}
+
+/// The primes up to `n` with a plain sieve.
+fn primes_up_to(n: u64) -> Vec<u64> {
+    let mut composite = vec![false; n as usize + 1];
+    let mut primes = vec![];
+
+    for i in 2..=n {
+        if composite[i as usize] {
+            continue;
+        }
+
+        primes.push(i);
+
+        for multiple in (i * i..=n).step_by(i as usize) {
+            composite[multiple as usize] = true;
+        }
+    }
+
+    primes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sieve_agrees_with_trial_division() {
+        let running = AtomicBool::new(false);
+
+        for limit in [0, 1, 2, 3, 10, 100, 1_000, 20_000] {
+            assert_eq!(
+                sum_primes_sieve(limit, &running),
+                sum_primes(limit, &running),
+                "limit {limit}"
+            );
+        }
+
+        // Trial division is too slow for the limits that span many segments
+        assert_eq!(
+            sum_primes_sieve(SEGMENT_SIZE, &running),
+            sum_primes_sieve(SEGMENT_SIZE - 1, &running)
+        );
+        assert_eq!(sum_primes_sieve(2_000_000, &running), Some(142_913_828_922));
+    }
+
+    #[test]
+    fn cancelled_sums_return_nothing() {
+        let cancelled = AtomicBool::new(true);
+        assert_eq!(sum_primes(1_000_000, &cancelled), None);
+        assert_eq!(sum_primes_sieve(2_000_000, &cancelled), None);
+    }
 }
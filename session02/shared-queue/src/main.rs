@@ -1,45 +1,82 @@
 use crossbeam::channel::{self, Receiver, Sender};
-use fake::{Fake, Faker};
 use std::{thread, time::Duration};
-use util::auth::User;
+use util::auth::{User, gen_users, seed_arg};
 
-fn main() {
-    let threads = num_cpus::get();
-    let n_users = threads * 4;
+/// Feeds `users` to `threads` consumers, pausing `produce_delay` after each enqueue and
+/// `consume_delay` after each user processed. Returns how many users each consumer handled.
+fn run(
+    threads: usize,
+    users: Vec<User>,
+    produce_delay: Duration,
+    consume_delay: Duration,
+) -> Vec<usize> {
     let (tx, rx): (Sender<User>, Receiver<User>) = channel::unbounded();
     println!("Spawning {} consumers...", threads);
     thread::scope(|scope| {
         // Consumer threads
-        for i in 0..threads {
-            let n = i + 1;
-            let rx2 = rx.clone();
-            scope.spawn(move || {
-                println!("CNS {}>>> Starting up.", n);
+        let consumers: Vec<_> = (0..threads)
+            .map(|i| {
+                let n = i + 1;
+                let rx2 = rx.clone();
+                scope.spawn(move || {
+                    println!("CNS {}>>> Starting up.", n);
+                    let mut processed = 0;
 
-                while let Ok(user) = rx2.recv() {
-                    println!("CNS {}>>> Processing user: {}", n, user);
-                    thread::sleep(Duration::from_millis(300));
-                }
+                    while let Ok(user) = rx2.recv() {
+                        println!("CNS {}>>> Processing user: {}", n, user);
+                        processed += 1;
+                        thread::sleep(consume_delay);
+                    }
 
-                println!("CNS {}>>> Shutting down.", n);
-            });
-        }
+                    println!("CNS {}>>> Shutting down.", n);
+                    processed
+                })
+            })
+            .collect();
 
         // Producer thread
         scope.spawn(move || {
-            println!("\nProducer starting to generate {} users...", n_users);
+            println!("\nProducer starting to generate {} users...", users.len());
 
-            for i in 0..n_users {
+            for (i, user) in users.into_iter().enumerate() {
                 let n = i + 1;
-                let user: User = Faker.fake();
                 println!("PRD >>> Enqueueing user {}.", n);
                 tx.send(user).expect(&format!("Failed to send user {}.", n));
-                thread::sleep(Duration::from_millis(50));
+                thread::sleep(produce_delay);
             }
 
             println!("Producer finished.");
             drop(tx);
         });
-    });
+
+        consumers
+            .into_iter()
+            .map(|handle| handle.join().expect("Consumer panicked."))
+            .collect()
+    })
+}
+
+fn main() {
+    let threads = num_cpus::get();
+    let users = gen_users(threads * 4, seed_arg());
+    run(
+        threads,
+        users,
+        Duration::from_millis(50),
+        Duration::from_millis(300),
+    );
     println!("All threads are completed.");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_produced_user_is_consumed_once() {
+        let users = gen_users(24, Some(42));
+        let processed = run(4, users, Duration::ZERO, Duration::from_millis(1));
+        assert_eq!(processed.len(), 4);
+        assert_eq!(processed.iter().sum::<usize>(), 24);
+    }
+}
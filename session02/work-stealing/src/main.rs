@@ -1,98 +1,74 @@
-use crossbeam::deque::{Injector, Stealer, Worker};
-use fake::{Fake, Faker};
 use std::{
     sync::{
         Arc,
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicUsize, Ordering},
     },
     thread,
     time::Duration,
-    usize,
 };
-use util::auth::User;
+use util::{
+    auth::{User, gen_users, seed_arg},
+    threading::WorkStealingPool,
+};
 
-fn producer(injector: Arc<Injector<User>>, shutdown: Arc<AtomicBool>, n_users: usize) {
-    println!("\nProducer starting to generate {} users...", n_users);
+fn producer(pool: &WorkStealingPool<User>, users: Vec<User>, delay: Duration) {
+    println!("\nProducer starting to generate {} users...", users.len());
 
-    for i in 0..n_users {
+    for (i, user) in users.into_iter().enumerate() {
         let n = i + 1;
-        let user: User = Faker.fake();
         println!("PRD >>> Enqueueing user {}.", n);
-        injector.push(user);
-        thread::sleep(Duration::from_millis(50));
+        pool.push(user);
+        thread::sleep(delay);
     }
 
     println!("Producer finished.");
-    shutdown.store(true, Ordering::SeqCst);
 }
 
-fn consumer(
-    n: usize,
-    local: &Worker<User>,
-    stealers: &[Stealer<User>],
-    injector: &Injector<User>,
-    shutdown: &AtomicBool,
-) {
-    println!("CNS {}>>> Starting up.", n);
-
-    loop {
-        if shutdown.load(Ordering::SeqCst) {
-            break;
-        }
-
-        if let Some(user) = find_task(local, stealers, injector) {
-            println!("CNS {}>>> Processing user: {}", n, user);
-            thread::sleep(Duration::from_millis(300));
-        } else {
-            thread::sleep(Duration::from_millis(50));
-        }
-    }
-
-    println!("CNS {}>>> Shutting down.", n);
+fn consumer(user: User, delay: Duration) {
+    let name = thread::current().name().unwrap_or_default().to_string();
+    println!("{}>>> Processing user: {}", name, user);
+    thread::sleep(delay);
 }
 
-fn find_task<'a>(
-    local: &'a Worker<User>,
-    stealers: &'a [Stealer<User>],
-    injector: &'a Injector<User>,
-) -> Option<User> {
-    local.pop().or_else(|| {
-        stealers
-            .iter()
-            .filter(|x| !x.is_empty())
-            .map(|x| x.steal())
-            .find(|x| x.is_success())
-            .and_then(|x| x.success())
-            .or_else(|| injector.steal().success())
-    })
+/// Pushes `users` through a pool of `threads` consumers and returns how many were processed.
+fn run(
+    threads: usize,
+    users: Vec<User>,
+    produce_delay: Duration,
+    consume_delay: Duration,
+) -> usize {
+    let processed = Arc::new(AtomicUsize::new(0));
+    let counter = processed.clone();
+    println!("Spawning {} consumers...", threads);
+    let pool = WorkStealingPool::new(threads, move |user| {
+        consumer(user, consume_delay);
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+    producer(&pool, users, produce_delay);
+    // Waits for the consumers to handle the remaining users
+    pool.shutdown();
+    processed.load(Ordering::SeqCst)
 }
 
 fn main() {
-    let threads = num_cpus::get();
-    let n_users = threads * 4;
-    let injector: Arc<Injector<User>> = Arc::new(Injector::new());
-    let workers: Vec<Worker<User>> = (0..n_users).map(|_| Worker::new_fifo()).collect();
-    let stealers: Vec<Stealer<User>> = workers.iter().map(|w| w.stealer()).collect();
-    let shutdown = Arc::new(AtomicBool::new(false));
-    println!("Spawning {} consumers...", threads);
-    thread::scope(|scope| {
-        // Producer thread
-        let injector2 = injector.clone();
-        let shutdown2 = shutdown.clone();
-        scope.spawn(move || {
-            producer(injector2, shutdown2, n_users);
-        });
-
-        // Consumer threads
-        for (i, worker) in workers.into_iter().enumerate() {
-            let n = i + 1;
-            let stealers = &stealers;
-            let injector = &injector;
-            let shutdown = &shutdown;
-            scope.spawn(move || {
-                consumer(n, &worker, stealers, injector, shutdown);
-            });
-        }
-    });
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let users = gen_users(threads * 4, seed_arg());
+    run(
+        threads,
+        users,
+        Duration::from_millis(50),
+        Duration::from_millis(300),
+    );
     println!("All threads are completed.");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_produced_user_is_processed() {
+        let users = gen_users(24, Some(42));
+        assert_eq!(run(4, users, Duration::ZERO, Duration::from_millis(1)), 24);
+    }
+}
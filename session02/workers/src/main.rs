@@ -1,42 +1,22 @@
-use std::{sync::mpsc, thread};
-
-type Job = Box<dyn FnOnce() + Send + 'static>;
-
-enum Command {
-    Run(Job),
-    Exit,
-}
+use util::threading::ThreadPool;
 
 fn hi_there() {
     println!("Hello from the worker thread!");
 }
 
 fn main() {
-    let (tx, rx) = mpsc::channel::<Command>();
-    let handle = thread::spawn(move || {
-        while let Ok(command) = rx.recv() {
-            match command {
-                Command::Run(job) => {
-                    job();
-                }
-                Command::Exit => {
-                    println!("Exiting...");
-                    break;
-                }
-            }
-        }
-    });
+    let pool = ThreadPool::new(4);
     let job = || println!("Hello from my closure!");
     let job2 = || {
         for i in 1..=5 {
             println!("Job 2: {}", i);
         }
     };
-    tx.send(Command::Run(Box::new(hi_there))).unwrap();
-    tx.send(Command::Run(Box::new(job))).unwrap();
-    tx.send(Command::Run(Box::new(job2))).unwrap();
-    tx.send(Command::Run(Box::new(|| println!("I'm in the box!"))))
-        .unwrap();
-    tx.send(Command::Exit).unwrap();
-    handle.join().unwrap();
+    pool.execute(hi_there);
+    pool.execute(job);
+    pool.execute(job2);
+    pool.execute(|| println!("I'm in the box!"));
+    // Dropping the pool tells the workers to exit and joins them
+    drop(pool);
+    println!("Exiting...");
 }
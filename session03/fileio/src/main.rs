@@ -1,12 +1,14 @@
 use anyhow::{Result, anyhow};
 use std::{
     fs::File,
-    io::{self, BufRead, BufReader, Lines},
-    path::Path,
+    io::{self, BufRead, BufReader, Lines, SeekFrom},
+    path::{Path, PathBuf},
+    thread,
 };
 use tokio::{
     fs::File as TkFile,
-    io::{AsyncBufReadExt, BufReader as TkBufReader},
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, BufReader as TkBufReader},
+    task::JoinSet,
 };
 
 fn read_lines<P: AsRef<Path>>(filename: P) -> Result<Lines<BufReader<File>>> {
@@ -50,6 +52,98 @@ async fn lines_count_async<P: AsRef<Path>>(filename: P) -> Result<usize> {
     Ok(lines_count)
 }
 
+/// One chunk per CPU.
+fn default_chunks() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Counts the non-empty lines like `lines_count_async`, but splits the file into `chunks`
+/// byte ranges counted concurrently. A line is counted by the range it starts in, so lines
+/// straddling a boundary are counted once.
+async fn count_lines_parallel<P: AsRef<Path>>(filename: P, chunks: usize) -> Result<usize> {
+    if chunks == 0 {
+        return Err(anyhow!("chunks must be at least 1."));
+    }
+
+    let filename = filename.as_ref().to_path_buf();
+    let len = tokio::fs::metadata(&filename).await?.len();
+    let chunk_size = len.div_ceil(chunks as u64).max(1);
+    let mut tasks = JoinSet::new();
+    let mut start = 0;
+
+    while start < len {
+        let end = (start + chunk_size).min(len);
+        tasks.spawn(count_lines_in_range(filename.clone(), start, end));
+        start = end;
+    }
+
+    let mut lines_count = 0;
+
+    while let Some(result) = tasks.join_next().await {
+        lines_count += result??;
+    }
+
+    Ok(lines_count)
+}
+
+/// Counts the non-empty lines starting in `start..end`. Reads one byte before the range to
+/// tell whether `start` begins a line, and one past it in case the last line is just `\r\n`.
+async fn count_lines_in_range(filename: PathBuf, start: u64, end: u64) -> Result<usize> {
+    let from = start.saturating_sub(1);
+    let mut file = TkFile::open(&filename).await?;
+    file.seek(SeekFrom::Start(from)).await?;
+    let mut reader = TkBufReader::new(file.take(end + 1 - from));
+    let mut pos = from;
+    let mut at_line_start = start == 0;
+    // A line starting with '\r' is empty if '\n' follows
+    let mut pending_cr = false;
+    let mut lines_count = 0;
+
+    'read: loop {
+        let buf = reader.fill_buf().await?;
+
+        if buf.is_empty() {
+            break;
+        }
+
+        let read = buf.len();
+
+        for &byte in buf {
+            if pending_cr {
+                pending_cr = false;
+
+                if byte != b'\n' {
+                    lines_count += 1;
+                }
+            }
+
+            if pos >= end {
+                break 'read;
+            }
+
+            if pos >= start && at_line_start {
+                match byte {
+                    b'\n' => {}
+                    b'\r' => pending_cr = true,
+                    _ => lines_count += 1,
+                }
+            }
+
+            at_line_start = byte == b'\n';
+            pos += 1;
+        }
+
+        reader.consume(read);
+    }
+
+    // The file ended right after a '\r'
+    if pending_cr {
+        lines_count += 1;
+    }
+
+    Ok(lines_count)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let filename = match std::env::current_dir() {
@@ -77,16 +171,63 @@ async fn main() -> Result<()> {
     }
 
     let now = std::time::Instant::now();
-    let (c1, c2, ..) = tokio::join!(
-        lines_count_async(filename.clone()),
-        lines_count_async(filename.clone()),
-        lines_count_async(filename.clone()),
-        lines_count_async(filename.clone()),
-    );
+    let lines_count = lines_count_async(filename.clone()).await?;
     println!(
         "Read {} lines in {:.4} seconds.",
-        c1? + c2?,
+        lines_count,
+        now.elapsed().as_secs_f64()
+    );
+
+    // The number of chunks can be passed as the first argument
+    let chunks = match std::env::args().nth(1) {
+        Some(arg) => arg
+            .parse()
+            .map_err(|_| anyhow!("'{}' is not a number of chunks.", arg))?,
+        None => default_chunks(),
+    };
+    let now = std::time::Instant::now();
+    let lines_count = count_lines_parallel(&filename, chunks).await?;
+    println!(
+        "Read {} lines in {} chunks in {:.4} seconds.",
+        lines_count,
+        chunks,
         now.elapsed().as_secs_f64()
     );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn parallel_count_matches_the_sequential_one() {
+        let filename = std::env::temp_dir().join(format!("fileio-{}.txt", std::process::id()));
+        let mut text = String::new();
+
+        for i in 0..200 {
+            match i % 7 {
+                0 => text.push('\n'),
+                1 => text.push_str("\r\n"),
+                2 => text.push_str("windows line\r\n"),
+                _ => text.push_str(&format!("line {} {}\n", i, "x".repeat(i % 13))),
+            }
+        }
+
+        text.push_str("no trailing newline");
+        std::fs::write(&filename, &text).unwrap();
+        let expected = lines_count_async(&filename).await.unwrap();
+        assert_eq!(expected, 143);
+
+        for chunks in [1, 2, 3, 7, 64, text.len(), text.len() * 2] {
+            assert_eq!(
+                count_lines_parallel(&filename, chunks).await.unwrap(),
+                expected,
+                "{chunks} chunks"
+            );
+        }
+
+        assert!(count_lines_parallel(&filename, 0).await.is_err());
+        std::fs::remove_file(&filename).unwrap();
+    }
+}
@@ -0,0 +1,68 @@
+use crossterm::event::KeyEvent;
+use std::collections::VecDeque;
+
+/// The last `capacity` key events, oldest first. Pushing to a full history evicts the
+/// oldest event.
+#[derive(Debug, Clone)]
+pub struct KeyHistory {
+    events: VecDeque<KeyEvent>,
+    capacity: usize,
+}
+
+impl KeyHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, event: KeyEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+
+        self.events.push_back(event);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &KeyEvent> {
+        self.events.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyCode;
+
+    fn chars(history: &KeyHistory) -> String {
+        history
+            .iter()
+            .filter_map(|key| key.code.as_char())
+            .collect()
+    }
+
+    #[test]
+    fn oldest_events_are_evicted() {
+        let mut history = KeyHistory::new(3);
+        assert!(history.is_empty());
+
+        for c in "abcde".chars() {
+            history.push(KeyEvent::from(KeyCode::Char(c)));
+        }
+
+        assert_eq!(chars(&history), "cde");
+
+        let mut disabled = KeyHistory::new(0);
+        disabled.push(KeyEvent::from(KeyCode::Enter));
+        assert!(disabled.is_empty());
+    }
+}
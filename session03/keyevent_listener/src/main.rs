@@ -1,30 +1,43 @@
-use crossterm::event::KeyCode;
-use std::{thread, time::Duration};
-use util::{Result, io::KeyListener, sync::mpsc::error::TryRecvError};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::{sync::mpsc::TryRecvError, thread, time::Duration};
+use util::{Result, io::spawn_key_listener};
+
+use history::KeyHistory;
+
+mod history;
+
+/// Number of key events Ctrl-R prints.
+const HISTORY_SIZE: usize = 20;
 
 fn main() -> Result<()> {
-    let mut key_listener = KeyListener::new()?;
-    println!("Press keys (ESC to quit):");
+    let key_listener = spawn_key_listener()?;
+    let mut history = KeyHistory::new(HISTORY_SIZE);
+    println!("Press keys (Ctrl-R for the last {HISTORY_SIZE}, ESC to quit):");
 
     // Main thread continues without blocking
     loop {
         match key_listener.try_recv() {
             Ok(key) => match key.code {
                 KeyCode::Esc => break,
-                KeyCode::Char(c) => {
-                    if key.modifiers.is_empty() {
-                        println!("Pressed: {}", c);
-                    } else {
-                        println!("Pressed: {} with {:?}", c, key.modifiers);
+                KeyCode::Char('r') if key.modifiers == KeyModifiers::CONTROL => {
+                    if history.is_empty() {
+                        println!("No keys pressed yet.");
+                        continue;
                     }
+
+                    let keys = history.iter().map(describe).collect::<Vec<_>>();
+                    println!("Recent keys: {}", keys.join(", "));
+                }
+                _ => {
+                    println!("Pressed: {}", describe(&key));
+                    history.push(key);
                 }
-                _ => println!("Pressed: {:?} with {:?}", key.code, key.modifiers),
             },
             Err(TryRecvError::Disconnected) => {
                 // Listener is disconnected
                 break;
             }
-            Err(_) => {
+            Err(TryRecvError::Empty) => {
                 thread::sleep(Duration::from_millis(10));
             }
         }
@@ -32,3 +45,11 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+fn describe(key: &KeyEvent) -> String {
+    match key.code {
+        KeyCode::Char(c) if key.modifiers.is_empty() => c.to_string(),
+        KeyCode::Char(c) => format!("{} with {:?}", c, key.modifiers),
+        _ => format!("{:?} with {:?}", key.code, key.modifiers),
+    }
+}
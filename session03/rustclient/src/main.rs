@@ -1,12 +1,96 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use clap::Parser;
 use serde::Deserialize;
 use serde_json::Value as JsonValue;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
     time::{Duration, timeout},
 };
-use util::io;
+use util::{
+    io,
+    retry::{BackoffPolicy, retry_with_backoff},
+};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+#[derive(Parser)]
+#[command()]
+struct Args {
+    /// Address of the TCP server
+    #[arg(long, default_value = "127.0.0.1:8123")]
+    host: String,
+    /// How long to wait for a connection, in milliseconds
+    #[arg(long, default_value_t = 3000)]
+    connect_timeout: u64,
+    /// How long to wait for each server line, in milliseconds
+    #[arg(long, default_value_t = 1000)]
+    read_timeout: u64,
+    /// How many times to retry connecting before giving up
+    #[arg(long, default_value_t = 5)]
+    retries: u32,
+}
+
+impl Args {
+    fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout)
+    }
+
+    fn read_timeout(&self) -> Duration {
+        Duration::from_millis(self.read_timeout)
+    }
+}
+
+/// A line-framed connection to the TCP server.
+struct Connection {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Connection {
+    async fn open(args: &Args) -> Result<Self> {
+        let stream = timeout(args.connect_timeout(), TcpStream::connect(&args.host))
+            .await
+            .map_err(|_| anyhow!("timed out after {:?}", args.connect_timeout()))??;
+        let (reader, writer) = stream.into_split();
+        let mut connection = Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        };
+        println!();
+        println!("Connected to {}", args.host);
+
+        if !connection.print_replies(args.read_timeout()).await? {
+            return Err(anyhow!("server closed the connection"));
+        }
+
+        Ok(connection)
+    }
+
+    async fn send(&mut self, line: &str) -> Result<()> {
+        self.writer.write_all(line.as_bytes()).await?;
+        self.writer.write_all(b"\n").await?;
+        Ok(())
+    }
+
+    /// Prints whole lines until the server stays quiet for `wait`. Returns `false` once the
+    /// server has closed the connection.
+    async fn print_replies(&mut self, wait: Duration) -> Result<bool> {
+        loop {
+            // `next_line` is cancel safe, so a timeout never drops half a line.
+            match timeout(wait, self.lines.next_line()).await {
+                Err(_) => return Ok(true),
+                Ok(Ok(Some(line))) => println!("{line}"),
+                Ok(Ok(None)) => return Ok(false),
+                Ok(Err(e)) => return Err(e.into()),
+            }
+        }
+    }
+}
 
 async fn get_my_ip() -> Result<String> {
     const URL: &'static str = "https://httpbin.org/ip";
@@ -35,42 +119,67 @@ async fn get_weather() -> Result<JsonValue> {
     Ok(json)
 }
 
-async fn connect_to_tcp() -> Result<()> {
-    const HOST: &'static str = "127.0.0.1:8123";
-    const BUFFER_SIZE: usize = 1024;
-
-    let mut stream = TcpStream::connect(HOST).await?;
-    println!();
-    println!("Connected to {}", HOST);
-
-    let mut buffer = vec![0u8; BUFFER_SIZE];
-
-    if let Ok(Ok(n)) = timeout(Duration::from_millis(500), stream.read(&mut buffer)).await {
-        let response = String::from_utf8_lossy(&buffer[..n]);
-        println!("{}", response.trim_end());
+/// Connects to the server, retrying with a doubling delay up to `args.retries` times.
+async fn connect_with_backoff(args: &Args) -> Result<Connection> {
+    let policy = BackoffPolicy {
+        base: INITIAL_BACKOFF,
+        max: MAX_BACKOFF,
+        max_retries: args.retries,
+        ..Default::default()
     };
+    let mut attempt = 0;
+
+    retry_with_backoff(&policy, || {
+        attempt += 1;
+        let attempt = attempt;
+        async move {
+            Connection::open(args).await.inspect_err(|e| {
+                eprintln!(
+                    "Could not connect to {}: {e} (attempt {attempt}/{})",
+                    args.host,
+                    args.retries + 1
+                )
+            })
+        }
+    })
+    .await
+}
+
+async fn connect_to_tcp(args: &Args) -> Result<()> {
+    let mut connection = connect_with_backoff(args).await?;
 
     loop {
         let input = match io::get_str(Some("> ")) {
             Ok(s) => s,
             Err(_) => return Ok(()),
         };
-        stream.write_all(input.as_bytes()).await?;
-        stream.write_all(b"\n").await?;
+        let quit = input.trim().eq_ignore_ascii_case("QUIT");
 
-        let n = match timeout(Duration::from_secs(1), stream.read(&mut buffer)).await {
-            Ok(Ok(n)) => n,
-            Ok(Err(_)) => continue,
-            Err(_) => continue,
-        };
+        if let Err(e) = connection.send(&input).await {
+            eprintln!("Connection lost: {e}");
+            connection = connect_with_backoff(args).await?;
+            connection.send(&input).await?;
+        }
+
+        let open = connection
+            .print_replies(args.read_timeout())
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Read failed: {e}");
+                false
+            });
+
+        if open {
+            continue;
+        }
 
-        if n == 0 {
+        if quit {
             println!("Server closed connection.");
             break;
         }
 
-        let response = String::from_utf8_lossy(&buffer[..n]);
-        println!("{}", response.trim_end());
+        println!("Connection lost, reconnecting...");
+        connection = connect_with_backoff(args).await?;
     }
 
     Ok(())
@@ -78,6 +187,7 @@ async fn connect_to_tcp() -> Result<()> {
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = Args::parse();
     let ip = get_my_ip().await?;
     println!("My IP address: {ip}");
 
@@ -89,7 +199,7 @@ async fn main() -> Result<()> {
     println!("{weather:#?}");
 
     println!("Trying to connect to TCP server...");
-    connect_to_tcp().await?;
+    connect_to_tcp(&args).await?;
 
     Ok(())
 }
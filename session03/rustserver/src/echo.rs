@@ -0,0 +1,49 @@
+/// Sent to every client when it connects, over TCP or WebSocket.
+pub const WELCOME: &str = "Welcome to the Rust TCP server!\r\nType something and it will be echoed back.\r\nSend 'QUIT' to exit.";
+/// Sent before closing a connection that stayed silent for too long.
+pub const IDLE_TIMEOUT: &str = "Idle timeout, bye.";
+/// Sent before closing a connection that sent QUIT.
+pub const BYE: &str = "Bye.";
+
+/// What to do with a message received from a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// Blank messages are ignored.
+    Nothing,
+    /// Send the trimmed message back.
+    Echo(String),
+    /// The client sent QUIT; say bye and close the connection.
+    Quit,
+}
+
+/// Decides the reply to `message`, shared by the TCP and the WebSocket server.
+pub fn handle_message(message: &str) -> Reply {
+    let message = message.trim();
+
+    if message.is_empty() {
+        Reply::Nothing
+    } else if message.eq_ignore_ascii_case("QUIT") {
+        Reply::Quit
+    } else {
+        Reply::Echo(message.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_trimmed_echoed_or_quit() {
+        assert_eq!(handle_message(" \r\n"), Reply::Nothing);
+        assert_eq!(
+            handle_message("hello\r\n"),
+            Reply::Echo("hello".to_string())
+        );
+        assert_eq!(handle_message("quit\r\n"), Reply::Quit);
+        assert_eq!(
+            handle_message("quit now"),
+            Reply::Echo("quit now".to_string())
+        );
+    }
+}
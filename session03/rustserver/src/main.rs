@@ -1,61 +1,134 @@
 use anyhow::Result;
+use echo::Reply;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpListener,
+    net::{TcpListener, TcpStream},
     spawn,
+    sync::Semaphore,
+    time::timeout,
 };
 
+mod echo;
+mod ws;
+
+const HOST: &str = "127.0.0.1:8123";
+/// Serves the same echo over WebSocket at `/ws`.
+const WS_HOST: &str = "127.0.0.1:8124";
+const BUFFER_SIZE: usize = 1024;
+/// Used when `MAX_CONNECTIONS` is not set.
+const DEFAULT_MAX_CONNECTIONS: usize = 100;
+/// Used when `IDLE_TIMEOUT_SECS` is not set.
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 60;
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    const HOST: &'static str = "127.0.0.1:8123";
-    const BUFFER_SIZE: usize = 1024;
-
+    let max_connections = env_or("MAX_CONNECTIONS", DEFAULT_MAX_CONNECTIONS).max(1);
+    let idle_timeout = Duration::from_secs(env_or("IDLE_TIMEOUT_SECS", DEFAULT_IDLE_TIMEOUT_SECS));
     let listener = TcpListener::bind(HOST).await?;
+    let ws_listener = TcpListener::bind(WS_HOST).await?;
     println!();
     println!("Listening on {}", HOST);
     println!("You can use PuTTY or any TCP client to send mesages to this server.");
     println!(
         "If you see strange squares when first connected, try to make a RAW connection instead of Telnet."
     );
+    println!("Browsers and other WebSocket clients can connect to ws://{WS_HOST}/ws");
+    println!(
+        "Accepting up to {max_connections} connections, idle ones are closed after {}s.",
+        idle_timeout.as_secs()
+    );
     println!();
 
+    let permits = Arc::new(Semaphore::new(max_connections));
+    let ws_app = ws::router(permits.clone(), idle_timeout);
+    spawn(async move {
+        let service = ws_app.into_make_service_with_connect_info::<SocketAddr>();
+
+        if let Err(e) = axum::serve(ws_listener, service).await {
+            eprintln!("WebSocket server failed: {e}");
+        }
+    });
+
     loop {
         let (mut socket, address) = listener.accept().await?;
+        let Ok(permit) = permits.clone().try_acquire_owned() else {
+            println!("Rejecting {address:?}, {max_connections} connections are already open");
+            let _ = socket
+                .write_all(b"Server is busy, try again later.\r\n")
+                .await;
+            continue;
+        };
+        let permits = permits.clone();
+        println!(
+            "Connection from {address:?} ({} open)",
+            max_connections - permits.available_permits()
+        );
         spawn(async move {
-            println!("Connection from {address:?}");
-            let welcome = b"Welcome to the Rust TCP server!\r\nType something and it will be echoed back.\r\nSend 'QUIT' to exit.\r\n";
-
-            if let Err(e) = socket.write_all(welcome).await {
-                eprintln!("Failed to write welcome message: {e}");
-                return;
+            if let Err(e) = handle_connection(&mut socket, address, idle_timeout).await {
+                eprintln!("Connection from {address:?} failed: {e}");
             }
 
-            let mut buffer = vec![0; BUFFER_SIZE];
-
-            loop {
-                let n = socket
-                    .read(&mut buffer)
-                    .await
-                    .expect("Failed to read data from the socket!");
+            drop(permit);
+            println!(
+                "Closed connection from {address:?} ({} open)",
+                max_connections - permits.available_permits()
+            );
+        });
+    }
+}
 
-                if n == 0 {
-                    println!("Closing connection from {address:?}");
-                    return;
-                }
+/// Echoes what the client sends until it disconnects, sends QUIT or stays silent for
+/// `idle_timeout`. Errors only affect this connection.
+async fn handle_connection(
+    socket: &mut TcpStream,
+    address: SocketAddr,
+    idle_timeout: Duration,
+) -> Result<()> {
+    socket
+        .write_all(format!("{}\r\n", echo::WELCOME).as_bytes())
+        .await?;
+    let mut buffer = vec![0; BUFFER_SIZE];
 
-                let message = String::from_utf8_lossy(&buffer[..n]).trim().to_string();
+    loop {
+        let Ok(read) = timeout(idle_timeout, socket.read(&mut buffer)).await else {
+            println!("Connection from {address:?} was idle for too long");
+            socket
+                .write_all(format!("{}\r\n", echo::IDLE_TIMEOUT).as_bytes())
+                .await?;
+            break;
+        };
+        let n = read?;
 
-                if message.is_empty() {
-                    continue;
-                }
+        if n == 0 {
+            break;
+        }
 
+        match echo::handle_message(&String::from_utf8_lossy(&buffer[..n])) {
+            Reply::Nothing => {}
+            Reply::Echo(message) => {
                 println!("{message}");
-
-                if message.eq_ignore_ascii_case("QUIT") {
-                    println!("Received QUIT, closing connection from {address:?}");
-                    return;
-                }
+                socket
+                    .write_all(format!("{message}\r\n").as_bytes())
+                    .await?;
             }
-        });
+            Reply::Quit => {
+                println!("Received QUIT from {address:?}");
+                socket
+                    .write_all(format!("{}\r\n", echo::BYE).as_bytes())
+                    .await?;
+                break;
+            }
+        }
     }
+
+    socket.shutdown().await?;
+    Ok(())
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
 }
@@ -0,0 +1,164 @@
+use axum::{
+    Router,
+    extract::{
+        ConnectInfo, State, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::timeout,
+};
+
+use crate::echo::{self, Reply};
+
+#[derive(Clone)]
+struct WsState {
+    permits: Arc<Semaphore>,
+    idle_timeout: Duration,
+}
+
+/// Serves `/ws`, the TCP server's echo over WebSocket so browsers can connect too. Every
+/// text frame is one message. Connections take their permit from the same `permits` as the
+/// TCP ones.
+pub fn router(permits: Arc<Semaphore>, idle_timeout: Duration) -> Router {
+    Router::new()
+        .route("/ws", get(upgrade))
+        .with_state(WsState {
+            permits,
+            idle_timeout,
+        })
+}
+
+async fn upgrade(
+    ws: WebSocketUpgrade,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    State(state): State<WsState>,
+) -> Response {
+    let Ok(permit) = state.permits.clone().try_acquire_owned() else {
+        println!("Rejecting WebSocket {address:?}, the server is full");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Server is busy, try again later.",
+        )
+            .into_response();
+    };
+
+    println!("WebSocket connection from {address:?}");
+    ws.on_upgrade(move |socket| async move {
+        if let Err(e) = handle_socket(socket, address, state.idle_timeout, permit).await {
+            eprintln!("WebSocket connection from {address:?} failed: {e}");
+        }
+
+        println!("Closed WebSocket connection from {address:?}");
+    })
+}
+
+/// Echoes what the client sends until it disconnects, sends QUIT or stays silent for
+/// `idle_timeout`, like `handle_connection` does for TCP.
+async fn handle_socket(
+    mut socket: WebSocket,
+    address: SocketAddr,
+    idle_timeout: Duration,
+    _permit: OwnedSemaphorePermit,
+) -> Result<(), axum::Error> {
+    socket.send(Message::text(echo::WELCOME)).await?;
+
+    loop {
+        let Ok(received) = timeout(idle_timeout, socket.recv()).await else {
+            println!("WebSocket connection from {address:?} was idle for too long");
+            socket.send(Message::text(echo::IDLE_TIMEOUT)).await?;
+            break;
+        };
+        let message = match received {
+            None | Some(Ok(Message::Close(_))) => return Ok(()),
+            Some(Ok(Message::Text(text))) => text.to_string(),
+            Some(Ok(Message::Binary(data))) => String::from_utf8_lossy(&data).into_owned(),
+            // Pings are answered by axum
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(e),
+        };
+
+        match echo::handle_message(&message) {
+            Reply::Nothing => {}
+            Reply::Echo(message) => {
+                println!("{message}");
+                socket.send(Message::text(message)).await?;
+            }
+            Reply::Quit => {
+                println!("Received QUIT from {address:?}");
+                socket.send(Message::text(echo::BYE)).await?;
+                break;
+            }
+        }
+    }
+
+    socket.send(Message::Close(None)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Stream;
+    use futures::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{connect_async, tungstenite};
+
+    async fn serve(max_connections: usize) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = router(
+            Arc::new(Semaphore::new(max_connections)),
+            Duration::from_secs(5),
+        );
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            .unwrap()
+        });
+        address
+    }
+
+    async fn next_text<S>(client: &mut S) -> String
+    where
+        S: Stream<Item = Result<tungstenite::Message, tungstenite::Error>> + Unpin,
+    {
+        let message = client.next().await.unwrap().unwrap();
+        message.to_text().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn messages_are_echoed_until_quit() {
+        let address = serve(1).await;
+        let (mut client, _) = connect_async(format!("ws://{address}/ws")).await.unwrap();
+        assert_eq!(next_text(&mut client).await, echo::WELCOME);
+
+        // The only permit is taken
+        let error = connect_async(format!("ws://{address}/ws"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, tungstenite::Error::Http(response) if response.status().as_u16() == 503),
+            "{error}"
+        );
+
+        client
+            .send(tungstenite::Message::text("hello\r\n"))
+            .await
+            .unwrap();
+        assert_eq!(next_text(&mut client).await, "hello");
+        client
+            .send(tungstenite::Message::text("quit"))
+            .await
+            .unwrap();
+        assert_eq!(next_text(&mut client).await, echo::BYE);
+        assert!(client.next().await.unwrap().unwrap().is_close());
+    }
+}
@@ -2,11 +2,12 @@ use anyhow::{Result, anyhow};
 use dotenvy::dotenv;
 use sea_orm::{
     ActiveModelTrait, ColumnTrait, ConnectionTrait, Database, DbConn, DbErr, EntityTrait,
-    QueryFilter, Schema, Set,
+    PaginatorTrait, QueryFilter, QueryOrder, Schema, Set, SqlErr,
 };
-use std::env;
+use std::{env, path::PathBuf};
 
 mod entities;
+mod seed;
 use entities::user::{self, ActiveModel as UserActiveModel, Entity as User, Model as UserModel};
 
 /// Sets up the database connection and runs migrations.
@@ -27,34 +28,6 @@ async fn setup_database() -> Result<DbConn> {
     Ok(db)
 }
 
-async fn seed_users(db: &DbConn) -> Result<()> {
-    let users_to_seed = vec![
-        UserActiveModel {
-            name: Set("Alice".to_owned()),
-            email: Set("alice@example.com".to_owned()),
-            ..Default::default()
-        },
-        UserActiveModel {
-            name: Set("Bob".to_owned()),
-            email: Set("bob@example.com".to_owned()),
-            ..Default::default()
-        },
-    ];
-
-    User::insert_many(users_to_seed)
-        .on_conflict(
-            // The path to Column is now cleaner thanks to `use entities::user;`
-            sea_orm::sea_query::OnConflict::column(user::Column::Email)
-                .do_nothing()
-                .to_owned(),
-        )
-        .exec(db)
-        .await?;
-
-    println!("Seeded initial users.");
-    Ok(())
-}
-
 /// Lists all users in the database.
 async fn list_all_users(db: &DbConn, context: &str) -> Result<()> {
     println!("\n--- {} ---", context);
@@ -93,26 +66,54 @@ async fn update_user_name(db: &DbConn, id: i32, new_name: &str) -> Result<UserMo
     Ok(model)
 }
 
-/// Finds users whose name contains a given string.
-async fn find_users_by_name(db: &DbConn, search_str: &str) -> Result<()> {
+/// Changes a user's email, refusing one that already belongs to another user.
+async fn update_user_email(db: &DbConn, id: i32, new_email: &str) -> Result<UserModel> {
     println!(
-        "\n--- Finding users with names containing '{}' ---",
-        search_str
+        "\n--- Updating user with ID {} to email '{}' ---",
+        id, new_email
     );
 
-    let found_users: Vec<UserModel> = User::find()
-        .filter(user::Column::Name.contains(search_str))
-        .all(db)
-        .await?;
+    let mut user_to_update: UserActiveModel = User::find_by_id(id)
+        .one(db)
+        .await?
+        .ok_or(DbErr::RecordNotFound(format!(
+            "User with ID {} not found",
+            id
+        )))?
+        .into();
 
-    if found_users.is_empty() {
-        println!("No users found matching the criteria.");
-    } else {
-        for user in found_users {
-            println!("{:?}", user);
-        }
+    user_to_update.email = Set(new_email.to_owned());
+    let model = user_to_update
+        .update(db)
+        .await
+        .map_err(|e| match e.sql_err() {
+            Some(SqlErr::UniqueConstraintViolation(_)) => {
+                anyhow!("Email '{}' is already used by another user", new_email)
+            }
+            _ => anyhow!(e),
+        })?;
+    Ok(model)
+}
+
+/// Finds users whose name contains a given string, one page at a time. `page` starts at 1.
+/// Returns the users on that page and the total number of matches.
+async fn search_users(
+    db: &DbConn,
+    query: &str,
+    page: u64,
+    page_size: u64,
+) -> Result<(Vec<UserModel>, u64)> {
+    if page == 0 || page_size == 0 {
+        return Err(anyhow!("page and page_size must be at least 1"));
     }
-    Ok(())
+
+    let paginator = User::find()
+        .filter(user::Column::Name.contains(query))
+        .order_by_asc(user::Column::Id)
+        .paginate(db, page_size);
+    let total = paginator.num_items().await?;
+    let users = paginator.fetch_page(page - 1).await?;
+    Ok((users, total))
 }
 
 /// Deletes a user by their ID.
@@ -137,8 +138,14 @@ async fn main() -> Result<()> {
     // 1. Setup database and create schema
     let db = setup_database().await?;
 
-    // 2. Seed the database with initial data
-    seed_users(&db).await?;
+    // 2. Seed the database from SEED_FILE (.json or .csv) or the built-in users
+    let seed_file = env::var("SEED_FILE").ok().map(PathBuf::from);
+    let users = seed::load_users(seed_file.as_deref())?;
+    let report = seed::seed_users(&db, &users).await?;
+    println!(
+        "Seeded {} new user(s), skipped {} already present.",
+        report.inserted, report.skipped
+    );
 
     // 3. List all records
     list_all_users(&db, "Initial list of users").await?;
@@ -150,14 +157,99 @@ async fn main() -> Result<()> {
     // 5. List records again to see the update
     list_all_users(&db, "Users after update").await?;
 
-    // 6. Find users by name
-    find_users_by_name(&db, "Bob").await?;
+    // 6. Change an email, then try to take one that is already in use
+    let updated_user = update_user_email(&db, 1, "alice.smith@example.com").await?;
+    println!("Updated user: {:?}", updated_user);
+
+    if let Err(e) = update_user_email(&db, 1, "bob@example.com").await {
+        println!("Could not update email: {}", e);
+    }
+
+    // 7. Search users by name, a page at a time
+    let search = "Bob";
+    println!(
+        "\n--- Searching users with names containing '{}' ---",
+        search
+    );
+    let (found_users, total) = search_users(&db, search, 1, 10).await?;
+
+    if found_users.is_empty() {
+        println!("No users found matching the criteria.");
+    } else {
+        for user in &found_users {
+            println!("{:?}", user);
+        }
+
+        println!("Showing {} of {} match(es).", found_users.len(), total);
+    }
 
-    // 7. Delete a record
+    // 8. Delete a record
     delete_user_by_id(&db, 2).await?;
 
-    // 8. Final list of users
+    // 9. Final list of users
     list_all_users(&db, "Final list of users after deletion").await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn seeded_db(users: &[(&str, &str)]) -> DbConn {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        let backend = db.get_database_backend();
+        db.execute(backend.build(&Schema::new(backend).create_table_from_entity(User)))
+            .await
+            .unwrap();
+        let users = users
+            .iter()
+            .map(|(name, email)| seed::SeedUser {
+                name: name.to_string(),
+                email: email.to_string(),
+            })
+            .collect::<Vec<_>>();
+        seed::seed_users(&db, &users).await.unwrap();
+        db
+    }
+
+    #[tokio::test]
+    async fn search_users_pages_through_matches() {
+        let db = seeded_db(&[
+            ("Ann", "ann@example.com"),
+            ("Anna", "anna@example.com"),
+            ("Bob", "bob@example.com"),
+            ("Hannah", "hannah@example.com"),
+        ])
+        .await;
+
+        let (users, total) = search_users(&db, "nn", 2, 2).await.unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(
+            users.iter().map(|u| u.name.as_str()).collect::<Vec<_>>(),
+            ["Hannah"]
+        );
+        assert!(search_users(&db, "nn", 0, 2).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn update_user_email_rejects_taken_emails() {
+        let db = seeded_db(&[("Ann", "ann@example.com"), ("Bob", "bob@example.com")]).await;
+
+        let user = update_user_email(&db, 1, "ann@example.org").await.unwrap();
+        assert_eq!(user.email, "ann@example.org");
+
+        let error = update_user_email(&db, 1, "bob@example.com")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("already used"));
+
+        let error = update_user_email(&db, 42, "x@example.com")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<DbErr>(),
+            Some(DbErr::RecordNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{Context, Result, anyhow};
+use sea_orm::{DbConn, EntityTrait, Set, sea_query::OnConflict};
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::entities::user::{self, ActiveModel as UserActiveModel, Entity as User};
+
+/// A user row to seed. Seed files are either a JSON array of these objects or a CSV file
+/// with a `name,email` header.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SeedUser {
+    pub name: String,
+    pub email: String,
+}
+
+/// How many seed rows were written and how many already existed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeedReport {
+    pub inserted: u64,
+    pub skipped: u64,
+}
+
+/// The users seeded when no seed file is available.
+pub fn default_users() -> Vec<SeedUser> {
+    vec![
+        SeedUser {
+            name: "Alice".to_owned(),
+            email: "alice@example.com".to_owned(),
+        },
+        SeedUser {
+            name: "Bob".to_owned(),
+            email: "bob@example.com".to_owned(),
+        },
+    ]
+}
+
+/// Reads the seed rows from `path`, picking the format from its extension. Falls back to
+/// [`default_users`] when no path is given or the file does not exist.
+pub fn load_users(path: Option<&Path>) -> Result<Vec<SeedUser>> {
+    let Some(path) = path else {
+        return Ok(default_users());
+    };
+
+    if !path.exists() {
+        println!(
+            "Seed file '{}' not found, using the default users.",
+            path.display()
+        );
+        return Ok(default_users());
+    }
+
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read seed file '{}'", path.display()))?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("json") => parse_json(&content),
+        Some("csv") => parse_csv(&content),
+        _ => Err(anyhow!(
+            "Seed file '{}' must be a .json or .csv file",
+            path.display()
+        )),
+    }
+    .with_context(|| format!("Invalid seed file '{}'", path.display()))
+}
+
+fn parse_json(content: &str) -> Result<Vec<SeedUser>> {
+    Ok(serde_json::from_str(content)?)
+}
+
+fn parse_csv(content: &str) -> Result<Vec<SeedUser>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+    let users = reader.deserialize().collect::<Result<Vec<SeedUser>, _>>()?;
+    Ok(users)
+}
+
+/// Inserts `users`, skipping the ones whose email is already taken.
+pub async fn seed_users(db: &DbConn, users: &[SeedUser]) -> Result<SeedReport> {
+    if users.is_empty() {
+        return Ok(SeedReport {
+            inserted: 0,
+            skipped: 0,
+        });
+    }
+
+    let models = users.iter().map(|u| UserActiveModel {
+        name: Set(u.name.clone()),
+        email: Set(u.email.clone()),
+        ..Default::default()
+    });
+    // `exec` fails with `RecordNotInserted` when every row conflicts, so count the affected
+    // rows instead.
+    let inserted = User::insert_many(models)
+        .on_conflict(
+            OnConflict::column(user::Column::Email)
+                .do_nothing()
+                .to_owned(),
+        )
+        .exec_without_returning(db)
+        .await?;
+
+    Ok(SeedReport {
+        inserted,
+        skipped: users.len() as u64 - inserted,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::{ConnectionTrait, Database, Schema};
+
+    #[test]
+    fn csv_and_json_rows_are_parsed() {
+        let expected = vec![SeedUser {
+            name: "Carol".to_owned(),
+            email: "carol@example.com".to_owned(),
+        }];
+        assert_eq!(
+            parse_csv("name, email\nCarol, carol@example.com\n").unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_json(r#"[{"name": "Carol", "email": "carol@example.com"}]"#).unwrap(),
+            expected
+        );
+        assert_eq!(
+            load_users(Some(Path::new("missing.json"))).unwrap(),
+            default_users()
+        );
+    }
+
+    #[tokio::test]
+    async fn existing_emails_are_skipped() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        let backend = db.get_database_backend();
+        db.execute(backend.build(&Schema::new(backend).create_table_from_entity(User)))
+            .await
+            .unwrap();
+
+        let report = seed_users(&db, &default_users()).await.unwrap();
+        assert_eq!(
+            report,
+            SeedReport {
+                inserted: 2,
+                skipped: 0
+            }
+        );
+
+        let mut users = default_users();
+        users.push(SeedUser {
+            name: "Carol".to_owned(),
+            email: "carol@example.com".to_owned(),
+        });
+        let report = seed_users(&db, &users).await.unwrap();
+        assert_eq!(
+            report,
+            SeedReport {
+                inserted: 1,
+                skipped: 2
+            }
+        );
+    }
+}
@@ -1,6 +1,14 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_initial;
+mod m20250901_000001_original_dimensions;
+mod m20250901_000002_image_phash;
+mod m20250901_000003_thumbnail_ready;
+mod m20250901_000004_case_insensitive_tags;
+mod m20250901_000005_soft_delete;
+mod m20250901_000006_exif;
+mod m20250901_000007_content_hash;
+mod m20250901_000008_thumbnail_failed;
 
 #[derive(DeriveIden)]
 pub enum Images {
@@ -14,8 +22,18 @@ pub enum Images {
     Width,
     Height,
     AltText,
+    OriginalWidth,
+    OriginalHeight,
+    Phash,
+    ThumbnailReady,
+    ThumbnailFailed,
     CreatedAt,
     UpdatedAt,
+    DeletedAt,
+    CapturedAt,
+    CameraModel,
+    Orientation,
+    ContentHash,
 }
 
 #[derive(DeriveIden)]
@@ -23,6 +41,7 @@ pub enum Tags {
     Table,
     Id,
     Name,
+    DeletedAt,
 }
 
 #[derive(DeriveIden)]
@@ -37,6 +56,16 @@ pub struct Migrator;
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20220101_000001_initial::Migration)]
+        vec![
+            Box::new(m20220101_000001_initial::Migration),
+            Box::new(m20250901_000001_original_dimensions::Migration),
+            Box::new(m20250901_000002_image_phash::Migration),
+            Box::new(m20250901_000003_thumbnail_ready::Migration),
+            Box::new(m20250901_000004_case_insensitive_tags::Migration),
+            Box::new(m20250901_000005_soft_delete::Migration),
+            Box::new(m20250901_000006_exif::Migration),
+            Box::new(m20250901_000007_content_hash::Migration),
+            Box::new(m20250901_000008_thumbnail_failed::Migration),
+        ]
     }
 }
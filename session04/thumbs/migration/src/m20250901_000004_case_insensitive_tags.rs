@@ -0,0 +1,49 @@
+use async_trait::async_trait;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        // Merge tags differing only by case or surrounding spaces into the oldest one,
+        // then store the names normalized and guard them with a case-insensitive index.
+        db.execute_unprepared(
+            "INSERT OR IGNORE INTO image_tags (image_id, tag_id)
+             SELECT it.image_id,
+                    (SELECT MIN(t2.id) FROM tags t2 WHERE lower(trim(t2.name)) = lower(trim(t.name)))
+             FROM image_tags it
+             INNER JOIN tags t ON t.id = it.tag_id",
+        )
+        .await?;
+        db.execute_unprepared(
+            "DELETE FROM image_tags
+             WHERE tag_id NOT IN (SELECT MIN(id) FROM tags GROUP BY lower(trim(name)))",
+        )
+        .await?;
+        db.execute_unprepared(
+            "DELETE FROM tags WHERE id NOT IN (SELECT MIN(id) FROM tags GROUP BY lower(trim(name)))",
+        )
+        .await?;
+        db.execute_unprepared("UPDATE tags SET name = lower(trim(name))")
+            .await?;
+        db.execute_unprepared(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_tags_name_nocase ON tags (lower(name))",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // The merged tags can't be restored
+        manager
+            .get_connection()
+            .execute_unprepared("DROP INDEX IF EXISTS idx_tags_name_nocase")
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use async_trait::async_trait;
+use sea_orm_migration::prelude::*;
+
+use crate::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Images::Table)
+                    .add_column(ColumnDef::new(Images::ContentHash).string().null())
+                    .to_owned(),
+            )
+            .await?;
+        // Reference counts look the hash up on every delete
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-images-content_hash")
+                    .if_not_exists()
+                    .table(Images::Table)
+                    .col(Images::ContentHash)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx-images-content_hash")
+                    .table(Images::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Images::Table)
+                    .drop_column(Images::ContentHash)
+                    .to_owned(),
+            )
+            .await
+    }
+}
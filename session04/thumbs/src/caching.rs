@@ -0,0 +1,166 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::Response,
+};
+use std::{fs::Metadata, time::SystemTime};
+
+/// Adds a weak `ETag` to successful file responses and answers conditional requests with
+/// `304 Not Modified`. It applies to any response carrying `Last-Modified` and `Content-Length`
+/// (`ServeDir` sets both, handlers serving files use `file_headers`), so the validator is
+/// derived from the file's size and modification time without reading it.
+pub async fn conditional_get(mut request: Request, next: Next) -> Response {
+    // The conditions are evaluated here, so the inner service always builds a full response
+    let if_none_match = request.headers_mut().remove(header::IF_NONE_MATCH);
+    let if_modified_since = request.headers_mut().remove(header::IF_MODIFIED_SINCE);
+    let mut response = next.run(request).await;
+
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+
+    let Some(last_modified) = header_str(response.headers(), header::LAST_MODIFIED)
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+    else {
+        return response;
+    };
+    let Some(length) = header_str(response.headers(), header::CONTENT_LENGTH) else {
+        return response;
+    };
+    let etag = make_etag(length, last_modified);
+
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+
+    let not_modified = match (&if_none_match, &if_modified_since) {
+        // If-Modified-Since is ignored when If-None-Match is present
+        (Some(v), _) => v.to_str().is_ok_and(|v| etag_matches(v, &etag)),
+        (None, Some(v)) => v
+            .to_str()
+            .ok()
+            .and_then(|v| httpdate::parse_http_date(v).ok())
+            .is_some_and(|since| last_modified <= since),
+        (None, None) => false,
+    };
+
+    if !not_modified {
+        return response;
+    }
+
+    let mut not_modified = Response::new(Body::empty());
+    *not_modified.status_mut() = StatusCode::NOT_MODIFIED;
+
+    for name in [header::ETAG, header::LAST_MODIFIED, header::CACHE_CONTROL] {
+        if let Some(value) = response.headers().get(&name) {
+            not_modified.headers_mut().insert(name, value.clone());
+        }
+    }
+
+    not_modified
+}
+
+/// `Last-Modified` and `Content-Length` headers for a file, so `conditional_get` can validate it.
+pub fn file_headers(metadata: &Metadata) -> Vec<(header::HeaderName, String)> {
+    let mut headers = vec![(header::CONTENT_LENGTH, metadata.len().to_string())];
+
+    if let Ok(modified) = metadata.modified() {
+        headers.push((header::LAST_MODIFIED, httpdate::fmt_http_date(modified)));
+    }
+
+    headers
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn make_etag(length: &str, last_modified: SystemTime) -> String {
+    let seconds = last_modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    format!("W/\"{}-{:x}\"", length, seconds)
+}
+
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    // Weak comparison: W/ prefixes are ignored
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|v| v == "*" || v.trim_start_matches("W/") == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, middleware};
+    use tower::ServiceExt;
+    use tower_http::services::ServeDir;
+
+    fn get(uri: &str, headers: &[(header::HeaderName, &str)]) -> Request {
+        let mut request = Request::builder().uri(uri);
+
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn second_request_with_etag_is_not_modified() {
+        let dir = std::env::temp_dir().join(format!("thumbs-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("1.png"), b"not really a png").unwrap();
+        let app = Router::new()
+            .nest_service("/assets", ServeDir::new(&dir))
+            .layer(middleware::from_fn(conditional_get));
+
+        let response = app
+            .clone()
+            .oneshot(get("/assets/1.png", &[]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let last_modified = response.headers()[header::LAST_MODIFIED]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let response = app
+            .clone()
+            .oneshot(get("/assets/1.png", &[(header::IF_NONE_MATCH, &etag)]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+
+        let response = app
+            .clone()
+            .oneshot(get(
+                "/assets/1.png",
+                &[(header::IF_MODIFIED_SINCE, &last_modified)],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = app
+            .oneshot(get(
+                "/assets/1.png",
+                &[(header::IF_NONE_MATCH, "W/\"0-0\"")],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
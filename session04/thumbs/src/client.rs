@@ -0,0 +1,183 @@
+use axum::http::StatusCode;
+use reqwest::{
+    Response,
+    multipart::{Form, Part},
+};
+use serde::{Deserialize, de::DeserializeOwned};
+
+use crate::{api_error::ApiError, db::prelude::*, idempotency::IDEMPOTENCY_KEY_HEADER};
+
+/// Form fields sent with [`ThumbsClient::upload_image`]. Unset fields fall back to the
+/// server's defaults, e.g. the title to the file name.
+#[derive(Debug, Clone, Default)]
+pub struct UploadMeta {
+    pub filename: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub alt_text: Option<String>,
+    /// Comma separated tag names.
+    pub tags: Option<String>,
+    /// Sent as the `Idempotency-Key` header, so the upload can be retried safely.
+    pub idempotency_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    message: String,
+}
+
+/// Typed client for the thumbs API. Error responses come back as the [`ApiError`] the
+/// server rendered; failing to reach the server or to read its response is a 502.
+#[derive(Debug, Clone)]
+pub struct ThumbsClient {
+    base_url: String,
+    http: reqwest::Client,
+}
+
+impl ThumbsClient {
+    /// `base_url` is the server's root, e.g. `http://localhost:3000`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_client(base_url, reqwest::Client::new())
+    }
+
+    /// Uses `http` for the requests, e.g. one configured with timeouts.
+    pub fn with_client(base_url: impl Into<String>, http: reqwest::Client) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            http,
+        }
+    }
+
+    pub async fn list_images(
+        &self,
+        page: u64,
+        page_size: u64,
+    ) -> Result<ResultSet<ModelWithRelated<ImageModel, TagModel>>, ApiError> {
+        let request = self
+            .http
+            .get(self.url("/images"))
+            .query(&[("page", page), ("page_size", page_size)]);
+        json(send(request).await?).await
+    }
+
+    pub async fn get_image(
+        &self,
+        id: i64,
+    ) -> Result<ModelWithRelated<ImageModel, TagModel>, ApiError> {
+        json(send(self.http.get(self.url(&format!("/images/{id}")))).await?).await
+    }
+
+    pub async fn upload_image(
+        &self,
+        bytes: Vec<u8>,
+        meta: &UploadMeta,
+    ) -> Result<ImageModel, ApiError> {
+        let mut form = Form::new();
+        let fields = [
+            ("filename", &meta.filename),
+            ("title", &meta.title),
+            ("description", &meta.description),
+            ("alt_text", &meta.alt_text),
+            ("tags", &meta.tags),
+        ];
+
+        for (name, value) in fields {
+            if let Some(value) = value {
+                form = form.text(name, value.clone());
+            }
+        }
+
+        let mut file = Part::bytes(bytes);
+
+        if let Some(filename) = &meta.filename {
+            file = file.file_name(filename.clone());
+        }
+
+        let mut request = self
+            .http
+            .post(self.url("/images"))
+            .multipart(form.part("image_file", file));
+
+        if let Some(key) = &meta.idempotency_key {
+            request = request.header(IDEMPOTENCY_KEY_HEADER, key);
+        }
+
+        json(send(request).await?).await
+    }
+
+    pub async fn delete_image(&self, id: i64) -> Result<(), ApiError> {
+        send(self.http.delete(self.url(&format!("/images/{id}")))).await?;
+        Ok(())
+    }
+
+    pub async fn image_tags(&self, id: i64) -> Result<ResultSet<TagModel>, ApiError> {
+        json(send(self.http.get(self.url(&format!("/images/{id}/tags/")))).await?).await
+    }
+
+    /// Adds `tag` to the image, creating the tag if needed. `tag` may list several
+    /// comma separated names. Use [`Self::add_tags`] for the image's tags afterwards.
+    pub async fn add_tag(&self, id: i64, tag: &str) -> Result<(), ApiError> {
+        let request = self
+            .http
+            .post(self.url(&format!("/images/{id}/tags/")))
+            .json(&serde_json::json!({ "tag": tag }));
+        send(request).await?;
+        Ok(())
+    }
+
+    /// Adds the tags named `names` to the image, creating the missing ones, and returns
+    /// all of the image's tags.
+    pub async fn add_tags(&self, id: i64, names: &[&str]) -> Result<ResultSet<TagModel>, ApiError> {
+        let request = self
+            .http
+            .post(self.url(&format!("/images/{id}/tags/")))
+            .json(&serde_json::json!({ "tags": names }));
+        json(send(request).await?).await
+    }
+
+    pub async fn list_tags(
+        &self,
+        page: u64,
+        page_size: u64,
+    ) -> Result<ResultSet<TagModel>, ApiError> {
+        let request = self
+            .http
+            .get(self.url("/tags/"))
+            .query(&[("page", page), ("page_size", page_size)]);
+        json(send(request).await?).await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+}
+
+fn unreachable(error: reqwest::Error) -> ApiError {
+    ApiError::new(StatusCode::BAD_GATEWAY, error)
+}
+
+/// Sends `request` and turns a non-2xx response into the [`ApiError`] it carries.
+async fn send(request: reqwest::RequestBuilder) -> Result<Response, ApiError> {
+    let response = request.send().await.map_err(unreachable)?;
+    let status = response.status();
+
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    // Errors that didn't come from a handler, e.g. a proxy's, may not be JSON
+    let text = response.text().await.map_err(unreachable)?;
+    let message = serde_json::from_str::<ErrorBody>(&text)
+        .map(|body| body.error.message)
+        .unwrap_or(text);
+    Err(ApiError::new(status, message))
+}
+
+async fn json<T: DeserializeOwned>(response: Response) -> Result<T, ApiError> {
+    response.json().await.map_err(unreachable)
+}
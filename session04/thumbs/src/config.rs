@@ -0,0 +1,411 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use util::{
+    config::{check, non_blank, parse, parse_bool},
+    web::{client_ip::TrustedProxies, cors::CorsOptions},
+};
+
+use crate::{
+    idempotency::DEFAULT_IDEMPOTENCY_TTL_SECS,
+    imaging::{
+        DEFAULT_DUPLICATE_DISTANCE, DEFAULT_MAX_IMAGE_DIMENSION, DEFAULT_THUMBNAIL_SIZE,
+        IMAGE_DIMENSION_LIMIT, ThumbnailFilter, ThumbnailFormat, ThumbnailOptions,
+    },
+    rate_limit::{DEFAULT_READS_PER_MINUTE, DEFAULT_WRITES_PER_MINUTE},
+    resumable::DEFAULT_RESUMABLE_UPLOAD_TTL_SECS,
+    thumbnails::DEFAULT_THUMBNAIL_QUEUE_SIZE,
+    upload::{DEFAULT_MAX_BATCH_SIZE, DEFAULT_MAX_UPLOAD_SIZE},
+};
+
+/// Days a soft deleted row is kept before `--purge-deleted` removes it.
+pub const DEFAULT_SOFT_DELETE_RETENTION_DAYS: u32 = 30;
+
+/// Settings read once from the environment (and `.env`) at startup.
+///
+/// | Variable | Default |
+/// |---|---|
+/// | `DATABASE_URL` | required |
+/// | `CORS_ORIGINS` | `http://localhost`, comma separated or `*` |
+/// | `CORS_METHODS` | `*`, comma separated |
+/// | `CORS_HEADERS` | `*`, comma separated |
+/// | `CORS_ALLOW_CREDENTIALS` | `false`, needs explicit origins, methods and headers |
+/// | `TRUSTED_PROXIES` | none, comma separated addresses or CIDR ranges whose `X-Forwarded-For` and `X-Real-IP` are believed |
+/// | `IMAGES_DIR` | `data/images` |
+/// | `MAX_IMAGE_DIMENSION` | 4096, at most 30000 |
+/// | `MAX_UPLOAD_SIZE` | 20971520 (20 MiB), in bytes |
+/// | `MAX_BATCH_SIZE` | 104857600 (100 MiB) or `MAX_UPLOAD_SIZE` if larger, bytes of all the files of one `POST /images` |
+/// | `DETECT_DUPLICATES` | `true` |
+/// | `DUPLICATE_DISTANCE` | 5 |
+/// | `CONTENT_ADDRESSED_STORAGE` | `false`, store originals once per sha256 |
+/// | `THUMBNAIL_SIZES` | 256, comma separated |
+/// | `THUMBNAIL_FORMAT` | `original` or `webp` |
+/// | `THUMBNAIL_FILTER` | `fast`, `nearest`, `triangle` or `lanczos3`, sharper ones cost more CPU |
+/// | `THUMBNAIL_SHARPEN` | 0 (off), sigma of an unsharp mask applied to each thumbnail, e.g. 0.5 |
+/// | `THUMBNAIL_QUEUE_SIZE` | 32 |
+/// | `SOFT_DELETE_IMAGES` | `false` |
+/// | `SOFT_DELETE_TAGS` | `false` |
+/// | `SOFT_DELETE_RETENTION_DAYS` | 30, how long `--purge-deleted` keeps soft deleted rows |
+/// | `RATE_LIMIT_READS_PER_MINUTE` | 600 per client IP, 0 disables |
+/// | `RATE_LIMIT_WRITES_PER_MINUTE` | 30 per client IP, 0 disables |
+/// | `LOG_DB_TIMINGS` | `false`, log each repository call's duration at debug level |
+/// | `IDEMPOTENCY_TTL_SECS` | 86400, how long an upload's `Idempotency-Key` is remembered, 0 disables |
+/// | `RESUMABLE_UPLOAD_TTL_SECS` | 86400, how long an unfinished `/uploads` upload is kept after its last chunk |
+/// | `DEBUG_ENDPOINTS` | `true` in debug builds, `false` in release, mounts `/debug/db-pool` |
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub database_url: String,
+    pub cors: CorsOptions,
+    pub trusted_proxies: TrustedProxies,
+    pub images_dir: PathBuf,
+    pub max_image_dimension: u32,
+    pub max_upload_size: usize,
+    pub max_batch_size: usize,
+    pub detect_duplicates: bool,
+    pub duplicate_distance: u32,
+    pub content_addressed_storage: bool,
+    pub thumbnails: ThumbnailOptions,
+    pub thumbnail_queue_size: usize,
+    pub soft_delete_images: bool,
+    pub soft_delete_tags: bool,
+    pub soft_delete_retention_days: u32,
+    pub rate_limit_reads_per_minute: u32,
+    pub rate_limit_writes_per_minute: u32,
+    pub log_db_timings: bool,
+    pub idempotency_ttl_secs: u64,
+    pub resumable_upload_ttl_secs: u64,
+    pub debug_endpoints: bool,
+}
+
+impl AppConfig {
+    pub fn from_env() -> Result<Self> {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    /// Reads every setting through `lookup` and reports all the missing or invalid ones
+    /// in a single error.
+    pub fn from_lookup<F: Fn(&str) -> Option<String>>(lookup: F) -> Result<Self> {
+        let mut errors = vec![];
+        let var = |name: &str| non_blank(lookup(name));
+
+        let database_url = var("DATABASE_URL").unwrap_or_else(|| {
+            errors.push("DATABASE_URL is required".to_string());
+            String::new()
+        });
+        let allow_credentials = parse_bool(
+            &mut errors,
+            "CORS_ALLOW_CREDENTIALS",
+            var("CORS_ALLOW_CREDENTIALS"),
+            false,
+        );
+        let cors = CorsOptions::parse(
+            &mut errors,
+            var("CORS_ORIGINS"),
+            var("CORS_METHODS"),
+            var("CORS_HEADERS"),
+            allow_credentials,
+        );
+        let trusted_proxies = TrustedProxies::parse(&mut errors, var("TRUSTED_PROXIES"));
+        let images_dir = PathBuf::from(var("IMAGES_DIR").unwrap_or_else(|| "data/images".into()));
+        let max_image_dimension = parse(
+            &mut errors,
+            "MAX_IMAGE_DIMENSION",
+            var("MAX_IMAGE_DIMENSION"),
+            DEFAULT_MAX_IMAGE_DIMENSION,
+        );
+
+        if !(1..=IMAGE_DIMENSION_LIMIT).contains(&max_image_dimension) {
+            errors.push(format!(
+                "MAX_IMAGE_DIMENSION must be between 1 and {IMAGE_DIMENSION_LIMIT}"
+            ));
+        }
+
+        let max_upload_size = parse(
+            &mut errors,
+            "MAX_UPLOAD_SIZE",
+            var("MAX_UPLOAD_SIZE"),
+            DEFAULT_MAX_UPLOAD_SIZE,
+        );
+
+        if max_upload_size == 0 {
+            errors.push("MAX_UPLOAD_SIZE must be at least 1".to_string());
+        }
+
+        let max_batch_size = parse(
+            &mut errors,
+            "MAX_BATCH_SIZE",
+            var("MAX_BATCH_SIZE"),
+            DEFAULT_MAX_BATCH_SIZE.max(max_upload_size),
+        );
+
+        if max_batch_size < max_upload_size {
+            errors.push("MAX_BATCH_SIZE must be at least MAX_UPLOAD_SIZE".to_string());
+        }
+
+        let detect_duplicates = parse_bool(
+            &mut errors,
+            "DETECT_DUPLICATES",
+            var("DETECT_DUPLICATES"),
+            true,
+        );
+        let duplicate_distance = parse(
+            &mut errors,
+            "DUPLICATE_DISTANCE",
+            var("DUPLICATE_DISTANCE"),
+            DEFAULT_DUPLICATE_DISTANCE,
+        );
+        let content_addressed_storage = parse_bool(
+            &mut errors,
+            "CONTENT_ADDRESSED_STORAGE",
+            var("CONTENT_ADDRESSED_STORAGE"),
+            false,
+        );
+        let mut sizes = vec![];
+
+        for v in var("THUMBNAIL_SIZES").unwrap_or_default().split(',') {
+            match v.trim().parse::<u32>() {
+                Ok(size) if size > 0 => sizes.push(size),
+                _ if v.trim().is_empty() => {}
+                _ => errors.push(format!("THUMBNAIL_SIZES: '{}' is not a size", v.trim())),
+            }
+        }
+
+        if sizes.is_empty() {
+            sizes.push(DEFAULT_THUMBNAIL_SIZE);
+        }
+
+        sizes.sort_unstable();
+        sizes.dedup();
+        let format = parse(
+            &mut errors,
+            "THUMBNAIL_FORMAT",
+            var("THUMBNAIL_FORMAT"),
+            ThumbnailFormat::default(),
+        );
+        let filter = parse(
+            &mut errors,
+            "THUMBNAIL_FILTER",
+            var("THUMBNAIL_FILTER"),
+            ThumbnailFilter::default(),
+        );
+        let sharpen = parse(
+            &mut errors,
+            "THUMBNAIL_SHARPEN",
+            var("THUMBNAIL_SHARPEN"),
+            0.0f32,
+        );
+
+        if !(sharpen.is_finite() && sharpen >= 0.0) {
+            errors.push("THUMBNAIL_SHARPEN must be 0 or more".to_string());
+        }
+
+        let thumbnail_queue_size = parse(
+            &mut errors,
+            "THUMBNAIL_QUEUE_SIZE",
+            var("THUMBNAIL_QUEUE_SIZE"),
+            DEFAULT_THUMBNAIL_QUEUE_SIZE,
+        );
+
+        if thumbnail_queue_size == 0 {
+            errors.push("THUMBNAIL_QUEUE_SIZE must be at least 1".to_string());
+        }
+
+        let soft_delete_images = parse_bool(
+            &mut errors,
+            "SOFT_DELETE_IMAGES",
+            var("SOFT_DELETE_IMAGES"),
+            false,
+        );
+        let soft_delete_tags = parse_bool(
+            &mut errors,
+            "SOFT_DELETE_TAGS",
+            var("SOFT_DELETE_TAGS"),
+            false,
+        );
+        let soft_delete_retention_days = parse(
+            &mut errors,
+            "SOFT_DELETE_RETENTION_DAYS",
+            var("SOFT_DELETE_RETENTION_DAYS"),
+            DEFAULT_SOFT_DELETE_RETENTION_DAYS,
+        );
+
+        let rate_limit_reads_per_minute = parse(
+            &mut errors,
+            "RATE_LIMIT_READS_PER_MINUTE",
+            var("RATE_LIMIT_READS_PER_MINUTE"),
+            DEFAULT_READS_PER_MINUTE,
+        );
+        let rate_limit_writes_per_minute = parse(
+            &mut errors,
+            "RATE_LIMIT_WRITES_PER_MINUTE",
+            var("RATE_LIMIT_WRITES_PER_MINUTE"),
+            DEFAULT_WRITES_PER_MINUTE,
+        );
+        let log_db_timings =
+            parse_bool(&mut errors, "LOG_DB_TIMINGS", var("LOG_DB_TIMINGS"), false);
+        let idempotency_ttl_secs = parse(
+            &mut errors,
+            "IDEMPOTENCY_TTL_SECS",
+            var("IDEMPOTENCY_TTL_SECS"),
+            DEFAULT_IDEMPOTENCY_TTL_SECS,
+        );
+        let resumable_upload_ttl_secs = parse(
+            &mut errors,
+            "RESUMABLE_UPLOAD_TTL_SECS",
+            var("RESUMABLE_UPLOAD_TTL_SECS"),
+            DEFAULT_RESUMABLE_UPLOAD_TTL_SECS,
+        );
+
+        let debug_endpoints = parse_bool(
+            &mut errors,
+            "DEBUG_ENDPOINTS",
+            var("DEBUG_ENDPOINTS"),
+            cfg!(debug_assertions),
+        );
+
+        check(errors)?;
+
+        Ok(Self {
+            database_url,
+            cors,
+            trusted_proxies,
+            images_dir,
+            max_image_dimension,
+            max_upload_size,
+            max_batch_size,
+            detect_duplicates,
+            duplicate_distance,
+            content_addressed_storage,
+            thumbnails: ThumbnailOptions {
+                sizes,
+                format,
+                filter,
+                sharpen: (sharpen > 0.0).then_some(sharpen),
+            },
+            thumbnail_queue_size,
+            soft_delete_images,
+            soft_delete_tags,
+            soft_delete_retention_days,
+            rate_limit_reads_per_minute,
+            rate_limit_writes_per_minute,
+            log_db_timings,
+            idempotency_ttl_secs,
+            resumable_upload_ttl_secs,
+            debug_endpoints,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use std::collections::HashMap;
+    use util::web::cors::Allowed;
+
+    fn config(vars: &[(&str, &str)]) -> Result<AppConfig> {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        AppConfig::from_lookup(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn defaults_apply_when_unset() {
+        let config = config(&[("DATABASE_URL", "sqlite::memory:")]).unwrap();
+        assert_eq!(config.images_dir, PathBuf::from("data/images"));
+        assert_eq!(
+            config.cors.origins,
+            Allowed::Only(vec![HeaderValue::from_static("http://localhost")])
+        );
+        assert_eq!(config.cors.methods, Allowed::Any);
+        assert!(!config.cors.allow_credentials);
+        assert_eq!(config.max_image_dimension, DEFAULT_MAX_IMAGE_DIMENSION);
+        assert_eq!(config.max_upload_size, DEFAULT_MAX_UPLOAD_SIZE);
+        assert_eq!(config.max_batch_size, DEFAULT_MAX_BATCH_SIZE);
+        assert!(config.detect_duplicates);
+        assert!(!config.content_addressed_storage);
+        assert_eq!(config.thumbnails.sizes, [DEFAULT_THUMBNAIL_SIZE]);
+        assert_eq!(config.thumbnails.format, ThumbnailFormat::Original);
+        assert_eq!(config.thumbnails.filter, ThumbnailFilter::Fast);
+        assert_eq!(config.thumbnails.sharpen, None);
+        assert_eq!(config.thumbnail_queue_size, DEFAULT_THUMBNAIL_QUEUE_SIZE);
+        assert!(!config.soft_delete_images);
+        assert!(!config.soft_delete_tags);
+        assert_eq!(
+            config.soft_delete_retention_days,
+            DEFAULT_SOFT_DELETE_RETENTION_DAYS
+        );
+        assert_eq!(config.rate_limit_reads_per_minute, DEFAULT_READS_PER_MINUTE);
+        assert_eq!(
+            config.rate_limit_writes_per_minute,
+            DEFAULT_WRITES_PER_MINUTE
+        );
+        assert!(!config.log_db_timings);
+        assert_eq!(config.idempotency_ttl_secs, DEFAULT_IDEMPOTENCY_TTL_SECS);
+        assert_eq!(
+            config.resumable_upload_ttl_secs,
+            DEFAULT_RESUMABLE_UPLOAD_TTL_SECS
+        );
+        assert_eq!(config.debug_endpoints, cfg!(debug_assertions));
+    }
+
+    #[test]
+    fn values_are_parsed() {
+        let config = config(&[
+            ("DATABASE_URL", "sqlite::memory:"),
+            ("CORS_ORIGINS", "http://a.test, http://b.test"),
+            ("DETECT_DUPLICATES", "off"),
+            ("CONTENT_ADDRESSED_STORAGE", "on"),
+            ("THUMBNAIL_SIZES", "512, 128,512"),
+            ("THUMBNAIL_FORMAT", "WebP"),
+            ("THUMBNAIL_FILTER", "Lanczos3"),
+            ("THUMBNAIL_SHARPEN", "0.5"),
+            ("SOFT_DELETE_TAGS", "yes"),
+            ("DEBUG_ENDPOINTS", "false"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.cors.origins,
+            Allowed::Only(vec![
+                HeaderValue::from_static("http://a.test"),
+                HeaderValue::from_static("http://b.test")
+            ])
+        );
+        assert!(!config.detect_duplicates);
+        assert!(config.content_addressed_storage);
+        assert_eq!(config.thumbnails.sizes, [128, 512]);
+        assert_eq!(config.thumbnails.format, ThumbnailFormat::WebP);
+        assert_eq!(config.thumbnails.filter, ThumbnailFilter::Lanczos3);
+        assert_eq!(config.thumbnails.sharpen, Some(0.5));
+        assert!(!config.soft_delete_images);
+        assert!(config.soft_delete_tags);
+        assert!(!config.debug_endpoints);
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let error = config(&[
+            ("MAX_IMAGE_DIMENSION", "big"),
+            ("THUMBNAIL_SIZES", "128,-1"),
+            ("THUMBNAIL_QUEUE_SIZE", "0"),
+            ("MAX_UPLOAD_SIZE", "0"),
+            ("MAX_BATCH_SIZE", "-1"),
+            ("CORS_ALLOW_CREDENTIALS", "true"),
+        ])
+        .unwrap_err()
+        .to_string();
+
+        for name in [
+            "DATABASE_URL",
+            "MAX_IMAGE_DIMENSION",
+            "THUMBNAIL_SIZES",
+            "THUMBNAIL_QUEUE_SIZE",
+            "MAX_UPLOAD_SIZE",
+            "MAX_BATCH_SIZE",
+            "CORS_ALLOW_CREDENTIALS",
+        ] {
+            assert!(error.contains(name), "{name} missing from {error}");
+        }
+    }
+}
@@ -18,8 +18,28 @@ pub struct Model {
     pub width: Option<i32>,
     pub height: Option<i32>,
     pub alt_text: Option<String>,
+    pub original_width: Option<i32>,
+    pub original_height: Option<i32>,
+    /// Perceptual hash (dHash) bits, used to detect near-duplicate uploads.
+    #[serde(skip_serializing)]
+    pub phash: Option<i64>,
+    /// False until the background worker has generated the thumbnails.
+    pub thumbnail_ready: bool,
+    /// Set when the thumbnails couldn't be queued or generated, so they never will be.
+    pub thumbnail_failed: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Set while the image is soft deleted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// EXIF capture time, camera and orientation of the upload, when it had them.
+    pub captured_at: Option<DateTime<Utc>>,
+    pub camera_model: Option<String>,
+    pub orientation: Option<i16>,
+    /// Hex sha256 of the stored original when it is kept in the content addressed store.
+    /// Rows with the same hash share one file.
+    #[serde(skip_serializing)]
+    pub content_hash: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -80,6 +100,13 @@ pub struct CreateImageDto {
     pub width: Option<i32>,
     pub height: Option<i32>,
     pub alt_text: Option<String>,
+    pub original_width: Option<i32>,
+    pub original_height: Option<i32>,
+    pub phash: Option<i64>,
+    pub captured_at: Option<DateTime<Utc>>,
+    pub camera_model: Option<String>,
+    pub orientation: Option<i16>,
+    pub content_hash: Option<String>,
     pub tags: Option<String>,
 }
 
@@ -96,8 +123,18 @@ impl From<CreateImageDto> for Model {
             width: req.width,
             height: req.height,
             alt_text: req.alt_text,
+            original_width: req.original_width,
+            original_height: req.original_height,
+            phash: req.phash,
+            thumbnail_ready: false,
+            thumbnail_failed: false,
             created_at: now,
             updated_at: now,
+            deleted_at: None,
+            captured_at: req.captured_at,
+            camera_model: req.camera_model,
+            orientation: req.orientation,
+            content_hash: req.content_hash,
         }
     }
 }
@@ -114,8 +151,18 @@ impl From<CreateImageDto> for ActiveModel {
             width: Set(req.width),
             height: Set(req.height),
             alt_text: Set(req.alt_text),
+            original_width: Set(req.original_width),
+            original_height: Set(req.original_height),
+            phash: Set(req.phash),
+            thumbnail_ready: Set(false),
+            thumbnail_failed: Set(false),
             created_at: NotSet,
             updated_at: NotSet,
+            deleted_at: NotSet,
+            captured_at: Set(req.captured_at),
+            camera_model: Set(req.camera_model),
+            orientation: Set(req.orientation),
+            content_hash: Set(req.content_hash),
         }
     }
 }
@@ -32,3 +32,4 @@ pub use ActiveModel as ImageTagModelDto;
 pub use Column as ImageTagColumn;
 pub use Entity as ImageTagEntity;
 pub use Model as ImageTagModel;
+pub use Relation as ImageTagRelation;
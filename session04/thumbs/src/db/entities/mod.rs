@@ -5,8 +5,13 @@ pub mod tag;
 pub use image::{
     CreateImageDto, ImageColumn, ImageEntity, ImageModel, ImageModelDto, UpdateImageDto,
 };
-pub use image_tag::{ImageTagColumn, ImageTagEntity, ImageTagModel, ImageTagModelDto};
-pub use tag::{CreateTagDto, TagColumn, TagEntity, TagModel, TagModelDto, UpdateTagDto};
+pub use image_tag::{
+    ImageTagColumn, ImageTagEntity, ImageTagModel, ImageTagModelDto, ImageTagRelation,
+};
+pub use tag::{
+    CreateTagDto, TagColumn, TagEntity, TagModel, TagModelDto, TagRelation, UpdateTagDto,
+    normalize_tag_name,
+};
 
 pub trait Merge<T> {
     fn merge(&self, model: &mut T);
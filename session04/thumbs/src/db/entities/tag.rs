@@ -1,4 +1,5 @@
-use sea_orm::{EntityTrait, NotSet, Set, prelude::*};
+use async_trait::async_trait;
+use sea_orm::{ActiveValue, EntityTrait, NotSet, Set, prelude::*};
 use serde::{Deserialize, Serialize};
 
 use super::Merge;
@@ -10,6 +11,9 @@ pub struct Model {
     pub id: i64,
     #[sea_orm(unique)]
     pub name: String,
+    /// Set while the tag is soft deleted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTimeUtc>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -33,7 +37,29 @@ impl Related<Entity> for super::image_tag::Entity {
     }
 }
 
-impl ActiveModelBehavior for ActiveModel {}
+#[async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    async fn before_save<C>(mut self, _db: &C, _insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        // Models converted with into() carry the name as Unchanged, which is inserted too
+        if let ActiveValue::Set(name) | ActiveValue::Unchanged(name) = &self.name {
+            let normalized = normalize_tag_name(name);
+
+            if &normalized != name {
+                self.name = Set(normalized);
+            }
+        }
+
+        Ok(self)
+    }
+}
+
+/// Tags are stored trimmed and lowercase so "Rust" and " rust " are the same tag.
+pub fn normalize_tag_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
 
 #[derive(Debug, Deserialize)]
 pub struct CreateTagDto {
@@ -45,6 +71,7 @@ impl From<CreateTagDto> for Model {
         Self {
             id: 0,
             name: req.name,
+            deleted_at: None,
         }
     }
 }
@@ -54,10 +81,13 @@ impl From<CreateTagDto> for ActiveModel {
         Self {
             id: NotSet,
             name: Set(req.name),
+            deleted_at: NotSet,
         }
     }
 }
 
+/// A missing or blank `name` leaves the tag's name as it is, like the unset fields of
+/// [`super::UpdateImageDto`].
 #[derive(Debug, Deserialize)]
 pub struct UpdateTagDto {
     pub name: Option<String>,
@@ -65,7 +95,7 @@ pub struct UpdateTagDto {
 
 impl Merge<ActiveModel> for UpdateTagDto {
     fn merge(&self, model: &mut ActiveModel) {
-        if let Some(name) = self.name.as_ref() {
+        if let Some(name) = self.name.as_ref().filter(|n| !n.trim().is_empty()) {
             model.name = Set(name.clone());
         }
     }
@@ -75,3 +105,4 @@ pub use ActiveModel as TagModelDto;
 pub use Column as TagColumn;
 pub use Entity as TagEntity;
 pub use Model as TagModel;
+pub use Relation as TagRelation;
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use sea_orm::{ConnectionTrait, DatabaseConnection, Statement, Value};
+use std::future::Future;
+use util::migration_lock::{self, LockStore, LockValue};
+
+pub use util::migration_lock::LockOutcome;
+
+struct SeaOrmStore<'a>(&'a DatabaseConnection);
+
+impl LockStore for SeaOrmStore<'_> {
+    async fn execute(&self, sql: &str, values: Vec<LockValue>) -> Result<u64> {
+        let values = values.into_iter().map(|value| match value {
+            LockValue::Text(text) => Value::from(text),
+            LockValue::Int(int) => Value::from(int),
+        });
+        let statement = Statement::from_sql_and_values(self.0.get_database_backend(), sql, values);
+        Ok(self.0.execute(statement).await?.rows_affected())
+    }
+}
+
+/// Runs `migrate` while holding the lock of [`util::migration_lock`], so instances starting
+/// together migrate one at a time.
+pub async fn with_migration_lock<T, F, Fut>(
+    db: &DatabaseConnection,
+    migrate: F,
+) -> Result<(T, LockOutcome)>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    migration_lock::with_migration_lock(&SeaOrmStore(db), migrate).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::Database;
+
+    #[tokio::test]
+    async fn execute_binds_the_values_and_counts_the_rows() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        db.execute_unprepared("CREATE TABLE locks (owner TEXT NOT NULL, acquired_at BIGINT)")
+            .await
+            .unwrap();
+        let store = SeaOrmStore(&db);
+
+        for owner in ["a", "b"] {
+            let values = vec![LockValue::Text(owner.to_string()), LockValue::Int(1)];
+            let inserted = store
+                .execute(
+                    "INSERT INTO locks (owner, acquired_at) VALUES (?, ?)",
+                    values,
+                )
+                .await
+                .unwrap();
+            assert_eq!(inserted, 1);
+        }
+
+        let updated = store
+            .execute(
+                "UPDATE locks SET acquired_at = ? WHERE owner = ?",
+                vec![LockValue::Int(2), LockValue::Text("a".to_string())],
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated, 1);
+        let removed = store
+            .execute(
+                "DELETE FROM locks WHERE acquired_at < ?",
+                vec![LockValue::Int(2)],
+            )
+            .await
+            .unwrap();
+        assert_eq!(removed, 1);
+    }
+}
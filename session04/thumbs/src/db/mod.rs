@@ -1,4 +1,5 @@
 pub mod entities;
+pub mod migration_lock;
 pub mod repositories;
 pub mod prelude {
     pub use super::entities::*;
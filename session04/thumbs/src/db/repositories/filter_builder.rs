@@ -0,0 +1,206 @@
+use sea_orm::{
+    Condition, QueryFilter, Select,
+    prelude::*,
+    sea_query::{ConditionExpression, IntoLikeExpr},
+};
+use std::marker::PhantomData;
+
+use super::FilterCondition;
+
+/// Builds the filter the repositories take from typed column comparisons, e.g.
+/// `FilterBuilder::all().eq(ImageColumn::MimeType, "image/png").boxed()`. A builder from
+/// [`FilterBuilder::all`] ANDs what is added to it, one from [`FilterBuilder::any`] ORs it,
+/// and [`FilterBuilder::group`] nests one in the other.
+#[derive(Debug, Clone)]
+pub struct FilterBuilder<E: EntityTrait> {
+    condition: Condition,
+    entity: PhantomData<fn() -> E>,
+}
+
+impl<E: EntityTrait> FilterBuilder<E> {
+    /// Matches the rows matching everything added. Matches every row while empty.
+    pub fn all() -> Self {
+        Self::new(Condition::all())
+    }
+
+    /// Matches the rows matching anything added. Matches no row while empty.
+    pub fn any() -> Self {
+        Self::new(Condition::any())
+    }
+
+    fn new(condition: Condition) -> Self {
+        Self {
+            condition,
+            entity: PhantomData,
+        }
+    }
+
+    pub fn eq<V: Into<Value>>(self, column: E::Column, value: V) -> Self {
+        self.expr(column.eq(value))
+    }
+
+    pub fn ne<V: Into<Value>>(self, column: E::Column, value: V) -> Self {
+        self.expr(column.ne(value))
+    }
+
+    pub fn gte<V: Into<Value>>(self, column: E::Column, value: V) -> Self {
+        self.expr(column.gte(value))
+    }
+
+    pub fn lte<V: Into<Value>>(self, column: E::Column, value: V) -> Self {
+        self.expr(column.lte(value))
+    }
+
+    /// SQL `LIKE`, so `%` and `_` in `pattern` are wildcards.
+    pub fn like<T: IntoLikeExpr>(self, column: E::Column, pattern: T) -> Self {
+        self.expr(column.like(pattern))
+    }
+
+    /// Matches any of `values`. An empty list matches nothing.
+    pub fn in_<V, I>(self, column: E::Column, values: I) -> Self
+    where
+        V: Into<Value>,
+        I: IntoIterator<Item = V>,
+    {
+        self.expr(column.is_in(values))
+    }
+
+    pub fn is_null(self, column: E::Column) -> Self {
+        self.expr(column.is_null())
+    }
+
+    /// Adds an expression built elsewhere, e.g. a subquery like `tagged_with`.
+    pub fn expr<T: Into<ConditionExpression>>(mut self, expr: T) -> Self {
+        self.condition = self.condition.add(expr);
+        self
+    }
+
+    /// Adds `other` as a single term, e.g. an `any` group inside an `all` one.
+    pub fn group(self, other: FilterBuilder<E>) -> Self {
+        self.expr(other.condition)
+    }
+
+    /// Adds what `f` builds from `value` when there is one, for optional query parameters.
+    pub fn when<T>(self, value: Option<T>, f: impl FnOnce(Self, T) -> Self) -> Self {
+        match value {
+            Some(value) => f(self, value),
+            None => self,
+        }
+    }
+
+    /// Whether nothing was added yet.
+    pub fn is_empty(&self) -> bool {
+        self.condition.is_empty()
+    }
+
+    pub fn condition(self) -> Condition {
+        self.condition
+    }
+
+    /// The boxed filter the repositories' `list`, `count` and `find_one` take.
+    pub fn boxed(self) -> Box<dyn FilterCondition<E> + Send + Sync> {
+        Box::new(self)
+    }
+}
+
+impl<E: EntityTrait> FilterCondition<E> for FilterBuilder<E> {
+    fn apply(&self, query: Select<E>) -> Select<E> {
+        query.filter(self.condition.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::prelude::*;
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::{Database, DbBackend, QueryTrait};
+
+    fn sql(filter: FilterBuilder<TagEntity>) -> String {
+        let sql = filter
+            .apply(TagEntity::find())
+            .build(DbBackend::Sqlite)
+            .to_string();
+        sql.split_once(" WHERE ")
+            .map(|(_, clause)| clause.to_string())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn terms_are_anded_or_ored_and_groups_nest() {
+        assert_eq!(sql(FilterBuilder::all()), "TRUE");
+
+        let filter = FilterBuilder::all()
+            .eq(TagColumn::Name, "cats")
+            .is_null(TagColumn::DeletedAt);
+        assert_eq!(
+            sql(filter),
+            r#""tags"."name" = 'cats' AND "tags"."deleted_at" IS NULL"#
+        );
+
+        let filter = FilterBuilder::any()
+            .like(TagColumn::Name, "c%")
+            .in_(TagColumn::Id, [1, 2]);
+        assert_eq!(
+            sql(filter),
+            r#""tags"."name" LIKE 'c%' OR "tags"."id" IN (1, 2)"#
+        );
+
+        let filter = FilterBuilder::all().gte(TagColumn::Id, 2).group(
+            FilterBuilder::any()
+                .eq(TagColumn::Name, "cats")
+                .eq(TagColumn::Name, "dogs"),
+        );
+        assert_eq!(
+            sql(filter),
+            r#""tags"."id" >= 2 AND ("tags"."name" = 'cats' OR "tags"."name" = 'dogs')"#
+        );
+
+        let filter = FilterBuilder::all()
+            .when(None::<i64>, |f, id| f.eq(TagColumn::Id, id))
+            .when(Some("cats"), |f, name| f.ne(TagColumn::Name, name));
+        assert_eq!(sql(filter), r#""tags"."name" <> 'cats'"#);
+    }
+
+    #[tokio::test]
+    async fn repositories_take_the_built_filter() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let tags = TagRepository::new(db);
+        let mut ids = vec![];
+
+        for name in ["filter-a", "filter-b", "filter-c"] {
+            let tag = TagModel {
+                id: 0,
+                name: name.to_string(),
+                deleted_at: None,
+            };
+            ids.push(tags.create(tag).await.unwrap().id);
+        }
+
+        let filter = FilterBuilder::all()
+            .like(TagColumn::Name, "filter-%")
+            .group(
+                FilterBuilder::any()
+                    .eq(TagColumn::Id, ids[0])
+                    .eq(TagColumn::Name, "filter-c"),
+            );
+        let mut names = tags
+            .list(Some(filter.boxed()), None, None)
+            .await
+            .unwrap()
+            .data
+            .into_iter()
+            .map(|tag| tag.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["filter-a", "filter-c"]);
+
+        let filter = FilterBuilder::all()
+            .in_(TagColumn::Id, ids.clone())
+            .lte(TagColumn::Id, ids[1]);
+        assert_eq!(tags.count(Some(filter.boxed())).await.unwrap(), 2);
+        let none = FilterBuilder::all().in_(TagColumn::Id, Vec::<i64>::new());
+        assert_eq!(tags.count(Some(none.boxed())).await.unwrap(), 0);
+    }
+}
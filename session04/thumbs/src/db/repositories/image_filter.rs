@@ -0,0 +1,322 @@
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    Condition, QueryFilter, Select,
+    prelude::*,
+    sea_query::{Expr, Func, Query, SimpleExpr},
+};
+use serde::Deserialize;
+
+use super::{FilterBuilder, FilterCondition};
+use crate::db::entities::*;
+
+/// Image listing filters parsed from the query string, e.g.
+/// `?min_width=800&mime=image/png&created_after=2025-01-01T00:00:00Z&tag=cats`.
+/// All the supplied fields must match. Sizes are in bytes.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ImageFilter {
+    pub min_width: Option<i32>,
+    pub max_width: Option<i32>,
+    pub min_height: Option<i32>,
+    pub max_height: Option<i32>,
+    pub min_size: Option<i64>,
+    pub max_size: Option<i64>,
+    pub mime: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    /// Name of a tag the image must have (case-insensitive).
+    pub tag: Option<String>,
+}
+
+impl ImageFilter {
+    /// Rejects negative dimensions and sizes, which would otherwise just match nothing.
+    pub fn validate(&self) -> Result<(), String> {
+        let fields = [
+            ("min_width", self.min_width.map(i64::from)),
+            ("max_width", self.max_width.map(i64::from)),
+            ("min_height", self.min_height.map(i64::from)),
+            ("max_height", self.max_height.map(i64::from)),
+            ("min_size", self.min_size),
+            ("max_size", self.max_size),
+        ];
+
+        for (name, value) in fields {
+            if value.is_some_and(|v| v < 0) {
+                return Err(format!("{name} must not be negative."));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn condition(&self) -> Condition {
+        let non_blank = |v: &Option<String>| {
+            v.as_deref()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+        };
+
+        FilterBuilder::<ImageEntity>::all()
+            .when(self.min_width, |f, v| f.gte(ImageColumn::Width, v))
+            .when(self.max_width, |f, v| f.lte(ImageColumn::Width, v))
+            .when(self.min_height, |f, v| f.gte(ImageColumn::Height, v))
+            .when(self.max_height, |f, v| f.lte(ImageColumn::Height, v))
+            .when(self.min_size, |f, v| f.gte(ImageColumn::FileSize, v))
+            .when(self.max_size, |f, v| f.lte(ImageColumn::FileSize, v))
+            .when(non_blank(&self.mime), |f, v| f.eq(ImageColumn::MimeType, v))
+            .when(self.created_after, |f, v| f.gte(ImageColumn::CreatedAt, v))
+            .when(self.created_before, |f, v| f.lte(ImageColumn::CreatedAt, v))
+            .when(non_blank(&self.tag), |f, v| f.expr(tagged_with(&v)))
+            .condition()
+    }
+}
+
+/// Matches images that have a tag named `name`, ignoring case. It is a subquery on the
+/// image rather than a related filter, so several of them can be ANDed together.
+pub fn tagged_with(name: &str) -> SimpleExpr {
+    ImageColumn::Id.in_subquery(
+        Query::select()
+            .column((ImageTagEntity, ImageTagColumn::ImageId))
+            .from(ImageTagEntity)
+            .inner_join(
+                TagEntity,
+                Expr::col((TagEntity, TagColumn::Id))
+                    .equals((ImageTagEntity, ImageTagColumn::TagId)),
+            )
+            .and_where(
+                Expr::expr(Func::lower(Expr::col((TagEntity, TagColumn::Name))))
+                    .eq(name.trim().to_lowercase()),
+            )
+            .to_owned(),
+    )
+}
+
+impl FilterCondition<ImageEntity> for ImageFilter {
+    fn apply(&self, query: Select<ImageEntity>) -> Select<ImageEntity> {
+        query.filter(self.condition())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::prelude::*;
+    use crate::testing::dto;
+    use chrono::TimeZone;
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::{Database, DatabaseConnection};
+
+    async fn setup() -> DatabaseConnection {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo = ImageRepository::new(db.clone());
+        let images = [
+            ("small", "image/png", 640, 480, 1_000, 2024, "cats"),
+            ("wide", "image/jpeg", 1920, 1080, 50_000, 2025, "cats,dogs"),
+            ("tall", "image/png", 1080, 1920, 80_000, 2025, "dogs"),
+        ];
+
+        for (title, mime_type, width, height, file_size, year, tags) in images {
+            let image = repo
+                .create_with_tags(CreateImageDto {
+                    file_size,
+                    mime_type: mime_type.to_string(),
+                    width: Some(width),
+                    height: Some(height),
+                    tags: Some(tags.to_string()),
+                    ..dto(title)
+                })
+                .await
+                .unwrap();
+            ImageEntity::update_many()
+                .col_expr(
+                    ImageColumn::CreatedAt,
+                    Expr::value(Utc.with_ymd_and_hms(year, 6, 1, 0, 0, 0).unwrap()),
+                )
+                .filter(ImageColumn::Id.eq(image.id))
+                .exec(&db)
+                .await
+                .unwrap();
+        }
+
+        db
+    }
+
+    async fn titles(db: &DatabaseConnection, filter: ImageFilter) -> Vec<String> {
+        let mut titles = ImageRepository::new(db.clone())
+            .list(Some(Box::new(filter)), None, None)
+            .await
+            .unwrap()
+            .data
+            .into_iter()
+            .map(|image| image.title)
+            .collect::<Vec<_>>();
+        titles.sort();
+        titles
+    }
+
+    #[tokio::test]
+    async fn each_field_narrows_results() {
+        let db = setup().await;
+        assert_eq!(titles(&db, ImageFilter::default()).await.len(), 3);
+
+        let filter = ImageFilter {
+            min_width: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, filter).await, ["tall", "wide"]);
+
+        let filter = ImageFilter {
+            max_width: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, filter).await, ["small"]);
+
+        let filter = ImageFilter {
+            min_height: Some(1500),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, filter).await, ["tall"]);
+
+        let filter = ImageFilter {
+            max_height: Some(1080),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, filter).await, ["small", "wide"]);
+
+        let filter = ImageFilter {
+            min_size: Some(50_000),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, filter).await, ["tall", "wide"]);
+
+        let filter = ImageFilter {
+            max_size: Some(50_000),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, filter).await, ["small", "wide"]);
+
+        let filter = ImageFilter {
+            mime: Some("image/jpeg".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, filter).await, ["wide"]);
+
+        let filter = ImageFilter {
+            created_after: Some(Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, filter).await, ["tall", "wide"]);
+
+        let filter = ImageFilter {
+            created_before: Some(Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, filter).await, ["small"]);
+
+        let filter = ImageFilter {
+            tag: Some("cats".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, filter).await, ["small", "wide"]);
+    }
+
+    #[tokio::test]
+    async fn combined_fields_are_anded() {
+        let db = setup().await;
+        let filter = ImageFilter {
+            mime: Some("image/png".to_string()),
+            tag: Some("dogs".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, filter).await, ["tall"]);
+
+        let filter = ImageFilter {
+            min_width: Some(1000),
+            tag: Some("cats".to_string()),
+            created_after: Some(Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, filter).await, ["wide"]);
+
+        let filter = ImageFilter {
+            mime: Some("image/jpeg".to_string()),
+            tag: Some("dogs".to_string()),
+            max_width: Some(1000),
+            ..Default::default()
+        };
+        assert!(titles(&db, filter).await.is_empty());
+
+        // Oversized images carrying a tag
+        let filter = ImageFilter {
+            min_width: Some(1000),
+            min_height: Some(1000),
+            min_size: Some(10_000),
+            tag: Some("dogs".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, filter).await, ["tall", "wide"]);
+    }
+
+    #[tokio::test]
+    async fn tags_match_case_insensitively_and_and_together() {
+        let db = setup().await;
+        let titles = |tags: &'static [&'static str]| {
+            let db = db.clone();
+            async move {
+                let condition = tags
+                    .iter()
+                    .fold(Condition::all(), |c, tag| c.add(tagged_with(tag)));
+                let mut titles = ImageRepository::new(db)
+                    .list(Some(Box::new(condition)), None, None)
+                    .await
+                    .unwrap()
+                    .data
+                    .into_iter()
+                    .map(|image| image.title)
+                    .collect::<Vec<_>>();
+                titles.sort();
+                titles
+            }
+        };
+
+        assert_eq!(titles(&["CATS"]).await, ["small", "wide"]);
+        assert_eq!(titles(&["Cats", "dogs"]).await, ["wide"]);
+        assert!(titles(&["cats", "birds"]).await.is_empty());
+    }
+
+    #[test]
+    fn parses_query_string() {
+        let uri = "/images?min_width=800&mime=image%2Fpng&created_after=2025-01-01T00:00:00Z"
+            .parse()
+            .unwrap();
+        let axum::extract::Query(filter) =
+            axum::extract::Query::<ImageFilter>::try_from_uri(&uri).unwrap();
+        assert_eq!(filter.min_width, Some(800));
+        assert_eq!(filter.mime.as_deref(), Some("image/png"));
+        assert_eq!(
+            filter.created_after,
+            Some(Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap())
+        );
+        assert!(filter.validate().is_ok());
+    }
+
+    #[test]
+    fn negative_values_are_rejected() {
+        let filter = ImageFilter {
+            max_size: Some(-1),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.validate().unwrap_err(),
+            "max_size must not be negative."
+        );
+
+        let filter = ImageFilter {
+            min_width: Some(-5),
+            ..Default::default()
+        };
+        assert!(filter.validate().is_err());
+    }
+}
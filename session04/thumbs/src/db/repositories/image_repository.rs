@@ -1,12 +1,16 @@
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use migration::OnConflict;
 use sea_orm::{
-    DatabaseTransaction, DeleteResult, JoinType, PaginatorTrait, QuerySelect, Set,
-    TransactionTrait, prelude::*,
+    Condition, DatabaseTransaction, DeleteResult, Iterable, JoinType, NotSet, PaginatorTrait,
+    QueryOrder, QuerySelect, Select, Set, TransactionTrait,
+    prelude::*,
+    sea_query::{Func, LikeExpr, SimpleExpr},
 };
+use serde::Serialize;
 
-use crate::db::prelude::*;
+use crate::{db::prelude::*, imaging::hamming_distance};
 
 #[async_trait]
 pub trait IImageRepository: IRepositoryWithRelated<ImageEntity, UpdateImageDto, TagEntity> {
@@ -22,16 +26,156 @@ pub trait IImageRepository: IRepositoryWithRelated<ImageEntity, UpdateImageDto,
     async fn add_tags(&self, id: i64, tags: Vec<i64>) -> Result<u64>;
     async fn remove_tags(&self, id: i64, tags: Vec<i64>) -> Result<u64>;
     async fn add_tags_from_str(&self, id: i64, tags: &str) -> Result<u64>;
+    /// Adds the tags named in `names` to the image in one transaction, creating the missing
+    /// ones and bringing back soft deleted ones. Repeated names and tags the image already
+    /// has are skipped. Returns how many tags were newly added.
+    async fn add_tag_names(&self, id: i64, names: &[String]) -> Result<u64>;
+    async fn find_by_phash_within(&self, hash: i64, distance: u32) -> Result<Option<ImageModel>>;
+    /// Finds images whose title, description or alt text contain every whitespace separated
+    /// term of `query`. Title matches rank first, then description, then alt text.
+    async fn search_text(
+        &self,
+        query: &str,
+        pagination: Option<Pagination>,
+    ) -> Result<ResultSet<ImageModel>>;
+    /// Returns false if the image doesn't exist (anymore).
+    async fn set_thumbnail_ready(&self, id: i64, ready: bool) -> Result<bool>;
+    /// Records that the thumbnails of `id` couldn't be generated.
+    async fn set_thumbnail_failed(&self, id: i64) -> Result<()>;
+    /// Number of rows, soft deleted ones included, sharing the stored original `hash`.
+    async fn count_content_refs(&self, hash: &str) -> Result<u64>;
+    /// Count, total size and newest upload of the live images, with a single query.
+    async fn totals(&self) -> Result<ImageTotals>;
 }
 
+/// Aggregates returned by [`IImageRepository::totals`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ImageTotals {
+    pub count: u64,
+    /// Sum of the images' `file_size`.
+    pub total_bytes: u64,
+    /// `created_at` of the newest image, `None` when there are none.
+    pub newest_created_at: Option<DateTime<Utc>>,
+}
+
+/// Columns clients can sort images by with `?sort=`.
+pub const IMAGE_SORT_COLUMNS: &[ImageColumn] = &[
+    ImageColumn::Id,
+    ImageColumn::Title,
+    ImageColumn::FileSize,
+    ImageColumn::Width,
+    ImageColumn::Height,
+    ImageColumn::CreatedAt,
+    ImageColumn::UpdatedAt,
+];
+
 pub struct ImageRepository {
     db: DatabaseConnection,
+    soft_delete: bool,
 }
 
 impl ImageRepository {
     pub fn new(db: DatabaseConnection) -> Self {
-        Self { db }
+        Self {
+            db,
+            soft_delete: false,
+        }
+    }
+
+    /// Makes `delete` mark images as deleted instead of removing them.
+    pub fn with_soft_delete(mut self, enabled: bool) -> Self {
+        self.soft_delete = enabled;
+        self
+    }
+}
+
+/// Images that aren't soft deleted.
+fn find_active() -> Select<ImageEntity> {
+    ImageEntity::find().filter(ImageColumn::DeletedAt.is_null())
+}
+
+/// Inserts the image `model` describes on `db` and links the tags in its comma separated
+/// `tags`, so the caller's transaction can cover other writes as well.
+pub async fn insert_image_with_tags<C: ConnectionTrait>(
+    db: &C,
+    model: CreateImageDto,
+) -> Result<ImageModel> {
+    let names = normalized_tag_names(model.tags.iter().flat_map(|tags| tags.split(',')));
+    let active_model: ImageModelDto = model.into();
+    let image = active_model.insert(db).await?;
+
+    if !names.is_empty() {
+        link_tag_names(db, image.id, names).await?;
+    }
+
+    Ok(image)
+}
+
+/// Normalizes `names`, dropping the blank ones and the repeats.
+fn normalized_tag_names(names: impl Iterator<Item = impl AsRef<str>>) -> Vec<String> {
+    let mut names = names
+        .map(|name| normalize_tag_name(name.as_ref()))
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Links the tags named `names`, normalized and deduplicated, to the image on `db`.
+async fn link_tag_names<C: ConnectionTrait>(db: &C, id: i64, names: Vec<String>) -> Result<u64> {
+    TagEntity::insert_many(names.iter().map(|tag| TagModelDto {
+        name: Set(tag.clone()),
+        ..Default::default()
+    }))
+    .on_conflict(OnConflict::new().do_nothing().to_owned())
+    .exec_without_returning(db)
+    .await?;
+    // Reusing the name of a soft deleted tag brings it back
+    TagEntity::update_many()
+        .col_expr(TagColumn::DeletedAt, Expr::value(None::<DateTime<Utc>>))
+        .filter(TagColumn::Name.is_in(names.clone()))
+        .filter(TagColumn::DeletedAt.is_not_null())
+        .exec(db)
+        .await?;
+
+    let tag_ids = TagEntity::find()
+        .filter(TagColumn::Name.is_in(names))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|tag| tag.id)
+        .collect::<Vec<_>>();
+
+    if tag_ids.is_empty() {
+        return Ok(0);
     }
+
+    let result = ImageTagEntity::insert_many(tag_ids.iter().map(|&tag_id| ImageTagModelDto {
+        image_id: Set(id),
+        tag_id: Set(tag_id),
+    }))
+    .on_conflict(OnConflict::new().do_nothing().to_owned())
+    .exec_without_returning(db)
+    .await?;
+
+    Ok(result)
+}
+
+/// Columns `search_text` looks in, with the rank a match in each one adds.
+const TEXT_SEARCH_COLUMNS: [(ImageColumn, i32); 3] = [
+    (ImageColumn::Title, 4),
+    (ImageColumn::Description, 2),
+    (ImageColumn::AltText, 1),
+];
+
+/// A LIKE pattern matching `term` anywhere, with the LIKE wildcards in it escaped.
+fn like_pattern(term: &str) -> LikeExpr {
+    let escaped = term
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    LikeExpr::new(format!("%{escaped}%")).escape('\\')
 }
 
 #[async_trait]
@@ -50,9 +194,10 @@ impl IRepository<ImageEntity, UpdateImageDto> for ImageRepository {
     async fn list(
         &self,
         filter: Option<Box<dyn FilterCondition<ImageEntity> + Send + Sync>>,
+        order_by: Option<OrderBy<ImageColumn>>,
         pagination: Option<Pagination>,
     ) -> Result<ResultSet<<ImageEntity as EntityTrait>::Model>> {
-        let mut query = <ImageEntity as EntityTrait>::find();
+        let mut query = find_active();
 
         if let Some(f) = &filter {
             query = f.apply(query);
@@ -60,6 +205,10 @@ impl IRepository<ImageEntity, UpdateImageDto> for ImageRepository {
 
         let total = query.clone().count(self.database()).await?;
 
+        if let Some(o) = &order_by {
+            query = o.apply(query).order_by_asc(ImageColumn::Id);
+        }
+
         if let Some(p) = pagination {
             query = query.offset((p.page - 1) * p.page_size).limit(p.page_size);
         }
@@ -70,6 +219,41 @@ impl IRepository<ImageEntity, UpdateImageDto> for ImageRepository {
             data,
             total,
             pagination,
+            next_cursor: None,
+        })
+    }
+
+    async fn list_after(
+        &self,
+        cursor: Option<Cursor>,
+        limit: u64,
+    ) -> Result<ResultSet<ImageModel>> {
+        let limit = limit.clamp(1, MAX_PAGE_SIZE);
+        let total = find_active().count(self.database()).await?;
+        let mut query = find_active();
+
+        if let Some(c) = cursor {
+            query = query.filter(ImageColumn::Id.gt(c.after));
+        }
+
+        // Fetch one extra row to know whether there is a next page
+        let mut data = query
+            .order_by_asc(ImageColumn::Id)
+            .limit(limit + 1)
+            .all(self.database())
+            .await?;
+        let next_cursor = if data.len() as u64 > limit {
+            data.truncate(limit as usize);
+            data.last().map(|m| Cursor { after: m.id })
+        } else {
+            None
+        };
+
+        Ok(ResultSet {
+            data,
+            total,
+            pagination: None,
+            next_cursor,
         })
     }
 
@@ -77,7 +261,7 @@ impl IRepository<ImageEntity, UpdateImageDto> for ImageRepository {
         &self,
         filter: Option<Box<dyn FilterCondition<ImageEntity> + Send + Sync>>,
     ) -> Result<u64> {
-        let mut query = <ImageEntity as EntityTrait>::find();
+        let mut query = find_active();
 
         if let Some(f) = &filter {
             query = f.apply(query);
@@ -86,8 +270,44 @@ impl IRepository<ImageEntity, UpdateImageDto> for ImageRepository {
         query.count(self.database()).await.map_err(Into::into)
     }
 
+    async fn count_distinct(
+        &self,
+        column: ImageColumn,
+        filter: Option<Box<dyn FilterCondition<ImageEntity> + Send + Sync>>,
+    ) -> Result<u64> {
+        let mut query = find_active();
+
+        if let Some(f) = &filter {
+            query = f.apply(query);
+        }
+
+        let count = query
+            .select_only()
+            .column_as(
+                SimpleExpr::from(Func::count_distinct(Expr::col((ImageEntity, column)))),
+                "count",
+            )
+            .into_tuple::<i64>()
+            .one(self.database())
+            .await?
+            .unwrap_or(0);
+        Ok(count as u64)
+    }
+
     async fn get(&self, id: i64) -> Result<Option<<ImageEntity as EntityTrait>::Model>> {
-        ImageEntity::find_by_id(id)
+        find_active()
+            .filter(ImageColumn::Id.eq(id))
+            .one(self.database())
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn find_one(
+        &self,
+        filter: Box<dyn FilterCondition<ImageEntity> + Send + Sync>,
+    ) -> Result<Option<<ImageEntity as EntityTrait>::Model>> {
+        filter
+            .apply(find_active())
             .one(self.database())
             .await
             .map_err(Into::into)
@@ -104,8 +324,100 @@ impl IRepository<ImageEntity, UpdateImageDto> for ImageRepository {
             .map_err(Into::into)
     }
 
+    async fn create_many(&self, models: Vec<ImageModel>) -> Result<Vec<ImageModel>> {
+        if models.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let txn = self.begin_transaction().await?;
+        // SQLite can't return the inserted rows, but the ids they get
+        // are all above the largest one before the insert.
+        let last_id = ImageEntity::find()
+            .select_only()
+            .column_as(ImageColumn::Id.max(), "id")
+            .into_tuple::<Option<i64>>()
+            .one(&txn)
+            .await?
+            .flatten()
+            .unwrap_or(0);
+        let mut active_models = Vec::with_capacity(models.len());
+
+        for model in models {
+            let mut active_model: ImageModelDto = model.into();
+            active_model.id = NotSet;
+            // insert_many skips the entity hooks
+            active_models.push(active_model.before_save(&txn, true).await?);
+        }
+
+        ImageEntity::insert_many(active_models)
+            .exec_without_returning(&txn)
+            .await?;
+        let created = ImageEntity::find()
+            .filter(ImageColumn::Id.gt(last_id))
+            .order_by_asc(ImageColumn::Id)
+            .all(&txn)
+            .await?;
+        txn.commit().await?;
+        Ok(created)
+    }
+
+    async fn upsert(
+        &self,
+        model: ImageModel,
+        conflict_columns: Vec<ImageColumn>,
+    ) -> Result<ImageModel> {
+        if conflict_columns.is_empty() {
+            return Err(anyhow!("Upsert requires at least one conflict column."));
+        }
+
+        let txn = self.begin_transaction().await?;
+        let is_conflict_column = |column: &ImageColumn| {
+            conflict_columns
+                .iter()
+                .any(|c| c.as_str() == column.as_str())
+        };
+        let mut active_model: ImageModelDto = model.into();
+
+        if !is_conflict_column(&ImageColumn::Id) {
+            active_model.id = NotSet;
+        }
+
+        let active_model = active_model.before_save(&txn, true).await?;
+        let condition = conflict_columns
+            .iter()
+            .try_fold(Condition::all(), |condition, column| {
+                let value = active_model.get(*column).into_value()?;
+                Some(condition.add(column.eq(value)))
+            })
+            .ok_or_else(|| anyhow!("Upsert requires values for the conflict columns."))?;
+        let update_columns = ImageColumn::iter()
+            .filter(|c| {
+                !matches!(c, ImageColumn::Id | ImageColumn::CreatedAt) && !is_conflict_column(c)
+            })
+            .collect::<Vec<_>>();
+        let mut on_conflict = OnConflict::columns(conflict_columns.clone());
+
+        if update_columns.is_empty() {
+            on_conflict.do_nothing();
+        } else {
+            on_conflict.update_columns(update_columns);
+        }
+
+        ImageEntity::insert(active_model)
+            .on_conflict(on_conflict)
+            .exec_without_returning(&txn)
+            .await?;
+        let model = ImageEntity::find()
+            .filter(condition)
+            .one(&txn)
+            .await?
+            .ok_or_else(|| anyhow!("Image not found after upsert."))?;
+        txn.commit().await?;
+        Ok(model)
+    }
     async fn update(&self, id: i64, model: UpdateImageDto) -> Result<ImageModel> {
-        let existing = ImageEntity::find_by_id(id)
+        let existing = find_active()
+            .filter(ImageColumn::Id.eq(id))
             .one(&self.db)
             .await?
             .ok_or_else(|| sea_orm::DbErr::RecordNotFound("Image not found".to_owned()))?;
@@ -118,12 +430,75 @@ impl IRepository<ImageEntity, UpdateImageDto> for ImageRepository {
     }
 
     async fn delete(&self, id: i64) -> Result<()> {
-        ImageEntity::delete_by_id(id)
+        delete_image(self.database(), id, self.soft_delete).await
+    }
+
+    fn soft_deletes(&self) -> bool {
+        self.soft_delete
+    }
+
+    async fn list_deleted(
+        &self,
+        filter: Option<Box<dyn FilterCondition<ImageEntity> + Send + Sync>>,
+        pagination: Option<Pagination>,
+    ) -> Result<ResultSet<ImageModel>> {
+        let mut query = ImageEntity::find().filter(ImageColumn::DeletedAt.is_not_null());
+
+        if let Some(f) = &filter {
+            query = f.apply(query);
+        }
+
+        let total = query.clone().count(self.database()).await?;
+        query = query
+            .order_by_desc(ImageColumn::DeletedAt)
+            .order_by_asc(ImageColumn::Id);
+
+        if let Some(p) = pagination {
+            query = query.offset((p.page - 1) * p.page_size).limit(p.page_size);
+        }
+
+        let data = query.all(self.database()).await?;
+
+        Ok(ResultSet {
+            data,
+            total,
+            pagination,
+            next_cursor: None,
+        })
+    }
+
+    async fn restore(&self, id: i64) -> Result<bool> {
+        let result = ImageEntity::update_many()
+            .col_expr(ImageColumn::DeletedAt, Expr::value(None::<DateTime<Utc>>))
+            .filter(ImageColumn::Id.eq(id))
+            .filter(ImageColumn::DeletedAt.is_not_null())
             .exec(self.database())
-            .await
-            .map_err(anyhow::Error::from)?;
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
 
-        Ok(())
+    async fn purge_deleted(&self, before: DateTime<Utc>) -> Result<Vec<ImageModel>> {
+        let txn = self.begin_transaction().await?;
+        let purged = ImageEntity::find()
+            .filter(ImageColumn::DeletedAt.lt(before))
+            .all(&txn)
+            .await?;
+
+        if purged.is_empty() {
+            return Ok(purged);
+        }
+
+        let ids = purged.iter().map(|m| m.id).collect::<Vec<_>>();
+        ImageTagEntity::delete_many()
+            .filter(ImageTagColumn::ImageId.is_in(ids.clone()))
+            .exec(&txn)
+            .await?;
+        ImageEntity::delete_many()
+            .filter(ImageColumn::Id.is_in(ids))
+            .exec(&txn)
+            .await?;
+        txn.commit().await?;
+        Ok(purged)
     }
 }
 
@@ -135,9 +510,10 @@ impl IRepositoryWithRelated<ImageEntity, UpdateImageDto, TagEntity> for ImageRep
         filter_related: Option<
             Box<dyn FilterRelatedCondition<ImageEntity, TagEntity> + Send + Sync>,
         >,
+        order_by: Option<OrderBy<ImageColumn>>,
         pagination: Option<Pagination>,
     ) -> Result<ResultSet<ModelWithRelated<ImageModel, TagModel>>> {
-        let mut query = <ImageEntity as EntityTrait>::find();
+        let mut query = find_active();
 
         if let Some(f) = &filter {
             query = f.apply(query);
@@ -145,6 +521,14 @@ impl IRepositoryWithRelated<ImageEntity, UpdateImageDto, TagEntity> for ImageRep
 
         let count_query = query.clone();
         let total = count_query.count(self.database()).await?;
+
+        // The id breaks ties so pages don't overlap, and is the default order
+        if let Some(o) = &order_by {
+            query = o.apply(query);
+        }
+
+        let query = query.order_by_asc(ImageColumn::Id);
+        let page_query = query.clone();
         let mut query = query.find_with_related(TagEntity);
 
         if let Some(l) = &filter_related {
@@ -152,16 +536,29 @@ impl IRepositoryWithRelated<ImageEntity, UpdateImageDto, TagEntity> for ImageRep
         }
 
         if let Some(p) = pagination {
-            query = query.offset((p.page - 1) * p.page_size).limit(p.page_size);
+            // offset/limit on the joined query would count tag rows, not images,
+            // so select the ids of the images on the page first.
+            let ids: Vec<i64> = page_query
+                .select_only()
+                .column(ImageColumn::Id)
+                .offset((p.page - 1) * p.page_size)
+                .limit(p.page_size)
+                .into_tuple()
+                .all(self.database())
+                .await?;
+            query = query.filter(ImageColumn::Id.is_in(ids));
         }
 
         let data = query
             .all(self.database())
             .await?
             .into_iter()
-            .map(|e| ModelWithRelated {
-                item: e.0,
-                related: e.1,
+            .map(|(image, tags)| ModelWithRelated {
+                item: image,
+                related: tags
+                    .into_iter()
+                    .filter(|t| t.deleted_at.is_none())
+                    .collect(),
             })
             .collect();
 
@@ -169,6 +566,7 @@ impl IRepositoryWithRelated<ImageEntity, UpdateImageDto, TagEntity> for ImageRep
             data,
             total,
             pagination,
+            next_cursor: None,
         })
     }
 
@@ -176,11 +574,16 @@ impl IRepositoryWithRelated<ImageEntity, UpdateImageDto, TagEntity> for ImageRep
         &self,
         id: i64,
     ) -> Result<Option<ModelWithRelated<ImageModel, TagModel>>> {
-        let image = <ImageEntity as EntityTrait>::find_by_id(id)
+        let image = find_active()
+            .filter(ImageColumn::Id.eq(id))
             .one(self.database())
             .await?;
         let Some(image) = image else { return Ok(None) };
-        let tags = image.find_related(TagEntity).all(self.database()).await?;
+        let tags = image
+            .find_related(TagEntity)
+            .filter(TagColumn::DeletedAt.is_null())
+            .all(self.database())
+            .await?;
 
         Ok(Some(ModelWithRelated {
             item: image,
@@ -189,25 +592,54 @@ impl IRepositoryWithRelated<ImageEntity, UpdateImageDto, TagEntity> for ImageRep
     }
 
     async fn delete_related(&self, id: i64) -> Result<()> {
-        ImageTagEntity::delete_many()
-            .filter(ImageTagColumn::ImageId.eq(id))
-            .exec(self.database())
+        unlink_tags(self.database(), id).await
+    }
+
+    async fn delete_with_related(&self, id: i64) -> Result<()> {
+        let soft_delete = self.soft_delete;
+        self.with_transaction(move |txn| {
+            Box::pin(async move {
+                if !soft_delete {
+                    unlink_tags(txn, id).await?;
+                }
+
+                delete_image(txn, id, soft_delete).await
+            })
+        })
+        .await
+    }
+}
+
+/// Deletes the image on `db`, or only marks it as deleted when `soft_delete` is set.
+async fn delete_image<C: ConnectionTrait>(db: &C, id: i64, soft_delete: bool) -> Result<()> {
+    if soft_delete {
+        ImageEntity::update_many()
+            .col_expr(ImageColumn::DeletedAt, Expr::value(Utc::now()))
+            .filter(ImageColumn::Id.eq(id))
+            .filter(ImageColumn::DeletedAt.is_null())
+            .exec(db)
             .await?;
-        Ok(())
+        return Ok(());
     }
+
+    ImageEntity::delete_by_id(id).exec(db).await?;
+    Ok(())
+}
+
+/// Removes the links between the image and its tags on `db`.
+async fn unlink_tags<C: ConnectionTrait>(db: &C, id: i64) -> Result<()> {
+    ImageTagEntity::delete_many()
+        .filter(ImageTagColumn::ImageId.eq(id))
+        .exec(db)
+        .await?;
+    Ok(())
 }
 
 #[async_trait]
 impl IImageRepository for ImageRepository {
     async fn create_with_tags(&self, model: CreateImageDto) -> Result<ImageModel> {
-        let tags = model.tags.clone();
-        let active_model: ImageModelDto = model.into();
-        let result = active_model.insert(self.database()).await?;
-        let Some(tags) = tags else {
-            return Ok(result);
-        };
-        self.add_tags_from_str(result.id, &tags).await?;
-        Ok(result)
+        self.with_transaction(move |txn| Box::pin(insert_image_with_tags(txn, model)))
+            .await
     }
 
     async fn list_tags(
@@ -217,14 +649,15 @@ impl IImageRepository for ImageRepository {
         pagination: Option<Pagination>,
     ) -> Result<ResultSet<TagModel>> {
         let mut query = <TagEntity as EntityTrait>::find()
-            .join(
+            .join_rev(
                 JoinType::InnerJoin,
                 ImageTagEntity::belongs_to(TagEntity)
                     .from(ImageTagColumn::TagId)
                     .to(TagColumn::Id)
                     .into(),
             )
-            .filter(ImageTagColumn::ImageId.eq(id));
+            .filter(ImageTagColumn::ImageId.eq(id))
+            .filter(TagColumn::DeletedAt.is_null());
 
         if let Some(f) = &filter {
             query = f.apply(query);
@@ -241,6 +674,7 @@ impl IImageRepository for ImageRepository {
             data,
             total,
             pagination,
+            next_cursor: None,
         })
     }
 
@@ -301,48 +735,436 @@ impl IImageRepository for ImageRepository {
     }
 
     async fn add_tags_from_str(&self, id: i64, tags: &str) -> Result<u64> {
-        if tags.is_empty() {
-            return Ok(0);
-        }
+        let names = tags.split(',').map(str::to_owned).collect::<Vec<_>>();
+        self.add_tag_names(id, &names).await
+    }
 
-        let tags = tags
-            .split(',')
-            .map(str::trim)
-            .filter(|s| !s.is_empty())
-            .collect::<Vec<_>>();
+    async fn add_tag_names(&self, id: i64, names: &[String]) -> Result<u64> {
+        let names = normalized_tag_names(names.iter());
 
-        if tags.is_empty() {
+        if names.is_empty() {
             return Ok(0);
         }
 
-        TagEntity::insert_many(tags.iter().map(|&tag| TagModelDto {
-            name: Set(tag.to_string()),
-            ..Default::default()
-        }))
-        .on_conflict(OnConflict::new().do_nothing().to_owned())
-        .exec_without_returning(self.database())
-        .await?;
+        self.with_transaction(move |txn| Box::pin(link_tag_names(txn, id, names)))
+            .await
+    }
 
-        let tag_ids = TagEntity::find()
-            .filter(TagColumn::Name.is_in(tags))
+    async fn find_by_phash_within(&self, hash: i64, distance: u32) -> Result<Option<ImageModel>> {
+        // SQLite has no popcount, so compare the hashes here. Only the ids and hashes are loaded.
+        let hashes: Vec<(i64, i64)> = find_active()
+            .select_only()
+            .column(ImageColumn::Id)
+            .column(ImageColumn::Phash)
+            .filter(ImageColumn::Phash.is_not_null())
+            .into_tuple()
             .all(self.database())
-            .await?
+            .await?;
+        let closest = hashes
             .into_iter()
-            .map(|tag| tag.id)
-            .collect::<Vec<_>>();
+            .map(|(id, phash)| (hamming_distance(hash as u64, phash as u64), id))
+            .filter(|(d, _)| *d <= distance)
+            .min();
+        let Some((_, id)) = closest else {
+            return Ok(None);
+        };
 
-        if tag_ids.is_empty() {
-            return Ok(0);
+        self.get(id).await
+    }
+
+    async fn search_text(
+        &self,
+        query: &str,
+        pagination: Option<Pagination>,
+    ) -> Result<ResultSet<ImageModel>> {
+        let terms = query.split_whitespace().collect::<Vec<_>>();
+
+        if terms.is_empty() {
+            return Err(anyhow!("Search query is empty"));
         }
 
-        let result = ImageTagEntity::insert_many(tag_ids.iter().map(|&tag_id| ImageTagModelDto {
-            image_id: Set(id),
-            tag_id: Set(tag_id),
-        }))
-        .on_conflict(OnConflict::new().do_nothing().to_owned())
-        .exec_without_returning(self.database())
-        .await?;
+        // Plain LIKE for now. SQLite's LIKE is case insensitive for ASCII. FTS5 can replace
+        // this once the data set needs it.
+        let mut condition = Condition::all();
+        let mut rank = SimpleExpr::from(0);
 
-        Ok(result)
+        for term in terms {
+            let mut any_column = Condition::any();
+
+            for (column, weight) in TEXT_SEARCH_COLUMNS {
+                let matches = Expr::col((ImageEntity, column)).like(like_pattern(term));
+                any_column = any_column.add(matches.clone());
+                rank = rank.add(Expr::case(matches, weight).finally(0));
+            }
+
+            condition = condition.add(any_column);
+        }
+
+        let mut query = find_active().filter(condition);
+        let total = query.clone().count(self.database()).await?;
+        query = query
+            .order_by(rank, sea_orm::Order::Desc)
+            .order_by_asc(ImageColumn::Id);
+
+        if let Some(p) = pagination {
+            query = query.offset((p.page - 1) * p.page_size).limit(p.page_size);
+        }
+
+        let data = query.all(self.database()).await?;
+        Ok(ResultSet {
+            data,
+            total,
+            pagination,
+            next_cursor: None,
+        })
+    }
+
+    async fn set_thumbnail_ready(&self, id: i64, ready: bool) -> Result<bool> {
+        let result = ImageEntity::update_many()
+            .col_expr(ImageColumn::ThumbnailReady, Expr::value(ready))
+            .filter(ImageColumn::Id.eq(id))
+            .exec(self.database())
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+
+    async fn set_thumbnail_failed(&self, id: i64) -> Result<()> {
+        ImageEntity::update_many()
+            .col_expr(ImageColumn::ThumbnailFailed, Expr::value(true))
+            .filter(ImageColumn::Id.eq(id))
+            .exec(self.database())
+            .await?;
+        Ok(())
+    }
+
+    async fn count_content_refs(&self, hash: &str) -> Result<u64> {
+        // Soft deleted rows can still be restored, so they keep the file alive
+        let count = ImageEntity::find()
+            .filter(ImageColumn::ContentHash.eq(hash))
+            .count(self.database())
+            .await?;
+        Ok(count)
+    }
+
+    async fn totals(&self) -> Result<ImageTotals> {
+        let row = find_active()
+            .select_only()
+            .column_as(ImageColumn::Id.count(), "count")
+            .column_as(ImageColumn::FileSize.sum(), "total_bytes")
+            .column_as(ImageColumn::CreatedAt.max(), "newest_created_at")
+            .into_tuple::<(i64, Option<i64>, Option<DateTime<Utc>>)>()
+            .one(self.database())
+            .await?;
+        let Some((count, total_bytes, newest_created_at)) = row else {
+            return Ok(ImageTotals::default());
+        };
+
+        Ok(ImageTotals {
+            count: count as u64,
+            total_bytes: total_bytes.unwrap_or(0) as u64,
+            newest_created_at,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::dto;
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::{ConnectionTrait, Database};
+
+    #[tokio::test]
+    async fn finds_closest_phash_within_distance() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo = ImageRepository::new(db);
+        repo.create_with_tags(dto("none")).await.unwrap();
+        repo.create_with_tags(CreateImageDto {
+            phash: Some(0x0000_0000_ffff_ffff),
+            ..dto("far")
+        })
+        .await
+        .unwrap();
+        let near = repo
+            .create_with_tags(CreateImageDto {
+                phash: Some(-1),
+                ..dto("near")
+            })
+            .await
+            .unwrap();
+
+        // Differs from `near` (all bits set) by 3 bits
+        let found = repo.find_by_phash_within(!0b111, 5).await.unwrap();
+        assert_eq!(found.map(|m| m.id), Some(near.id));
+        assert!(
+            repo.find_by_phash_within(!0b111, 2)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn list_with_related_sorts_before_paging() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo = ImageRepository::new(db);
+
+        for title in ["b", "c", "a"] {
+            repo.create_with_tags(CreateImageDto {
+                tags: Some("one,two".to_string()),
+                ..dto(title)
+            })
+            .await
+            .unwrap();
+        }
+
+        let titles = |result: ResultSet<ModelWithRelated<ImageModel, TagModel>>| {
+            result
+                .data
+                .into_iter()
+                .map(|m| m.item.title)
+                .collect::<Vec<_>>()
+        };
+        let page = Pagination {
+            page: 1,
+            page_size: 2,
+        };
+        let result = repo
+            .list_with_related(
+                None,
+                None,
+                Some(OrderBy::desc(ImageColumn::Title)),
+                Some(page),
+            )
+            .await
+            .unwrap();
+        assert_eq!(titles(result), ["c", "b"]);
+
+        let result = repo
+            .list_with_related(
+                None,
+                None,
+                Some(OrderBy::asc(ImageColumn::Title)),
+                Some(page),
+            )
+            .await
+            .unwrap();
+        assert_eq!(titles(result), ["a", "b"]);
+
+        let result = repo
+            .list_with_related(None, None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(titles(result), ["b", "c", "a"]);
+    }
+
+    #[tokio::test]
+    async fn keyset_and_offset_pages_match() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo = ImageRepository::new(db);
+
+        for i in 0..7 {
+            repo.create_with_tags(dto(&format!("image {i}")))
+                .await
+                .unwrap();
+        }
+
+        let mut by_offset = vec![];
+        let mut page = 1;
+
+        loop {
+            let result = repo
+                .list(
+                    None,
+                    Some(OrderBy::asc(ImageColumn::Id)),
+                    Some(Pagination { page, page_size: 3 }),
+                )
+                .await
+                .unwrap();
+
+            if result.data.is_empty() {
+                break;
+            }
+
+            by_offset.extend(result.data);
+            page += 1;
+        }
+
+        let mut by_cursor = vec![];
+        let mut cursor = None;
+
+        loop {
+            let result = repo.list_after(cursor, 3).await.unwrap();
+            assert_eq!(result.total, 7);
+            by_cursor.extend(result.data);
+            cursor = result.next_cursor;
+
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(by_offset.len(), 7);
+        assert_eq!(by_cursor, by_offset);
+    }
+
+    #[tokio::test]
+    async fn create_many_and_upsert_images() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo = ImageRepository::new(db);
+
+        let created = repo
+            .create_many(vec![dto("a").into(), dto("b").into(), dto("c").into()])
+            .await
+            .unwrap();
+        let titles = created.iter().map(|m| m.title.as_str()).collect::<Vec<_>>();
+        assert_eq!(titles, ["a", "b", "c"]);
+        assert!(created.windows(2).all(|w| w[0].id < w[1].id));
+
+        let mut changed = created[1].clone();
+        changed.title = "b2".to_string();
+        let upserted = repo.upsert(changed, vec![ImageColumn::Id]).await.unwrap();
+        assert_eq!(upserted.id, created[1].id);
+        assert_eq!(upserted.title, "b2");
+        assert_eq!(upserted.created_at, created[1].created_at);
+
+        let inserted = repo
+            .upsert(
+                ImageModel {
+                    id: 100,
+                    ..dto("d").into()
+                },
+                vec![ImageColumn::Id],
+            )
+            .await
+            .unwrap();
+        assert_eq!(inserted.id, 100);
+        assert_eq!(repo.count(None).await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn soft_deleted_images_are_hidden_until_restored_or_purged() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo = ImageRepository::new(db).with_soft_delete(true);
+        let kept = repo.create_with_tags(dto("kept")).await.unwrap();
+        let deleted = repo.create_with_tags(dto("deleted")).await.unwrap();
+
+        repo.delete(deleted.id).await.unwrap();
+        assert_eq!(repo.count(None).await.unwrap(), 1);
+        assert_eq!(repo.list(None, None, None).await.unwrap().data, [kept]);
+        assert_eq!(repo.list_after(None, 10).await.unwrap().total, 1);
+        assert!(repo.get(deleted.id).await.unwrap().is_none());
+        assert_eq!(repo.list_deleted(None, None).await.unwrap().total, 1);
+
+        assert!(repo.restore(deleted.id).await.unwrap());
+        assert!(!repo.restore(deleted.id).await.unwrap());
+        assert!(repo.get(deleted.id).await.unwrap().is_some());
+
+        repo.delete(deleted.id).await.unwrap();
+        let yesterday = Utc::now() - chrono::TimeDelta::days(1);
+        assert!(repo.purge_deleted(yesterday).await.unwrap().is_empty());
+        let purged = repo
+            .purge_deleted(Utc::now() + chrono::TimeDelta::seconds(1))
+            .await
+            .unwrap();
+        assert_eq!(
+            purged.iter().map(|m| m.id).collect::<Vec<_>>(),
+            [deleted.id]
+        );
+        assert_eq!(repo.list_deleted(None, None).await.unwrap().total, 0);
+        assert!(!repo.restore(deleted.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn failed_delete_keeps_the_image_and_its_links() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo = ImageRepository::new(db.clone());
+        let mut tagged = dto("tagged");
+        tagged.tags = Some("one,two".to_string());
+        let tagged = repo.create_with_tags(tagged).await.unwrap();
+
+        // Fails after the links are already removed
+        db.execute_unprepared(
+            "CREATE TRIGGER fail_delete BEFORE DELETE ON images \
+             BEGIN SELECT RAISE(ABORT, 'failed'); END",
+        )
+        .await
+        .unwrap();
+        assert!(repo.delete_with_related(tagged.id).await.is_err());
+        let kept = repo.get_with_related(tagged.id).await.unwrap().unwrap();
+        assert_eq!(kept.related.len(), 2);
+
+        db.execute_unprepared("DROP TRIGGER fail_delete")
+            .await
+            .unwrap();
+        repo.delete_with_related(tagged.id).await.unwrap();
+        assert!(repo.get(tagged.id).await.unwrap().is_none());
+        assert_eq!(ImageTagEntity::find().count(&db).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn list_tags_returns_only_the_images_tags() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo = ImageRepository::new(db);
+        let mut tagged = dto("tagged");
+        tagged.tags = Some("one,two".to_string());
+        let tagged = repo.create_with_tags(tagged).await.unwrap();
+        let mut other = dto("other");
+        other.tags = Some("three".to_string());
+        repo.create_with_tags(other).await.unwrap();
+
+        let tags = repo.list_tags(tagged.id, None, None).await.unwrap();
+        assert_eq!(tags.total, 2);
+        let mut names = tags.data.into_iter().map(|t| t.name).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["one", "two"]);
+    }
+
+    #[tokio::test]
+    async fn delete_removes_rows_without_soft_delete() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo = ImageRepository::new(db);
+        let image = repo.create_with_tags(dto("gone")).await.unwrap();
+
+        repo.delete(image.id).await.unwrap();
+        assert_eq!(repo.count(None).await.unwrap(), 0);
+        assert_eq!(repo.list_deleted(None, None).await.unwrap().total, 0);
+    }
+
+    #[tokio::test]
+    async fn search_text_ands_terms_and_ranks_title_matches_first() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo = ImageRepository::new(db);
+        let add = |title: &str, description: &str, alt_text: &str| {
+            let mut model = dto(title);
+            model.description = Some(description.to_string());
+            model.alt_text = Some(alt_text.to_string());
+            repo.create_with_tags(model)
+        };
+        let alt = add("beach", "sunset", "red sky").await.unwrap();
+        let title = add("Red sky", "sunset", "").await.unwrap();
+        let description = add("evening", "a red sky at night", "").await.unwrap();
+        add("red car", "parked", "").await.unwrap();
+        add("100% sky", "", "").await.unwrap();
+
+        let found = repo.search_text("  RED   sky ", None).await.unwrap();
+        assert_eq!(found.total, 3);
+        assert_eq!(
+            found.data.iter().map(|m| m.id).collect::<Vec<_>>(),
+            [title.id, description.id, alt.id]
+        );
+
+        let found = repo.search_text("%", None).await.unwrap();
+        assert_eq!(found.total, 1);
+        assert!(repo.search_text("   ", None).await.is_err());
     }
 }
@@ -1,18 +1,26 @@
 use anyhow::Result;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
 use sea_orm::{
-    Condition, DatabaseConnection, DatabaseTransaction, EntityTrait, PrimaryKeyTrait, QueryFilter,
-    Select, SelectTwoMany,
+    ColumnTrait, Condition, DatabaseConnection, DatabaseTransaction, EntityTrait, Order,
+    PrimaryKeyTrait, QueryFilter, QueryOrder, Select, SelectTwoMany,
 };
 use serde::{Deserialize, Serialize};
 
 use super::entities::Merge;
 
+mod filter_builder;
+mod image_filter;
 mod image_repository;
 mod tag_repository;
+mod timing;
 
+pub use filter_builder::*;
+pub use image_filter::*;
 pub use image_repository::*;
 pub use tag_repository::*;
+pub use timing::*;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ModelWithRelated<M, R> {
@@ -20,12 +28,27 @@ pub struct ModelWithRelated<M, R> {
     pub related: Vec<R>,
 }
 
+/// Largest page size a client can request.
+pub const MAX_PAGE_SIZE: u64 = 100;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Pagination {
     pub page: u64,
     pub page_size: u64,
 }
 
+impl Pagination {
+    /// Makes client supplied values safe to use: pages start at 1 and
+    /// `page_size` is clamped to `1..=MAX_PAGE_SIZE`.
+    pub fn normalized(self) -> Self {
+        Self {
+            page: self.page.max(1),
+            page_size: self.page_size.clamp(1, MAX_PAGE_SIZE),
+        }
+    }
+}
+
 impl Default for Pagination {
     fn default() -> Self {
         Self {
@@ -35,11 +58,66 @@ impl Default for Pagination {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct OrderBy<C: ColumnTrait> {
+    pub column: C,
+    pub order: Order,
+}
+
+impl<C: ColumnTrait> OrderBy<C> {
+    pub fn asc(column: C) -> Self {
+        Self {
+            column,
+            order: Order::Asc,
+        }
+    }
+
+    pub fn desc(column: C) -> Self {
+        Self {
+            column,
+            order: Order::Desc,
+        }
+    }
+
+    /// Parses `column` or `-column` (descending). Only the given `columns` can be used
+    /// so clients can't sort on whatever they like.
+    pub fn parse(value: &str, columns: &[C]) -> std::result::Result<Self, String> {
+        let value = value.trim();
+        let (name, descending) = match value.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (value, false),
+        };
+        let column = columns
+            .iter()
+            .find(|c| c.as_str() == name)
+            .ok_or_else(|| format!("Cannot sort by '{name}'."))?;
+
+        if descending {
+            Ok(Self::desc(*column))
+        } else {
+            Ok(Self::asc(*column))
+        }
+    }
+
+    pub fn apply<Q: QueryOrder>(&self, query: Q) -> Q {
+        query.order_by(self.column, self.order.clone())
+    }
+}
+
+/// Position for keyset pagination: the id of the last item of the previous page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub after: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResultSet<T> {
     pub data: Vec<T>,
     pub total: u64,
     pub pagination: Option<Pagination>,
+    /// Set by `list_after` when there are more items to fetch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<Cursor>,
 }
 
 impl Default for ResultSet<()> {
@@ -48,6 +126,7 @@ impl Default for ResultSet<()> {
             data: vec![],
             total: 0,
             pagination: None,
+            next_cursor: None,
         }
     }
 }
@@ -144,6 +223,36 @@ pub trait IHasDatabase {
     async fn begin_transaction(&self) -> Result<DatabaseTransaction>;
 }
 
+/// Transaction helpers for anything with a database, repository trait objects included.
+#[async_trait]
+pub trait IHasDatabaseExt: IHasDatabase + Sync {
+    /// Runs `f` in a new transaction, committing it when `f` returns `Ok` and rolling it
+    /// back when it returns `Err`.
+    async fn with_transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        F: for<'t> FnOnce(&'t DatabaseTransaction) -> BoxFuture<'t, Result<T>> + Send,
+        T: Send,
+    {
+        let txn = self.begin_transaction().await?;
+
+        match f(&txn).await {
+            Ok(value) => {
+                txn.commit().await?;
+                Ok(value)
+            }
+            Err(e) => {
+                if let Err(rollback) = txn.rollback().await {
+                    tracing::error!("Failed to roll back transaction: {}", rollback);
+                }
+
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<D: IHasDatabase + Sync + ?Sized> IHasDatabaseExt for D {}
+
 #[async_trait]
 pub trait IRepository<E, U>: IHasDatabase
 where
@@ -153,24 +262,74 @@ where
     async fn list(
         &self,
         filter: Option<Box<dyn FilterCondition<E> + Send + Sync>>,
+        order_by: Option<OrderBy<<E as EntityTrait>::Column>>,
         pagination: Option<Pagination>,
     ) -> Result<ResultSet<<E as EntityTrait>::Model>>;
+    /// Keyset pagination: returns up to `limit` items with an id greater than the cursor,
+    /// in id order. Unlike `list` it doesn't scan the skipped rows, so deep pages stay fast
+    /// and don't shift when rows are inserted.
+    async fn list_after(
+        &self,
+        cursor: Option<Cursor>,
+        limit: u64,
+    ) -> Result<ResultSet<<E as EntityTrait>::Model>>;
     async fn count(&self, filter: Option<Box<dyn FilterCondition<E> + Send + Sync>>)
     -> Result<u64>;
+    /// Number of distinct non-null values of `column` among the rows matching the filter.
+    async fn count_distinct(
+        &self,
+        column: <E as EntityTrait>::Column,
+        filter: Option<Box<dyn FilterCondition<E> + Send + Sync>>,
+    ) -> Result<u64>;
     async fn get(
         &self,
         id: <<E as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType,
     ) -> Result<Option<<E as EntityTrait>::Model>>;
+    /// The first row matching the filter, or `None` when nothing matches. Fetches at most one row.
+    async fn find_one(
+        &self,
+        filter: Box<dyn FilterCondition<E> + Send + Sync>,
+    ) -> Result<Option<<E as EntityTrait>::Model>>;
     async fn create(&self, model: <E as EntityTrait>::Model) -> Result<<E as EntityTrait>::Model>;
+    /// Inserts all the models with a single statement. Nothing is inserted if any of them fails.
+    async fn create_many(
+        &self,
+        models: Vec<<E as EntityTrait>::Model>,
+    ) -> Result<Vec<<E as EntityTrait>::Model>>;
+    /// Inserts the model or, when it conflicts with an existing row on `conflict_columns`,
+    /// updates that row instead.
+    async fn upsert(
+        &self,
+        model: <E as EntityTrait>::Model,
+        conflict_columns: Vec<<E as EntityTrait>::Column>,
+    ) -> Result<<E as EntityTrait>::Model>;
     async fn update(
         &self,
         id: <<E as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType,
         model: U,
     ) -> Result<<E as EntityTrait>::Model>;
+    /// Removes the row, or only marks it as deleted when the repository soft deletes.
     async fn delete(
         &self,
         id: <<E as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType,
     ) -> Result<()>;
+    /// Whether `delete` only marks rows as deleted. Marked rows are left out of every other
+    /// query until they are restored or purged.
+    fn soft_deletes(&self) -> bool;
+    /// Soft deleted rows matching the filter, most recently deleted first.
+    async fn list_deleted(
+        &self,
+        filter: Option<Box<dyn FilterCondition<E> + Send + Sync>>,
+        pagination: Option<Pagination>,
+    ) -> Result<ResultSet<<E as EntityTrait>::Model>>;
+    /// Clears the deletion mark. Returns false if the row isn't soft deleted.
+    async fn restore(
+        &self,
+        id: <<E as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType,
+    ) -> Result<bool>;
+    /// Permanently removes the rows soft deleted before `before`, with their links, and
+    /// returns them.
+    async fn purge_deleted(&self, before: DateTime<Utc>) -> Result<Vec<<E as EntityTrait>::Model>>;
 }
 
 #[async_trait]
@@ -184,6 +343,7 @@ where
         &self,
         filter: Option<Box<dyn FilterCondition<E> + Send + Sync>>,
         filter_related: Option<Box<dyn FilterRelatedCondition<E, R> + Send + Sync>>,
+        order_by: Option<OrderBy<<E as EntityTrait>::Column>>,
         pagination: Option<Pagination>,
     ) -> Result<ResultSet<ModelWithRelated<<E as EntityTrait>::Model, <R as EntityTrait>::Model>>>;
     async fn get_with_related(
@@ -194,4 +354,87 @@ where
         &self,
         id: <<E as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType,
     ) -> Result<()>;
+    /// Deletes the row like `delete` in one transaction with its links. Soft deleted rows
+    /// keep their links so they can be restored.
+    async fn delete_with_related(
+        &self,
+        id: <<E as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType,
+    ) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pagination_is_normalized() {
+        let pagination = Pagination {
+            page: 0,
+            page_size: 10_000,
+        }
+        .normalized();
+        assert_eq!(pagination.page, 1);
+        assert_eq!(pagination.page_size, MAX_PAGE_SIZE);
+
+        let pagination: Pagination = serde_json::from_str(r#"{"page": 3}"#).unwrap();
+        assert_eq!(
+            pagination.normalized(),
+            Pagination {
+                page: 3,
+                page_size: 10
+            }
+        );
+    }
+
+    #[test]
+    fn order_by_parses_allowed_columns_only() {
+        use crate::db::entities::ImageColumn;
+        use sea_orm::IdenStatic;
+
+        let columns = [ImageColumn::Title, ImageColumn::CreatedAt];
+        let order_by = OrderBy::parse("-created_at", &columns).unwrap();
+        assert_eq!(order_by.column.as_str(), "created_at");
+        assert!(matches!(order_by.order, Order::Desc));
+
+        let order_by = OrderBy::parse("title", &columns).unwrap();
+        assert_eq!(order_by.column.as_str(), "title");
+        assert!(matches!(order_by.order, Order::Asc));
+
+        assert!(OrderBy::parse("file_size", &columns).is_err());
+        assert!(OrderBy::parse("-", &columns).is_err());
+    }
+
+    #[tokio::test]
+    async fn with_transaction_commits_on_ok_and_rolls_back_on_err() {
+        use migration::{Migrator, MigratorTrait};
+        use sea_orm::{ConnectionTrait, Database};
+
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let tags = TagRepository::new(db);
+        let seeded = tags.count(None).await.unwrap();
+
+        tags.with_transaction(|txn| {
+            Box::pin(async move {
+                txn.execute_unprepared("INSERT INTO tags (name) VALUES ('kept')")
+                    .await?;
+                Ok(())
+            })
+        })
+        .await
+        .unwrap();
+        assert_eq!(tags.count(None).await.unwrap(), seeded + 1);
+
+        let result: Result<()> = tags
+            .with_transaction(|txn| {
+                Box::pin(async move {
+                    txn.execute_unprepared("INSERT INTO tags (name) VALUES ('dropped')")
+                        .await?;
+                    Err(anyhow::anyhow!("failed after the insert"))
+                })
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(tags.count(None).await.unwrap(), seeded + 1);
+    }
 }
@@ -1,9 +1,12 @@
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use migration::OnConflict;
 use sea_orm::{
-    DatabaseTransaction, DeleteResult, PaginatorTrait, QuerySelect, QueryTrait, Set,
-    TransactionTrait, prelude::*,
+    Condition, DatabaseTransaction, DeleteResult, Iterable, JoinType, NotSet, PaginatorTrait,
+    QueryOrder, QuerySelect, QueryTrait, Select, Set, TransactionTrait,
+    prelude::*,
+    sea_query::{Func, SimpleExpr},
 };
 
 use crate::db::prelude::*;
@@ -17,24 +20,49 @@ pub trait ITagRepository: IRepositoryWithRelated<TagEntity, UpdateTagDto, ImageE
         filter_related: Option<
             Box<dyn FilterRelatedCondition<ImageEntity, TagEntity> + Send + Sync>,
         >,
+        order_by: Option<OrderBy<ImageColumn>>,
         pagination: Option<Pagination>,
     ) -> Result<ResultSet<ModelWithRelated<ImageModel, TagModel>>>;
     async fn add_image(&self, id: i64, related_id: i64) -> Result<ImageTagModel>;
     async fn remove_image(&self, id: i64, related_id: i64) -> Result<DeleteResult>;
     async fn add_images(&self, id: i64, images: Vec<i64>) -> Result<u64>;
     async fn remove_images(&self, id: i64, images: Vec<i64>) -> Result<u64>;
+    /// Every tag with the number of images using it, most used first, or only the first
+    /// `limit` of them.
+    async fn image_counts(&self, limit: Option<u64>) -> Result<Vec<(TagModel, u64)>>;
+    /// The tag named `name` in any case, soft deleted ones included since their names
+    /// are still taken.
+    async fn find_by_name(&self, name: &str) -> Result<Option<TagModel>>;
 }
 
+/// Columns clients can sort tags by with `?sort=`.
+pub const TAG_SORT_COLUMNS: &[TagColumn] = &[TagColumn::Id, TagColumn::Name];
+
 pub struct TagRepository {
     db: DatabaseConnection,
+    soft_delete: bool,
 }
 
 impl TagRepository {
     pub fn new(db: DatabaseConnection) -> Self {
-        Self { db }
+        Self {
+            db,
+            soft_delete: false,
+        }
+    }
+
+    /// Makes `delete` mark tags as deleted instead of removing them.
+    pub fn with_soft_delete(mut self, enabled: bool) -> Self {
+        self.soft_delete = enabled;
+        self
     }
 }
 
+/// Tags that aren't soft deleted.
+fn find_active() -> Select<TagEntity> {
+    TagEntity::find().filter(TagColumn::DeletedAt.is_null())
+}
+
 #[async_trait]
 impl IHasDatabase for TagRepository {
     fn database(&self) -> &DatabaseConnection {
@@ -51,9 +79,10 @@ impl IRepository<TagEntity, UpdateTagDto> for TagRepository {
     async fn list(
         &self,
         filter: Option<Box<dyn FilterCondition<TagEntity> + Send + Sync>>,
+        order_by: Option<OrderBy<TagColumn>>,
         pagination: Option<Pagination>,
     ) -> Result<ResultSet<TagModel>> {
-        let mut query = <TagEntity as EntityTrait>::find();
+        let mut query = find_active();
 
         if let Some(f) = &filter {
             query = f.apply(query);
@@ -61,6 +90,10 @@ impl IRepository<TagEntity, UpdateTagDto> for TagRepository {
 
         let total = query.clone().count(self.database()).await?;
 
+        if let Some(o) = &order_by {
+            query = o.apply(query).order_by_asc(TagColumn::Id);
+        }
+
         if let Some(p) = pagination {
             query = query.offset((p.page - 1) * p.page_size).limit(p.page_size);
         }
@@ -71,6 +104,37 @@ impl IRepository<TagEntity, UpdateTagDto> for TagRepository {
             data,
             total,
             pagination,
+            next_cursor: None,
+        })
+    }
+
+    async fn list_after(&self, cursor: Option<Cursor>, limit: u64) -> Result<ResultSet<TagModel>> {
+        let limit = limit.clamp(1, MAX_PAGE_SIZE);
+        let total = find_active().count(self.database()).await?;
+        let mut query = find_active();
+
+        if let Some(c) = cursor {
+            query = query.filter(TagColumn::Id.gt(c.after));
+        }
+
+        // Fetch one extra row to know whether there is a next page
+        let mut data = query
+            .order_by_asc(TagColumn::Id)
+            .limit(limit + 1)
+            .all(self.database())
+            .await?;
+        let next_cursor = if data.len() as u64 > limit {
+            data.truncate(limit as usize);
+            data.last().map(|m| Cursor { after: m.id })
+        } else {
+            None
+        };
+
+        Ok(ResultSet {
+            data,
+            total,
+            pagination: None,
+            next_cursor,
         })
     }
 
@@ -78,7 +142,7 @@ impl IRepository<TagEntity, UpdateTagDto> for TagRepository {
         &self,
         filter: Option<Box<dyn FilterCondition<TagEntity> + Send + Sync>>,
     ) -> Result<u64> {
-        let mut query = <TagEntity as EntityTrait>::find();
+        let mut query = find_active();
 
         if let Some(f) = &filter {
             query = f.apply(query);
@@ -87,23 +151,164 @@ impl IRepository<TagEntity, UpdateTagDto> for TagRepository {
         query.count(self.database()).await.map_err(Into::into)
     }
 
+    async fn count_distinct(
+        &self,
+        column: TagColumn,
+        filter: Option<Box<dyn FilterCondition<TagEntity> + Send + Sync>>,
+    ) -> Result<u64> {
+        let mut query = find_active();
+
+        if let Some(f) = &filter {
+            query = f.apply(query);
+        }
+
+        let count = query
+            .select_only()
+            .column_as(
+                SimpleExpr::from(Func::count_distinct(Expr::col((TagEntity, column)))),
+                "count",
+            )
+            .into_tuple::<i64>()
+            .one(self.database())
+            .await?
+            .unwrap_or(0);
+        Ok(count as u64)
+    }
+
     async fn get(&self, id: i64) -> Result<Option<TagModel>> {
-        TagEntity::find_by_id(id)
+        find_active()
+            .filter(TagColumn::Id.eq(id))
+            .one(self.database())
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn find_one(
+        &self,
+        filter: Box<dyn FilterCondition<TagEntity> + Send + Sync>,
+    ) -> Result<Option<TagModel>> {
+        filter
+            .apply(find_active())
             .one(self.database())
             .await
             .map_err(Into::into)
     }
 
     async fn create(&self, model: TagModel) -> Result<TagModel> {
-        let active_model: TagModelDto = model.into();
+        let existing = TagEntity::find()
+            .filter(TagColumn::Name.eq(normalize_tag_name(&model.name)))
+            .one(self.database())
+            .await?;
+
+        if let Some(mut existing) = existing {
+            // Creating a soft deleted tag again brings it back
+            if existing.deleted_at.is_some() {
+                self.restore(existing.id).await?;
+                existing.deleted_at = None;
+            }
+
+            return Ok(existing);
+        }
+
+        let active_model = TagModelDto {
+            id: NotSet,
+            name: Set(model.name),
+            deleted_at: NotSet,
+        };
         active_model
             .insert(self.database())
             .await
             .map_err(Into::into)
     }
 
+    async fn create_many(&self, models: Vec<TagModel>) -> Result<Vec<TagModel>> {
+        if models.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let txn = self.begin_transaction().await?;
+        // SQLite can't return the inserted rows, but the ids they get
+        // are all above the largest one before the insert.
+        let last_id = TagEntity::find()
+            .select_only()
+            .column_as(TagColumn::Id.max(), "id")
+            .into_tuple::<Option<i64>>()
+            .one(&txn)
+            .await?
+            .flatten()
+            .unwrap_or(0);
+        let mut active_models = Vec::with_capacity(models.len());
+
+        for model in models {
+            let mut active_model: TagModelDto = model.into();
+            active_model.id = NotSet;
+            // insert_many skips the entity hooks
+            active_models.push(active_model.before_save(&txn, true).await?);
+        }
+
+        TagEntity::insert_many(active_models)
+            .exec_without_returning(&txn)
+            .await?;
+        let created = TagEntity::find()
+            .filter(TagColumn::Id.gt(last_id))
+            .order_by_asc(TagColumn::Id)
+            .all(&txn)
+            .await?;
+        txn.commit().await?;
+        Ok(created)
+    }
+
+    async fn upsert(&self, model: TagModel, conflict_columns: Vec<TagColumn>) -> Result<TagModel> {
+        if conflict_columns.is_empty() {
+            return Err(anyhow!("Upsert requires at least one conflict column."));
+        }
+
+        let txn = self.begin_transaction().await?;
+        let is_conflict_column = |column: &TagColumn| {
+            conflict_columns
+                .iter()
+                .any(|c| c.as_str() == column.as_str())
+        };
+        let mut active_model: TagModelDto = model.into();
+
+        if !is_conflict_column(&TagColumn::Id) {
+            active_model.id = NotSet;
+        }
+
+        let active_model = active_model.before_save(&txn, true).await?;
+        let condition = conflict_columns
+            .iter()
+            .try_fold(Condition::all(), |condition, column| {
+                let value = active_model.get(*column).into_value()?;
+                Some(condition.add(column.eq(value)))
+            })
+            .ok_or_else(|| anyhow!("Upsert requires values for the conflict columns."))?;
+        let update_columns = TagColumn::iter()
+            .filter(|c| !matches!(c, TagColumn::Id) && !is_conflict_column(c))
+            .collect::<Vec<_>>();
+        let mut on_conflict = OnConflict::columns(conflict_columns.clone());
+
+        if update_columns.is_empty() {
+            on_conflict.do_nothing();
+        } else {
+            on_conflict.update_columns(update_columns);
+        }
+
+        TagEntity::insert(active_model)
+            .on_conflict(on_conflict)
+            .exec_without_returning(&txn)
+            .await?;
+        let model = TagEntity::find()
+            .filter(condition)
+            .one(&txn)
+            .await?
+            .ok_or_else(|| anyhow!("Tag not found after upsert."))?;
+        txn.commit().await?;
+        Ok(model)
+    }
     async fn update(&self, id: i64, model: UpdateTagDto) -> Result<TagModel> {
-        let existing = TagEntity::find_by_id(id)
+        let existing = find_active()
+            .filter(TagColumn::Id.eq(id))
             .one(&self.db)
             .await?
             .ok_or_else(|| sea_orm::DbErr::RecordNotFound("Tag not found".to_owned()))?;
@@ -116,12 +321,75 @@ impl IRepository<TagEntity, UpdateTagDto> for TagRepository {
     }
 
     async fn delete(&self, id: i64) -> Result<()> {
-        TagEntity::delete_by_id(id)
+        delete_tag(self.database(), id, self.soft_delete).await
+    }
+
+    fn soft_deletes(&self) -> bool {
+        self.soft_delete
+    }
+
+    async fn list_deleted(
+        &self,
+        filter: Option<Box<dyn FilterCondition<TagEntity> + Send + Sync>>,
+        pagination: Option<Pagination>,
+    ) -> Result<ResultSet<TagModel>> {
+        let mut query = TagEntity::find().filter(TagColumn::DeletedAt.is_not_null());
+
+        if let Some(f) = &filter {
+            query = f.apply(query);
+        }
+
+        let total = query.clone().count(self.database()).await?;
+        query = query
+            .order_by_desc(TagColumn::DeletedAt)
+            .order_by_asc(TagColumn::Id);
+
+        if let Some(p) = pagination {
+            query = query.offset((p.page - 1) * p.page_size).limit(p.page_size);
+        }
+
+        let data = query.all(self.database()).await?;
+
+        Ok(ResultSet {
+            data,
+            total,
+            pagination,
+            next_cursor: None,
+        })
+    }
+
+    async fn restore(&self, id: i64) -> Result<bool> {
+        let result = TagEntity::update_many()
+            .col_expr(TagColumn::DeletedAt, Expr::value(None::<DateTime<Utc>>))
+            .filter(TagColumn::Id.eq(id))
+            .filter(TagColumn::DeletedAt.is_not_null())
             .exec(self.database())
-            .await
-            .map_err(anyhow::Error::from)?;
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+
+    async fn purge_deleted(&self, before: DateTime<Utc>) -> Result<Vec<TagModel>> {
+        let txn = self.begin_transaction().await?;
+        let purged = TagEntity::find()
+            .filter(TagColumn::DeletedAt.lt(before))
+            .all(&txn)
+            .await?;
 
-        Ok(())
+        if purged.is_empty() {
+            return Ok(purged);
+        }
+
+        let ids = purged.iter().map(|m| m.id).collect::<Vec<_>>();
+        ImageTagEntity::delete_many()
+            .filter(ImageTagColumn::TagId.is_in(ids.clone()))
+            .exec(&txn)
+            .await?;
+        TagEntity::delete_many()
+            .filter(TagColumn::Id.is_in(ids))
+            .exec(&txn)
+            .await?;
+        txn.commit().await?;
+        Ok(purged)
     }
 }
 
@@ -133,9 +401,10 @@ impl IRepositoryWithRelated<TagEntity, UpdateTagDto, ImageEntity> for TagReposit
         filter_related: Option<
             Box<dyn FilterRelatedCondition<TagEntity, ImageEntity> + Send + Sync>,
         >,
+        order_by: Option<OrderBy<TagColumn>>,
         pagination: Option<Pagination>,
     ) -> Result<ResultSet<ModelWithRelated<TagModel, ImageModel>>> {
-        let mut query = <TagEntity as EntityTrait>::find();
+        let mut query = find_active();
 
         if let Some(f) = &filter {
             query = f.apply(query);
@@ -143,6 +412,14 @@ impl IRepositoryWithRelated<TagEntity, UpdateTagDto, ImageEntity> for TagReposit
 
         let count_query = query.clone();
         let total = count_query.count(self.database()).await?;
+
+        // The id breaks ties so pages don't overlap, and is the default order
+        if let Some(o) = &order_by {
+            query = o.apply(query);
+        }
+
+        let query = query.order_by_asc(TagColumn::Id);
+        let page_query = query.clone();
         let mut query = query.find_with_related(ImageEntity);
 
         if let Some(l) = &filter_related {
@@ -150,16 +427,29 @@ impl IRepositoryWithRelated<TagEntity, UpdateTagDto, ImageEntity> for TagReposit
         }
 
         if let Some(p) = pagination {
-            query = query.offset((p.page - 1) * p.page_size).limit(p.page_size);
+            // offset/limit on the joined query would count image rows, not tags,
+            // so select the ids of the tags on the page first.
+            let ids: Vec<i64> = page_query
+                .select_only()
+                .column(TagColumn::Id)
+                .offset((p.page - 1) * p.page_size)
+                .limit(p.page_size)
+                .into_tuple()
+                .all(self.database())
+                .await?;
+            query = query.filter(TagColumn::Id.is_in(ids));
         }
 
         let data = query
             .all(self.database())
             .await?
             .into_iter()
-            .map(|e| ModelWithRelated {
-                item: e.0,
-                related: e.1,
+            .map(|(tag, images)| ModelWithRelated {
+                item: tag,
+                related: images
+                    .into_iter()
+                    .filter(|i| i.deleted_at.is_none())
+                    .collect(),
             })
             .collect();
 
@@ -167,6 +457,7 @@ impl IRepositoryWithRelated<TagEntity, UpdateTagDto, ImageEntity> for TagReposit
             data,
             total,
             pagination,
+            next_cursor: None,
         })
     }
 
@@ -174,11 +465,16 @@ impl IRepositoryWithRelated<TagEntity, UpdateTagDto, ImageEntity> for TagReposit
         &self,
         id: i64,
     ) -> Result<Option<ModelWithRelated<TagModel, ImageModel>>> {
-        let tag = <TagEntity as EntityTrait>::find_by_id(id)
+        let tag = find_active()
+            .filter(TagColumn::Id.eq(id))
             .one(self.database())
             .await?;
         let Some(tag) = tag else { return Ok(None) };
-        let images = tag.find_related(ImageEntity).all(self.database()).await?;
+        let images = tag
+            .find_related(ImageEntity)
+            .filter(ImageColumn::DeletedAt.is_null())
+            .all(self.database())
+            .await?;
 
         Ok(Some(ModelWithRelated {
             item: tag,
@@ -187,16 +483,59 @@ impl IRepositoryWithRelated<TagEntity, UpdateTagDto, ImageEntity> for TagReposit
     }
 
     async fn delete_related(&self, id: i64) -> Result<()> {
-        ImageTagEntity::delete_many()
-            .filter(ImageTagColumn::TagId.eq(id))
-            .exec(self.database())
+        unlink_images(self.database(), id).await
+    }
+
+    async fn delete_with_related(&self, id: i64) -> Result<()> {
+        let soft_delete = self.soft_delete;
+        self.with_transaction(move |txn| {
+            Box::pin(async move {
+                if !soft_delete {
+                    unlink_images(txn, id).await?;
+                }
+
+                delete_tag(txn, id, soft_delete).await
+            })
+        })
+        .await
+    }
+}
+
+/// Deletes the tag on `db`, or only marks it as deleted when `soft_delete` is set.
+async fn delete_tag<C: ConnectionTrait>(db: &C, id: i64, soft_delete: bool) -> Result<()> {
+    if soft_delete {
+        TagEntity::update_many()
+            .col_expr(TagColumn::DeletedAt, Expr::value(Utc::now()))
+            .filter(TagColumn::Id.eq(id))
+            .filter(TagColumn::DeletedAt.is_null())
+            .exec(db)
             .await?;
-        Ok(())
+        return Ok(());
     }
+
+    TagEntity::delete_by_id(id).exec(db).await?;
+    Ok(())
+}
+
+/// Removes the links between the tag and its images on `db`.
+async fn unlink_images<C: ConnectionTrait>(db: &C, id: i64) -> Result<()> {
+    ImageTagEntity::delete_many()
+        .filter(ImageTagColumn::TagId.eq(id))
+        .exec(db)
+        .await?;
+    Ok(())
 }
 
 #[async_trait]
 impl ITagRepository for TagRepository {
+    async fn find_by_name(&self, name: &str) -> Result<Option<TagModel>> {
+        TagEntity::find()
+            .filter(TagColumn::Name.eq(normalize_tag_name(name)))
+            .one(self.database())
+            .await
+            .map_err(Into::into)
+    }
+
     async fn list_images(
         &self,
         id: i64,
@@ -204,6 +543,7 @@ impl ITagRepository for TagRepository {
         filter_related: Option<
             Box<dyn FilterRelatedCondition<ImageEntity, TagEntity> + Send + Sync>,
         >,
+        order_by: Option<OrderBy<ImageColumn>>,
         pagination: Option<Pagination>,
     ) -> Result<ResultSet<ModelWithRelated<ImageModel, TagModel>>> {
         let image_ids = <ImageTagEntity as EntityTrait>::find()
@@ -211,8 +551,9 @@ impl ITagRepository for TagRepository {
             .select_only()
             .column(ImageTagColumn::ImageId)
             .into_query();
-        let mut filter_query =
-            <ImageEntity as EntityTrait>::find().filter(ImageColumn::Id.in_subquery(image_ids));
+        let mut filter_query = <ImageEntity as EntityTrait>::find()
+            .filter(ImageColumn::Id.in_subquery(image_ids))
+            .filter(ImageColumn::DeletedAt.is_null());
 
         if let Some(f) = &filter {
             filter_query = f.apply(filter_query);
@@ -220,6 +561,14 @@ impl ITagRepository for TagRepository {
 
         let count_query = filter_query.clone();
         let total = count_query.count(self.database()).await?;
+
+        // The id breaks ties so pages don't overlap, and is the default order
+        if let Some(o) = &order_by {
+            filter_query = o.apply(filter_query);
+        }
+
+        let filter_query = filter_query.order_by_asc(ImageColumn::Id);
+        let page_query = filter_query.clone();
         let mut query = filter_query.find_with_related(TagEntity);
 
         if let Some(l) = &filter_related {
@@ -227,7 +576,17 @@ impl ITagRepository for TagRepository {
         }
 
         if let Some(p) = pagination {
-            query = query.offset((p.page - 1) * p.page_size).limit(p.page_size);
+            // offset/limit on the joined query would count tag rows, not images,
+            // so select the ids of the images on the page first.
+            let ids: Vec<i64> = page_query
+                .select_only()
+                .column(ImageColumn::Id)
+                .offset((p.page - 1) * p.page_size)
+                .limit(p.page_size)
+                .into_tuple()
+                .all(self.database())
+                .await?;
+            query = query.filter(ImageColumn::Id.is_in(ids));
         }
 
         let data = query
@@ -236,7 +595,10 @@ impl ITagRepository for TagRepository {
             .into_iter()
             .map(|(image_model, tag_models)| ModelWithRelated {
                 item: image_model,
-                related: tag_models,
+                related: tag_models
+                    .into_iter()
+                    .filter(|t| t.deleted_at.is_none())
+                    .collect(),
             })
             .collect();
 
@@ -244,6 +606,7 @@ impl ITagRepository for TagRepository {
             data,
             total,
             pagination,
+            next_cursor: None,
         })
     }
 
@@ -304,4 +667,401 @@ impl ITagRepository for TagRepository {
 
         Ok(result.rows_affected)
     }
+
+    async fn image_counts(&self, limit: Option<u64>) -> Result<Vec<(TagModel, u64)>> {
+        // Links to soft deleted images stay until they are purged, so only count the live ones
+        let count = Expr::col((ImageEntity, ImageColumn::Id)).count();
+        let rows = find_active()
+            .select_only()
+            .column(TagColumn::Id)
+            .column(TagColumn::Name)
+            .column_as(count.clone(), "count")
+            .join(JoinType::LeftJoin, TagRelation::ImageTag.def())
+            .join(
+                JoinType::LeftJoin,
+                ImageTagRelation::ImageEntity
+                    .def()
+                    .on_condition(|_, right| {
+                        Condition::all().add(Expr::col((right, ImageColumn::DeletedAt)).is_null())
+                    }),
+            )
+            .group_by(TagColumn::Id)
+            .group_by(TagColumn::Name)
+            .order_by_desc(count)
+            .order_by_asc(TagColumn::Name)
+            .limit(limit)
+            .into_tuple::<(i64, String, i64)>()
+            .all(self.database())
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(id, name, count)| {
+                let tag = TagModel {
+                    id,
+                    name,
+                    deleted_at: None,
+                };
+                (tag, count as u64)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::dto;
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::{ConnectionTrait, Database};
+
+    #[tokio::test]
+    async fn case_variants_collapse_into_one_tag() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let tags = TagRepository::new(db.clone());
+        let images = ImageRepository::new(db);
+        let seeded = tags.count(None).await.unwrap();
+
+        let rust = tags
+            .create(TagModel {
+                id: 0,
+                name: "Rust".to_string(),
+                deleted_at: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(rust.name, "rust");
+
+        for name in ["rust", " rust "] {
+            let tag = tags
+                .create(TagModel {
+                    id: 0,
+                    name: name.to_string(),
+                    deleted_at: None,
+                })
+                .await
+                .unwrap();
+            assert_eq!(tag.id, rust.id);
+        }
+
+        let image = images
+            .create_with_tags(CreateImageDto {
+                tags: Some("Rust, rust , RUST".to_string()),
+                ..dto("test")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(tags.count(None).await.unwrap(), seeded + 1);
+        let links = ImageTagEntity::find()
+            .filter(ImageTagColumn::ImageId.eq(image.id))
+            .all(images.database())
+            .await
+            .unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].tag_id, rust.id);
+    }
+
+    #[tokio::test]
+    async fn migration_merges_existing_duplicates() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        // Everything before the case-insensitive tags migration
+        Migrator::up(&db, Some(4)).await.unwrap();
+        db.execute_unprepared(
+            "INSERT INTO images (id, title, extension, file_size, mime_type, created_at, updated_at)
+             VALUES (1, 'a', 'png', 1, 'image/png', '2025-01-01T00:00:00Z', '2025-01-01T00:00:00Z'),
+                    (2, 'b', 'png', 1, 'image/png', '2025-01-01T00:00:00Z', '2025-01-01T00:00:00Z');
+             INSERT INTO tags (id, name) VALUES (101, 'Rust'), (102, 'rust'), (103, ' RUST ');
+             INSERT INTO image_tags (image_id, tag_id) VALUES (1, 101), (1, 102), (2, 103);",
+        )
+        .await
+        .unwrap();
+        Migrator::up(&db, None).await.unwrap();
+
+        let tags = TagEntity::find()
+            .filter(TagColumn::Id.gt(100))
+            .all(&db)
+            .await
+            .unwrap();
+        assert_eq!(
+            tags,
+            vec![TagModel {
+                id: 101,
+                name: "rust".to_string(),
+                deleted_at: None,
+            }]
+        );
+        let images = TagRepository::new(db)
+            .list_images(101, None, None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(images.total, 2);
+    }
+
+    #[tokio::test]
+    async fn create_many_and_upsert_tags() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo = TagRepository::new(db);
+        let tag = |name: &str| TagModel {
+            id: 0,
+            name: name.to_string(),
+            deleted_at: None,
+        };
+        let seeded = repo.count(None).await.unwrap();
+
+        let created = repo
+            .create_many(vec![tag("Alpha"), tag("beta")])
+            .await
+            .unwrap();
+        let names = created.iter().map(|m| m.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["alpha", "beta"]);
+
+        // "gamma" must not stay behind when "BETA" fails
+        assert!(
+            repo.create_many(vec![tag("gamma"), tag("BETA")])
+                .await
+                .is_err()
+        );
+        assert_eq!(repo.count(None).await.unwrap(), seeded + 2);
+
+        let upserted = repo
+            .upsert(tag(" Alpha "), vec![TagColumn::Name])
+            .await
+            .unwrap();
+        assert_eq!(upserted, created[0]);
+
+        let inserted = repo
+            .upsert(tag("delta"), vec![TagColumn::Name])
+            .await
+            .unwrap();
+        assert_eq!(inserted.name, "delta");
+        assert_eq!(repo.count(None).await.unwrap(), seeded + 3);
+    }
+
+    #[tokio::test]
+    async fn image_counts_and_count_distinct() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let tags = TagRepository::new(db.clone());
+        let images = ImageRepository::new(db);
+
+        for (title, mime_type, tag_names) in [
+            ("a", "image/png", "cats,dogs"),
+            ("b", "image/png", "cats"),
+            ("c", "image/jpeg", "cats,birds"),
+        ] {
+            images
+                .create_with_tags(CreateImageDto {
+                    mime_type: mime_type.to_string(),
+                    tags: Some(tag_names.to_string()),
+                    ..dto(title)
+                })
+                .await
+                .unwrap();
+        }
+
+        let counts = tags.image_counts(None).await.unwrap();
+        let named = counts
+            .iter()
+            .map(|(tag, count)| (tag.name.as_str(), *count))
+            .collect::<Vec<_>>();
+        assert_eq!(named[..3], [("cats", 3), ("birds", 1), ("dogs", 1)]);
+        // Unused tags are listed too
+        assert_eq!(counts.len() as u64, tags.count(None).await.unwrap());
+        assert!(counts[3..].iter().all(|(_, count)| *count == 0));
+        assert_eq!(tags.image_counts(Some(2)).await.unwrap(), counts[..2]);
+
+        assert_eq!(
+            images
+                .count_distinct(ImageColumn::MimeType, None)
+                .await
+                .unwrap(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_page_by_item_not_by_joined_row() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let tags = TagRepository::new(db.clone());
+        let images = ImageRepository::new(db);
+
+        for title in ["a", "b", "c"] {
+            images
+                .create_with_tags(CreateImageDto {
+                    // Sorted before the default tags
+                    tags: Some("a1,a2,a3".to_string()),
+                    ..dto(title)
+                })
+                .await
+                .unwrap();
+        }
+
+        let page = |page| Pagination { page, page_size: 2 };
+        let mut listed = vec![];
+
+        for n in 1..=2 {
+            let result = tags
+                .list_with_related(
+                    None,
+                    None,
+                    Some(OrderBy::asc(TagColumn::Name)),
+                    Some(page(n)),
+                )
+                .await
+                .unwrap();
+            listed.extend(
+                result
+                    .data
+                    .into_iter()
+                    .map(|m| (m.item.name, m.related.len())),
+            );
+        }
+
+        // The default tags follow, with no images
+        assert_eq!(
+            listed,
+            [
+                ("a1".to_string(), 3),
+                ("a2".to_string(), 3),
+                ("a3".to_string(), 3),
+                ("architecture".to_string(), 0),
+            ]
+        );
+
+        let a1 = tags
+            .find_one(Box::new(Condition::all().add(TagColumn::Name.eq("a1"))))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut listed = vec![];
+
+        for n in 1..=2 {
+            let result = tags
+                .list_images(a1.id, None, None, None, Some(page(n)))
+                .await
+                .unwrap();
+            assert_eq!(result.total, 3);
+            assert!(result.data.iter().all(|m| m.related.len() == 3));
+            listed.extend(result.data.into_iter().map(|m| m.item.title));
+        }
+
+        assert_eq!(listed, ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn find_one_matches_an_exact_name() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let tags = TagRepository::new(db);
+        let created = tags
+            .create(TagModel {
+                id: 0,
+                name: "ferris".to_string(),
+                deleted_at: None,
+            })
+            .await
+            .unwrap();
+
+        let found = tags
+            .find_one(Box::new(Condition::all().add(TagColumn::Name.eq("ferris"))))
+            .await
+            .unwrap();
+        assert_eq!(found, Some(created));
+
+        let missing = tags
+            .find_one(Box::new(Condition::all().add(TagColumn::Name.eq("ferr"))))
+            .await
+            .unwrap();
+        assert_eq!(missing, None);
+    }
+
+    #[tokio::test]
+    async fn soft_deleted_tags_are_hidden_and_revived_by_name() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let tags = TagRepository::new(db.clone()).with_soft_delete(true);
+        let images = ImageRepository::new(db).with_soft_delete(true);
+        let image = images
+            .create_with_tags(CreateImageDto {
+                tags: Some("alpha,beta".to_string()),
+                ..dto("test")
+            })
+            .await
+            .unwrap();
+        let alpha = tags
+            .find_one(Box::new(Condition::all().add(TagColumn::Name.eq("alpha"))))
+            .await
+            .unwrap()
+            .unwrap();
+
+        tags.delete(alpha.id).await.unwrap();
+        assert!(tags.get(alpha.id).await.unwrap().is_none());
+        let related = images.get_with_related(image.id).await.unwrap().unwrap();
+        assert_eq!(
+            related
+                .related
+                .iter()
+                .map(|t| t.name.as_str())
+                .collect::<Vec<_>>(),
+            ["beta"]
+        );
+        let counts = tags.image_counts(None).await.unwrap();
+        assert!(counts.iter().all(|(t, _)| t.id != alpha.id));
+
+        // Soft deleted images aren't counted either
+        images.delete(image.id).await.unwrap();
+        let counts = tags.image_counts(None).await.unwrap();
+        assert!(counts.iter().all(|(_, count)| *count == 0));
+
+        let revived = tags
+            .create(TagModel {
+                id: 0,
+                name: "Alpha".to_string(),
+                deleted_at: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(revived.id, alpha.id);
+        assert!(tags.get(alpha.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn blank_names_leave_the_tag_unchanged() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let tags = TagRepository::new(db);
+        let tag = tags
+            .create(TagModel {
+                id: 0,
+                name: "Rust".to_string(),
+                deleted_at: None,
+            })
+            .await
+            .unwrap();
+
+        for name in [None, Some(""), Some("  ")] {
+            let name = name.map(str::to_string);
+            let updated = tags.update(tag.id, UpdateTagDto { name }).await.unwrap();
+            assert_eq!(updated, tag);
+        }
+
+        let renamed = tags
+            .update(
+                tag.id,
+                UpdateTagDto {
+                    name: Some(" Ferris ".to_string()),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(renamed.name, "ferris");
+        assert_eq!(
+            tags.find_by_name("FERRIS").await.unwrap().map(|t| t.id),
+            Some(tag.id)
+        );
+    }
 }
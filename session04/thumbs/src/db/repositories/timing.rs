@@ -0,0 +1,512 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    DatabaseConnection, DatabaseTransaction, DeleteResult, EntityTrait, PrimaryKeyTrait,
+};
+use std::{future::Future, time::Instant};
+use tracing::{Instrument, field};
+
+use super::*;
+use crate::db::entities::*;
+
+/// Rows a repository call returned or affected, reported by [`timed`]. Counts report the
+/// counted total.
+pub trait RowCount {
+    fn row_count(&self) -> u64;
+}
+
+impl<T> RowCount for Vec<T> {
+    fn row_count(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+impl<T> RowCount for ResultSet<T> {
+    fn row_count(&self) -> u64 {
+        self.data.len() as u64
+    }
+}
+
+impl<T> RowCount for Option<T> {
+    fn row_count(&self) -> u64 {
+        self.is_some() as u64
+    }
+}
+
+impl<M, R> RowCount for ModelWithRelated<M, R> {
+    fn row_count(&self) -> u64 {
+        1 + self.related.len() as u64
+    }
+}
+
+macro_rules! single_row {
+    ($($model:ty),*) => {
+        $(
+            impl RowCount for $model {
+                fn row_count(&self) -> u64 {
+                    1
+                }
+            }
+        )*
+    };
+}
+
+single_row!(ImageModel, TagModel, ImageTagModel, ImageTotals);
+
+impl RowCount for DeleteResult {
+    fn row_count(&self) -> u64 {
+        self.rows_affected
+    }
+}
+
+impl RowCount for u64 {
+    fn row_count(&self) -> u64 {
+        *self
+    }
+}
+
+impl RowCount for bool {
+    fn row_count(&self) -> u64 {
+        *self as u64
+    }
+}
+
+impl RowCount for () {
+    fn row_count(&self) -> u64 {
+        0
+    }
+}
+
+/// Runs the repository `operation` on `entity` inside a debug level `db` span and logs its
+/// elapsed time and row count when it finishes.
+pub async fn timed<T, F>(entity: &'static str, operation: &'static str, f: F) -> Result<T>
+where
+    T: RowCount,
+    F: Future<Output = Result<T>>,
+{
+    let span = tracing::debug_span!(
+        "db",
+        entity,
+        operation,
+        rows = field::Empty,
+        elapsed_ms = field::Empty
+    );
+    let start = Instant::now();
+    let result = f.instrument(span.clone()).await;
+    span.record("elapsed_ms", start.elapsed().as_secs_f64() * 1000.0);
+    let _entered = span.enter();
+
+    match &result {
+        Ok(value) => {
+            span.record("rows", value.row_count());
+            tracing::debug!("query finished");
+        }
+        Err(e) => tracing::debug!(error = %e, "query failed"),
+    }
+
+    result
+}
+
+type Id<E> = <<E as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType;
+
+/// Decorates a repository, running every call through [`timed`]. Repositories are only
+/// wrapped in it when `LOG_DB_TIMINGS` is on, so the others pay nothing for the timings.
+pub struct Timed<R> {
+    entity: &'static str,
+    inner: R,
+}
+
+impl<R> Timed<R> {
+    /// `entity` names the table in the spans, e.g. `images`.
+    pub fn new(entity: &'static str, inner: R) -> Self {
+        Self { entity, inner }
+    }
+}
+
+#[async_trait]
+impl<R: IHasDatabase + Send + Sync> IHasDatabase for Timed<R> {
+    fn database(&self) -> &DatabaseConnection {
+        self.inner.database()
+    }
+
+    async fn begin_transaction(&self) -> Result<DatabaseTransaction> {
+        self.inner.begin_transaction().await
+    }
+}
+
+#[async_trait]
+impl<E, U, R> IRepository<E, U> for Timed<R>
+where
+    E: EntityTrait + Send + Sync,
+    E::Model: RowCount,
+    U: Merge<<E as EntityTrait>::ActiveModel> + Send + Sync + 'static,
+    R: IRepository<E, U> + Send + Sync,
+{
+    async fn list(
+        &self,
+        filter: Option<Box<dyn FilterCondition<E> + Send + Sync>>,
+        order_by: Option<OrderBy<<E as EntityTrait>::Column>>,
+        pagination: Option<Pagination>,
+    ) -> Result<ResultSet<<E as EntityTrait>::Model>> {
+        let f = self.inner.list(filter, order_by, pagination);
+        timed(self.entity, "list", f).await
+    }
+
+    async fn list_after(
+        &self,
+        cursor: Option<Cursor>,
+        limit: u64,
+    ) -> Result<ResultSet<<E as EntityTrait>::Model>> {
+        timed(
+            self.entity,
+            "list_after",
+            self.inner.list_after(cursor, limit),
+        )
+        .await
+    }
+
+    async fn count(
+        &self,
+        filter: Option<Box<dyn FilterCondition<E> + Send + Sync>>,
+    ) -> Result<u64> {
+        timed(self.entity, "count", self.inner.count(filter)).await
+    }
+
+    async fn count_distinct(
+        &self,
+        column: <E as EntityTrait>::Column,
+        filter: Option<Box<dyn FilterCondition<E> + Send + Sync>>,
+    ) -> Result<u64> {
+        let f = self.inner.count_distinct(column, filter);
+        timed(self.entity, "count_distinct", f).await
+    }
+
+    async fn get(&self, id: Id<E>) -> Result<Option<<E as EntityTrait>::Model>> {
+        timed(self.entity, "get", self.inner.get(id)).await
+    }
+
+    async fn find_one(
+        &self,
+        filter: Box<dyn FilterCondition<E> + Send + Sync>,
+    ) -> Result<Option<<E as EntityTrait>::Model>> {
+        timed(self.entity, "find_one", self.inner.find_one(filter)).await
+    }
+
+    async fn create(&self, model: <E as EntityTrait>::Model) -> Result<<E as EntityTrait>::Model> {
+        timed(self.entity, "create", self.inner.create(model)).await
+    }
+
+    async fn create_many(
+        &self,
+        models: Vec<<E as EntityTrait>::Model>,
+    ) -> Result<Vec<<E as EntityTrait>::Model>> {
+        timed(self.entity, "create_many", self.inner.create_many(models)).await
+    }
+
+    async fn upsert(
+        &self,
+        model: <E as EntityTrait>::Model,
+        conflict_columns: Vec<<E as EntityTrait>::Column>,
+    ) -> Result<<E as EntityTrait>::Model> {
+        let f = self.inner.upsert(model, conflict_columns);
+        timed(self.entity, "upsert", f).await
+    }
+
+    async fn update(&self, id: Id<E>, model: U) -> Result<<E as EntityTrait>::Model> {
+        timed(self.entity, "update", self.inner.update(id, model)).await
+    }
+
+    async fn delete(&self, id: Id<E>) -> Result<()> {
+        timed(self.entity, "delete", self.inner.delete(id)).await
+    }
+
+    fn soft_deletes(&self) -> bool {
+        self.inner.soft_deletes()
+    }
+
+    async fn list_deleted(
+        &self,
+        filter: Option<Box<dyn FilterCondition<E> + Send + Sync>>,
+        pagination: Option<Pagination>,
+    ) -> Result<ResultSet<<E as EntityTrait>::Model>> {
+        let f = self.inner.list_deleted(filter, pagination);
+        timed(self.entity, "list_deleted", f).await
+    }
+
+    async fn restore(&self, id: Id<E>) -> Result<bool> {
+        timed(self.entity, "restore", self.inner.restore(id)).await
+    }
+
+    async fn purge_deleted(&self, before: DateTime<Utc>) -> Result<Vec<<E as EntityTrait>::Model>> {
+        timed(
+            self.entity,
+            "purge_deleted",
+            self.inner.purge_deleted(before),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl<E, U, Rel, R> IRepositoryWithRelated<E, U, Rel> for Timed<R>
+where
+    E: EntityTrait + Send + Sync,
+    E::Model: RowCount,
+    U: Merge<<E as EntityTrait>::ActiveModel> + Send + Sync + 'static,
+    Rel: EntityTrait + Send + Sync,
+    R: IRepositoryWithRelated<E, U, Rel> + Send + Sync,
+{
+    async fn list_with_related(
+        &self,
+        filter: Option<Box<dyn FilterCondition<E> + Send + Sync>>,
+        filter_related: Option<Box<dyn FilterRelatedCondition<E, Rel> + Send + Sync>>,
+        order_by: Option<OrderBy<<E as EntityTrait>::Column>>,
+        pagination: Option<Pagination>,
+    ) -> Result<ResultSet<ModelWithRelated<<E as EntityTrait>::Model, <Rel as EntityTrait>::Model>>>
+    {
+        let f = self
+            .inner
+            .list_with_related(filter, filter_related, order_by, pagination);
+        timed(self.entity, "list_with_related", f).await
+    }
+
+    async fn get_with_related(
+        &self,
+        id: Id<E>,
+    ) -> Result<Option<ModelWithRelated<<E as EntityTrait>::Model, <Rel as EntityTrait>::Model>>>
+    {
+        timed(
+            self.entity,
+            "get_with_related",
+            self.inner.get_with_related(id),
+        )
+        .await
+    }
+
+    async fn delete_related(&self, id: Id<E>) -> Result<()> {
+        timed(self.entity, "delete_related", self.inner.delete_related(id)).await
+    }
+
+    async fn delete_with_related(&self, id: Id<E>) -> Result<()> {
+        timed(
+            self.entity,
+            "delete_with_related",
+            self.inner.delete_with_related(id),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl<R: IImageRepository + Send + Sync> IImageRepository for Timed<R> {
+    async fn create_with_tags(&self, model: CreateImageDto) -> Result<ImageModel> {
+        timed(
+            self.entity,
+            "create_with_tags",
+            self.inner.create_with_tags(model),
+        )
+        .await
+    }
+
+    async fn list_tags(
+        &self,
+        id: i64,
+        filter: Option<Box<dyn FilterCondition<TagEntity> + Send + Sync>>,
+        pagination: Option<Pagination>,
+    ) -> Result<ResultSet<TagModel>> {
+        let f = self.inner.list_tags(id, filter, pagination);
+        timed(self.entity, "list_tags", f).await
+    }
+
+    async fn add_tag(&self, id: i64, related_id: i64) -> Result<()> {
+        timed(self.entity, "add_tag", self.inner.add_tag(id, related_id)).await
+    }
+
+    async fn remove_tag(&self, id: i64, related_id: i64) -> Result<DeleteResult> {
+        timed(
+            self.entity,
+            "remove_tag",
+            self.inner.remove_tag(id, related_id),
+        )
+        .await
+    }
+
+    async fn add_tags(&self, id: i64, tags: Vec<i64>) -> Result<u64> {
+        timed(self.entity, "add_tags", self.inner.add_tags(id, tags)).await
+    }
+
+    async fn remove_tags(&self, id: i64, tags: Vec<i64>) -> Result<u64> {
+        timed(self.entity, "remove_tags", self.inner.remove_tags(id, tags)).await
+    }
+
+    async fn add_tags_from_str(&self, id: i64, tags: &str) -> Result<u64> {
+        let f = self.inner.add_tags_from_str(id, tags);
+        timed(self.entity, "add_tags_from_str", f).await
+    }
+
+    async fn add_tag_names(&self, id: i64, names: &[String]) -> Result<u64> {
+        timed(
+            self.entity,
+            "add_tag_names",
+            self.inner.add_tag_names(id, names),
+        )
+        .await
+    }
+
+    async fn find_by_phash_within(&self, hash: i64, distance: u32) -> Result<Option<ImageModel>> {
+        let f = self.inner.find_by_phash_within(hash, distance);
+        timed(self.entity, "find_by_phash_within", f).await
+    }
+
+    async fn search_text(
+        &self,
+        query: &str,
+        pagination: Option<Pagination>,
+    ) -> Result<ResultSet<ImageModel>> {
+        timed(
+            self.entity,
+            "search_text",
+            self.inner.search_text(query, pagination),
+        )
+        .await
+    }
+
+    async fn set_thumbnail_ready(&self, id: i64, ready: bool) -> Result<bool> {
+        let f = self.inner.set_thumbnail_ready(id, ready);
+        timed(self.entity, "set_thumbnail_ready", f).await
+    }
+
+    async fn set_thumbnail_failed(&self, id: i64) -> Result<()> {
+        let f = self.inner.set_thumbnail_failed(id);
+        timed(self.entity, "set_thumbnail_failed", f).await
+    }
+
+    async fn count_content_refs(&self, hash: &str) -> Result<u64> {
+        let f = self.inner.count_content_refs(hash);
+        timed(self.entity, "count_content_refs", f).await
+    }
+
+    async fn totals(&self) -> Result<ImageTotals> {
+        timed(self.entity, "totals", self.inner.totals()).await
+    }
+}
+
+#[async_trait]
+impl<R: ITagRepository + Send + Sync> ITagRepository for Timed<R> {
+    async fn list_images(
+        &self,
+        id: i64,
+        filter: Option<Box<dyn FilterCondition<ImageEntity> + Send + Sync>>,
+        filter_related: Option<
+            Box<dyn FilterRelatedCondition<ImageEntity, TagEntity> + Send + Sync>,
+        >,
+        order_by: Option<OrderBy<ImageColumn>>,
+        pagination: Option<Pagination>,
+    ) -> Result<ResultSet<ModelWithRelated<ImageModel, TagModel>>> {
+        let f = self
+            .inner
+            .list_images(id, filter, filter_related, order_by, pagination);
+        timed(self.entity, "list_images", f).await
+    }
+
+    async fn add_image(&self, id: i64, related_id: i64) -> Result<ImageTagModel> {
+        timed(
+            self.entity,
+            "add_image",
+            self.inner.add_image(id, related_id),
+        )
+        .await
+    }
+
+    async fn remove_image(&self, id: i64, related_id: i64) -> Result<DeleteResult> {
+        timed(
+            self.entity,
+            "remove_image",
+            self.inner.remove_image(id, related_id),
+        )
+        .await
+    }
+
+    async fn add_images(&self, id: i64, images: Vec<i64>) -> Result<u64> {
+        timed(self.entity, "add_images", self.inner.add_images(id, images)).await
+    }
+
+    async fn remove_images(&self, id: i64, images: Vec<i64>) -> Result<u64> {
+        timed(
+            self.entity,
+            "remove_images",
+            self.inner.remove_images(id, images),
+        )
+        .await
+    }
+
+    async fn image_counts(&self, limit: Option<u64>) -> Result<Vec<(TagModel, u64)>> {
+        timed(self.entity, "image_counts", self.inner.image_counts(limit)).await
+    }
+
+    async fn find_by_name(&self, name: &str) -> Result<Option<TagModel>> {
+        timed(self.entity, "find_by_name", self.inner.find_by_name(name)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::dto;
+    use anyhow::anyhow;
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::Database;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn result_is_passed_through() {
+        let rows = timed("images", "list", async { Ok(vec![1, 2, 3]) })
+            .await
+            .unwrap();
+        assert_eq!(rows.row_count(), 3);
+
+        let error = timed::<u64, _>("images", "count", async { Err(anyhow!("no such table")) })
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "no such table");
+    }
+
+    #[tokio::test]
+    async fn timed_repositories_delegate_every_call() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let tags: Arc<dyn ITagRepository + Send + Sync> = Arc::new(Timed::new(
+            "tags",
+            TagRepository::new(db.clone()).with_soft_delete(true),
+        ));
+        let tag = tags
+            .create(TagModel {
+                id: 0,
+                name: "timed".to_string(),
+                deleted_at: None,
+            })
+            .await
+            .unwrap();
+
+        assert!(tags.soft_deletes());
+        assert_eq!(tags.find_by_name("TIMED").await.unwrap(), Some(tag.clone()));
+        tags.delete(tag.id).await.unwrap();
+        assert!(tags.restore(tag.id).await.unwrap());
+
+        // Both are timed under their own name, add_tags_from_str calls the inner add_tag_names
+        let images: Arc<dyn IImageRepository + Send + Sync> =
+            Arc::new(Timed::new("images", ImageRepository::new(db)));
+        let image = images.create_with_tags(dto("timed")).await.unwrap();
+        assert_eq!(
+            images.add_tags_from_str(image.id, "a,timed").await.unwrap(),
+            2
+        );
+        let names = ["b".to_string(), "A".to_string()];
+        assert_eq!(images.add_tag_names(image.id, &names).await.unwrap(), 1);
+        assert_eq!(
+            images.list_tags(image.id, None, None).await.unwrap().total,
+            3
+        );
+    }
+}
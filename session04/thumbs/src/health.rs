@@ -0,0 +1,138 @@
+use axum::{Extension, Json, Router, http::StatusCode, routing::get};
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use util::web::health;
+
+use crate::api_error::ApiError;
+
+/// The shared probes and metrics, with `/readyz` pinging the database.
+pub fn routes() -> Router {
+    health::routes(|db: DatabaseConnection| async move { db.ping().await })
+}
+
+/// Diagnostics that leak internals, only mounted when `DEBUG_ENDPOINTS` is on.
+pub fn debug_routes() -> Router {
+    Router::new().route("/debug/db-pool", get(db_pool))
+}
+
+/// How long [`db_pool`] waits for a connection before reporting the pool as starved.
+const ACQUIRE_PROBE_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Connection pool health, read before probing it. sqlx doesn't keep acquire statistics,
+/// so `acquire_wait_ms` is how long this request then waited for a connection. It is
+/// missing when none came within 250 ms, so a starved pool doesn't block the report.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PoolStats {
+    pub connections: u32,
+    pub idle: usize,
+    pub in_use: u32,
+    pub min_connections: u32,
+    pub max_connections: u32,
+    pub acquire_timeout_ms: u128,
+    pub acquire_wait_ms: Option<f64>,
+}
+
+async fn db_pool(
+    Extension(db): Extension<DatabaseConnection>,
+) -> Result<Json<PoolStats>, ApiError> {
+    let pool = db.get_sqlite_connection_pool();
+    let connections = pool.size();
+    let idle = pool.num_idle();
+    let start = Instant::now();
+    let acquire_wait_ms = match tokio::time::timeout(ACQUIRE_PROBE_TIMEOUT, pool.acquire()).await {
+        Ok(Ok(_connection)) => Some(start.elapsed().as_secs_f64() * 1000.0),
+        Ok(Err(e)) => {
+            return Err(ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("No connection available: {e}"),
+            ));
+        }
+        Err(_) => None,
+    };
+
+    let options = pool.options();
+    Ok(Json(PoolStats {
+        connections,
+        idle,
+        in_use: connections.saturating_sub(idle as u32),
+        min_connections: options.get_min_connections(),
+        max_connections: options.get_max_connections(),
+        acquire_timeout_ms: options.get_acquire_timeout().as_millis(),
+        acquire_wait_ms,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use sea_orm::Database;
+    use tower::ServiceExt;
+
+    async fn status(db: &DatabaseConnection, uri: &str) -> StatusCode {
+        routes()
+            .layer(Extension(db.clone()))
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn readyz_reports_a_closed_pool() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        assert_eq!(status(&db, "/healthz").await, StatusCode::OK);
+        assert_eq!(status(&db, "/readyz").await, StatusCode::OK);
+
+        db.clone().close().await.unwrap();
+        assert_eq!(status(&db, "/healthz").await, StatusCode::OK);
+        assert_eq!(
+            status(&db, "/readyz").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    async fn pool_stats(db: &DatabaseConnection) -> PoolStats {
+        let response = debug_routes()
+            .layer(Extension(db.clone()))
+            .oneshot(Request::get("/debug/db-pool").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn db_pool_reports_the_pool() {
+        let mut options = sea_orm::ConnectOptions::new("sqlite::memory:");
+        options.max_connections(3).min_connections(1);
+        let db = Database::connect(options).await.unwrap();
+        let stats = pool_stats(&db).await;
+        assert_eq!(stats.max_connections, 3);
+        assert_eq!(stats.min_connections, 1);
+        assert!(stats.connections >= 1);
+        assert_eq!(stats.in_use, 0);
+        assert!(stats.acquire_wait_ms.is_some());
+    }
+
+    #[tokio::test]
+    async fn db_pool_reports_a_starved_pool_without_waiting_it_out() {
+        let mut options = sea_orm::ConnectOptions::new("sqlite::memory:");
+        options
+            .max_connections(1)
+            .acquire_timeout(Duration::from_secs(30));
+        let db = Database::connect(options).await.unwrap();
+        let _held = db.get_sqlite_connection_pool().acquire().await.unwrap();
+
+        let start = Instant::now();
+        let stats = pool_stats(&db).await;
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(stats.in_use, 1);
+        assert_eq!(stats.acquire_wait_ms, None);
+    }
+}
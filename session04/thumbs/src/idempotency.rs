@@ -0,0 +1,167 @@
+use axum::http::HeaderMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::api_error::ApiError;
+
+/// Header a client sets to make `POST /images` safe to retry.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Seconds a key is remembered when `IDEMPOTENCY_TTL_SECS` is not set.
+pub const DEFAULT_IDEMPOTENCY_TTL_SECS: u64 = 86_400;
+/// Longest key accepted.
+const MAX_KEY_LENGTH: usize = 255;
+/// Expired keys are dropped once this many are tracked.
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+#[derive(Debug, Clone)]
+enum State {
+    /// The first request with the key hasn't finished yet.
+    Pending,
+    /// The images the first request created.
+    Done(Vec<i64>),
+}
+
+/// What [`IdempotencyKeys::claim`] found for a key.
+#[derive(Debug)]
+pub enum Claim {
+    /// The key is new and now pending until the request completes it.
+    New(PendingKey),
+    /// Another request with the key is still running.
+    InProgress,
+    /// The key was used before and created these images.
+    Done(Vec<i64>),
+}
+
+/// Remembers which images each `Idempotency-Key` created for `ttl`, so a retried upload
+/// returns the images of the first attempt. Keys are kept in memory and forgotten on
+/// restart. A ttl of 0 disables it.
+#[derive(Debug, Clone)]
+pub struct IdempotencyKeys {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, (State, Instant)>>>,
+}
+
+impl IdempotencyKeys {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Arc::default(),
+        }
+    }
+
+    /// Claims `key` for this request unless it is pending or done within the ttl.
+    pub fn claim(&self, key: &str) -> Claim {
+        self.claim_at(key, Instant::now())
+    }
+
+    fn claim_at(&self, key: &str, now: Instant) -> Claim {
+        let pending = || {
+            Claim::New(PendingKey {
+                keys: self.clone(),
+                key: key.to_string(),
+            })
+        };
+
+        if self.ttl.is_zero() {
+            return pending();
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= MAX_TRACKED_KEYS {
+            entries.retain(|_, (_, created)| now.saturating_duration_since(*created) < self.ttl);
+        }
+
+        match entries.get(key) {
+            Some((state, created)) if now.saturating_duration_since(*created) < self.ttl => {
+                match state {
+                    State::Pending => Claim::InProgress,
+                    State::Done(ids) => Claim::Done(ids.clone()),
+                }
+            }
+            _ => {
+                entries.insert(key.to_string(), (State::Pending, now));
+                pending()
+            }
+        }
+    }
+}
+
+/// A claimed key. Unless it is completed, dropping it forgets the key so a failed or
+/// abandoned request can be retried.
+#[derive(Debug)]
+pub struct PendingKey {
+    keys: IdempotencyKeys,
+    key: String,
+}
+
+impl PendingKey {
+    /// Records the images the request created.
+    pub fn complete(self, ids: Vec<i64>) {
+        if let Some((state, _)) = self.keys.entries.lock().unwrap().get_mut(&self.key) {
+            *state = State::Done(ids);
+        }
+    }
+}
+
+impl Drop for PendingKey {
+    fn drop(&mut self) {
+        let mut entries = self.keys.entries.lock().unwrap();
+
+        if matches!(entries.get(&self.key), Some((State::Pending, _))) {
+            entries.remove(&self.key);
+        }
+    }
+}
+
+/// The request's `Idempotency-Key`, if it sent one.
+pub fn key_from_headers(headers: &HeaderMap) -> Result<Option<String>, ApiError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+    let key = value
+        .to_str()
+        .map(str::trim)
+        .map_err(|_| ApiError::bad_request("Idempotency-Key must be visible ASCII"))?;
+
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        return Err(ApiError::bad_request(format!(
+            "Idempotency-Key must be 1 to {MAX_KEY_LENGTH} characters"
+        )));
+    }
+
+    Ok(Some(key.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_remembered_until_they_expire() {
+        let keys = IdempotencyKeys::new(Duration::from_secs(60));
+        let now = Instant::now();
+
+        let Claim::New(pending) = keys.claim_at("a", now) else {
+            panic!("a is new");
+        };
+        assert!(matches!(keys.claim_at("a", now), Claim::InProgress));
+        pending.complete(vec![1, 2]);
+        assert!(matches!(keys.claim_at("a", now), Claim::Done(ids) if ids == [1, 2]));
+        assert!(matches!(
+            keys.claim_at("a", now + Duration::from_secs(60)),
+            Claim::New(_)
+        ));
+
+        // Dropping an uncompleted claim lets the key be retried
+        drop(keys.claim_at("b", now));
+        assert!(matches!(keys.claim_at("b", now), Claim::New(_)));
+
+        let disabled = IdempotencyKeys::new(Duration::ZERO);
+        let _first = disabled.claim_at("a", now);
+        assert!(matches!(disabled.claim_at("a", now), Claim::New(_)));
+    }
+}
@@ -0,0 +1,645 @@
+use ::image::{
+    DynamicImage, ImageFormat, ImageReader, Limits, imageops::FilterType, metadata::Orientation,
+};
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use exif::{In, Tag, Value};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Seek},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Default longest side for stored originals when `MAX_IMAGE_DIMENSION` is not set.
+pub const DEFAULT_MAX_IMAGE_DIMENSION: u32 = 4096;
+/// Images with a side larger than this are rejected before decoding.
+pub const IMAGE_DIMENSION_LIMIT: u32 = 30_000;
+/// Hamming distance used when `DUPLICATE_DISTANCE` is not set.
+pub const DEFAULT_DUPLICATE_DISTANCE: u32 = 5;
+/// Thumbnail size used when `THUMBNAIL_SIZES` is not set.
+pub const DEFAULT_THUMBNAIL_SIZE: u32 = 256;
+/// Brightness difference below which the unsharp mask leaves a pixel alone, so flat areas
+/// don't get their noise amplified.
+const SHARPEN_THRESHOLD: i32 = 2;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbnailFormat {
+    /// Keep the format of the uploaded original.
+    #[default]
+    Original,
+    WebP,
+}
+
+impl ThumbnailFormat {
+    pub fn extension<'a>(&self, original: &'a str) -> &'a str {
+        match self {
+            ThumbnailFormat::Original => original,
+            ThumbnailFormat::WebP => "webp",
+        }
+    }
+}
+
+/// How thumbnails are downscaled. The sharper the filter, the more CPU each thumbnail
+/// takes: `nearest` is the cheapest but blocky, `lanczos3` keeps text and edges crisp at
+/// several times the cost of `fast`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbnailFilter {
+    /// The `image` crate's box sampling thumbnail; quick but a little soft.
+    #[default]
+    Fast,
+    Nearest,
+    Triangle,
+    Lanczos3,
+}
+
+impl ThumbnailFilter {
+    /// Scales `img` down to fit in a `size` square, keeping the aspect ratio.
+    pub fn thumbnail(&self, img: &DynamicImage, size: u32) -> DynamicImage {
+        match self {
+            ThumbnailFilter::Fast => img.thumbnail(size, size),
+            ThumbnailFilter::Nearest => img.resize(size, size, FilterType::Nearest),
+            ThumbnailFilter::Triangle => img.resize(size, size, FilterType::Triangle),
+            ThumbnailFilter::Lanczos3 => img.resize(size, size, FilterType::Lanczos3),
+        }
+    }
+}
+
+impl FromStr for ThumbnailFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "" | "fast" => Ok(ThumbnailFilter::Fast),
+            "nearest" => Ok(ThumbnailFilter::Nearest),
+            "triangle" => Ok(ThumbnailFilter::Triangle),
+            "lanczos3" => Ok(ThumbnailFilter::Lanczos3),
+            _ => Err(anyhow!("Unsupported thumbnail filter '{}'", s)),
+        }
+    }
+}
+
+/// Sizes, format and quality of the thumbnails generated for each upload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThumbnailOptions {
+    pub sizes: Vec<u32>,
+    pub format: ThumbnailFormat,
+    pub filter: ThumbnailFilter,
+    /// Sigma of the unsharp mask applied after downscaling, `None` to skip it. Blurring
+    /// every thumbnail once more roughly doubles the time `fast` takes.
+    pub sharpen: Option<f32>,
+}
+
+impl ThumbnailOptions {
+    /// The thumbnail of `img` for `size`, as [`generate_thumbnails`] saves it.
+    pub fn thumbnail(&self, img: &DynamicImage, size: u32) -> DynamicImage {
+        let thumbnail = self.filter.thumbnail(img, size);
+
+        match self.sharpen {
+            Some(sigma) => thumbnail.unsharpen(sigma, SHARPEN_THRESHOLD),
+            None => thumbnail,
+        }
+    }
+}
+
+impl Default for ThumbnailOptions {
+    fn default() -> Self {
+        Self {
+            sizes: vec![DEFAULT_THUMBNAIL_SIZE],
+            format: ThumbnailFormat::default(),
+            filter: ThumbnailFilter::default(),
+            sharpen: None,
+        }
+    }
+}
+
+impl FromStr for ThumbnailFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "" | "original" => Ok(ThumbnailFormat::Original),
+            "webp" => Ok(ThumbnailFormat::WebP),
+            _ => Err(anyhow!("Unsupported thumbnail format '{}'", s)),
+        }
+    }
+}
+
+/// Computes a 64-bit difference hash (dHash): the image is shrunk to 9x8 grayscale and
+/// each bit tells whether a pixel is brighter than its right neighbour. Resizing,
+/// re-encoding and small edits only flip a few bits.
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+
+            if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+
+    hash
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Detects the image format from the content and checks it against the client supplied
+/// `mime_type` (if any). Fails if the content isn't an image format the service can both
+/// decode and encode, or if it doesn't match the declared type.
+pub fn detect_format<R: BufRead + Seek>(reader: R, mime_type: &str) -> Result<ImageFormat> {
+    let format = ImageReader::new(reader)
+        .with_guessed_format()?
+        .format()
+        .ok_or_else(|| anyhow!("Unrecognized image format"))?;
+
+    if !format.reading_enabled() || !format.writing_enabled() {
+        return Err(anyhow!("Unsupported image format {:?}", format));
+    }
+
+    let mime_type = mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+
+    if mime_type.is_empty() {
+        return Ok(format);
+    }
+
+    // Common non-standard aliases sent by browsers
+    let declared = match mime_type.as_str() {
+        "image/jpg" | "image/pjpeg" => Some(ImageFormat::Jpeg),
+        "image/x-png" => Some(ImageFormat::Png),
+        "image/x-ms-bmp" => Some(ImageFormat::Bmp),
+        _ => ImageFormat::from_mime_type(&mime_type),
+    };
+
+    if declared != Some(format) {
+        return Err(anyhow!(
+            "Content type {} does not match the detected format {:?} ({})",
+            mime_type,
+            format,
+            format.to_mime_type()
+        ));
+    }
+
+    Ok(format)
+}
+
+/// Reads the image dimensions from the header only, without decoding the pixels.
+pub fn read_dimensions<R: BufRead + Seek>(reader: R) -> Result<(u32, u32)> {
+    ImageReader::new(reader)
+        .with_guessed_format()?
+        .into_dimensions()
+        .map_err(|e| anyhow!("Failed to read image dimensions: {}", e))
+}
+
+/// Decodes the image refusing anything larger than `IMAGE_DIMENSION_LIMIT` on either side.
+pub fn decode<R: BufRead + Seek>(reader: R) -> Result<(DynamicImage, Option<ImageFormat>)> {
+    let mut reader = ImageReader::new(reader).with_guessed_format()?;
+    let format = reader.format();
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(IMAGE_DIMENSION_LIMIT);
+    limits.max_image_height = Some(IMAGE_DIMENSION_LIMIT);
+    reader.limits(limits);
+    let img = reader
+        .decode()
+        .map_err(|e| anyhow!("Failed to decode image: {}", e))?;
+    Ok((img, format))
+}
+
+/// Decodes the image stored at `path` and turns it upright by its EXIF orientation.
+pub fn open_upright(path: &Path) -> Result<DynamicImage> {
+    let (mut img, _) = decode(BufReader::new(File::open(path)?))?;
+    let exif = read_exif(BufReader::new(File::open(path)?));
+    apply_orientation(&mut img, exif.orientation);
+    Ok(img)
+}
+
+/// The EXIF fields kept for an upload. Each one is `None` when the image has no EXIF data
+/// or lacks that tag.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExifInfo {
+    /// `DateTimeOriginal`. EXIF times carry no zone, so it is taken as UTC.
+    pub captured_at: Option<DateTime<Utc>>,
+    pub camera_model: Option<String>,
+    /// The raw orientation tag, 1 to 8.
+    pub orientation: Option<u16>,
+}
+
+/// Reads the EXIF fields of the image. Missing or unreadable EXIF data isn't an error, the
+/// fields are just left empty.
+pub fn read_exif<R: BufRead + Seek>(mut reader: R) -> ExifInfo {
+    let Ok(exif) = exif::Reader::new().read_from_container(&mut reader) else {
+        return ExifInfo::default();
+    };
+    let ascii = |tag: Tag| {
+        let field = exif.get_field(tag, In::PRIMARY)?;
+        let Value::Ascii(values) = &field.value else {
+            return None;
+        };
+        let value = String::from_utf8_lossy(values.first()?).trim().to_string();
+        (!value.is_empty()).then_some(value)
+    };
+
+    ExifInfo {
+        captured_at: ascii(Tag::DateTimeOriginal)
+            .and_then(|v| NaiveDateTime::parse_from_str(&v, "%Y:%m:%d %H:%M:%S").ok())
+            .map(|v| v.and_utc()),
+        camera_model: ascii(Tag::Model),
+        orientation: exif
+            .get_field(Tag::Orientation, In::PRIMARY)
+            .and_then(|f| f.value.get_uint(0))
+            .and_then(|v| u16::try_from(v).ok())
+            .filter(|v| (1..=8).contains(v)),
+    }
+}
+
+/// Rotates and flips the image so it displays upright per its EXIF orientation.
+pub fn apply_orientation(img: &mut DynamicImage, orientation: Option<u16>) {
+    if let Some(orientation) = orientation
+        .and_then(|v| u8::try_from(v).ok())
+        .and_then(Orientation::from_exif)
+    {
+        img.apply_orientation(orientation);
+    }
+}
+
+pub fn exceeds_limit(width: u32, height: u32) -> bool {
+    width > IMAGE_DIMENSION_LIMIT || height > IMAGE_DIMENSION_LIMIT
+}
+
+/// Downscales the image so its longest side fits in `max_dimension`, keeping the aspect ratio.
+/// Returns `None` when the image already fits.
+pub fn fit_within(img: &DynamicImage, max_dimension: u32) -> Option<DynamicImage> {
+    if img.width() <= max_dimension && img.height() <= max_dimension {
+        return None;
+    }
+
+    Some(img.resize(max_dimension, max_dimension, FilterType::Lanczos3))
+}
+
+pub fn encode(img: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>> {
+    let mut buffer = Cursor::new(Vec::new());
+    img.write_to(&mut buffer, format)?;
+    Ok(buffer.into_inner())
+}
+
+pub fn save_thumbnail<P: AsRef<Path>>(
+    img: &DynamicImage,
+    path: P,
+    format: ThumbnailFormat,
+) -> Result<()> {
+    match format {
+        ThumbnailFormat::Original => img.save(path)?,
+        // The WebP encoder only accepts 8-bit RGB(A) buffers
+        ThumbnailFormat::WebP => {
+            DynamicImage::ImageRgba8(img.to_rgba8()).save_with_format(path, ImageFormat::WebP)?
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates a thumbnail of every size in `options` next to `file_path`, keeping the aspect
+/// ratio. Already written thumbnails are removed if one of them fails.
+pub fn generate_thumbnails<P: AsRef<Path>>(
+    img: &DynamicImage,
+    file_path: P,
+    options: &ThumbnailOptions,
+) -> Result<Vec<PathBuf>> {
+    let file_path = file_path.as_ref();
+    let format = options.format;
+    let mut thumb_paths = vec![];
+
+    for &size in options.sizes.iter() {
+        let thumbnail = options.thumbnail(img, size);
+        let thumb_path = get_image_thumb_path(file_path, size, format);
+
+        if let Err(e) = save_thumbnail(&thumbnail, &thumb_path, format) {
+            for path in thumb_paths.iter() {
+                let _ = std::fs::remove_file(path);
+            }
+
+            return Err(anyhow!("Failed to save thumbnail: {}", e));
+        }
+
+        thumb_paths.push(thumb_path);
+    }
+
+    Ok(thumb_paths)
+}
+
+pub fn get_image_thumb_name(filename: &str, size: u32, format: ThumbnailFormat) -> String {
+    if filename.is_empty() {
+        return filename.to_owned();
+    }
+
+    let path = Path::new(filename);
+    let base_name = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    format!(
+        "{}_thumb_{}.{}",
+        base_name,
+        size,
+        format.extension(&extension)
+    )
+}
+
+pub fn get_image_thumb_path<P: AsRef<Path>>(
+    filename: P,
+    size: u32,
+    format: ThumbnailFormat,
+) -> PathBuf {
+    let path = filename.as_ref();
+    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+    let thumb_file_name = get_image_thumb_name(&path.to_string_lossy(), size, format);
+    parent.join(thumb_file_name)
+}
+
+/// Lists the thumbnails generated for the image as `(size, path)` pairs, whatever
+/// sizes and formats were configured when they were created. Thumbnails created
+/// before sizes were configurable (`{id}_thumb.ext`) are reported as `DEFAULT_THUMBNAIL_SIZE`.
+pub fn list_image_thumbs<P: AsRef<Path>>(filename: P) -> Vec<(u32, PathBuf)> {
+    let path = filename.as_ref();
+    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+    let prefix = format!(
+        "{}_thumb",
+        path.file_stem().unwrap_or_default().to_string_lossy()
+    );
+    let dir = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let thumb_path = entry.path();
+            let stem = thumb_path.file_stem()?.to_string_lossy().into_owned();
+            let size = match stem.strip_prefix(&prefix)? {
+                "" => DEFAULT_THUMBNAIL_SIZE,
+                rest => rest.strip_prefix('_')?.parse().ok()?,
+            };
+            Some((size, parent.join(thumb_path.file_name()?)))
+        })
+        .collect()
+}
+
+/// Finds the existing thumbnail closest to the requested size, preferring the configured
+/// format so thumbnails created before a `THUMBNAIL_FORMAT` change still resolve.
+pub fn find_image_thumb_path<P: AsRef<Path>>(
+    filename: P,
+    size: u32,
+    format: ThumbnailFormat,
+) -> Option<PathBuf> {
+    let filename = filename.as_ref();
+    let original_ext = filename.extension().unwrap_or_default().to_string_lossy();
+    let preferred_ext = format.extension(&original_ext).to_owned();
+    list_image_thumbs(filename)
+        .into_iter()
+        .min_by_key(|(thumb_size, thumb_path)| {
+            let other_format = thumb_path.extension().unwrap_or_default() != preferred_ext.as_str();
+            // On a tie, the larger thumbnail scales down better
+            (
+                thumb_size.abs_diff(size),
+                other_format,
+                std::cmp::Reverse(*thumb_size),
+            )
+        })
+        .map(|(_, thumb_path)| thumb_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::RgbImage;
+
+    fn png_bytes(width: u32, height: u32) -> Vec<u8> {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(width, height));
+        encode(&img, ImageFormat::Png).unwrap()
+    }
+
+    #[test]
+    fn oversized_image_is_downscaled_within_limit() {
+        let bytes = png_bytes(1200, 600);
+        let (img, format) = decode(Cursor::new(&bytes)).unwrap();
+        let resized = fit_within(&img, 400).expect("image should be downscaled");
+        assert_eq!((resized.width(), resized.height()), (400, 200));
+
+        let stored = encode(&resized, format.unwrap()).unwrap();
+        let (width, height) = read_dimensions(Cursor::new(&stored)).unwrap();
+        assert!(width <= 400 && height <= 400);
+    }
+
+    #[test]
+    fn image_within_limit_is_kept() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(300, 200));
+        assert!(fit_within(&img, 400).is_none());
+    }
+
+    #[test]
+    fn filters_and_sharpening_change_the_thumbnail() {
+        // Fine stripes, the kind of detail downscaled screenshots lose
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(300, 200, |x, y| {
+            let v = if (x / 3 + y / 5) % 2 == 0 { 230 } else { 20 };
+            ::image::Rgb([v, v / 2, 255 - v])
+        }));
+        let thumbnail = |filter, sharpen| {
+            ThumbnailOptions {
+                filter,
+                sharpen,
+                ..Default::default()
+            }
+            .thumbnail(&img, 64)
+        };
+
+        let nearest = thumbnail(ThumbnailFilter::Nearest, None);
+        let lanczos3 = thumbnail(ThumbnailFilter::Lanczos3, None);
+        assert_eq!((lanczos3.width(), lanczos3.height()), (64, 43));
+        assert_eq!((nearest.width(), nearest.height()), (64, 43));
+        assert_ne!(lanczos3.as_bytes(), nearest.as_bytes());
+
+        // The default matches the plain `thumbnail` call used before filters were configurable
+        let fast = thumbnail(ThumbnailFilter::default(), None);
+        assert_eq!(fast.as_bytes(), img.thumbnail(64, 64).as_bytes());
+        let sharpened = thumbnail(ThumbnailFilter::default(), Some(1.0));
+        assert_ne!(sharpened.as_bytes(), fast.as_bytes());
+    }
+
+    #[test]
+    fn png_upload_produces_webp_thumbnail() {
+        let dir = std::env::temp_dir().join(format!("thumbs-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filepath = dir.join("1.png");
+        std::fs::write(&filepath, png_bytes(640, 480)).unwrap();
+
+        let (img, _) = decode(Cursor::new(std::fs::read(&filepath).unwrap())).unwrap();
+        let thumb_path = get_image_thumb_path(&filepath, 256, ThumbnailFormat::WebP);
+        save_thumbnail(&img.thumbnail(256, 256), &thumb_path, ThumbnailFormat::WebP).unwrap();
+
+        assert_eq!(thumb_path.file_name().unwrap(), "1_thumb_256.webp");
+        let thumb = ImageReader::open(&thumb_path)
+            .unwrap()
+            .with_guessed_format()
+            .unwrap();
+        assert_eq!(thumb.format(), Some(ImageFormat::WebP));
+        assert_eq!(
+            find_image_thumb_path(&filepath, 256, ThumbnailFormat::Original),
+            Some(thumb_path)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn closest_thumbnail_size_is_served() {
+        let dir = std::env::temp_dir().join(format!("thumbs-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filepath = dir.join("1.png");
+        let img = DynamicImage::ImageRgb8(RgbImage::new(640, 480));
+
+        for size in [128, 256, 512] {
+            let thumb_path = get_image_thumb_path(&filepath, size, ThumbnailFormat::Original);
+            save_thumbnail(
+                &img.thumbnail(size, size),
+                thumb_path,
+                ThumbnailFormat::Original,
+            )
+            .unwrap();
+        }
+
+        let closest = |size| {
+            find_image_thumb_path(&filepath, size, ThumbnailFormat::Original)
+                .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+        };
+        assert_eq!(closest(300).as_deref(), Some("1_thumb_256.png"));
+        assert_eq!(closest(1000).as_deref(), Some("1_thumb_512.png"));
+        assert_eq!(closest(192).as_deref(), Some("1_thumb_256.png"));
+        assert_eq!(list_image_thumbs(&filepath).len(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn content_type_must_match_detected_format() {
+        let bytes = png_bytes(4, 4);
+        assert_eq!(
+            detect_format(Cursor::new(&bytes), "").unwrap(),
+            ImageFormat::Png
+        );
+        assert_eq!(
+            detect_format(Cursor::new(&bytes), "image/PNG").unwrap(),
+            ImageFormat::Png
+        );
+
+        let err = detect_format(Cursor::new(&bytes), "image/jpeg").unwrap_err();
+        assert!(err.to_string().contains("Png"));
+        assert!(detect_format(Cursor::new(b"just some text"), "text/plain").is_err());
+        assert!(detect_format(Cursor::new(b"just some text"), "").is_err());
+    }
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let fx = x as f32 / width as f32 * std::f32::consts::TAU;
+            let fy = y as f32 / height as f32 * std::f32::consts::PI;
+            let v = (128.0 + 127.0 * fx.sin() * fy.cos()) as u8;
+            ::image::Rgb([v, v / 2, 255 - v])
+        }))
+    }
+
+    #[test]
+    fn resized_copy_is_a_near_duplicate() {
+        let img = gradient(640, 480);
+        let copy = img.resize(320, 240, FilterType::Lanczos3);
+        let other = DynamicImage::ImageRgb8(RgbImage::from_fn(640, 480, |x, _| {
+            ::image::Rgb(if x % 80 < 40 {
+                [0, 0, 0]
+            } else {
+                [255, 255, 255]
+            })
+        }));
+
+        assert!(hamming_distance(dhash(&img), dhash(&copy)) <= DEFAULT_DUPLICATE_DISTANCE);
+        assert!(hamming_distance(dhash(&img), dhash(&other)) > DEFAULT_DUPLICATE_DISTANCE);
+    }
+
+    #[test]
+    fn absurd_dimensions_are_rejected() {
+        assert!(exceeds_limit(IMAGE_DIMENSION_LIMIT + 1, 10));
+        assert!(!exceeds_limit(IMAGE_DIMENSION_LIMIT, IMAGE_DIMENSION_LIMIT));
+    }
+
+    /// Wraps a JPEG with an APP1 segment holding the given EXIF fields.
+    fn jpeg_with_exif(img: &DynamicImage, fields: &[exif::Field]) -> Vec<u8> {
+        let mut writer = exif::experimental::Writer::new();
+
+        for field in fields {
+            writer.push_field(field);
+        }
+
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        let tiff = tiff.into_inner();
+        let jpeg = encode(img, ImageFormat::Jpeg).unwrap();
+        let mut bytes = jpeg[..2].to_vec();
+        bytes.extend([0xff, 0xe1]);
+        bytes.extend(((tiff.len() + 8) as u16).to_be_bytes());
+        bytes.extend(b"Exif\0\0");
+        bytes.extend(tiff);
+        bytes.extend(&jpeg[2..]);
+        bytes
+    }
+
+    #[test]
+    fn exif_orientation_rotates_the_image() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(40, 20));
+        let field = |tag, value| exif::Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value,
+        };
+        let bytes = jpeg_with_exif(
+            &img,
+            &[
+                field(Tag::Orientation, Value::Short(vec![6])),
+                field(Tag::Model, Value::Ascii(vec![b"Camera X".to_vec()])),
+                field(
+                    Tag::DateTimeOriginal,
+                    Value::Ascii(vec![b"2024:05:06 07:08:09".to_vec()]),
+                ),
+            ],
+        );
+
+        let info = read_exif(Cursor::new(&bytes));
+        assert_eq!(info.orientation, Some(6));
+        assert_eq!(info.camera_model.as_deref(), Some("Camera X"));
+        assert_eq!(
+            info.captured_at.unwrap().to_rfc3339(),
+            "2024-05-06T07:08:09+00:00"
+        );
+
+        let (mut decoded, _) = decode(Cursor::new(&bytes)).unwrap();
+        apply_orientation(&mut decoded, info.orientation);
+        assert_eq!((decoded.width(), decoded.height()), (20, 40));
+    }
+
+    #[test]
+    fn missing_exif_is_left_empty() {
+        assert_eq!(read_exif(Cursor::new(png_bytes(4, 4))), ExifInfo::default());
+        assert_eq!(read_exif(Cursor::new(b"not an image")), ExifInfo::default());
+
+        let mut img = DynamicImage::ImageRgb8(RgbImage::new(40, 20));
+        apply_orientation(&mut img, None);
+        assert_eq!((img.width(), img.height()), (40, 20));
+    }
+}
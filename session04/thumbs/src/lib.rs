@@ -0,0 +1,18 @@
+pub mod caching;
+pub mod client;
+pub mod config;
+pub mod db;
+pub mod health;
+pub mod idempotency;
+pub mod imaging;
+pub mod list_query;
+pub mod maintenance;
+pub mod rate_limit;
+pub mod resizing;
+pub mod resumable;
+pub mod storage;
+pub mod testing;
+pub mod thumbnails;
+pub mod upload;
+
+pub use util::web::api_error;
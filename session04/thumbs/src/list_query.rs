@@ -0,0 +1,157 @@
+use axum::{
+    extract::{FromRequestParts, Query},
+    http::request::Parts,
+};
+use sea_orm::{ColumnTrait, Order};
+use serde::Deserialize;
+
+use crate::{
+    api_error::ApiError,
+    db::repositories::{MAX_PAGE_SIZE, OrderBy, Pagination},
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RawListQuery {
+    page: Option<u64>,
+    page_size: Option<u64>,
+    sort: Option<String>,
+    order: Option<String>,
+}
+
+/// `?page=&page_size=&sort=&order=` for list endpoints, validated when extracted so
+/// handlers get a ready `Pagination`. `sort` also accepts the `-column` form for descending
+/// order; the column itself is checked by `order_by` since each endpoint allows different ones.
+#[derive(Debug, Clone, Default)]
+pub struct ListQuery {
+    pub pagination: Pagination,
+    sort: Option<String>,
+    order: Option<Order>,
+}
+
+impl ListQuery {
+    pub fn order_by<C: ColumnTrait>(&self, columns: &[C]) -> Result<Option<OrderBy<C>>, ApiError> {
+        let Some(sort) = &self.sort else {
+            return Ok(None);
+        };
+        let mut order_by = OrderBy::parse(sort, columns).map_err(ApiError::bad_request)?;
+
+        if let Some(order) = &self.order {
+            order_by.order = order.clone();
+        }
+
+        Ok(Some(order_by))
+    }
+
+    fn validate(raw: RawListQuery) -> Result<Self, String> {
+        let defaults = Pagination::default();
+        let page = raw.page.unwrap_or(defaults.page);
+        let page_size = raw.page_size.unwrap_or(defaults.page_size);
+
+        if page == 0 {
+            return Err("page must be at least 1.".to_string());
+        }
+
+        if !(1..=MAX_PAGE_SIZE).contains(&page_size) {
+            return Err(format!("page_size must be between 1 and {MAX_PAGE_SIZE}."));
+        }
+
+        let sort = raw
+            .sort
+            .map(|v| v.trim().to_owned())
+            .filter(|v| !v.is_empty());
+        let order = match raw.order.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(v) if v.eq_ignore_ascii_case("asc") => Some(Order::Asc),
+            Some(v) if v.eq_ignore_ascii_case("desc") => Some(Order::Desc),
+            Some(v) => return Err(format!("order must be asc or desc, not '{v}'.")),
+        };
+
+        match &sort {
+            None if order.is_some() => return Err("order requires sort.".to_string()),
+            Some(s) if s.starts_with('-') && order.is_some() => {
+                return Err("Use either a '-' prefix in sort or order, not both.".to_string());
+            }
+            _ => {}
+        }
+
+        Ok(Self {
+            pagination: Pagination { page, page_size },
+            sort,
+            order,
+        })
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ListQuery {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(raw) = Query::<RawListQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| ApiError::bad_request(e.body_text()))?;
+        Self::validate(raw).map_err(ApiError::bad_request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::entities::ImageColumn;
+    use axum::http::{Request, StatusCode};
+    use sea_orm::IdenStatic;
+
+    async fn extract(query: &str) -> Result<ListQuery, ApiError> {
+        let (mut parts, _) = Request::get(format!("/images?{query}"))
+            .body(())
+            .unwrap()
+            .into_parts();
+        ListQuery::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn defaults_and_valid_values() {
+        let query = extract("").await.unwrap();
+        assert_eq!(query.pagination, Pagination::default());
+        assert!(query.order_by(&[ImageColumn::Title]).unwrap().is_none());
+
+        let query = extract("page=2&page_size=50&sort=title&order=DESC")
+            .await
+            .unwrap();
+        assert_eq!(
+            query.pagination,
+            Pagination {
+                page: 2,
+                page_size: 50
+            }
+        );
+        let order_by = query.order_by(&[ImageColumn::Title]).unwrap().unwrap();
+        assert_eq!(order_by.column.as_str(), "title");
+        assert!(matches!(order_by.order, Order::Desc));
+
+        let query = extract("sort=-title").await.unwrap();
+        let order_by = query.order_by(&[ImageColumn::Title]).unwrap().unwrap();
+        assert!(matches!(order_by.order, Order::Desc));
+    }
+
+    #[tokio::test]
+    async fn invalid_values_are_bad_requests() {
+        for query in [
+            "page=0",
+            "page=abc",
+            "page_size=0",
+            "page_size=1000",
+            "order=up&sort=title",
+            "order=asc",
+            "sort=-title&order=asc",
+        ] {
+            let error = extract(query).await.unwrap_err();
+            assert_eq!(error.status, StatusCode::BAD_REQUEST, "{query}");
+            assert!(!error.message.is_empty());
+        }
+
+        let query = extract("sort=file_size").await.unwrap();
+        let error = query.order_by(&[ImageColumn::Title]).unwrap_err();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+    }
+}
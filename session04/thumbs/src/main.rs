@@ -1,42 +1,87 @@
-use ::image::ImageReader;
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use axum::{
     Extension, Json, Router,
     body::Body,
-    extract::{Multipart, Path as axum_path},
-    http::{HeaderValue, StatusCode},
+    extract::{DefaultBodyLimit, Multipart, Path as axum_path, Query},
+    http::{HeaderMap, StatusCode, header},
+    middleware,
     response::{IntoResponse, Response},
-    routing::{delete, get, post, put},
+    routing::{delete, get, head, patch, post, put},
 };
 use dotenvy::dotenv;
 use mime_guess::get_mime_extensions_str;
 use sea_orm::{prelude::*, *};
 use sea_orm_migration::prelude::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{
     fs,
+    net::SocketAddr,
     path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
 use tokio_util::io::ReaderStream;
-use tower_http::{
-    cors::{Any, CorsLayer},
-    services::ServeDir,
-};
+use tower_http::{compression::CompressionLayer, services::ServeDir};
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_subscriber::{
     EnvFilter, filter::LevelFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt,
 };
+use util::web::{client_ip, request_log, shutdown};
 
 use migration::{Migrator, MigratorTrait};
 
-mod db;
-use db::prelude::*;
+use api_error::ApiError;
+use config::AppConfig;
+use db::{
+    migration_lock::{LockOutcome, with_migration_lock},
+    prelude::*,
+};
+use idempotency::{Claim, IdempotencyKeys};
+use list_query::ListQuery;
+use rate_limit::RateLimits;
+use resumable::{ResumableUploads, UPLOAD_LENGTH_HEADER, UPLOAD_OFFSET_HEADER, UploadInfo};
+use shutdown::InFlight;
+use thumbnails::{ThumbnailJob, ThumbnailQueue};
+use thumbs::{
+    api_error, caching, config, db, health, idempotency, imaging, list_query, maintenance,
+    rate_limit, resizing, resumable, storage, thumbnails, upload,
+};
 
+/// Body of `POST /images/{id}/tags/`, naming the tags in either field or both.
 #[derive(Deserialize)]
 struct AddTagRequest {
-    tag: String,
+    /// One tag name, or several comma separated.
+    #[serde(default)]
+    tag: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct TagCount {
+    #[serde(flatten)]
+    tag: TagModel,
+    count: u64,
+}
+
+/// Body of `GET /stats`.
+#[derive(Serialize)]
+struct Stats {
+    images: u64,
+    tags: u64,
+    total_bytes: u64,
+    newest_image_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// The `STATS_TOP_TAGS` tags with the most images.
+    top_tags: Vec<TagCount>,
+}
+
+/// Tags listed in `Stats::top_tags`.
+const STATS_TOP_TAGS: u64 = 10;
+
+/// `?after=` switches listing to keyset pagination, see `IRepository::list_after`.
+#[derive(Deserialize)]
+struct AfterQuery {
+    after: Option<i64>,
 }
 
 #[tokio::main]
@@ -61,20 +106,61 @@ async fn main() -> Result<()> {
 
 async fn run() -> Result<()> {
     tracing::info!("Configuring database");
-    let db_url = std::env::var("DATABASE_URL")?;
-    let db = setup_database(&db_url).await?;
+    let config = Arc::new(AppConfig::from_env()?);
+    let db = setup_database(&config.database_url).await?;
     /*
      * Must specify the associated types.
      * IImageRepository<Entity = Type, PrimaryKey = Type, Model = Type, ActiveModel = Type, UpdateModel = Type, Related = Type, RelatedPrimaryKey = Type>
      */
-    let images_repo: Arc<dyn IImageRepository + Send + Sync> =
-        Arc::new(ImageRepository::new(db.clone()));
-    let tags_repo: Arc<dyn ITagRepository + Send + Sync> = Arc::new(TagRepository::new(db.clone()));
+    let images_repo = ImageRepository::new(db.clone()).with_soft_delete(config.soft_delete_images);
+    let tags_repo = TagRepository::new(db.clone()).with_soft_delete(config.soft_delete_tags);
+    let (images_repo, tags_repo): (
+        Arc<dyn IImageRepository + Send + Sync>,
+        Arc<dyn ITagRepository + Send + Sync>,
+    ) = match config.log_db_timings {
+        true => (
+            Arc::new(Timed::new("images", images_repo)),
+            Arc::new(Timed::new("tags", tags_repo)),
+        ),
+        false => (Arc::new(images_repo), Arc::new(tags_repo)),
+    };
     tracing::info!("Database configured successfully.");
 
+    let args = std::env::args().collect::<Vec<_>>();
+
+    if args.iter().any(|a| a == "--cleanup") {
+        let dry_run = args.iter().any(|a| a == "--dry-run");
+        return cleanup(images_repo.as_ref(), &config.images_dir, dry_run).await;
+    }
+
+    if args.iter().any(|a| a == "--purge-deleted") {
+        return purge_deleted(images_repo.as_ref(), tags_repo.as_ref(), &config).await;
+    }
+
+    let thumbnail_queue = ThumbnailQueue::spawn(
+        images_repo.clone(),
+        config.thumbnail_queue_size,
+        config.thumbnails.clone(),
+    );
+
     tracing::info!("Configuring application");
-    let app = setup_router()
+    let in_flight = InFlight::default();
+    let idempotency_keys = IdempotencyKeys::new(Duration::from_secs(config.idempotency_ttl_secs));
+    let resumable_uploads = ResumableUploads::new(
+        &config.images_dir,
+        Duration::from_secs(config.resumable_upload_ttl_secs),
+    );
+    resumable_uploads.spawn_purge();
+    let app = setup_router(&config)
+        .layer(middleware::from_fn_with_state(
+            in_flight.clone(),
+            shutdown::track_in_flight,
+        ))
+        .layer(Extension(config))
         .layer(Extension(db))
+        .layer(Extension(thumbnail_queue))
+        .layer(Extension(idempotency_keys))
+        .layer(Extension(resumable_uploads))
         .layer(Extension(images_repo))
         .layer(Extension(tags_repo));
     tracing::info!("Application configured successfully.");
@@ -82,7 +168,59 @@ async fn run() -> Result<()> {
     tracing::info!("Starting server");
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     tracing::info!("Server listening on http://localhost:3000");
-    axum::serve(listener, app).await?;
+    // The rate limiter tells clients apart by their address
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown::ctrl_c(in_flight))
+    .await?;
+    tracing::info!("Server stopped");
+    Ok(())
+}
+
+async fn cleanup(
+    repo: &(dyn IImageRepository + Send + Sync),
+    images_dir: &Path,
+    dry_run: bool,
+) -> Result<()> {
+    tracing::info!(
+        "Looking for orphaned files in {}{}",
+        images_dir.display(),
+        if dry_run { " (dry run)" } else { "" }
+    );
+    let report = maintenance::cleanup_orphans(repo, images_dir, dry_run).await?;
+
+    for path in report.orphans.iter() {
+        tracing::info!("Orphaned file: {}", path.display());
+    }
+
+    tracing::info!(
+        "Scanned {} files, found {} orphans, removed {}.",
+        report.scanned,
+        report.orphans.len(),
+        report.removed
+    );
+    Ok(())
+}
+
+async fn purge_deleted(
+    images: &(dyn IImageRepository + Send + Sync),
+    tags: &(dyn ITagRepository + Send + Sync),
+    config: &AppConfig,
+) -> Result<()> {
+    tracing::info!(
+        "Purging rows soft deleted more than {} day(s) ago",
+        config.soft_delete_retention_days
+    );
+    let retention = chrono::TimeDelta::days(config.soft_delete_retention_days.into());
+    let report = maintenance::purge_deleted(images, tags, &config.images_dir, retention).await?;
+    tracing::info!(
+        "Purged {} image(s) and {} tag(s), removed {} file(s).",
+        report.images,
+        report.tags,
+        report.files_removed
+    );
     Ok(())
 }
 
@@ -157,357 +295,998 @@ async fn setup_database(db_url: &str) -> Result<DatabaseConnection> {
     let db = Database::connect(opt).await?;
     tracing::info!("Connected to the database at {}", db_url);
 
-    // Apply migrations
+    // Apply migrations, one instance at a time
     tracing::info!("Applying migrations...");
-    Migrator::up(&db, None).await?;
-    tracing::info!("Migrations applied successfully.");
+    let ((), outcome) =
+        with_migration_lock(&db, || async { Ok(Migrator::up(&db, None).await?) }).await?;
+
+    match outcome {
+        LockOutcome::Acquired => tracing::info!("Migrations applied successfully."),
+        LockOutcome::Waited => {
+            tracing::info!("Migrations applied after waiting for another instance.")
+        }
+    }
 
     Ok(db)
 }
 
-fn setup_router() -> Router {
+/// Markdown served by `GET /about`, relative to the working directory.
+const ABOUT_PATH: &str = "static/about.md";
+
+fn setup_router(config: &AppConfig) -> Router {
     let curdir = std::env::current_dir().unwrap();
     let static_path = curdir.join("wwwroot");
-    let images_path = curdir.join("data/images");
-    let origins = std::env::var("CORS_ORIGINS")
-        .unwrap_or_else(|_| "http://localhost".to_string())
-        .split(',')
-        .map(|s| s.trim().parse::<HeaderValue>().unwrap())
-        .collect::<Vec<_>>();
-    let cors = CorsLayer::new()
-        .allow_origin(origins)
-        .allow_methods(Any)
-        .allow_headers(Any);
+    let cors = config.cors.layer().expose_headers([
+        request_log::REQUEST_ID_HEADER,
+        header::LOCATION,
+        UPLOAD_OFFSET_HEADER,
+        UPLOAD_LENGTH_HEADER,
+    ]);
 
     tracing::info!("Configuring router");
     Router::new()
         .route("/about", get(about))
         .route("/images", get(image_list))
         .route("/images/count", get(image_count))
+        .route("/images/search", get(image_search))
+        .route("/images/search/text", get(image_search_text))
         .route("/images/{id}", get(image_get))
-        .route("/images", post(image_add))
+        .route(
+            "/images",
+            post(image_add).layer(DefaultBodyLimit::max(
+                config.max_batch_size + upload::FORM_OVERHEAD,
+            )),
+        )
         .route("/images/{id}", put(image_update))
         .route("/images/{id}", delete(image_delete))
+        .route("/images/{id}/restore", post(image_restore))
+        .route("/images/{id}/thumb", get(image_thumb))
+        .route("/images/{id}/thumb/{size}", get(image_thumb_size))
+        .route("/images/{id}/resized", get(image_resized))
+        .route("/images/{id}/original", get(image_original))
         .route("/images/{id}/tags/", get(image_tag_list))
         .route("/images/{id}/tags/", post(image_tag_add))
         .route("/images/{id}/tags/{tag_id}", delete(image_tag_remove))
+        .route("/uploads", post(upload_create))
+        .route("/uploads/{id}", head(upload_status))
+        .route("/uploads/{id}", patch(upload_append))
+        .route("/uploads/{id}", delete(upload_delete))
         .route("/tags/", get(tag_list))
         .route("/tags/count", get(tag_count))
+        .route("/tags/counts", get(tag_image_counts))
+        .route("/stats", get(stats))
         .route("/tags/{id}", get(tag_get))
         .route("/tags/", post(tag_add))
         .route("/tags/{id}", put(tag_update))
         .route("/tags/{id}", delete(tag_delete))
+        .route("/tags/{id}/restore", post(tag_restore))
         .route("/tags/{id}/images/", get(tag_image_list))
         .route("/tags/{id}/images/", post(tag_image_add))
         .route("/tags/{id}/images/{tag_id}", delete(tag_image_remove))
-        .nest_service("/assets", ServeDir::new(images_path))
+        .nest_service("/assets", ServeDir::new(&config.images_dir))
         .fallback_service(ServeDir::new(static_path).append_index_html_on_directories(true))
+        .layer(middleware::from_fn(caching::conditional_get))
+        .layer(CompressionLayer::new())
+        .layer(middleware::from_fn_with_state(
+            RateLimits::new(
+                config.rate_limit_reads_per_minute,
+                config.rate_limit_writes_per_minute,
+            ),
+            rate_limit::rate_limit,
+        ))
         .layer(cors)
+        .merge(health::routes())
+        .merge(if config.debug_endpoints {
+            health::debug_routes()
+        } else {
+            Router::new()
+        })
+        .layer(middleware::from_fn(request_log::request_log))
+        .layer(middleware::from_fn_with_state(
+            config.trusted_proxies.clone(),
+            client_ip::resolve_client_ip,
+        ))
 }
 
 // Handlers
-async fn about() -> Result<impl IntoResponse, (StatusCode, String)> {
-    let file = tokio::fs::File::open("static/about.md")
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
+async fn about(headers: HeaderMap) -> Result<Response, ApiError> {
+    serve_markdown(Path::new(ABOUT_PATH), &headers).await
+}
+
+/// Serves the markdown file at `path` as `text/markdown`, or rendered to HTML when the
+/// request's `Accept` lists `text/html`.
+async fn serve_markdown(path: &Path, headers: &HeaderMap) -> Result<Response, ApiError> {
+    let file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(ApiError::not_found("Page not found."));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let metadata = file.metadata().await?;
     let response = Response::builder()
         .status(StatusCode::OK)
-        .body(body)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(response)
+        .header(header::VARY, header::ACCEPT.as_str());
+
+    if !accepts_html(headers) {
+        let mut response = response.header(header::CONTENT_TYPE, "text/markdown; charset=utf-8");
+
+        for (name, value) in caching::file_headers(&metadata) {
+            response = response.header(name, value);
+        }
+
+        return Ok(response.body(Body::from_stream(ReaderStream::new(file)))?);
+    }
+
+    // The rendered length differs from the file's, so only Last-Modified is kept
+    let markdown = tokio::fs::read_to_string(path).await?;
+    let mut html = String::with_capacity(markdown.len() * 3 / 2);
+    pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(&markdown));
+    let mut response = response.header(header::CONTENT_TYPE, "text/html; charset=utf-8");
+
+    if let Ok(modified) = metadata.modified() {
+        response = response.header(header::LAST_MODIFIED, httpdate::fmt_http_date(modified));
+    }
+
+    Ok(response.body(Body::from(html))?)
+}
+
+fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|range| range.split(';').next())
+        .any(|media| media.trim().eq_ignore_ascii_case("text/html"))
 }
 
 async fn image_list(
     Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
-) -> Result<Json<ResultSet<ModelWithRelated<ImageModel, TagModel>>>, (StatusCode, String)> {
-    match repo.list_with_related(None, None, None).await {
+    Query(filter): Query<ImageFilter>,
+    list: ListQuery,
+) -> Result<Json<ResultSet<ModelWithRelated<ImageModel, TagModel>>>, ApiError> {
+    filter.validate().map_err(ApiError::bad_request)?;
+    let order_by = list.order_by(IMAGE_SORT_COLUMNS)?;
+
+    match repo
+        .list_with_related(
+            Some(Box::new(filter)),
+            None,
+            order_by,
+            Some(list.pagination),
+        )
+        .await
+    {
         Ok(images) => Ok(Json(images)),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Err(e) => Err(e.into()),
     }
 }
 
 async fn image_count(
     Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
-) -> Result<Json<u64>, (StatusCode, String)> {
-    match repo.count(None).await {
+    Query(filter): Query<ImageFilter>,
+) -> Result<Json<u64>, ApiError> {
+    filter.validate().map_err(ApiError::bad_request)?;
+
+    match repo.count(Some(Box::new(filter))).await {
         Ok(count) => Ok(Json(count)),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn image_search(
+    Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
+    Query(params): Query<Vec<(String, String)>>,
+    list: ListQuery,
+) -> Result<Json<ResultSet<ModelWithRelated<ImageModel, TagModel>>>, ApiError> {
+    // Query<T> can't collect repeated keys into a Vec, so pick every tag= from the pairs
+    let filter = params
+        .iter()
+        .filter(|(key, value)| key == "tag" && !value.trim().is_empty())
+        .fold(FilterBuilder::all(), |f, (_, tag)| f.expr(tagged_with(tag)));
+
+    if filter.is_empty() {
+        return Err(ApiError::bad_request("tag is required."));
+    }
+
+    let order_by = list.order_by(IMAGE_SORT_COLUMNS)?;
+
+    match repo
+        .list_with_related(Some(filter.boxed()), None, order_by, Some(list.pagination))
+        .await
+    {
+        Ok(images) => Ok(Json(images)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct TextSearch {
+    q: Option<String>,
+}
+
+async fn image_search_text(
+    Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
+    Query(search): Query<TextSearch>,
+    list: ListQuery,
+) -> Result<Json<ResultSet<ImageModel>>, ApiError> {
+    let query = search.q.unwrap_or_default();
+
+    if query.trim().is_empty() {
+        return Err(ApiError::bad_request("q is required."));
+    }
+
+    match repo.search_text(&query, Some(list.pagination)).await {
+        Ok(images) => Ok(Json(images)),
+        Err(e) => Err(e.into()),
     }
 }
 
 async fn image_get(
     Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
     axum_path(id): axum_path<i64>,
-) -> Result<Json<ModelWithRelated<ImageModel, TagModel>>, (StatusCode, String)> {
+) -> Result<Json<ModelWithRelated<ImageModel, TagModel>>, ApiError> {
     match repo.get_with_related(id).await {
         Ok(Some(image)) => Ok(Json(image)),
-        Ok(None) => Err((StatusCode::NOT_FOUND, "Image not found".to_string())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Ok(None) => Err(ApiError::not_found("Image not found")),
+        Err(e) => Err(e.into()),
     }
 }
 
+/// An upload checked by [`prepare_upload`], ready to be stored. The decoded image isn't
+/// kept, so a batch holds one in memory at a time.
+struct PreparedUpload {
+    dto: CreateImageDto,
+    extension: String,
+    /// The file to store, the upload itself or its downscaled copy.
+    upload: upload::TempUpload,
+}
+
+/// Accepts one or more `image_file` fields. The other fields are matched to the files by
+/// position, so the second `title` belongs to the second file; a field sent once applies to
+/// every file. A single file returns its `ImageModel`, several return an array. Every file
+/// is checked before anything is stored, so one bad file fails the whole batch.
+///
+/// With an `Idempotency-Key` header, repeating a request that succeeded returns the images
+/// it created instead of storing them again, and a repeat sent while the first one is still
+/// running is rejected with 409.
 async fn image_add(
     Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
+    Extension(thumbnail_queue): Extension<ThumbnailQueue>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Extension(idempotency_keys): Extension<IdempotencyKeys>,
+    headers: HeaderMap,
+    multipart: Multipart,
+) -> Result<Response, ApiError> {
+    let mut models = match idempotency::key_from_headers(&headers)? {
+        None => add_images(&repo, &thumbnail_queue, &config, multipart).await?,
+        Some(key) => match idempotency_keys.claim(&key) {
+            Claim::New(pending) => {
+                // Dropping `pending` on an error forgets the key again
+                let models = add_images(&repo, &thumbnail_queue, &config, multipart).await?;
+                pending.complete(models.iter().map(|model| model.id).collect());
+                models
+            }
+            Claim::InProgress => {
+                return Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    "An upload with this Idempotency-Key is still in progress",
+                ));
+            }
+            Claim::Done(ids) => {
+                let mut models = Vec::with_capacity(ids.len());
+
+                for id in ids {
+                    match repo.get(id).await? {
+                        Some(model) => models.push(model),
+                        None => return Err(ApiError::not_found("Image not found")),
+                    }
+                }
+
+                models
+            }
+        },
+    };
+
+    if models.len() > 1 {
+        Ok(Json(models).into_response())
+    } else {
+        Ok(Json(models.remove(0)).into_response())
+    }
+}
+
+/// Stores the uploads of an `image_add` request and returns the created images.
+async fn add_images(
+    repo: &Arc<dyn IImageRepository + Send + Sync>,
+    thumbnail_queue: &ThumbnailQueue,
+    config: &AppConfig,
     mut multipart: Multipart,
-) -> Result<Json<ImageModel>, (StatusCode, String)> {
+) -> Result<Vec<ImageModel>, ApiError> {
+    let images_dir = &config.images_dir;
+    fs::create_dir_all(images_dir)?;
+
     // Read the form data from the multipart fields
-    let mut fields = std::collections::HashMap::new();
-    let mut image_bytes = None;
+    let mut fields: std::collections::HashMap<String, Vec<String>> =
+        std::collections::HashMap::new();
+    let mut uploads = vec![];
+    let mut batch_size = 0;
 
     while let Some(field) = multipart
         .next_field()
         .await
-        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?
+        .map_err(ApiError::bad_request)?
     {
         let name = field.name().unwrap_or("").to_string();
 
         if name == "image_file" {
-            // This is the file field
-            image_bytes = Some(
-                field
-                    .bytes()
-                    .await
-                    .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?,
-            );
+            if uploads.len() == upload::MAX_BATCH_FILES {
+                return Err(ApiError::bad_request(format!(
+                    "At most {} images can be uploaded at once",
+                    upload::MAX_BATCH_FILES
+                )));
+            }
+
+            // This is the file field, written to disk as it arrives rather than buffered
+            let upload = upload::stream_to_file(
+                field,
+                &storage::incoming_dir(images_dir),
+                config.max_upload_size,
+            )
+            .await?;
+            batch_size += upload.size();
+
+            if batch_size > config.max_batch_size as u64 {
+                return Err(ApiError::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!(
+                        "Images are larger than {} bytes together.",
+                        config.max_batch_size
+                    ),
+                ));
+            }
+
+            uploads.push(upload);
         } else {
             // This is a regular form field
-            let value = field
-                .text()
-                .await
-                .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
-            fields.insert(name, value);
+            let value = field.text().await.map_err(ApiError::bad_request)?;
+            fields.entry(name).or_default().push(value);
         }
     }
 
-    // Unwrap the image_bytes and check if it has data
-    let image_data =
-        image_bytes.ok_or((StatusCode::BAD_REQUEST, "No image provided".to_string()))?;
+    if uploads.is_empty() {
+        return Err(ApiError::bad_request("No image provided"));
+    }
+
+    store_uploads(repo, thumbnail_queue, config, uploads, fields).await
+}
 
-    if image_data.is_empty() {
-        return Err((StatusCode::BAD_REQUEST, "Image is empty".to_string()));
+/// Checks every upload, then creates their images and queues the thumbnails. `fields` are
+/// the form values, matched to the uploads as described on [`image_add`].
+async fn store_uploads(
+    repo: &Arc<dyn IImageRepository + Send + Sync>,
+    thumbnail_queue: &ThumbnailQueue,
+    config: &AppConfig,
+    uploads: Vec<upload::TempUpload>,
+    fields: std::collections::HashMap<String, Vec<String>>,
+) -> Result<Vec<ImageModel>, ApiError> {
+    let batch = uploads.len() > 1;
+
+    // A field is sent once for every file or once per file, anything else is ambiguous
+    for (name, values) in &fields {
+        if values.len() > 1 && values.len() != uploads.len() {
+            return Err(ApiError::bad_request(format!(
+                "Got {} '{}' fields for {} files, send it once or once per file",
+                values.len(),
+                name,
+                uploads.len()
+            )));
+        }
     }
 
-    // Load image to get dimensions
-    let img = ImageReader::new(std::io::Cursor::new(&image_data))
-        .with_guessed_format()
-        .map_err(|e| {
-            (
-                StatusCode::BAD_REQUEST,
-                format!("Invalid image format: {}", e),
-            )
-        })?
-        .decode()
-        .map_err(|e| {
-            (
-                StatusCode::BAD_REQUEST,
-                format!("Failed to decode image: {}", e),
-            )
-        })?;
-    let (width, height) = (img.width(), img.height());
-    let images_dir = images_dir();
-    fs::create_dir_all(&images_dir)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut prepared = Vec::with_capacity(uploads.len());
+
+    for (index, upload) in uploads.into_iter().enumerate() {
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .and_then(|values| match values.len() {
+                    1 => values.first(),
+                    _ => values.get(index),
+                })
+                .cloned()
+        };
+        let filename = field("filename").unwrap_or_default();
+        let item = prepare_upload(repo, config, upload, field)
+            .await
+            .and_then(|item| {
+                // Stored images were checked already, compare with the rest of the batch too
+                let phash = item.dto.phash.unwrap_or_default();
+                let duplicate = prepared.iter().position(|other: &PreparedUpload| {
+                    let other = other.dto.phash.unwrap_or_default();
+                    imaging::hamming_distance(phash as u64, other as u64)
+                        <= config.duplicate_distance
+                });
+
+                match duplicate {
+                    Some(other) if config.detect_duplicates => Err(ApiError::new(
+                        StatusCode::CONFLICT,
+                        format!("Image is a duplicate of file {}.", other + 1),
+                    )),
+                    _ => Ok(item),
+                }
+            })
+            .map_err(|e| match batch {
+                true => ApiError::new(
+                    e.status,
+                    format!("File {} ({}): {}", index + 1, filename, e.message),
+                ),
+                false => e,
+            })?;
+        prepared.push(item);
+    }
 
-    // start a transaction in case saving the image fails
-    let transaction = repo
-        .begin_transaction()
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let content_locks = storage::lock_contents(
+        prepared
+            .iter()
+            .filter_map(|item| item.dto.content_hash.as_deref()),
+    )
+    .await;
+    let saved = repo
+        .with_transaction({
+            let images_dir = config.images_dir.clone();
+            move |txn| Box::pin(async move { save_uploads(txn, &images_dir, prepared).await })
+        })
+        .await?;
+    // The new rows are committed, so a removal now counts them as references
+    drop(content_locks);
+
+    let mut models = Vec::with_capacity(saved.len());
+
+    for (image_model, file_path, original) in saved {
+        // Thumbnails are generated in the background; the image is usable right away
+        let job = ThumbnailJob {
+            id: image_model.id,
+            file_path,
+            original,
+        };
+
+        if let Err(e) = thumbnail_queue.enqueue(job).await {
+            tracing::error!(
+                "Failed to queue thumbnails for image {}: {}",
+                image_model.id,
+                e
+            );
+            thumbnails::mark_failed(repo, image_model.id).await;
+        }
+
+        models.push(image_model);
+    }
+
+    Ok(models)
+}
+
+/// Creates the records of `prepared` on `txn` and moves their files into place, returning
+/// each image with the path its thumbnails are named after and its original. The files
+/// moved before a failure are removed again, as the transaction is rolled back.
+async fn save_uploads(
+    txn: &DatabaseTransaction,
+    images_dir: &Path,
+    prepared: Vec<PreparedUpload>,
+) -> Result<Vec<(ImageModel, PathBuf, PathBuf)>> {
+    let mut saved = Vec::with_capacity(prepared.len());
+    let mut placed = vec![];
+    let result = async {
+        for item in prepared {
+            let image_model = insert_image_with_tags(txn, item.dto).await?;
+            let file_path = storage::image_path(images_dir, image_model.id, &item.extension);
+            let original = storage::original_path(images_dir, &image_model);
+
+            // A content addressed original may already be stored by an earlier upload
+            if !original.exists() {
+                if let Some(parent) = original.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                item.upload
+                    .persist(&original)
+                    .map_err(|e| anyhow!("Failed to save image: {}", e))?;
+                placed.push(original.clone());
+            }
+
+            saved.push((image_model, file_path, original));
+        }
+
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+
+    if let Err(e) = result {
+        for path in placed {
+            if let Err(e) = fs::remove_file(&path) {
+                tracing::warn!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+
+        return Err(e);
+    }
 
-    let mime_type = fields.get("mime_type").cloned().unwrap_or_default();
-    let filename = fields.get("filename").cloned().unwrap_or_default();
-    let mut extension = if filename.is_empty() {
-        None
+    Ok(saved)
+}
+
+/// Starts a resumable upload of `Upload-Length` bytes and returns 201 with its URL in
+/// `Location`. The optional JSON body holds the form fields of `POST /images` for the
+/// image, e.g. `{"title": "Cat", "tags": "cats"}`.
+async fn upload_create(
+    Extension(uploads): Extension<ResumableUploads>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Result<Response, ApiError> {
+    let length = resumable::header_u64(&headers, &UPLOAD_LENGTH_HEADER)?;
+
+    if length == 0 {
+        return Err(ApiError::bad_request("Image is empty"));
+    }
+
+    if length > config.max_upload_size as u64 {
+        return Err(ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Image is larger than {} bytes.", config.max_upload_size),
+        ));
+    }
+
+    let metadata = match body.is_empty() {
+        true => Default::default(),
+        false => serde_json::from_slice(&body).map_err(ApiError::bad_request)?,
+    };
+    let id = uploads.create(&UploadInfo { length, metadata }).await?;
+    let headers = [
+        (header::LOCATION, format!("/uploads/{id}")),
+        (UPLOAD_OFFSET_HEADER, "0".to_string()),
+        (UPLOAD_LENGTH_HEADER, length.to_string()),
+    ];
+    Ok((StatusCode::CREATED, headers).into_response())
+}
+
+/// Reports the bytes received in `Upload-Offset`, so an interrupted client knows where to
+/// resume.
+async fn upload_status(
+    Extension(uploads): Extension<ResumableUploads>,
+    axum_path(id): axum_path<String>,
+) -> Result<Response, ApiError> {
+    let (info, offset) = uploads.status(resumable::parse_id(&id)?).await?;
+    let headers = [
+        (UPLOAD_OFFSET_HEADER, offset.to_string()),
+        (UPLOAD_LENGTH_HEADER, info.length.to_string()),
+        (header::CACHE_CONTROL, "no-store".to_string()),
+    ];
+    Ok(headers.into_response())
+}
+
+/// Appends the body to the upload, which must have received `Upload-Offset` bytes so far,
+/// and returns 204 with the new offset. The chunk completing the upload stores the image as
+/// `POST /images` would and returns it with 201.
+async fn upload_append(
+    Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
+    Extension(thumbnail_queue): Extension<ThumbnailQueue>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    Extension(uploads): Extension<ResumableUploads>,
+    axum_path(id): axum_path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, ApiError> {
+    let id = resumable::parse_id(&id)?;
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+
+    if content_type != Some(resumable::CHUNK_CONTENT_TYPE) {
+        return Err(ApiError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Chunks must be sent as {}.", resumable::CHUNK_CONTENT_TYPE),
+        ));
+    }
+
+    let offset = resumable::header_u64(&headers, &UPLOAD_OFFSET_HEADER)?;
+    let (info, offset) = uploads.append(id, offset, body.into_data_stream()).await?;
+    let offset_header = [(UPLOAD_OFFSET_HEADER, offset.to_string())];
+
+    if offset < info.length {
+        return Ok((StatusCode::NO_CONTENT, offset_header).into_response());
+    }
+
+    let (info, upload) = uploads.finish(id).await?;
+    let fields = info
+        .metadata
+        .into_iter()
+        .map(|(name, value)| (name, vec![value]))
+        .collect();
+    let mut models = store_uploads(&repo, &thumbnail_queue, &config, vec![upload], fields).await?;
+    Ok((StatusCode::CREATED, offset_header, Json(models.remove(0))).into_response())
+}
+
+/// Cancels a resumable upload.
+async fn upload_delete(
+    Extension(uploads): Extension<ResumableUploads>,
+    axum_path(id): axum_path<String>,
+) -> Result<StatusCode, ApiError> {
+    uploads.remove(resumable::parse_id(&id)?).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Checks and decodes one uploaded file. `field` returns the form value meant for this file.
+async fn prepare_upload(
+    repo: &Arc<dyn IImageRepository + Send + Sync>,
+    config: &AppConfig,
+    mut upload: upload::TempUpload,
+    field: impl Fn(&str) -> Option<String>,
+) -> Result<PreparedUpload, ApiError> {
+    if upload.size() == 0 {
+        return Err(ApiError::bad_request("Image is empty"));
+    }
+
+    // Don't trust the client's mime_type; check it against what the content actually is
+    let format = imaging::detect_format(
+        upload.open()?,
+        field("mime_type").as_deref().unwrap_or_default(),
+    )
+    .map_err(|e| ApiError::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, e))?;
+
+    // Read the header first so absurdly large images are rejected before decoding
+    let (original_width, original_height) = imaging::read_dimensions(upload.open()?)
+        .map_err(|e| ApiError::bad_request(format!("Invalid image format: {}", e)))?;
+
+    if imaging::exceeds_limit(original_width, original_height) {
+        return Err(ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "Image dimensions {}x{} exceed the limit of {} pixels.",
+                original_width,
+                original_height,
+                imaging::IMAGE_DIMENSION_LIMIT
+            ),
+        ));
+    }
+
+    // Load image to get dimensions
+    let (mut img, _) = imaging::decode(upload.open()?).map_err(ApiError::bad_request)?;
+    // Turn the pixels upright so the thumbnails and the hash don't depend on the camera's
+    // orientation
+    let exif = imaging::read_exif(upload.open()?);
+    imaging::apply_orientation(&mut img, exif.orientation);
+    // Orientations 5 to 8 turn the image a quarter, report the original size upright too
+    let (original_width, original_height) = if exif.orientation.is_some_and(|o| o >= 5) {
+        (original_height, original_width)
     } else {
-        Path::new(&filename).extension().and_then(|x| x.to_str())
+        (original_width, original_height)
     };
 
-    if extension.is_none() {
-        extension = if !mime_type.is_empty() {
-            get_mime_extensions_str(&mime_type)
-                .and_then(|x| x.first())
-                .map(|x| *x)
-        } else {
-            None
+    // Hash the full size image so the downscaled copies of the same picture still match
+    let phash = imaging::dhash(&img) as i64;
+
+    if config.detect_duplicates {
+        let existing = repo
+            .find_by_phash_within(phash, config.duplicate_distance)
+            .await?;
+
+        if let Some(existing) = existing {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("Image is a duplicate of image {}.", existing.id),
+            ));
         }
     }
 
-    let extension = extension.unwrap_or("bin");
-    let title = fields.get("title").cloned().unwrap_or(filename.clone());
-    let alt_text = fields.get("alt_text").cloned().unwrap_or(title.clone());
+    // Downscale the stored original if it exceeds the configured maximum dimension
+    if let Some(resized) = imaging::fit_within(&img, config.max_image_dimension) {
+        let data = imaging::encode(&resized, format)
+            .map_err(|e| ApiError::internal(format!("Failed to downscale image: {}", e)))?;
+        // Stored instead of the upload, whose file is removed as it is replaced
+        upload = upload::TempUpload::from_bytes(&storage::incoming_dir(&config.images_dir), &data)?;
+        tracing::info!(
+            "Downscaled image from {}x{} to {}x{}",
+            original_width,
+            original_height,
+            resized.width(),
+            resized.height()
+        );
+        img = resized;
+    }
+
+    let (width, height) = (img.width(), img.height());
+    let file_size = upload.size() as i64;
+    // Hash what is actually stored, the downscaled copy when there is one
+    let content_hash = config
+        .content_addressed_storage
+        .then(|| upload.sha256().to_owned());
+
+    let mime_type = format.to_mime_type().to_string();
+    let filename = field("filename").unwrap_or_default();
+    // Keep the client's extension only if it agrees with the detected format
+    let extension = Path::new(&filename)
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_lowercase())
+        .filter(|x| format.extensions_str().contains(&x.as_str()))
+        .or_else(|| {
+            get_mime_extensions_str(&mime_type)
+                .and_then(|x| x.first())
+                .map(|x| (*x).to_owned())
+        })
+        .unwrap_or_else(|| format.extensions_str()[0].to_owned());
+    let title = field("title").unwrap_or(filename.clone());
+    let alt_text = field("alt_text").unwrap_or(title.clone());
 
     // Assign the missing information to the following image model and let the repository create the data record
-    let image_model = CreateImageDto {
+    let dto = CreateImageDto {
         title: title,
-        description: Some(fields.get("description").cloned().unwrap_or_default()),
-        extension: extension.to_string(),
-        file_size: image_data.len() as i64,
+        description: Some(field("description").unwrap_or_default()),
+        extension: extension.clone(),
+        file_size,
         mime_type: mime_type,
         width: Some(width as i32),
         height: Some(height as i32),
         alt_text: Some(alt_text),
-        tags: Some(fields.get("tags").cloned().unwrap_or_default()),
-    };
-
-    let image_model = match repo.create_with_tags(image_model).await {
-        Ok(image_model) => image_model,
-        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        original_width: Some(original_width as i32),
+        original_height: Some(original_height as i32),
+        phash: Some(phash),
+        captured_at: exif.captured_at,
+        camera_model: exif.camera_model,
+        orientation: exif.orientation.map(|v| v as i16),
+        content_hash,
+        tags: Some(field("tags").unwrap_or_default()),
     };
 
-    // Save the image file
-    let filename = format!("{}.{}", image_model.id, extension);
-    let file_path = images_dir.join(&filename);
-    fs::write(&file_path, &image_data).map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to save image: {}", e),
-        )
-    })?;
-
-    // Create thumbnail keeping aspect ratio (max 256px on longest side)
-    let thumbnail = img.thumbnail(256, 256);
-    let thumb_path = images_dir.join(&get_image_thumb_name(&filename));
-    thumbnail.save(&thumb_path).map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to save thumbnail: {}", e),
-        )
-    })?;
-
-    match transaction.commit().await {
-        Ok(_) => Ok(Json(image_model)),
-        Err(e) => {
-            let _ = fs::remove_file(&file_path);
-            let _ = fs::remove_file(&thumb_path);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
-        }
-    }
+    Ok(PreparedUpload {
+        dto,
+        extension,
+        upload,
+    })
 }
 
 async fn image_update(
     Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
     axum_path(id): axum_path<i64>,
     Json(image): Json<UpdateImageDto>,
-) -> Result<Json<ImageModel>, (StatusCode, String)> {
+) -> Result<Json<ImageModel>, ApiError> {
     match repo.update(id, image).await {
         Ok(updated) => Ok(Json(updated)),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Err(e) => Err(e.into()),
     }
 }
 
 async fn image_delete(
     Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
+    Extension(config): Extension<Arc<AppConfig>>,
     axum_path(id): axum_path<i64>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    // start a transaction in case saving the image fails
-    let transaction = repo
-        .begin_transaction()
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+) -> Result<impl IntoResponse, ApiError> {
     let image = repo
         .get(id)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .ok_or((StatusCode::NOT_FOUND, "Image not found.".to_string()))?;
-    repo.delete_related(id)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    if let Err(e) = repo.delete(id).await {
-        return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+        .await?
+        .ok_or_else(|| ApiError::not_found("Image not found."))?;
+    repo.delete_with_related(id).await?;
+
+    // Remove the files only once the record is gone. Soft deleted images keep them until
+    // they are purged.
+    if !repo.soft_deletes() {
+        maintenance::remove_image_files(repo.as_ref(), &config.images_dir, &image).await?;
     }
 
-    let images_dir = images_dir();
-    let filepath = images_dir.join(format!("{}.{}", id, image.extension));
+    Ok((StatusCode::NO_CONTENT, ()))
+}
 
-    if filepath.exists() {
-        if let Err(e) = fs::remove_file(&filepath) {
-            tracing::warn!("{}", e);
-        }
+async fn image_restore(
+    Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
+    axum_path(id): axum_path<i64>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !repo.restore(id).await? {
+        return Err(ApiError::not_found("Deleted image not found."));
     }
 
-    let thumbpath = get_image_thumb_path(filepath);
+    Ok((StatusCode::NO_CONTENT, ()))
+}
 
-    if thumbpath.exists() {
-        if let Err(e) = fs::remove_file(&thumbpath) {
-            tracing::warn!("{}", e);
-        }
+async fn image_thumb(
+    Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    axum_path(id): axum_path<i64>,
+) -> Result<impl IntoResponse, ApiError> {
+    serve_image_thumb(repo, &config, id, imaging::DEFAULT_THUMBNAIL_SIZE).await
+}
+
+async fn image_thumb_size(
+    Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    axum_path((id, size)): axum_path<(i64, u32)>,
+) -> Result<impl IntoResponse, ApiError> {
+    serve_image_thumb(repo, &config, id, size).await
+}
+
+async fn image_resized(
+    Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    axum_path(id): axum_path<i64>,
+    Query(params): Query<resizing::ResizeParams>,
+) -> Result<Response, ApiError> {
+    params.validate().map_err(ApiError::bad_request)?;
+    let image = repo
+        .get(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Image not found."))?;
+    let filepath = storage::image_path(&config.images_dir, id, &image.extension);
+    let original = storage::original_path(&config.images_dir, &image);
+
+    if !original.exists() {
+        return Err(ApiError::not_found("Image file not found."));
     }
 
-    match transaction.commit().await {
-        Ok(_) => Ok((StatusCode::NO_CONTENT, ())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    let resized_path =
+        tokio::task::spawn_blocking(move || resizing::resize_cached(&original, &filepath, &params))
+            .await??;
+    serve_file(&resized_path).await
+}
+
+async fn image_original(
+    Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
+    Extension(config): Extension<Arc<AppConfig>>,
+    axum_path(id): axum_path<i64>,
+) -> Result<Response, ApiError> {
+    let image = repo
+        .get(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Image not found."))?;
+    let original = storage::original_path(&config.images_dir, &image);
+
+    if !original.exists() {
+        return Err(ApiError::not_found("Image file not found."));
     }
+
+    serve_file(&original).await
 }
 
 async fn image_tag_list(
     Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
     axum_path(id): axum_path<i64>,
-) -> Result<Json<ResultSet<TagModel>>, (StatusCode, String)> {
-    match repo.list_tags(id, None, None).await {
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<ResultSet<TagModel>>, ApiError> {
+    match repo
+        .list_tags(id, None, Some(pagination.normalized()))
+        .await
+    {
         Ok(tags) => Ok(Json(tags)),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Err(e) => Err(e.into()),
     }
 }
 
+/// Adds every tag in the request, then returns all of the image's tags. Tags the image
+/// already has are left alone.
 async fn image_tag_add(
     Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
     axum_path(id): axum_path<i64>,
     Json(payload): Json<AddTagRequest>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    match repo.add_tags_from_str(id, &payload.tag).await {
-        Ok(_) => Ok((StatusCode::NO_CONTENT, ())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+) -> Result<Json<ResultSet<TagModel>>, ApiError> {
+    let mut names = payload.tags;
+
+    if let Some(tag) = payload.tag {
+        names.extend(tag.split(',').map(str::to_owned));
+    }
+
+    if names.iter().all(|name| name.trim().is_empty()) {
+        return Err(ApiError::bad_request("No tag given."));
+    }
+
+    if repo.get(id).await?.is_none() {
+        return Err(ApiError::not_found("Image not found."));
     }
+
+    repo.add_tag_names(id, &names).await?;
+    Ok(Json(repo.list_tags(id, None, None).await?))
 }
 
 async fn image_tag_remove(
     Extension(repo): Extension<Arc<dyn IImageRepository + Send + Sync>>,
     axum_path((id, tag_id)): axum_path<(i64, i64)>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+) -> Result<impl IntoResponse, ApiError> {
     match repo.remove_tag(id, tag_id).await {
         Ok(_) => Ok((StatusCode::NO_CONTENT, ())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Err(e) => Err(e.into()),
     }
 }
 
 async fn tag_list(
     Extension(repo): Extension<Arc<dyn ITagRepository + Send + Sync>>,
-) -> Result<Json<ResultSet<TagModel>>, (StatusCode, String)> {
-    match repo.list(None, None).await {
+    Query(after): Query<AfterQuery>,
+    list: ListQuery,
+) -> Result<Json<ResultSet<TagModel>>, ApiError> {
+    let order_by = list.order_by(TAG_SORT_COLUMNS)?;
+
+    let result = match after.after {
+        Some(_) if order_by.is_some() => {
+            return Err(ApiError::bad_request("after can't be combined with sort."));
+        }
+        Some(after) => {
+            repo.list_after(
+                Some(db::repositories::Cursor { after }),
+                list.pagination.page_size,
+            )
+            .await
+        }
+        None => repo.list(None, order_by, Some(list.pagination)).await,
+    };
+
+    match result {
         Ok(tags) => Ok(Json(tags)),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Err(e) => Err(e.into()),
     }
 }
 
 async fn tag_count(
     Extension(repo): Extension<Arc<dyn ITagRepository + Send + Sync>>,
-) -> Result<Json<u64>, (StatusCode, String)> {
+) -> Result<Json<u64>, ApiError> {
     match repo.count(None).await {
         Ok(count) => Ok(Json(count)),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn tag_image_counts(
+    Extension(repo): Extension<Arc<dyn ITagRepository + Send + Sync>>,
+) -> Result<Json<Vec<TagCount>>, ApiError> {
+    match repo.image_counts(None).await {
+        Ok(counts) => Ok(Json(
+            counts
+                .into_iter()
+                .map(|(tag, count)| TagCount { tag, count })
+                .collect(),
+        )),
+        Err(e) => Err(e.into()),
     }
 }
 
+/// Everything the landing page shows, with one query for the images and two for the tags.
+async fn stats(
+    Extension(images): Extension<Arc<dyn IImageRepository + Send + Sync>>,
+    Extension(tags): Extension<Arc<dyn ITagRepository + Send + Sync>>,
+) -> Result<Json<Stats>, ApiError> {
+    let (totals, tag_total, counts) = tokio::try_join!(
+        images.totals(),
+        tags.count(None),
+        tags.image_counts(Some(STATS_TOP_TAGS))
+    )?;
+    let top_tags = counts
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(tag, count)| TagCount { tag, count })
+        .collect();
+
+    Ok(Json(Stats {
+        images: totals.count,
+        tags: tag_total,
+        total_bytes: totals.total_bytes,
+        newest_image_at: totals.newest_created_at,
+        top_tags,
+    }))
+}
+
 async fn tag_get(
     Extension(repo): Extension<Arc<dyn ITagRepository + Send + Sync>>,
     axum_path(id): axum_path<i64>,
-) -> Result<Json<TagModel>, (StatusCode, String)> {
+) -> Result<Json<TagModel>, ApiError> {
     match repo.get(id).await {
         Ok(Some(tag)) => Ok(Json(tag)),
-        Ok(None) => Err((StatusCode::NOT_FOUND, "Tag not found".to_string())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Ok(None) => Err(ApiError::not_found("Tag not found")),
+        Err(e) => Err(e.into()),
     }
 }
 
 async fn tag_add(
     Extension(repo): Extension<Arc<dyn ITagRepository + Send + Sync>>,
     Json(tag): Json<TagModel>,
-) -> Result<Json<TagModel>, (StatusCode, String)> {
+) -> Result<Json<TagModel>, ApiError> {
     match repo.create(tag).await {
         Ok(created) => Ok(Json(created)),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -515,88 +1294,128 @@ async fn tag_update(
     Extension(repo): Extension<Arc<dyn ITagRepository + Send + Sync>>,
     axum_path(id): axum_path<i64>,
     Json(tag): Json<UpdateTagDto>,
-) -> Result<Json<TagModel>, (StatusCode, String)> {
+) -> Result<Json<TagModel>, ApiError> {
+    let name = tag.name.as_deref().map(normalize_tag_name);
+
     match repo.update(id, tag).await {
         Ok(updated) => Ok(Json(updated)),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        // The unique index on the name decides, so two renames can't both get through
+        Err(e) if is_unique_violation(&e) => Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("Tag '{}' already exists.", name.unwrap_or_default()),
+        )),
+        Err(e) => Err(e.into()),
     }
 }
 
+fn is_unique_violation(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<DbErr>().and_then(DbErr::sql_err),
+        Some(SqlErr::UniqueConstraintViolation(_))
+    )
+}
+
 async fn tag_delete(
     Extension(repo): Extension<Arc<dyn ITagRepository + Send + Sync>>,
     axum_path(id): axum_path<i64>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let transaction = repo
-        .begin_transaction()
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    repo.delete_related(id)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    repo.delete(id)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    transaction
-        .commit()
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+) -> Result<impl IntoResponse, ApiError> {
+    repo.delete_with_related(id).await?;
+    Ok((StatusCode::NO_CONTENT, ()))
+}
+
+async fn tag_restore(
+    Extension(repo): Extension<Arc<dyn ITagRepository + Send + Sync>>,
+    axum_path(id): axum_path<i64>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !repo.restore(id).await? {
+        return Err(ApiError::not_found("Deleted tag not found."));
+    }
+
     Ok((StatusCode::NO_CONTENT, ()))
 }
 
 async fn tag_image_list(
     Extension(repo): Extension<Arc<dyn ITagRepository + Send + Sync>>,
     axum_path(id): axum_path<i64>,
-) -> Result<Json<ResultSet<ModelWithRelated<ImageModel, TagModel>>>, (StatusCode, String)> {
-    match repo.list_images(id, None, None, None).await {
+    list: ListQuery,
+) -> Result<Json<ResultSet<ModelWithRelated<ImageModel, TagModel>>>, ApiError> {
+    let order_by = list.order_by(IMAGE_SORT_COLUMNS)?;
+
+    match repo
+        .list_images(id, None, None, order_by, Some(list.pagination))
+        .await
+    {
         Ok(images) => Ok(Json(images)),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Err(e) => Err(e.into()),
     }
 }
 
 async fn tag_image_add(
     Extension(repo): Extension<Arc<dyn ITagRepository + Send + Sync>>,
     axum_path((id, image_id)): axum_path<(i64, i64)>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+) -> Result<impl IntoResponse, ApiError> {
     match repo.add_image(id, image_id).await {
         Ok(_) => Ok((StatusCode::NO_CONTENT, ())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Err(e) => Err(e.into()),
     }
 }
 
 async fn tag_image_remove(
     Extension(repo): Extension<Arc<dyn ITagRepository + Send + Sync>>,
     axum_path((id, image_id)): axum_path<(i64, i64)>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+) -> Result<impl IntoResponse, ApiError> {
     match repo.remove_image(id, image_id).await {
         Ok(_) => Ok((StatusCode::NO_CONTENT, ())),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Err(e) => Err(e.into()),
     }
 }
 
 // helper functions
-fn images_dir() -> PathBuf {
-    let images_env_dir = std::env::var("IMAGES_DIR").unwrap_or("data/images".to_string());
-    PathBuf::from(images_env_dir)
-}
+async fn serve_image_thumb(
+    repo: Arc<dyn IImageRepository + Send + Sync>,
+    config: &AppConfig,
+    id: i64,
+    size: u32,
+) -> Result<Response, ApiError> {
+    let image = repo
+        .get(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("Image not found."))?;
 
-fn get_image_thumb_name(filename: &str) -> String {
-    if filename.is_empty() {
-        return filename.to_owned();
+    if image.thumbnail_failed {
+        return Err(ApiError::internal("Thumbnail generation failed."));
     }
 
-    let path = Path::new(filename);
-    let base_name = path.file_stem().unwrap_or_default().to_string_lossy();
-    let extension = path.extension().unwrap_or_default().to_string_lossy();
-    format!("{}_thumb.{}", base_name, extension)
+    if !image.thumbnail_ready {
+        return Response::builder()
+            .status(StatusCode::ACCEPTED)
+            .header(header::RETRY_AFTER, "1")
+            .body(Body::empty())
+            .map_err(ApiError::from);
+    }
+
+    let filepath = storage::image_path(&config.images_dir, id, &image.extension);
+    let thumb_path = imaging::find_image_thumb_path(&filepath, size, config.thumbnails.format)
+        .ok_or_else(|| ApiError::not_found("Thumbnail not found."))?;
+    serve_file(&thumb_path).await
 }
 
-fn get_image_thumb_path<P: AsRef<Path>>(filename: P) -> PathBuf {
-    let path = filename.as_ref();
-    let parent = path.parent().unwrap_or_else(|| Path::new(""));
-    let base_name = path.file_stem().unwrap_or_default().to_string_lossy();
-    let extension = path.extension().unwrap_or_default().to_string_lossy();
-    let thumb_file_name = format!("{}_thumb.{}", base_name, extension);
-    parent.join(thumb_file_name)
+async fn serve_file(path: &Path) -> Result<Response, ApiError> {
+    let content_type = mime_guess::from_path(path).first_or_octet_stream();
+    let file = tokio::fs::File::open(path).await?;
+    let metadata = file.metadata().await?;
+    let stream = ReaderStream::new(file);
+    let body = Body::from_stream(stream);
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type.as_ref());
+
+    for (name, value) in caching::file_headers(&metadata) {
+        response = response.header(name, value);
+    }
+
+    let response = response.body(body)?;
+    Ok(response)
 }
 
 fn parse_i64(s: Option<&String>) -> Option<i64> {
@@ -606,3 +1425,757 @@ fn parse_i64(s: Option<&String>) -> Option<i64> {
 fn parse_i32(s: Option<&String>) -> Option<i32> {
     s.and_then(|v| v.parse::<i32>().ok())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::{DynamicImage, ImageFormat, RgbImage};
+    use axum::http::Request;
+    use thumbs::testing::dto;
+    use tower::ServiceExt;
+
+    const BOUNDARY: &str = "thumbs-test-boundary";
+
+    fn png(shade: u8) -> Vec<u8> {
+        let img = RgbImage::from_fn(8, 8, |x, _| ::image::Rgb([shade, x as u8 * 30, 0]));
+        imaging::encode(&DynamicImage::ImageRgb8(img), ImageFormat::Png).unwrap()
+    }
+
+    fn multipart_body(parts: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut body = vec![];
+
+        for (name, value) in parts {
+            body.extend_from_slice(format!("--{BOUNDARY}\r\n").as_bytes());
+            body.extend_from_slice(
+                format!("Content-Disposition: form-data; name=\"{name}\"\r\n\r\n").as_bytes(),
+            );
+            body.extend_from_slice(value);
+            body.extend_from_slice(b"\r\n");
+        }
+
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+        body
+    }
+
+    async fn setup() -> (Router, Arc<dyn IImageRepository + Send + Sync>, PathBuf) {
+        setup_with(&[]).await
+    }
+
+    /// Like [`setup`], with `vars` added to the configuration.
+    async fn setup_with(
+        vars: &[(&str, &str)],
+    ) -> (Router, Arc<dyn IImageRepository + Send + Sync>, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("thumbs-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        // The handler's transaction holds a connection while the repository uses another,
+        // which the single connection of an in-memory database can't do
+        let db = setup_database(&format!("sqlite://{}", dir.join("test.db").display()))
+            .await
+            .unwrap();
+        let images_dir = dir.to_string_lossy().into_owned();
+        let config = AppConfig::from_lookup(|name| match name {
+            "DATABASE_URL" => Some("sqlite::memory:".to_string()),
+            "IMAGES_DIR" => Some(images_dir.clone()),
+            "DETECT_DUPLICATES" => Some("false".to_string()),
+            _ => vars
+                .iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| (*value).to_string()),
+        })
+        .unwrap();
+        let repo: Arc<dyn IImageRepository + Send + Sync> = Arc::new(ImageRepository::new(db));
+        let queue = ThumbnailQueue::spawn(repo.clone(), 4, config.thumbnails.clone());
+        let uploads = ResumableUploads::new(&config.images_dir, Duration::from_secs(60));
+        let app = setup_router(&config)
+            .layer(Extension(uploads))
+            .layer(Extension(Arc::new(config)))
+            .layer(Extension(queue))
+            .layer(Extension(IdempotencyKeys::new(Duration::from_secs(60))))
+            .layer(Extension(repo.clone()));
+        (app, repo, dir)
+    }
+
+    /// A PNG of noise, which hardly compresses, so it takes about `3 * side * side` bytes.
+    fn noisy_png(side: u32) -> Vec<u8> {
+        let mut state = 0x2545_f491_u32;
+        let img = RgbImage::from_fn(side, side, |_, _| {
+            // xorshift32
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let [r, g, b, _] = state.to_le_bytes();
+            ::image::Rgb([r, g, b])
+        });
+        imaging::encode(&DynamicImage::ImageRgb8(img), ImageFormat::Png).unwrap()
+    }
+
+    async fn post_images(app: Router, parts: &[(&str, &[u8])]) -> (StatusCode, serde_json::Value) {
+        post_images_with_key(app, None, parts).await
+    }
+
+    async fn post_images_with_key(
+        app: Router,
+        idempotency_key: Option<&str>,
+        parts: &[(&str, &[u8])],
+    ) -> (StatusCode, serde_json::Value) {
+        let mut request = Request::post("/images").header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        );
+
+        if let Some(key) = idempotency_key {
+            request = request.header(idempotency::IDEMPOTENCY_KEY_HEADER, key);
+        }
+
+        let request = request.body(Body::from(multipart_body(parts))).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn resumable_upload_is_stored_once_complete() {
+        let (app, repo, dir) = setup().await;
+        let data = png(40);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::post("/uploads")
+                    .header(UPLOAD_LENGTH_HEADER, data.len())
+                    .body(Body::from(r#"{"title": "chunked", "tags": "cats"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()[&UPLOAD_OFFSET_HEADER], "0");
+        let location = response.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let append = |offset: usize, chunk: &[u8]| {
+            Request::patch(&location)
+                .header(header::CONTENT_TYPE, resumable::CHUNK_CONTENT_TYPE)
+                .header(UPLOAD_OFFSET_HEADER, offset)
+                .body(Body::from(chunk.to_vec()))
+                .unwrap()
+        };
+        let half = data.len() / 2;
+
+        let response = app.clone().oneshot(append(0, &data[..half])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[&UPLOAD_OFFSET_HEADER], half.to_string());
+
+        // A client that lost the response asks where to resume
+        let response = app
+            .clone()
+            .oneshot(Request::head(&location).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[&UPLOAD_OFFSET_HEADER], half.to_string());
+        let response = app.clone().oneshot(append(0, &data[..half])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(repo.count(None).await.unwrap(), 0);
+
+        let response = app
+            .clone()
+            .oneshot(append(half, &data[half..]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let image: ImageModel = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(image.title, "chunked");
+        assert_eq!(image.file_size, data.len() as i64);
+        let tags = repo.list_tags(image.id, None, None).await.unwrap();
+        assert_eq!(tags.data[0].name, "cats");
+
+        let response = app
+            .oneshot(Request::head(&location).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    async fn get_about(accept: Option<&str>) -> Response {
+        let app = Router::new().route("/about", get(about));
+        let mut request = Request::get("/about");
+
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body_text(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn about_is_served_as_markdown() {
+        let response = get_about(None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/markdown; charset=utf-8"
+        );
+        assert_eq!(response.headers()[header::VARY], "accept");
+        assert!(body_text(response).await.starts_with("# Lorem Ipsum"));
+    }
+
+    #[tokio::test]
+    async fn about_is_rendered_when_html_is_accepted() {
+        let response = get_about(Some("text/html,application/xhtml+xml;q=0.9,*/*;q=0.8")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+        let html = body_text(response).await;
+        assert!(html.starts_with("<h1>Lorem Ipsum</h1>"), "{html}");
+        assert!(html.contains("<hr />"));
+    }
+
+    #[tokio::test]
+    async fn missing_markdown_is_not_found() {
+        let error = serve_markdown(Path::new("static/missing.md"), &HeaderMap::new())
+            .await
+            .unwrap_err();
+        assert_eq!(error.status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn failed_thumbnails_are_not_awaited_forever() {
+        let (app, repo, dir) = setup().await;
+        let (status, body) = post_images(app.clone(), &[("image_file", &png(0))]).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let id = body["id"].as_i64().unwrap();
+        let get_thumb = || Request::get(format!("/images/{id}/thumb")).body(Body::empty());
+
+        // Let the worker finish, then pretend it gave up before it got to the image
+        for _ in 0..100 {
+            if repo.get(id).await.unwrap().unwrap().thumbnail_ready {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        repo.set_thumbnail_ready(id, false).await.unwrap();
+        let response = app.clone().oneshot(get_thumb().unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        repo.set_thumbnail_failed(id).await.unwrap();
+        let response = app.oneshot(get_thumb().unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn single_upload_returns_one_image() {
+        let (app, repo, dir) = setup().await;
+        let (status, body) = post_images(
+            app,
+            &[
+                ("title", b"one"),
+                ("filename", b"one.png"),
+                ("image_file", &png(0)),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["title"], "one");
+        assert_eq!(repo.count(None).await.unwrap(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn upload_of_several_megabytes_is_streamed_into_place() {
+        let (app, repo, dir) = setup().await;
+        // Over the 2 MB axum allows unless the route raises the limit
+        let data = noisy_png(1200);
+        assert!(data.len() > 4 * 1024 * 1024);
+        let (status, body) = post_images(app, &[("title", b"large"), ("image_file", &data)]).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["file_size"], data.len());
+
+        let image = repo
+            .get(body["id"].as_i64().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            fs::read(storage::original_path(&dir, &image)).unwrap(),
+            data
+        );
+        assert_eq!(
+            fs::read_dir(storage::incoming_dir(&dir)).unwrap().count(),
+            0
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_upload_is_stored_downscaled() {
+        let (app, repo, dir) = setup_with(&[("MAX_IMAGE_DIMENSION", "50")]).await;
+        let (status, body) = post_images(app, &[("image_file", &noisy_png(120))]).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let image = repo
+            .get(body["id"].as_i64().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        let stored = ::image::open(storage::original_path(&dir, &image)).unwrap();
+        assert!(stored.width() <= 50 && stored.height() <= 50);
+        assert_eq!(
+            (image.width, image.height),
+            (Some(stored.width() as i32), Some(stored.height() as i32))
+        );
+        assert_eq!(
+            (image.original_width, image.original_height),
+            (Some(120), Some(120))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn shared_original_outlives_all_but_the_last_image() {
+        let (app, repo, dir) = setup_with(&[("CONTENT_ADDRESSED_STORAGE", "true")]).await;
+        let data = png(0);
+        let add = |title: &'static str| {
+            let app = app.clone();
+            let data = data.clone();
+            async move {
+                let (status, body) =
+                    post_images(app, &[("title", title.as_bytes()), ("image_file", &data)]).await;
+                assert_eq!(status, StatusCode::OK, "{body}");
+                body["id"].as_i64().unwrap()
+            }
+        };
+        let delete = |id: i64| {
+            let request = Request::delete(format!("/images/{id}"))
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            }
+        };
+
+        let first = add("first").await;
+        let second = add("second").await;
+        let image = repo.get(first).await.unwrap().unwrap();
+        let original = storage::original_path(&dir, &image);
+        assert_eq!(fs::read(&original).unwrap(), data);
+
+        delete(first).await;
+        assert!(original.exists());
+        delete(second).await;
+        assert!(!original.exists());
+
+        // Deleting the last image while the same bytes are uploaded again never leaves the
+        // new image without its original
+        let mut last = add("again").await;
+
+        for _ in 0..5 {
+            let ((), next) = tokio::join!(delete(last), add("again"));
+            let image = repo.get(next).await.unwrap().unwrap();
+            assert!(storage::original_path(&dir, &image).exists());
+            last = next;
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn batch_upload_creates_every_image_or_none() {
+        let (app, repo, dir) = setup().await;
+        let (status, body) = post_images(
+            app.clone(),
+            &[
+                ("title", b"first"),
+                ("title", b"second"),
+                ("tags", b"shared"),
+                ("image_file", &png(0)),
+                ("image_file", &png(200)),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let titles = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|image| image["title"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["first", "second"]);
+        assert_eq!(repo.count(None).await.unwrap(), 2);
+
+        let (status, body) = post_images(
+            app,
+            &[
+                ("filename", b"good.png"),
+                ("filename", b"bad.png"),
+                ("image_file", &png(100)),
+                ("image_file", b"not an image"),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(
+            body["error"]["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("File 2 (bad.png): ")
+        );
+        assert_eq!(repo.count(None).await.unwrap(), 2);
+        // Only the first batch's originals are left, the rejected uploads were removed
+        let files = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".png") && !name.contains("_thumb"))
+            .count();
+        assert_eq!(files, 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_insert_rolls_back_the_batch_and_its_files() {
+        let (app, repo, dir) = setup().await;
+        repo.database()
+            .execute_unprepared(
+                "CREATE TRIGGER fail_insert BEFORE INSERT ON images WHEN NEW.title = 'second' \
+                 BEGIN SELECT RAISE(ABORT, 'failed'); END",
+            )
+            .await
+            .unwrap();
+
+        let (status, body) = post_images(
+            app,
+            &[
+                ("title", b"first"),
+                ("title", b"second"),
+                ("image_file", &png(0)),
+                ("image_file", &png(200)),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{body}");
+        assert_eq!(repo.count(None).await.unwrap(), 0);
+        // Neither the first original moved into place nor the uploads are left behind
+        let files = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(".png"))
+            .count();
+        assert_eq!(files, 0);
+        assert_eq!(
+            fs::read_dir(storage::incoming_dir(&dir)).unwrap().count(),
+            0
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_or_ambiguous_batches_are_rejected() {
+        let (app, repo, dir) =
+            setup_with(&[("MAX_UPLOAD_SIZE", "1000"), ("MAX_BATCH_SIZE", "1500")]).await;
+        let data = noisy_png(16);
+        assert!((750..1000).contains(&data.len()));
+
+        let (status, body) =
+            post_images(app.clone(), &[("image_file", &data), ("image_file", &data)]).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{body}");
+
+        let (status, body) = post_images(
+            app,
+            &[
+                ("title", b"a"),
+                ("title", b"b"),
+                ("title", b"c"),
+                ("image_file", &png(0)),
+                ("image_file", &png(200)),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["error"]["message"],
+            "Got 3 'title' fields for 2 files, send it once or once per file"
+        );
+        assert_eq!(repo.count(None).await.unwrap(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn retried_upload_with_the_same_key_creates_one_image() {
+        let (app, repo, dir) = setup().await;
+        let parts: &[(&str, &[u8])] = &[("title", b"once"), ("image_file", &png(0))];
+
+        let (status, first) = post_images_with_key(app.clone(), Some("upload-1"), parts).await;
+        assert_eq!(status, StatusCode::OK, "{first}");
+        let (status, retry) = post_images_with_key(app.clone(), Some("upload-1"), parts).await;
+        assert_eq!(status, StatusCode::OK, "{retry}");
+        assert_eq!(retry["id"], first["id"]);
+        assert_eq!(repo.count(None).await.unwrap(), 1);
+
+        // A different key is a different upload
+        let (status, other) = post_images_with_key(app, Some("upload-2"), parts).await;
+        assert_eq!(status, StatusCode::OK, "{other}");
+        assert_ne!(other["id"], first["id"]);
+        assert_eq!(repo.count(None).await.unwrap(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn client_round_trips_through_a_running_server() {
+        use thumbs::client::{ThumbsClient, UploadMeta};
+
+        let dir = std::env::temp_dir().join(format!("thumbs-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let db = setup_database(&format!("sqlite://{}", dir.join("test.db").display()))
+            .await
+            .unwrap();
+        let images_dir = dir.to_string_lossy().into_owned();
+        let config = Arc::new(
+            AppConfig::from_lookup(|name| match name {
+                "DATABASE_URL" => Some("sqlite::memory:".to_string()),
+                "IMAGES_DIR" => Some(images_dir.clone()),
+                _ => None,
+            })
+            .unwrap(),
+        );
+        let images_repo: Arc<dyn IImageRepository + Send + Sync> =
+            Arc::new(ImageRepository::new(db.clone()));
+        let tags_repo: Arc<dyn ITagRepository + Send + Sync> =
+            Arc::new(TagRepository::new(db.clone()));
+        let queue = ThumbnailQueue::spawn(images_repo.clone(), 4, config.thumbnails.clone());
+        let app = setup_router(&config)
+            .layer(Extension(config))
+            .layer(Extension(db))
+            .layer(Extension(queue))
+            .layer(Extension(IdempotencyKeys::new(Duration::from_secs(60))))
+            .layer(Extension(images_repo))
+            .layer(Extension(tags_repo));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = ThumbsClient::new(format!("http://{address}/"));
+
+        let meta = UploadMeta {
+            filename: Some("client.png".to_string()),
+            title: Some("From the client".to_string()),
+            tags: Some("typed".to_string()),
+            ..Default::default()
+        };
+        let image = client.upload_image(png(0), &meta).await.unwrap();
+        assert_eq!(image.title, "From the client");
+
+        client.add_tag(image.id, "second").await.unwrap();
+        let added = client.add_tags(image.id, &["third"]).await.unwrap();
+        assert_eq!(added.total, 3);
+        let fetched = client.get_image(image.id).await.unwrap();
+        assert_eq!(fetched.item, image);
+        let mut tags = fetched
+            .related
+            .iter()
+            .map(|tag| tag.name.as_str())
+            .collect::<Vec<_>>();
+        tags.sort_unstable();
+        assert_eq!(tags, ["second", "third", "typed"]);
+        assert_eq!(client.image_tags(image.id).await.unwrap().total, 3);
+
+        let images = client.list_images(1, 10).await.unwrap();
+        assert_eq!(images.total, 1);
+        let tags = client.list_tags(1, 100).await.unwrap();
+        assert!(tags.data.iter().any(|tag| tag.name == "typed"));
+
+        client.delete_image(image.id).await.unwrap();
+        let error = client.get_image(image.id).await.unwrap_err();
+        assert_eq!(error.status, StatusCode::NOT_FOUND);
+        assert_eq!(error.message, "Image not found");
+
+        let error = client
+            .upload_image(b"not an image".to_vec(), &meta)
+            .await
+            .unwrap_err();
+        assert_eq!(error.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn renaming_a_tag_onto_another_is_a_conflict() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo: Arc<dyn ITagRepository + Send + Sync> = Arc::new(TagRepository::new(db));
+        let mut ids = vec![];
+
+        for name in ["cats", "dogs"] {
+            let tag = TagModel {
+                id: 0,
+                name: name.to_string(),
+                deleted_at: None,
+            };
+            ids.push(repo.create(tag).await.unwrap().id);
+        }
+
+        let app = Router::new()
+            .route("/tags/{id}", put(tag_update))
+            .layer(Extension(repo.clone()));
+        let rename = |id: i64, name: &str| {
+            Request::put(format!("/tags/{id}"))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::json!({ "name": name }).to_string()))
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(rename(ids[1], " CATS")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(repo.get(ids[1]).await.unwrap().unwrap().name, "dogs");
+
+        // Changing only the case of its own name is fine
+        let response = app.oneshot(rename(ids[1], "Dogs")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn several_tags_are_added_at_once() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo: Arc<dyn IImageRepository + Send + Sync> = Arc::new(ImageRepository::new(db));
+        let image = repo
+            .create_with_tags(CreateImageDto {
+                tags: Some("batch-a".to_string()),
+                ..dto("tagged")
+            })
+            .await
+            .unwrap();
+        let app = Router::new()
+            .route("/images/{id}/tags/", post(image_tag_add))
+            .layer(Extension(repo));
+        let add = |id: i64, body: serde_json::Value| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::post(format!("/images/{id}/tags/"))
+                            .header(header::CONTENT_TYPE, "application/json")
+                            .body(Body::from(body.to_string()))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let body = serde_json::from_slice::<serde_json::Value>(&bytes).unwrap();
+                let mut names = body["data"]
+                    .as_array()
+                    .map(|tags| {
+                        tags.iter()
+                            .map(|tag| tag["name"].as_str().unwrap().to_string())
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default();
+                names.sort();
+                (status, names)
+            }
+        };
+
+        // Repeated and already added tags are skipped
+        let body = serde_json::json!({ "tags": ["batch-b", "batch-a", " Batch-B ", "batch-c"] });
+        assert_eq!(
+            add(image.id, body).await,
+            (
+                StatusCode::OK,
+                vec!["batch-a".into(), "batch-b".into(), "batch-c".into()]
+            )
+        );
+        let body = serde_json::json!({ "tag": "batch-d,batch-a", "tags": ["batch-c"] });
+        let (status, names) = add(image.id, body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(names, ["batch-a", "batch-b", "batch-c", "batch-d"]);
+
+        let body = serde_json::json!({ "tags": [" "] });
+        assert_eq!(add(image.id, body).await.0, StatusCode::BAD_REQUEST);
+        let body = serde_json::json!({ "tags": ["batch-e"] });
+        assert_eq!(add(image.id + 1, body).await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn stats_sum_up_images_and_tags() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let images: Arc<dyn IImageRepository + Send + Sync> =
+            Arc::new(ImageRepository::new(db.clone()));
+        let tags: Arc<dyn ITagRepository + Send + Sync> = Arc::new(TagRepository::new(db));
+        let app = Router::new()
+            .route("/stats", get(stats))
+            .layer(Extension(images.clone()))
+            .layer(Extension(tags.clone()));
+        let get_stats = || async {
+            let response = app
+                .clone()
+                .oneshot(Request::get("/stats").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+
+        let body = get_stats().await;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "images": 0,
+                // The migrations seed a few tags
+                "tags": tags.count(None).await.unwrap(),
+                "total_bytes": 0,
+                "newest_image_at": null,
+                "top_tags": [],
+            })
+        );
+
+        let mut newest = None;
+
+        for (title, file_size, tag_names) in [("a", 100, "cats,dogs"), ("b", 250, "cats")] {
+            let image = images
+                .create_with_tags(CreateImageDto {
+                    file_size,
+                    tags: Some(tag_names.to_string()),
+                    ..dto(title)
+                })
+                .await
+                .unwrap();
+            newest = Some(image.created_at);
+        }
+
+        let body = get_stats().await;
+        assert_eq!(body["images"], 2);
+        assert_eq!(body["tags"], tags.count(None).await.unwrap());
+        assert_eq!(body["total_bytes"], 350);
+        assert_eq!(body["newest_image_at"], serde_json::json!(newest));
+        assert_eq!(body["top_tags"][0]["name"], "cats");
+        assert_eq!(body["top_tags"][0]["count"], 2);
+        assert_eq!(body["top_tags"][1]["name"], "dogs");
+    }
+}
@@ -0,0 +1,336 @@
+use anyhow::Result;
+use chrono::{TimeDelta, Utc};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{db::prelude::*, imaging, resizing, storage};
+
+/// Ids are checked against the database in batches of this size.
+const BATCH_SIZE: usize = 500;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CleanupReport {
+    /// Number of image and thumbnail files found in the directory.
+    pub scanned: usize,
+    /// Files whose image id has no database row.
+    pub orphans: Vec<PathBuf>,
+    pub removed: usize,
+}
+
+/// Finds the image and thumbnail files in `images_dir` that have no matching image row and
+/// deletes them, unless `dry_run` is set. Files not named `{id}.{ext}` or `{id}_thumb...`
+/// are left alone. The content addressed originals no row references are removed too.
+pub async fn cleanup_orphans(
+    repo: &(dyn IImageRepository + Send + Sync),
+    images_dir: &Path,
+    dry_run: bool,
+) -> Result<CleanupReport> {
+    let mut report = CleanupReport::default();
+    let mut files: HashMap<i64, Vec<PathBuf>> = HashMap::new();
+
+    for entry in fs::read_dir(images_dir)? {
+        let path = entry?.path();
+
+        if !path.is_file() {
+            continue;
+        }
+
+        let Some(id) = parse_image_id(&path) else {
+            continue;
+        };
+
+        report.scanned += 1;
+        files.entry(id).or_default().push(path);
+    }
+
+    let ids = files.keys().copied().collect::<Vec<_>>();
+    let mut existing = HashSet::new();
+
+    for chunk in ids.chunks(BATCH_SIZE) {
+        let filter = FilterBuilder::all().in_(ImageColumn::Id, chunk.iter().copied());
+        let images = repo.list(Some(filter.clone().boxed()), None, None).await?;
+        existing.extend(images.data.into_iter().map(|image| image.id));
+        // Soft deleted images keep their files until they are purged
+        let deleted = repo.list_deleted(Some(filter.boxed()), None).await?;
+        existing.extend(deleted.data.into_iter().map(|image| image.id));
+    }
+
+    for (id, paths) in files {
+        if existing.contains(&id) {
+            continue;
+        }
+
+        for path in paths {
+            if !dry_run {
+                match fs::remove_file(&path) {
+                    Ok(_) => report.removed += 1,
+                    Err(e) => tracing::warn!("Failed to remove {}: {}", path.display(), e),
+                }
+            }
+
+            report.orphans.push(path);
+        }
+    }
+
+    cleanup_content_orphans(repo, images_dir, dry_run, &mut report).await?;
+    report.orphans.sort();
+    Ok(report)
+}
+
+/// The part of [`cleanup_orphans`] for the content addressed originals, named after their
+/// hash.
+async fn cleanup_content_orphans(
+    repo: &(dyn IImageRepository + Send + Sync),
+    images_dir: &Path,
+    dry_run: bool,
+    report: &mut CleanupReport,
+) -> Result<()> {
+    let content_dir = images_dir.join(storage::CONTENT_DIR);
+
+    if !content_dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(&content_dir)? {
+        let path = entry?.path();
+
+        let Some(hash) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .filter(|_| path.is_file())
+        else {
+            continue;
+        };
+
+        report.scanned += 1;
+        // An upload can't start reusing the file between the check and the removal
+        let _content_lock = storage::lock_contents([hash]).await;
+
+        if repo.count_content_refs(hash).await? > 0 {
+            continue;
+        }
+
+        if !dry_run {
+            match fs::remove_file(&path) {
+                Ok(_) => report.removed += 1,
+                Err(e) => tracing::warn!("Failed to remove {}: {}", path.display(), e),
+            }
+        }
+
+        report.orphans.push(path);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PurgeReport {
+    pub images: usize,
+    pub tags: usize,
+    /// Image and thumbnail files removed along with the purged images.
+    pub files_removed: usize,
+}
+
+/// Permanently removes the images and tags soft deleted more than `retention` ago, and the
+/// files of those images.
+pub async fn purge_deleted(
+    images: &(dyn IImageRepository + Send + Sync),
+    tags: &(dyn ITagRepository + Send + Sync),
+    images_dir: &Path,
+    retention: TimeDelta,
+) -> Result<PurgeReport> {
+    let before = Utc::now() - retention;
+    let purged_images = images.purge_deleted(before).await?;
+    let purged_tags = tags.purge_deleted(before).await?;
+    let mut report = PurgeReport {
+        images: purged_images.len(),
+        tags: purged_tags.len(),
+        files_removed: 0,
+    };
+
+    for image in purged_images {
+        report.files_removed += remove_image_files(images, images_dir, &image).await?;
+    }
+
+    Ok(report)
+}
+
+/// Removes the files of an image whose row is gone: its thumbnails, resized copies and the
+/// original. A content addressed original is only removed once no other row references it.
+/// Failures are logged, and the number of image and thumbnail files removed is returned.
+pub async fn remove_image_files(
+    repo: &(dyn IImageRepository + Send + Sync),
+    images_dir: &Path,
+    image: &ImageModel,
+) -> Result<usize> {
+    let filepath = storage::image_path(images_dir, image.id, &image.extension);
+    let _content_lock = storage::lock_contents(image.content_hash.as_deref()).await;
+    let original = match &image.content_hash {
+        Some(hash) if repo.count_content_refs(hash).await? > 0 => None,
+        _ => Some(storage::original_path(images_dir, image)),
+    };
+    let mut removed = 0;
+    let mut remove = |path: &Path| match fs::remove_file(path) {
+        Ok(_) => removed += 1,
+        Err(e) => tracing::warn!("{}", e),
+    };
+
+    if let Some(original) = original
+        && original.exists()
+    {
+        remove(&original);
+    }
+
+    for (_, thumbpath) in imaging::list_image_thumbs(&filepath) {
+        remove(&thumbpath);
+    }
+
+    resizing::remove_cached(&filepath);
+    Ok(removed)
+}
+
+fn parse_image_id(path: &Path) -> Option<i64> {
+    let stem = path.file_stem()?.to_str()?;
+    let id = match stem.split_once('_') {
+        Some((id, rest)) if rest.starts_with("thumb") => id,
+        Some(_) => return None,
+        None => stem,
+    };
+    id.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::dto;
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::Database;
+
+    #[tokio::test]
+    async fn removes_files_without_rows() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo = ImageRepository::new(db);
+        let image = repo.create_with_tags(dto("test")).await.unwrap();
+
+        let dir = std::env::temp_dir().join(format!("thumbs-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let orphan = image.id + 1;
+        let names = [
+            format!("{}.png", image.id),
+            format!("{}_thumb_256.png", image.id),
+            format!("{}.png", orphan),
+            format!("{}_thumb_256.webp", orphan),
+            "notes.txt".to_string(),
+        ];
+
+        for name in names.iter() {
+            fs::write(dir.join(name), b"x").unwrap();
+        }
+
+        let report = cleanup_orphans(&repo, &dir, true).await.unwrap();
+        assert_eq!(report.scanned, 4);
+        assert_eq!(
+            report.orphans,
+            vec![dir.join(&names[2]), dir.join(&names[3])]
+        );
+        assert_eq!(report.removed, 0);
+        assert!(dir.join(&names[2]).exists());
+
+        let report = cleanup_orphans(&repo, &dir, false).await.unwrap();
+        assert_eq!(report.removed, 2);
+        assert!(!dir.join(&names[2]).exists());
+        assert!(!dir.join(&names[3]).exists());
+        assert!(dir.join(&names[0]).exists());
+        assert!(dir.join(&names[1]).exists());
+        assert!(dir.join(&names[4]).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn soft_deleted_files_are_kept_until_purged() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let images = ImageRepository::new(db.clone()).with_soft_delete(true);
+        let tags = TagRepository::new(db).with_soft_delete(true);
+        let image = images.create_with_tags(dto("test")).await.unwrap();
+        images.delete(image.id).await.unwrap();
+
+        let dir = std::env::temp_dir().join(format!("thumbs-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let names = [
+            format!("{}.png", image.id),
+            format!("{}_thumb_256.png", image.id),
+        ];
+
+        for name in names.iter() {
+            fs::write(dir.join(name), b"x").unwrap();
+        }
+
+        let report = cleanup_orphans(&images, &dir, true).await.unwrap();
+        assert!(report.orphans.is_empty());
+
+        let report = purge_deleted(&images, &tags, &dir, TimeDelta::days(1))
+            .await
+            .unwrap();
+        assert_eq!(report, PurgeReport::default());
+        assert!(dir.join(&names[0]).exists());
+
+        let report = purge_deleted(&images, &tags, &dir, TimeDelta::zero())
+            .await
+            .unwrap();
+        assert_eq!(report.images, 1);
+        assert_eq!(report.files_removed, 2);
+        assert!(!dir.join(&names[0]).exists());
+        assert!(!dir.join(&names[1]).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn content_files_are_removed_with_the_last_reference() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo = ImageRepository::new(db);
+        let hash = storage::sha256_hex(b"x");
+        let shared = || CreateImageDto {
+            content_hash: Some(hash.clone()),
+            ..dto("test")
+        };
+        let first = repo.create_with_tags(shared()).await.unwrap();
+        assert_eq!(repo.count_content_refs(&hash).await.unwrap(), 1);
+        let second = repo.create_with_tags(shared()).await.unwrap();
+        assert_eq!(repo.count_content_refs(&hash).await.unwrap(), 2);
+
+        let dir = std::env::temp_dir().join(format!("thumbs-{}", uuid::Uuid::new_v4()));
+        let original = storage::original_path(&dir, &first);
+        assert_eq!(original, storage::original_path(&dir, &second));
+        fs::create_dir_all(original.parent().unwrap()).unwrap();
+        fs::write(&original, b"x").unwrap();
+
+        for image in [&first, &second] {
+            fs::write(dir.join(format!("{}_thumb_256.png", image.id)), b"x").unwrap();
+        }
+
+        repo.delete(first.id).await.unwrap();
+        assert_eq!(repo.count_content_refs(&hash).await.unwrap(), 1);
+        assert_eq!(remove_image_files(&repo, &dir, &first).await.unwrap(), 1);
+        assert!(original.exists());
+
+        // Left behind by an upload whose row was never stored
+        let stray = original.with_file_name(format!("{}.png", storage::sha256_hex(b"y")));
+        fs::write(&stray, b"y").unwrap();
+        let report = cleanup_orphans(&repo, &dir, false).await.unwrap();
+        assert_eq!(report.orphans, std::slice::from_ref(&stray));
+        assert!(!stray.exists());
+        assert!(original.exists());
+
+        repo.delete(second.id).await.unwrap();
+        assert_eq!(repo.count_content_refs(&hash).await.unwrap(), 0);
+        assert_eq!(remove_image_files(&repo, &dir, &second).await.unwrap(), 2);
+        assert!(!original.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
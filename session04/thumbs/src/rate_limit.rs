@@ -0,0 +1,261 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use util::web::client_ip::ClientIp;
+
+use crate::api_error::ApiError;
+
+/// Write requests a client may make per minute when `RATE_LIMIT_WRITES_PER_MINUTE` is not set.
+pub const DEFAULT_WRITES_PER_MINUTE: u32 = 30;
+/// Read requests a client may make per minute when `RATE_LIMIT_READS_PER_MINUTE` is not set.
+pub const DEFAULT_READS_PER_MINUTE: u32 = 600;
+/// Most clients tracked at once. Past it the buckets idle for a minute are dropped, and if
+/// that frees nothing, the one idle the longest.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A token bucket per client IP holding up to `per_minute` tokens, refilled at
+/// `per_minute / 60` tokens a second. A limit of 0 lets everything through.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    per_minute: u32,
+    buckets: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Takes a token for `client`, or returns how long until the next one is available.
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+
+        let capacity = self.per_minute as f64;
+        let per_second = capacity / 60.0;
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(&client) {
+            // A bucket left alone for a minute is full again, so forgetting it changes nothing
+            buckets
+                .retain(|_, b| now.saturating_duration_since(b.updated) < Duration::from_secs(60));
+
+            // Many clients at once still can't grow the map, at worst the quietest one gets
+            // a full bucket back early
+            if buckets.len() >= MAX_TRACKED_CLIENTS
+                && let Some(oldest) = buckets
+                    .iter()
+                    .min_by_key(|(_, b)| b.updated)
+                    .map(|(ip, _)| *ip)
+            {
+                buckets.remove(&oldest);
+            }
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+}
+
+/// The limits for the safe (GET, HEAD, OPTIONS) and the write requests, kept apart so
+/// browsing stays generous while uploads are throttled.
+#[derive(Debug, Clone)]
+pub struct RateLimits {
+    pub reads: RateLimiter,
+    pub writes: RateLimiter,
+}
+
+impl RateLimits {
+    pub fn new(reads_per_minute: u32, writes_per_minute: u32) -> Self {
+        Self {
+            reads: RateLimiter::new(reads_per_minute),
+            writes: RateLimiter::new(writes_per_minute),
+        }
+    }
+}
+
+/// Middleware rejecting clients over their limit with 429 and a `Retry-After` header in
+/// seconds. Clients are told apart by their [`ClientIp`], which needs the router to be served
+/// with `into_make_service_with_connect_info`.
+pub async fn rate_limit(
+    State(limits): State<RateLimits>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limiter = if request.method().is_safe() {
+        &limits.reads
+    } else {
+        &limits.writes
+    };
+    // Without connection info every request counts against the same client
+    let ClientIp(client) = ClientIp::from_extensions(request.extensions());
+
+    if let Err(wait) = limiter.check(client) {
+        let mut response = ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many requests, try again later.",
+        )
+        .into_response();
+        let seconds = (wait.as_secs_f64().ceil() as u64).max(1);
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        return response;
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Router,
+        body::Body,
+        extract::connect_info::MockConnectInfo,
+        middleware,
+        routing::{get, post},
+    };
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn the_request_over_the_limit_is_rejected() {
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .route("/", post(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(
+                RateLimits::new(100, 3),
+                rate_limit,
+            ))
+            .layer(MockConnectInfo(SocketAddr::from(([10, 0, 0, 1], 1234))));
+        let send = |method: &str| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri("/")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        for _ in 0..3 {
+            assert_eq!(send("POST").await.unwrap().status(), StatusCode::OK);
+        }
+
+        let response = send("POST").await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "20");
+
+        // Reads have their own budget
+        assert_eq!(send("GET").await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn clients_behind_a_trusted_proxy_are_limited_apart() {
+        use util::web::client_ip::{TrustedProxies, X_FORWARDED_FOR, resolve_client_ip};
+
+        let mut errors = vec![];
+        let proxies = TrustedProxies::parse(&mut errors, Some("10.0.0.1".into()));
+        let app = Router::new()
+            .route("/", post(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(
+                RateLimits::new(100, 1),
+                rate_limit,
+            ))
+            .layer(middleware::from_fn_with_state(proxies, resolve_client_ip))
+            .layer(MockConnectInfo(SocketAddr::from(([10, 0, 0, 1], 1234))));
+        let send = |client: &str| {
+            app.clone().oneshot(
+                Request::post("/")
+                    .header(X_FORWARDED_FOR, client)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        assert_eq!(send("198.51.100.1").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(
+            send("198.51.100.1").await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(send("198.51.100.2").await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn buckets_refill_over_time_per_client() {
+        let limiter = RateLimiter::new(2);
+        let now = Instant::now();
+        let client = IpAddr::from([10, 0, 0, 1]);
+        let other = IpAddr::from([10, 0, 0, 2]);
+
+        assert!(limiter.check_at(client, now).is_ok());
+        assert!(limiter.check_at(client, now).is_ok());
+        assert_eq!(limiter.check_at(client, now), Err(Duration::from_secs(30)));
+        assert!(limiter.check_at(other, now).is_ok());
+        assert!(
+            limiter
+                .check_at(client, now + Duration::from_secs(30))
+                .is_ok()
+        );
+
+        let unlimited = RateLimiter::new(0);
+        assert!((0..100).all(|_| unlimited.check_at(client, now).is_ok()));
+    }
+
+    #[test]
+    fn the_longest_idle_client_is_forgotten_at_the_cap() {
+        let limiter = RateLimiter::new(1);
+        let start = Instant::now();
+        let client = |i: usize| IpAddr::from(std::net::Ipv4Addr::from(i as u32));
+
+        for i in 0..MAX_TRACKED_CLIENTS {
+            let at = start + Duration::from_millis(i as u64);
+            assert!(limiter.check_at(client(i), at).is_ok());
+        }
+
+        // None of them has been idle for a minute yet
+        let now = start + Duration::from_secs(11);
+        assert!(limiter.check_at(client(MAX_TRACKED_CLIENTS), now).is_ok());
+        assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_TRACKED_CLIENTS);
+        // The first client was forgotten, while the others are still limited
+        assert!(limiter.check_at(client(0), now).is_ok());
+        assert!(limiter.check_at(client(2), now).is_err());
+        assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_TRACKED_CLIENTS);
+    }
+}
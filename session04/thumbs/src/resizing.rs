@@ -0,0 +1,205 @@
+use ::image::{DynamicImage, imageops::FilterType};
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use std::{
+    fmt,
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use crate::imaging;
+
+/// Largest width or height that can be requested from the resize endpoint.
+pub const MAX_RESIZE_DIMENSION: u32 = 4096;
+/// Sub directory of the images directory holding the resized copies.
+pub const RESIZE_CACHE_DIR: &str = "cache";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Fill the whole box, cropping what doesn't fit.
+    Cover,
+    /// Fit the whole image inside the box, keeping the aspect ratio.
+    #[default]
+    Contain,
+}
+
+impl fmt::Display for Fit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fit::Cover => write!(f, "cover"),
+            Fit::Contain => write!(f, "contain"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ResizeParams {
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    pub fit: Fit,
+}
+
+impl ResizeParams {
+    pub fn validate(&self) -> Result<()> {
+        if self.w.is_none() && self.h.is_none() {
+            return Err(anyhow!("At least one of w or h is required"));
+        }
+
+        for v in [self.w, self.h].into_iter().flatten() {
+            if v == 0 || v > MAX_RESIZE_DIMENSION {
+                return Err(anyhow!(
+                    "Dimensions must be between 1 and {}",
+                    MAX_RESIZE_DIMENSION
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resizes the image into the requested box. With a single dimension, the other one
+    /// follows the aspect ratio and `fit` doesn't matter.
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        match (self.w, self.h) {
+            (Some(w), Some(h)) => match self.fit {
+                Fit::Cover => img.resize_to_fill(w, h, FilterType::Lanczos3),
+                Fit::Contain => img.resize(w, h, FilterType::Lanczos3),
+            },
+            (Some(w), None) => img.resize(w, u32::MAX, FilterType::Lanczos3),
+            (None, Some(h)) => img.resize(u32::MAX, h, FilterType::Lanczos3),
+            (None, None) => img.clone(),
+        }
+    }
+}
+
+/// `{images_dir}/cache/{id}_{w}x{h}_{fit}.{ext}` for the original at `file_path`.
+pub fn cache_path<P: AsRef<Path>>(file_path: P, params: &ResizeParams) -> PathBuf {
+    let path = file_path.as_ref();
+    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    parent.join(RESIZE_CACHE_DIR).join(format!(
+        "{}_{}x{}_{}.{}",
+        stem,
+        params.w.unwrap_or_default(),
+        params.h.unwrap_or_default(),
+        params.fit,
+        extension
+    ))
+}
+
+/// Returns the cached resized copy of the original, creating it first from `source` if
+/// needed. The copy is named after `file_path`, which is the original unless it is stored
+/// elsewhere (see [`crate::storage::original_path`]).
+pub fn resize_cached<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    file_path: Q,
+    params: &ResizeParams,
+) -> Result<PathBuf> {
+    let cached = cache_path(file_path, params);
+
+    if cached.exists() {
+        return Ok(cached);
+    }
+
+    let (img, format) = imaging::decode(BufReader::new(File::open(source)?))?;
+    let format = format.ok_or_else(|| anyhow!("Unknown image format"))?;
+    let resized = params.apply(&img);
+    let data = imaging::encode(&resized, format)?;
+
+    if let Some(parent) = cached.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Write to a temporary file first so concurrent requests never see a partial image
+    let temp = cached.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+    fs::write(&temp, data)?;
+    fs::rename(&temp, &cached)?;
+    Ok(cached)
+}
+
+/// Removes every cached resized copy of the original at `file_path`.
+pub fn remove_cached<P: AsRef<Path>>(file_path: P) {
+    let path = file_path.as_ref();
+    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+    let prefix = format!(
+        "{}_",
+        path.file_stem().unwrap_or_default().to_string_lossy()
+    );
+    let Ok(entries) = fs::read_dir(parent.join(RESIZE_CACHE_DIR)) else {
+        return;
+    };
+
+    for entry in entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+    {
+        if let Err(e) = fs::remove_file(entry.path()) {
+            tracing::warn!("{}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::image::{ImageFormat, RgbImage};
+
+    fn params(w: Option<u32>, h: Option<u32>, fit: Fit) -> ResizeParams {
+        ResizeParams { w, h, fit }
+    }
+
+    #[test]
+    fn fit_modes() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(800, 400));
+
+        let contain = params(Some(200), Some(200), Fit::Contain).apply(&img);
+        assert_eq!((contain.width(), contain.height()), (200, 100));
+
+        let cover = params(Some(200), Some(200), Fit::Cover).apply(&img);
+        assert_eq!((cover.width(), cover.height()), (200, 200));
+
+        let width_only = params(Some(400), None, Fit::Cover).apply(&img);
+        assert_eq!((width_only.width(), width_only.height()), (400, 200));
+    }
+
+    #[test]
+    fn absurd_dimensions_are_invalid() {
+        assert!(params(None, None, Fit::Contain).validate().is_err());
+        assert!(params(Some(0), None, Fit::Contain).validate().is_err());
+        assert!(
+            params(Some(MAX_RESIZE_DIMENSION + 1), Some(10), Fit::Cover)
+                .validate()
+                .is_err()
+        );
+        assert!(params(Some(10), Some(10), Fit::Cover).validate().is_ok());
+    }
+
+    #[test]
+    fn second_request_hits_the_cache() {
+        let dir = std::env::temp_dir().join(format!("thumbs-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let file_path = dir.join("1.png");
+        let img = DynamicImage::ImageRgb8(RgbImage::new(800, 400));
+        fs::write(&file_path, imaging::encode(&img, ImageFormat::Png).unwrap()).unwrap();
+
+        let params = params(Some(100), Some(100), Fit::Cover);
+        let cached = resize_cached(&file_path, &file_path, &params).unwrap();
+        assert_eq!(cached, dir.join("cache").join("1_100x100_cover.png"));
+        assert_eq!(::image::image_dimensions(&cached).unwrap(), (100, 100));
+
+        // Without the original, only a cache hit can succeed
+        fs::remove_file(&file_path).unwrap();
+        assert_eq!(
+            resize_cached(&file_path, &file_path, &params).unwrap(),
+            cached
+        );
+
+        remove_cached(&file_path);
+        assert!(!cached.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,411 @@
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, HeaderName, StatusCode},
+};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::{io::AsyncWriteExt, task::JoinHandle};
+use uuid::Uuid;
+
+use crate::{api_error::ApiError, upload::TempUpload};
+
+/// Seconds an unfinished upload is kept after its last chunk when `RESUMABLE_UPLOAD_TTL_SECS`
+/// is not set.
+pub const DEFAULT_RESUMABLE_UPLOAD_TTL_SECS: u64 = 86_400;
+/// Subdirectory of the images directory holding the unfinished uploads.
+pub const UPLOADS_DIR: &str = ".uploads";
+/// Total size of the upload, sent when it is created.
+pub const UPLOAD_LENGTH_HEADER: HeaderName = HeaderName::from_static("upload-length");
+/// Bytes received so far, sent with every chunk and returned after it.
+pub const UPLOAD_OFFSET_HEADER: HeaderName = HeaderName::from_static("upload-offset");
+/// Content type of the chunks.
+pub const CHUNK_CONTENT_TYPE: &str = "application/offset+octet-stream";
+/// Longest time between two runs of the expired uploads cleanup.
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// What is known about an upload before its content arrives.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadInfo {
+    /// Total size in bytes.
+    pub length: u64,
+    /// Form fields used when the upload completes, e.g. `title` or `tags`, as for
+    /// `POST /images`.
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+/// Uploads sent in chunks, so an interrupted transfer resumes where it stopped instead of
+/// starting over. Each one is a `{id}.part` file with the bytes received so far and a
+/// `{id}.json` file with its [`UploadInfo`], both in `{images_dir}/.uploads`, so they
+/// survive a restart. Uploads not written to for `ttl` are removed by [`Self::purge_expired`].
+#[derive(Debug, Clone)]
+pub struct ResumableUploads {
+    dir: PathBuf,
+    ttl: Duration,
+    /// Uploads a request is writing to, so two requests can't append to the same one.
+    busy: Arc<Mutex<HashSet<Uuid>>>,
+}
+
+impl ResumableUploads {
+    pub fn new(images_dir: &Path, ttl: Duration) -> Self {
+        Self {
+            dir: images_dir.join(UPLOADS_DIR),
+            ttl,
+            busy: Arc::default(),
+        }
+    }
+
+    /// Starts an empty upload and returns its id.
+    pub async fn create(&self, info: &UploadInfo) -> Result<Uuid, ApiError> {
+        let id = Uuid::new_v4();
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::File::create(self.part_path(id)).await?;
+        tokio::fs::write(self.info_path(id), serde_json::to_vec(info)?).await?;
+        Ok(id)
+    }
+
+    /// The upload's info and the number of bytes received so far.
+    pub async fn status(&self, id: Uuid) -> Result<(UploadInfo, u64), ApiError> {
+        let info = match tokio::fs::read(self.info_path(id)).await {
+            Ok(data) => serde_json::from_slice::<UploadInfo>(&data)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(ApiError::not_found("Upload not found."));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let offset = tokio::fs::metadata(self.part_path(id)).await?.len();
+        Ok((info, offset))
+    }
+
+    /// Appends the chunks of `stream` to the upload, which must have received exactly
+    /// `offset` bytes so far, and returns the new offset. Fails with 409 when the offset
+    /// doesn't match or another request is writing to the upload, and with 413 when the
+    /// chunk goes past the upload's length. The chunks received before an error are kept,
+    /// so the client can ask for the offset and resume from there.
+    pub async fn append<S, E>(
+        &self,
+        id: Uuid,
+        offset: u64,
+        stream: S,
+    ) -> Result<(UploadInfo, u64), ApiError>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+        E: fmt::Display,
+    {
+        let _claim = self.claim(id)?;
+        let (info, mut received) = self.status(id).await?;
+
+        if offset != received {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("Upload-Offset is {offset} but the upload has {received} bytes."),
+            ));
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(self.part_path(id))
+            .await?;
+        let mut stream = std::pin::pin!(stream);
+        let mut result = Ok(());
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    result = Err(ApiError::bad_request(e));
+                    break;
+                }
+            };
+
+            if received + chunk.len() as u64 > info.length {
+                result = Err(ApiError::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("Upload is longer than {} bytes.", info.length),
+                ));
+                break;
+            }
+
+            file.write_all(&chunk).await?;
+            received += chunk.len() as u64;
+        }
+
+        file.flush().await?;
+        result.map(|_| (info, received))
+    }
+
+    /// Hands a complete upload over as a [`TempUpload`], which removes the file unless it
+    /// is persisted. The upload is gone afterwards, even if storing the image fails.
+    pub async fn finish(&self, id: Uuid) -> Result<(UploadInfo, TempUpload), ApiError> {
+        let _claim = self.claim(id)?;
+        let (info, received) = self.status(id).await?;
+
+        if received != info.length {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("Upload has {received} of {} bytes.", info.length),
+            ));
+        }
+
+        tokio::fs::remove_file(self.info_path(id)).await?;
+        let path = self.part_path(id);
+        let upload = tokio::task::spawn_blocking(move || TempUpload::from_file(path)).await??;
+        Ok((info, upload))
+    }
+
+    /// Cancels the upload and removes what was received.
+    pub async fn remove(&self, id: Uuid) -> Result<(), ApiError> {
+        let _claim = self.claim(id)?;
+        self.status(id).await?;
+        remove_files(&self.dir, id);
+        Ok(())
+    }
+
+    /// Removes the uploads that weren't written to for longer than the ttl and returns how
+    /// many were removed. Uploads a request is writing to are left alone.
+    pub fn purge_expired(&self) -> io::Result<usize> {
+        self.purge_before(SystemTime::now() - self.ttl)
+    }
+
+    fn purge_before(&self, before: SystemTime) -> io::Result<usize> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let mut removed = 0;
+
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+
+            let Some(id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| Uuid::parse_str(stem).ok())
+            else {
+                continue;
+            };
+
+            // An upload's age is its `.part` file's, a `.json` file only counts once that is
+            // gone, e.g. after a failed removal
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("part") => {}
+                Some("json") if !self.part_path(id).exists() => {}
+                _ => continue,
+            }
+
+            let modified = match entry.metadata().and_then(|metadata| metadata.modified()) {
+                Ok(modified) => modified,
+                // Removed along with an upload purged earlier in this loop
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            if modified >= before {
+                continue;
+            }
+
+            let Ok(_claim) = self.claim(id) else {
+                continue;
+            };
+            remove_files(&self.dir, id);
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+
+    /// Runs [`Self::purge_expired`] in the background, at least once per ttl.
+    pub fn spawn_purge(&self) -> JoinHandle<()> {
+        let uploads = self.clone();
+        let period = uploads.ttl.clamp(Duration::from_secs(1), PURGE_INTERVAL);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+
+            loop {
+                interval.tick().await;
+                let purge = uploads.clone();
+
+                match tokio::task::spawn_blocking(move || purge.purge_expired()).await {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(removed)) => tracing::info!("Removed {removed} expired upload(s)"),
+                    Ok(Err(e)) => tracing::warn!("Failed to remove expired uploads: {e}"),
+                    Err(e) => tracing::warn!("Failed to remove expired uploads: {e}"),
+                }
+            }
+        })
+    }
+
+    fn claim(&self, id: Uuid) -> Result<BusyUpload, ApiError> {
+        if !self.busy.lock().unwrap().insert(id) {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "Another request is writing to this upload.",
+            ));
+        }
+
+        Ok(BusyUpload {
+            busy: self.busy.clone(),
+            id,
+        })
+    }
+
+    fn part_path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{id}.part"))
+    }
+
+    fn info_path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+}
+
+/// Marks an upload as busy until dropped.
+struct BusyUpload {
+    busy: Arc<Mutex<HashSet<Uuid>>>,
+    id: Uuid,
+}
+
+impl Drop for BusyUpload {
+    fn drop(&mut self) {
+        self.busy.lock().unwrap().remove(&self.id);
+    }
+}
+
+fn remove_files(dir: &Path, id: Uuid) {
+    for extension in ["part", "json"] {
+        let path = dir.join(format!("{id}.{extension}"));
+
+        if let Err(e) = fs::remove_file(&path)
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            tracing::warn!("Failed to remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Parses the upload id of a `/uploads/{id}` path. Anything else is a 404, as no upload
+/// can have that id.
+pub fn parse_id(id: &str) -> Result<Uuid, ApiError> {
+    Uuid::parse_str(id).map_err(|_| ApiError::not_found("Upload not found."))
+}
+
+/// The numeric value of the `name` header, which must be present.
+pub fn header_u64(headers: &HeaderMap, name: &HeaderName) -> Result<u64, ApiError> {
+    headers
+        .get(name)
+        .ok_or_else(|| ApiError::bad_request(format!("{name} header is required.")))?
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(|| ApiError::bad_request(format!("{name} must be a number of bytes.")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use sha2::{Digest, Sha256};
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("thumbs-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn chunk(data: &[u8]) -> impl Stream<Item = Result<Bytes, String>> {
+        stream::iter([Ok(Bytes::copy_from_slice(data))])
+    }
+
+    #[tokio::test]
+    async fn chunks_are_appended_until_the_upload_is_complete() {
+        let dir = temp_dir();
+        let uploads = ResumableUploads::new(&dir, Duration::from_secs(60));
+        let info = UploadInfo {
+            length: 10,
+            metadata: HashMap::from([("title".to_string(), "ten".to_string())]),
+        };
+        let id = uploads.create(&info).await.unwrap();
+        assert_eq!(uploads.status(id).await.unwrap(), (info.clone(), 0));
+
+        let (_, offset) = uploads.append(id, 0, chunk(b"01234")).await.unwrap();
+        assert_eq!(offset, 5);
+
+        // A chunk sent again after a lost response doesn't match the offset anymore
+        let error = uploads.append(id, 0, chunk(b"01234")).await.unwrap_err();
+        assert_eq!(error.status, StatusCode::CONFLICT);
+        let error = uploads.append(id, 5, chunk(b"567890")).await.unwrap_err();
+        assert_eq!(error.status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            uploads.finish(id).await.unwrap_err().status,
+            StatusCode::CONFLICT
+        );
+
+        // An interrupted chunk keeps what arrived
+        let failing = chunk(b"567").chain(stream::iter([Err("connection reset".to_string())]));
+        let error = uploads.append(id, 5, failing).await.unwrap_err();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(uploads.status(id).await.unwrap().1, 8);
+
+        let (_, offset) = uploads.append(id, 8, chunk(b"89")).await.unwrap();
+        assert_eq!(offset, 10);
+
+        let (finished, upload) = uploads.finish(id).await.unwrap();
+        assert_eq!(finished, info);
+        assert_eq!(upload.size(), 10);
+        assert_eq!(
+            upload.sha256(),
+            format!("{:x}", Sha256::digest(b"0123456789"))
+        );
+        assert_eq!(
+            uploads.status(id).await.unwrap_err().status,
+            StatusCode::NOT_FOUND
+        );
+
+        drop(upload);
+        assert_eq!(fs::read_dir(dir.join(UPLOADS_DIR)).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn idle_uploads_expire() {
+        let dir = temp_dir();
+        let uploads = ResumableUploads::new(&dir, Duration::from_secs(60));
+        assert_eq!(uploads.purge_expired().unwrap(), 0);
+
+        let info = UploadInfo {
+            length: 10,
+            ..Default::default()
+        };
+        let idle = uploads.create(&info).await.unwrap();
+        let busy = uploads.create(&info).await.unwrap();
+        let orphaned = uploads.create(&info).await.unwrap();
+        fs::remove_file(uploads.part_path(orphaned)).unwrap();
+        assert_eq!(uploads.purge_expired().unwrap(), 0);
+        assert!(uploads.info_path(orphaned).exists());
+
+        let claim = uploads.claim(busy).unwrap();
+        let later = SystemTime::now() + Duration::from_secs(1);
+        assert_eq!(uploads.purge_before(later).unwrap(), 2);
+        assert_eq!(
+            uploads.status(idle).await.unwrap_err().status,
+            StatusCode::NOT_FOUND
+        );
+        assert!(!uploads.info_path(orphaned).exists());
+        assert!(uploads.part_path(busy).exists());
+        assert!(uploads.info_path(busy).exists());
+
+        drop(claim);
+        uploads.remove(busy).await.unwrap();
+        assert_eq!(fs::read_dir(dir.join(UPLOADS_DIR)).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,86 @@
+use ::image::ImageFormat;
+use sha2::{Digest, Sha256};
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::db::prelude::*;
+
+/// Subdirectory of the images directory holding the content addressed originals.
+pub const CONTENT_DIR: &str = "content";
+
+/// Locks behind [`lock_contents`]. Hashes sharing one only wait for each other needlessly.
+const CONTENT_LOCK_STRIPES: usize = 64;
+
+static CONTENT_LOCKS: LazyLock<Vec<Mutex<()>>> =
+    LazyLock::new(|| (0..CONTENT_LOCK_STRIPES).map(|_| Mutex::new(())).collect());
+
+/// Subdirectory of the images directory the uploads are streamed to before they are moved
+/// into place. It is on the same file system, so the move is a rename.
+pub const INCOMING_DIR: &str = ".incoming";
+
+/// `{images_dir}/.incoming`, see [`INCOMING_DIR`].
+pub fn incoming_dir(images_dir: &Path) -> PathBuf {
+    images_dir.join(INCOMING_DIR)
+}
+
+/// `{images_dir}/{id}.{ext}`, where the original is stored unless it is content addressed.
+/// Thumbnails and resized copies are always named after this path.
+pub fn image_path(images_dir: &Path, id: i64, extension: &str) -> PathBuf {
+    images_dir.join(format!("{}.{}", id, extension))
+}
+
+/// `{images_dir}/content/{hash}.{ext}`. The extension follows the format rather than the
+/// client's file name, so every upload of the same bytes maps to the same file.
+pub fn content_path(images_dir: &Path, hash: &str, mime_type: &str) -> PathBuf {
+    let extension = ImageFormat::from_mime_type(mime_type)
+        .and_then(|format| format.extensions_str().first())
+        .copied()
+        .unwrap_or("bin");
+    images_dir
+        .join(CONTENT_DIR)
+        .join(format!("{}.{}", hash, extension))
+}
+
+/// Where the original of `image` is stored.
+pub fn original_path(images_dir: &Path, image: &ImageModel) -> PathBuf {
+    match &image.content_hash {
+        Some(hash) => content_path(images_dir, hash, &image.mime_type),
+        None => image_path(images_dir, image.id, &image.extension),
+    }
+}
+
+/// Locks the content addressed originals of `hashes` in this process until the guards are
+/// dropped. Storing an upload that may reuse an original and removing an unreferenced one
+/// hold it from the reference check to the file change, so a removal can't take the file
+/// an upload just found and reused.
+pub async fn lock_contents<'a>(
+    hashes: impl IntoIterator<Item = &'a str>,
+) -> Vec<MutexGuard<'static, ()>> {
+    let mut stripes = hashes
+        .into_iter()
+        .map(|hash| {
+            let mut hasher = DefaultHasher::new();
+            hash.hash(&mut hasher);
+            hasher.finish() as usize % CONTENT_LOCK_STRIPES
+        })
+        .collect::<Vec<_>>();
+    // Always taken in the same order, so two batches can't wait on each other
+    stripes.sort_unstable();
+    stripes.dedup();
+    let mut guards = Vec::with_capacity(stripes.len());
+
+    for stripe in stripes {
+        guards.push(CONTENT_LOCKS[stripe].lock().await);
+    }
+
+    guards
+}
+
+/// Hex sha256 of `data`.
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
@@ -0,0 +1,27 @@
+//! Fixtures shared by the library's tests and the binary's, which can't see the library's
+//! `#[cfg(test)]` items.
+
+use crate::db::entities::CreateImageDto;
+
+/// A one byte PNG titled `title` with every optional field empty, for tests to fill in with
+/// struct update syntax.
+pub fn dto(title: &str) -> CreateImageDto {
+    CreateImageDto {
+        title: title.to_string(),
+        description: None,
+        extension: "png".to_string(),
+        file_size: 1,
+        mime_type: "image/png".to_string(),
+        width: None,
+        height: None,
+        alt_text: None,
+        original_width: None,
+        original_height: None,
+        phash: None,
+        captured_at: None,
+        camera_model: None,
+        orientation: None,
+        content_hash: None,
+        tags: None,
+    }
+}
@@ -0,0 +1,196 @@
+use anyhow::{Result, anyhow};
+use std::{path::PathBuf, sync::Arc};
+use tokio::sync::mpsc;
+use util::metrics;
+
+use crate::{
+    db::prelude::*,
+    imaging::{self, ThumbnailOptions},
+};
+
+/// Number of pending jobs when `THUMBNAIL_QUEUE_SIZE` is not set.
+pub const DEFAULT_THUMBNAIL_QUEUE_SIZE: usize = 32;
+/// Histogram of the time spent encoding the thumbnails of one image.
+pub const THUMBNAIL_DURATION_METRIC: &str = "thumbnail_generation_seconds";
+
+pub struct ThumbnailJob {
+    pub id: i64,
+    /// The thumbnails are named after this path, see [`crate::storage::image_path`].
+    pub file_path: PathBuf,
+    /// The stored original, decoded only once the job's turn comes.
+    pub original: PathBuf,
+}
+
+/// Bounded queue of thumbnail jobs processed one at a time by a background task,
+/// so uploads don't wait for the thumbnails to be encoded.
+#[derive(Clone)]
+pub struct ThumbnailQueue {
+    sender: mpsc::Sender<ThumbnailJob>,
+}
+
+impl ThumbnailQueue {
+    pub fn spawn(
+        repo: Arc<dyn IImageRepository + Send + Sync>,
+        capacity: usize,
+        options: ThumbnailOptions,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        tokio::spawn(run(repo, receiver, Arc::new(options)));
+        Self { sender }
+    }
+
+    /// Waits for room in the queue when it is full.
+    pub async fn enqueue(&self, job: ThumbnailJob) -> Result<()> {
+        self.sender
+            .send(job)
+            .await
+            .map_err(|_| anyhow!("Thumbnail worker is not running"))
+    }
+}
+
+async fn run(
+    repo: Arc<dyn IImageRepository + Send + Sync>,
+    mut receiver: mpsc::Receiver<ThumbnailJob>,
+    options: Arc<ThumbnailOptions>,
+) {
+    while let Some(job) = receiver.recv().await {
+        let id = job.id;
+
+        if let Err(e) = process(&repo, job, options.clone()).await {
+            tracing::error!("Failed to generate thumbnails for image {}: {}", id, e);
+            mark_failed(&repo, id).await;
+        }
+    }
+}
+
+/// Records that the thumbnails of `id` won't be generated, so the thumbnail endpoints stop
+/// answering 202.
+pub async fn mark_failed(repo: &Arc<dyn IImageRepository + Send + Sync>, id: i64) {
+    if let Err(e) = repo.set_thumbnail_failed(id).await {
+        tracing::error!(
+            "Failed to mark the thumbnails of image {} as failed: {}",
+            id,
+            e
+        );
+    }
+}
+
+async fn process(
+    repo: &Arc<dyn IImageRepository + Send + Sync>,
+    job: ThumbnailJob,
+    options: Arc<ThumbnailOptions>,
+) -> Result<()> {
+    let ThumbnailJob {
+        id,
+        file_path,
+        original,
+    } = job;
+    let path = file_path.clone();
+    tokio::task::spawn_blocking(move || {
+        let image = imaging::open_upright(&original)?;
+        let histogram = metrics::registry().histogram(THUMBNAIL_DURATION_METRIC);
+        let _timer = histogram.start_timer();
+        imaging::generate_thumbnails(&image, &path, &options)
+    })
+    .await??;
+
+    if !repo.set_thumbnail_ready(id, true).await? {
+        // The image was deleted while its thumbnails were being generated
+        for (_, thumb_path) in imaging::list_image_thumbs(&file_path) {
+            let _ = std::fs::remove_file(thumb_path);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::dto;
+    use ::image::{DynamicImage, ImageFormat, RgbImage};
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::Database;
+    use std::time::Duration;
+
+    async fn setup() -> (Arc<dyn IImageRepository + Send + Sync>, ImageModel) {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo: Arc<dyn IImageRepository + Send + Sync> = Arc::new(ImageRepository::new(db));
+        let image = repo
+            .create_with_tags(CreateImageDto {
+                width: Some(640),
+                height: Some(480),
+                ..dto("test")
+            })
+            .await
+            .unwrap();
+        (repo, image)
+    }
+
+    async fn wait_for(
+        repo: &Arc<dyn IImageRepository + Send + Sync>,
+        id: i64,
+        done: impl Fn(&ImageModel) -> bool,
+    ) -> bool {
+        for _ in 0..100 {
+            if done(&repo.get(id).await.unwrap().unwrap()) {
+                return true;
+            }
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        false
+    }
+
+    #[tokio::test]
+    async fn thumbnails_are_generated_in_background() {
+        let (repo, image) = setup().await;
+        assert!(!image.thumbnail_ready);
+
+        let dir = std::env::temp_dir().join(format!("thumbs-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file_path = dir.join(format!("{}.png", image.id));
+        let png = imaging::encode(
+            &DynamicImage::ImageRgb8(RgbImage::new(640, 480)),
+            ImageFormat::Png,
+        )
+        .unwrap();
+        std::fs::write(&file_path, png).unwrap();
+        let queue = ThumbnailQueue::spawn(repo.clone(), 1, ThumbnailOptions::default());
+        queue
+            .enqueue(ThumbnailJob {
+                id: image.id,
+                file_path: file_path.clone(),
+                original: file_path.clone(),
+            })
+            .await
+            .unwrap();
+
+        assert!(wait_for(&repo, image.id, |m| m.thumbnail_ready).await);
+        assert!(!imaging::list_image_thumbs(&file_path).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_thumbnails_are_recorded() {
+        let (repo, image) = setup().await;
+        // The original is missing, e.g. deleted along with the image in the meantime
+        let file_path = std::env::temp_dir()
+            .join(format!("thumbs-{}", uuid::Uuid::new_v4()))
+            .join(format!("{}.png", image.id));
+        let queue = ThumbnailQueue::spawn(repo.clone(), 1, ThumbnailOptions::default());
+        queue
+            .enqueue(ThumbnailJob {
+                id: image.id,
+                file_path: file_path.clone(),
+                original: file_path,
+            })
+            .await
+            .unwrap();
+
+        assert!(wait_for(&repo, image.id, |m| m.thumbnail_failed).await);
+        assert!(!repo.get(image.id).await.unwrap().unwrap().thumbnail_ready);
+    }
+}
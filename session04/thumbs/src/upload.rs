@@ -0,0 +1,214 @@
+use axum::{body::Bytes, http::StatusCode};
+use futures::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
+use tokio::io::AsyncWriteExt;
+
+use crate::api_error::ApiError;
+
+/// Upload size used when `MAX_UPLOAD_SIZE` is not set, 20 MiB.
+pub const DEFAULT_MAX_UPLOAD_SIZE: usize = 20 * 1024 * 1024;
+/// Most `image_file` fields accepted in one request.
+pub const MAX_BATCH_FILES: usize = 20;
+/// Bytes of all the files of one request when `MAX_BATCH_SIZE` is not set, 100 MiB.
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100 * 1024 * 1024;
+/// Room left in the request body limit for the multipart framing and the other form fields.
+pub const FORM_OVERHEAD: usize = 64 * 1024;
+
+/// An upload streamed to a temporary file, in [`crate::storage::incoming_dir`] for image
+/// uploads. The file is removed when this is dropped, unless it was moved into place with
+/// [`TempUpload::persist`].
+#[derive(Debug)]
+pub struct TempUpload {
+    path: PathBuf,
+    size: u64,
+    sha256: String,
+    persisted: bool,
+}
+
+impl TempUpload {
+    /// Writes `data`, e.g. a downscaled copy of an upload, to a new temporary file in `dir`.
+    pub fn from_bytes(dir: &Path, data: &[u8]) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let upload = Self {
+            path: temp_path(dir),
+            size: data.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(data)),
+            persisted: false,
+        };
+        fs::write(&upload.path, data)?;
+        Ok(upload)
+    }
+
+    /// Takes over the file at `path`, e.g. a completed resumable upload, hashing it.
+    pub fn from_file(path: PathBuf) -> io::Result<Self> {
+        let mut upload = Self {
+            path,
+            size: 0,
+            sha256: String::new(),
+            persisted: false,
+        };
+        let mut hasher = Sha256::new();
+        upload.size = io::copy(&mut upload.open()?, &mut hasher)?;
+        upload.sha256 = format!("{:x}", hasher.finalize());
+        Ok(upload)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Hex sha256 of the uploaded bytes, computed while they were written.
+    pub fn sha256(&self) -> &str {
+        &self.sha256
+    }
+
+    /// Opens the uploaded file for reading from the start.
+    pub fn open(&self) -> io::Result<BufReader<File>> {
+        File::open(&self.path).map(BufReader::new)
+    }
+
+    /// Renames the file to `to`, which must be on the same file system.
+    pub fn persist(mut self, to: &Path) -> io::Result<()> {
+        fs::rename(&self.path, to)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempUpload {
+    fn drop(&mut self) {
+        if self.persisted {
+            return;
+        }
+
+        if let Err(e) = fs::remove_file(&self.path)
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            tracing::warn!("Failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Writes the chunks of `stream` to a new temporary file in `dir`, created if missing, as
+/// they arrive, so the upload is never held in memory as a whole. Fails with 413 as soon as more than
+/// `max_size` bytes were received. The partial file is removed on any error. The content is
+/// hashed on the way for the content addressed store.
+pub async fn stream_to_file<S, E>(
+    stream: S,
+    dir: &Path,
+    max_size: usize,
+) -> Result<TempUpload, ApiError>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: fmt::Display,
+{
+    let mut upload = TempUpload {
+        path: temp_path(dir),
+        size: 0,
+        sha256: String::new(),
+        persisted: false,
+    };
+    let mut hasher = Sha256::new();
+    tokio::fs::create_dir_all(dir).await?;
+    let mut file = tokio::fs::File::create(&upload.path).await?;
+    let mut stream = std::pin::pin!(stream);
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(ApiError::bad_request)?;
+        upload.size += chunk.len() as u64;
+
+        if upload.size > max_size as u64 {
+            return Err(ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Image is larger than {max_size} bytes."),
+            ));
+        }
+
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+    }
+
+    file.flush().await?;
+    upload.sha256 = format!("{:x}", hasher.finalize());
+    Ok(upload)
+}
+
+fn temp_path(dir: &Path) -> PathBuf {
+    dir.join(format!(".upload-{}.tmp", uuid::Uuid::new_v4()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    fn chunks(size: usize) -> impl Stream<Item = Result<Bytes, String>> {
+        let data = (0..size).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let chunks = data
+            .chunks(CHUNK_SIZE)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect::<Vec<_>>();
+        stream::iter(chunks)
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("thumbs-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn large_upload_is_streamed_to_disk() {
+        let dir = temp_dir();
+        let size = 5 * 1024 * 1024 + 7;
+        let upload = stream_to_file(chunks(size), &dir, DEFAULT_MAX_UPLOAD_SIZE)
+            .await
+            .unwrap();
+        assert_eq!(upload.size(), size as u64);
+        assert_eq!(fs::metadata(&upload.path).unwrap().len(), size as u64);
+        assert_eq!(
+            upload.sha256(),
+            format!("{:x}", Sha256::digest(fs::read(&upload.path).unwrap()))
+        );
+
+        let target = dir.join("1.bin");
+        let temp = upload.path.clone();
+        upload.persist(&target).unwrap();
+        assert!(!temp.exists());
+        assert_eq!(fs::read(&target).unwrap()[..3], [0, 1, 2]);
+
+        let upload = stream_to_file(chunks(10), &dir, DEFAULT_MAX_UPLOAD_SIZE)
+            .await
+            .unwrap();
+        let temp = upload.path.clone();
+        drop(upload);
+        assert!(!temp.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_or_failed_uploads_leave_nothing_behind() {
+        let dir = temp_dir();
+        let error = stream_to_file(chunks(3 * 1024 * 1024), &dir, 2 * 1024 * 1024)
+            .await
+            .unwrap_err();
+        assert_eq!(error.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let failing = chunks(CHUNK_SIZE).chain(stream::iter([Err("connection reset".to_string())]));
+        let error = stream_to_file(failing, &dir, DEFAULT_MAX_UPLOAD_SIZE)
+            .await
+            .unwrap_err();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -36,7 +36,7 @@ const ImageCard: React.FC<ImageCardProps> = ({ image, onClick, isSelected, onDel
         <div className={`card card-hover max-w-xs cursor-pointer animate-scale-in flex flex-col ${isSelected ? "ring-4 ring-blue-500 scale-105" : ""}`} onClick={onClick}>
             <div className="aspect-square m-1 flex items-center justify-center">
                 <div className="text-gray-400 text-center w-full mx-auto p-1">
-                    <ImageWithFallback src={thumbsApi.getThumbUri(filename)} alt={image.alt_text} className="w-full h-auto rounded" phClassName="w-16 h-16 mx-auto mb-2" />
+                    <ImageWithFallback src={thumbsApi.getThumbUri(image.id)} alt={image.alt_text} className="w-full h-auto rounded" phClassName="w-16 h-16 mx-auto mb-2" />
                     <p className="text-sm truncate">{filename}</p>
                 </div>
             </div>
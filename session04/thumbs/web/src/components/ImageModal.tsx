@@ -143,7 +143,7 @@ const ImageModal: React.FC<ImageModalProps> = ({ image, tags, onClose, onUpdate,
                     {/* Image Display */}
                     <div className="aspect-video bg-gradient-to-br from-gray-100 to-gray-200 rounded-lg mb-6 flex items-center justify-center">
                         <div className="flex flex-col items-center justify-center text-gray-400 text-center">
-                            <ImageWithFallback src={thumbsApi.getImageUri(filename)} alt={image.alt_text} className="max-w-full max-h-screen" phClassName="w-24 h-24 mx-auto mb-4" />
+                            <ImageWithFallback src={thumbsApi.getOriginalUri(image.id)} alt={image.alt_text} className="max-w-full max-h-screen" phClassName="w-24 h-24 mx-auto mb-4" />
                             <p className="p-2 text-sm truncate">{filename}</p>
                         </div>
                     </div>
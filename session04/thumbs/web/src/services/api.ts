@@ -1,8 +1,12 @@
 import axios from "axios";
-import { ResultSet, ImageModel, TagModel, ModelWithRelated } from "../types";
+import { ResultSet, ImageModel, TagModel, ModelWithRelated, Pagination } from "../types";
 
 const API_BASE_URL = import.meta.env.VITE_API_BASE_URL || "http://localhost:3000";
 
+// Matches MAX_PAGE_SIZE on the server
+export const MAX_PAGE_SIZE = 100;
+const firstPage: Pagination = { page: 1, page_size: MAX_PAGE_SIZE };
+
 const api = axios.create({
     baseURL: API_BASE_URL,
     headers: {
@@ -12,18 +16,16 @@ const api = axios.create({
 
 export const thumbsApi = {
     getImageUri: (name: string) => `${API_BASE_URL}/assets/${name}`,
-    getThumbUri: (name: string) => {
-        if (!name) return "";
-        const lastDotIndex = name.lastIndexOf(".");
-        const baseName = lastDotIndex !== -1 ? name.substring(0, lastDotIndex) : name;
-        const extension = lastDotIndex !== -1 ? name.substring(lastDotIndex) : "";
-        return `${API_BASE_URL}/assets/${baseName}_thumb${extension}`;
-    },
+    // Originals may live in the content addressed store, so let the server resolve the file
+    getOriginalUri: (id: number) => `${API_BASE_URL}/images/${id}/original`,
+    // The thumbnail extension depends on the server's THUMBNAIL_FORMAT, so let it resolve the file
+    getThumbUri: (id: number) => `${API_BASE_URL}/images/${id}/thumb`,
     getHome: () => api.get("/"),
     getAbout: () => api.get("/about"),
 
     // Image endpoints
-    getImages: () => api.get<ResultSet<ModelWithRelated<ImageModel, TagModel>>>("/images"),
+    getImages: (pagination: Pagination = firstPage) =>
+        api.get<ResultSet<ModelWithRelated<ImageModel, TagModel>>>("/images", { params: pagination }),
     getImageCount: () => api.get<number>("/images/count"),
     createImage: (formData: FormData) =>
         api.post("/images", formData, {
@@ -36,12 +38,14 @@ export const thumbsApi = {
     deleteImage: (id: number) => api.delete(`/images/${id}`),
 
     // Image tags endpoints
-    getImageTags: (id: number) => api.get<ResultSet<TagModel>>(`/images/${id}/tags/`),
-    addImageTag: (id: number, tag: string) => api.post(`/images/${id}/tags/`, { tag }),
+    getImageTags: (id: number, pagination: Pagination = firstPage) =>
+        api.get<ResultSet<TagModel>>(`/images/${id}/tags/`, { params: pagination }),
+    // Answers with all of the image's tags
+    addImageTag: (id: number, tag: string) => api.post<ResultSet<TagModel>>(`/images/${id}/tags/`, { tag }),
     removeImageTag: (id: number, tagId: number) => api.delete(`/images/${id}/tags/${tagId}`),
 
     // Tag endpoints
-    getTags: () => api.get<ResultSet<TagModel>>("/tags/"),
+    getTags: (pagination: Pagination = firstPage) => api.get<ResultSet<TagModel>>("/tags/", { params: pagination }),
     getTagCount: () => api.get<number>("/tags/count"),
     createTag: (tag: Omit<TagModel, "id">) => api.post<TagModel>("/tags/", tag),
     getTag: (id: number) => api.get<TagModel>(`/tags/${id}`),
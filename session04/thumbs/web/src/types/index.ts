@@ -8,6 +8,9 @@ export interface ImageModel {
     width?: number;
     height?: number;
     alt_text?: string;
+    original_width?: number;
+    original_height?: number;
+    thumbnail_ready?: boolean;
     created_at?: string;
     updated_at?: string;
 }
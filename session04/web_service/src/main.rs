@@ -1,32 +1,55 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use axum::{
     Json, Router,
+    extract::State,
     http::StatusCode,
     response::{Html, IntoResponse, Json as JsonResponse},
     routing::{get, post},
 };
+use jsonschema::Validator;
+use serde::Serialize;
 use serde_json::{Value as JsonValue, json};
-use tower_http::services::ServeDir;
+use std::{path::Path, sync::Arc};
+use tower_http::{compression::CompressionLayer, services::ServeDir};
+
+/// Used when `JSON_SCHEMA_PATH` is not set.
+const DEFAULT_SCHEMA_PATH: &str = "schema.json";
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
-    let app = create_router();
+    let schema_path =
+        std::env::var("JSON_SCHEMA_PATH").unwrap_or_else(|_| DEFAULT_SCHEMA_PATH.to_string());
+    let schema = load_schema(&schema_path)?;
+    let app = create_router(Arc::new(schema));
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     axum::serve(listener, app).await?;
 
     Ok(())
 }
 
+/// Reads and compiles the schema `post_json` validates against.
+fn load_schema(path: impl AsRef<Path>) -> Result<Validator> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read the JSON schema '{}'", path.display()))?;
+    let schema: JsonValue = serde_json::from_str(&content)
+        .with_context(|| format!("'{}' is not valid JSON", path.display()))?;
+    jsonschema::validator_for(&schema)
+        .map_err(|e| anyhow::anyhow!("'{}' is not a valid JSON schema: {e}", path.display()))
+}
+
 // Setup the router
-fn create_router() -> Router {
+fn create_router(schema: Arc<Validator>) -> Router {
     let static_path = std::env::current_dir().unwrap().join("wwwroot");
     Router::new()
         .route("/html", get(get_html))
         .route("/json", get(get_json))
         .route("/post", post(post_json))
         .fallback_service(ServeDir::new(static_path).append_index_html_on_directories(true))
+        .layer(CompressionLayer::new())
+        .with_state(schema)
 }
 
 async fn get_html() -> Html<String> {
@@ -42,12 +65,15 @@ async fn get_json() -> Json<JsonValue> {
     Json(data)
 }
 
-async fn post_json(payload: Json<JsonValue>) -> impl IntoResponse {
+async fn post_json(
+    State(schema): State<Arc<Validator>>,
+    payload: Json<JsonValue>,
+) -> impl IntoResponse {
     // Extract the JSON value from the payload
     let json_data = payload.0;
 
     // Validate the JSON
-    match validate_json(&json_data) {
+    match validate_json(&schema, json_data) {
         Ok(validated_json) => {
             // Process the validated JSON here
             println!("Received valid JSON: {}", validated_json);
@@ -63,30 +89,49 @@ async fn post_json(payload: Json<JsonValue>) -> impl IntoResponse {
         }
         Err(e) => {
             // Return error response
-            let error_response = json!({
+            let mut error_response = json!({
                 "status": "error",
                 "message": format!("Validation failed: {}", e)
             });
 
+            if let ValidationError::Schema(errors) = &e {
+                error_response["errors"] = json!(errors);
+            }
+
             (StatusCode::BAD_REQUEST, JsonResponse(error_response))
         }
     }
 }
 
-fn validate_json(json_input: &JsonValue) -> Result<JsonValue, ValidationError> {
-    match serde_json::to_string(json_input) {
-        Ok(json_string) => match serde_json::from_str(&json_string) {
-            Ok(validated) => Ok(validated),
-            Err(e) => Err(ValidationError::from(e)),
-        },
-        Err(e) => Err(ValidationError::InvalidJson(e.to_string())),
+fn validate_json(schema: &Validator, json_input: JsonValue) -> Result<JsonValue, ValidationError> {
+    let errors = schema
+        .iter_errors(&json_input)
+        .map(|e| FieldError {
+            field: e.instance_path.to_string(),
+            message: e.to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    if errors.is_empty() {
+        Ok(json_input)
+    } else {
+        Err(ValidationError::Schema(errors))
     }
 }
 
+/// A single schema violation. `field` is a JSON pointer to the offending value, empty for
+/// the document root.
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
 #[derive(Debug)]
 pub enum ValidationError {
     InvalidJson(String),
     UnsupportedType(String),
+    Schema(Vec<FieldError>),
 }
 
 impl std::fmt::Display for ValidationError {
@@ -94,6 +139,16 @@ impl std::fmt::Display for ValidationError {
         match self {
             ValidationError::InvalidJson(msg) => write!(f, "Invalid JSON format: {}", msg),
             ValidationError::UnsupportedType(msg) => write!(f, "Unsupported input type: {}", msg),
+            ValidationError::Schema(errors) => {
+                let errors = errors
+                    .iter()
+                    .map(|e| match e.field.as_str() {
+                        "" => e.message.clone(),
+                        field => format!("{field}: {}", e.message),
+                    })
+                    .collect::<Vec<_>>();
+                write!(f, "{}", errors.join("; "))
+            }
         }
     }
 }
@@ -105,3 +160,82 @@ impl From<serde_json::Error> for ValidationError {
         ValidationError::InvalidJson(error.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    fn router() -> Router {
+        let schema =
+            load_schema(Path::new(env!("CARGO_MANIFEST_DIR")).join(DEFAULT_SCHEMA_PATH)).unwrap();
+        create_router(Arc::new(schema))
+    }
+
+    async fn post(body: JsonValue) -> (StatusCode, JsonValue) {
+        let response = router()
+            .oneshot(
+                Request::post("/post")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn valid_payload_passes() {
+        let payload = json!({ "message": "Hello", "data": { "test": true } });
+        let (status, body) = post(payload.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"], payload);
+    }
+
+    #[tokio::test]
+    async fn missing_required_field_is_reported() {
+        let (status, body) = post(json!({ "data": "not an object" })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let errors = body["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().any(|e| {
+            e["field"] == ""
+                && e["message"]
+                    .as_str()
+                    .unwrap()
+                    .contains("\"message\" is a required property")
+        }));
+        assert!(errors.iter().any(|e| e["field"] == "/data"));
+        assert!(body["message"].as_str().unwrap().contains("/data: "));
+    }
+
+    #[tokio::test]
+    async fn responses_are_compressed_when_gzip_is_accepted() {
+        let get = |encoding: Option<&str>| {
+            let mut request = Request::get("/json");
+
+            if let Some(encoding) = encoding {
+                request = request.header("accept-encoding", encoding);
+            }
+
+            router().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        let response = get(Some("gzip")).await.unwrap();
+        assert_eq!(response.headers()["content-encoding"], "gzip");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&bytes[..2], [0x1f, 0x8b]);
+
+        let response = get(None).await.unwrap();
+        assert!(!response.headers().contains_key("content-encoding"));
+    }
+}
@@ -61,6 +61,18 @@ impl Collector {
                 let mut sys = System::new_all();
                 sys.refresh_all();
 
+                let command = CollectorCommand::Register {
+                    collector_id,
+                    hostname: System::host_name().unwrap_or_default(),
+                    os: System::long_os_version().unwrap_or_default(),
+                    cores: sys.cpus().len(),
+                };
+
+                if sender.send(command).is_err() {
+                    running.store(false, Ordering::Release);
+                    return;
+                }
+
                 let mut next_tick = Instant::now() + period;
 
                 while !stop_requested.load(Ordering::Relaxed) {
@@ -133,7 +145,7 @@ impl Collector {
 
     pub fn publish(&self, command: &CollectorCommand) -> Result<()> {
         let bytes = shared_data::encode(command);
-        println!("Sending {} bytes", bytes.len());
+        println!("Sending {}", util::format_bytes(bytes.len() as u64));
 
         let mut stream = TcpStream::connect(shared_data::DATA_COLLECTION_ADDRESS).map_err(|e| {
             RmxError::Network(format!(
@@ -6,10 +6,19 @@ use std::{
     sync::{Arc, mpsc},
     time::Duration,
 };
+use util::retry::{BackoffPolicy, retry_with_backoff_blocking};
 
 fn main() {
     const TRIES: u32 = 100;
     const ERRORS: u32 = 3;
+    // Each send is retried a few times before it counts as an error
+    const PUBLISH_RETRY: BackoffPolicy = BackoffPolicy {
+        base: Duration::from_millis(200),
+        max: Duration::from_secs(2),
+        multiplier: 2.0,
+        max_retries: 3,
+        jitter: 0.1,
+    };
 
     let (tx, rx) = mpsc::sync_channel::<shared_data::CollectorCommand>(10);
     let collector_id = shared_data::new_collector_id();
@@ -22,28 +31,30 @@ fn main() {
 
     'main_loop: loop {
         match rx.recv() {
-            Ok(command) => match collector.publish(&command) {
-                Ok(_) => {
-                    messages -= 1;
-                    errors = ERRORS;
-
-                    if messages == 0 {
-                        let command = CollectorCommand::Exit { collector_id };
-                        let _ = collector.publish(&command);
-                        break 'main_loop;
+            Ok(command) => {
+                match retry_with_backoff_blocking(&PUBLISH_RETRY, || collector.publish(&command)) {
+                    Ok(_) => {
+                        messages -= 1;
+                        errors = ERRORS;
+
+                        if messages == 0 {
+                            let command = CollectorCommand::Exit { collector_id };
+                            let _ = collector.publish(&command);
+                            break 'main_loop;
+                        }
                     }
-                }
-                Err(ex) => {
-                    errors -= 1;
-
-                    if errors == 0 {
-                        println!("Maximum errors sending to server exceeded. {}", ex);
-                        break;
-                    } else {
-                        println!("{}", ex);
+                    Err(ex) => {
+                        errors -= 1;
+
+                        if errors == 0 {
+                            println!("Maximum errors sending to server exceeded. {}", ex);
+                            break;
+                        } else {
+                            println!("{}", ex);
+                        }
                     }
                 }
-            },
+            }
             Err(_) => {
                 break 'main_loop;
             }
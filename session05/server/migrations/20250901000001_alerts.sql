@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS alerts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    collector_id TEXT,
+    raised TEXT,
+    kind TEXT,
+    value REAL,
+    threshold REAL,
+    samples INTEGER
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS collectors (
+    collector_id TEXT PRIMARY KEY,
+    hostname TEXT,
+    os TEXT,
+    cores INTEGER,
+    registered INTEGER
+);
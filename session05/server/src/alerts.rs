@@ -0,0 +1,199 @@
+use anyhow::Result;
+use serde::Deserialize;
+use shared_data::Metrics;
+use std::{collections::HashMap, fmt, fs, path::Path};
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct AlertThresholds {
+    /// CPU usage percentage (0.0..100.0) above which a sample counts as high.
+    pub cpu_usage: f32,
+    /// used_memory / total_memory ratio (0.0..1.0) above which a sample counts as high.
+    pub memory_ratio: f64,
+    /// Number of consecutive high samples required before an alert fires.
+    pub consecutive_samples: u32,
+}
+
+impl Default for AlertThresholds {
+    fn default() -> Self {
+        Self {
+            cpu_usage: 90.0,
+            memory_ratio: 0.95,
+            consecutive_samples: 3,
+        }
+    }
+}
+
+impl AlertThresholds {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            tracing::info!(
+                "Alerts config {} not found. Using default thresholds.",
+                path.display()
+            );
+            return Ok(Self::default());
+        }
+
+        let data = fs::read_to_string(path)?;
+        let mut thresholds: Self = serde_json::from_str(&data)?;
+        thresholds.consecutive_samples = thresholds.consecutive_samples.max(1);
+        Ok(thresholds)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertKind {
+    HighCpu,
+    HighMemory,
+}
+
+impl fmt::Display for AlertKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertKind::HighCpu => write!(f, "high_cpu"),
+            AlertKind::HighMemory => write!(f, "high_memory"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub collector_id: String,
+    pub kind: AlertKind,
+    pub value: f64,
+    pub threshold: f64,
+    pub samples: u32,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {:.2} > {:.2} for {} consecutive samples",
+            self.collector_id, self.kind, self.value, self.threshold, self.samples
+        )
+    }
+}
+
+/// Tracks consecutive threshold breaches per collector and raises an alert
+/// once a condition has persisted for `consecutive_samples`. The alert fires
+/// once per breach; the condition has to clear before it can fire again.
+#[derive(Debug, Default)]
+pub struct AlertEngine {
+    thresholds: AlertThresholds,
+    streaks: HashMap<(String, AlertKind), u32>,
+}
+
+impl AlertEngine {
+    pub fn new(thresholds: AlertThresholds) -> Self {
+        Self {
+            thresholds,
+            streaks: HashMap::new(),
+        }
+    }
+
+    pub fn check(&mut self, collector_id: &str, metrics: &Metrics) -> Vec<Alert> {
+        let memory_ratio = if metrics.total_memory > 0 {
+            metrics.used_memory as f64 / metrics.total_memory as f64
+        } else {
+            0.0
+        };
+        let checks = [
+            (
+                AlertKind::HighCpu,
+                metrics.cpu_usage as f64,
+                self.thresholds.cpu_usage as f64,
+            ),
+            (
+                AlertKind::HighMemory,
+                memory_ratio,
+                self.thresholds.memory_ratio,
+            ),
+        ];
+        let mut alerts = vec![];
+
+        for (kind, value, threshold) in checks {
+            let key = (collector_id.to_string(), kind);
+
+            if value <= threshold {
+                self.streaks.remove(&key);
+                continue;
+            }
+
+            let streak = self.streaks.entry(key).or_insert(0);
+            *streak = streak.saturating_add(1);
+
+            if *streak == self.thresholds.consecutive_samples {
+                alerts.push(Alert {
+                    collector_id: collector_id.to_string(),
+                    kind,
+                    value,
+                    threshold,
+                    samples: *streak,
+                });
+            }
+        }
+
+        alerts
+    }
+
+    pub fn forget(&mut self, collector_id: &str) {
+        self.streaks.retain(|(id, _), _| id != collector_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(cpu_usage: f32, used_memory: u64) -> Metrics {
+        Metrics {
+            total_memory: 100,
+            used_memory,
+            cpus: 4,
+            cpu_usage,
+            avg_cpu_usage: cpu_usage,
+        }
+    }
+
+    #[test]
+    fn fires_only_after_consecutive_samples() {
+        let mut engine = AlertEngine::new(AlertThresholds::default());
+        assert!(engine.check("a", &metrics(95.0, 10)).is_empty());
+        assert!(engine.check("a", &metrics(95.0, 10)).is_empty());
+
+        let alerts = engine.check("a", &metrics(95.0, 10));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].kind, AlertKind::HighCpu);
+
+        // No storm while the condition persists
+        assert!(engine.check("a", &metrics(95.0, 10)).is_empty());
+    }
+
+    #[test]
+    fn streak_resets_when_condition_clears() {
+        let mut engine = AlertEngine::new(AlertThresholds::default());
+        engine.check("a", &metrics(10.0, 99));
+        engine.check("a", &metrics(10.0, 99));
+        engine.check("a", &metrics(10.0, 10));
+        assert!(engine.check("a", &metrics(10.0, 99)).is_empty());
+        assert!(engine.check("a", &metrics(10.0, 99)).is_empty());
+
+        let alerts = engine.check("a", &metrics(10.0, 99));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].kind, AlertKind::HighMemory);
+    }
+
+    #[test]
+    fn collectors_are_tracked_independently() {
+        let mut engine = AlertEngine::new(AlertThresholds {
+            consecutive_samples: 2,
+            ..Default::default()
+        });
+        engine.check("a", &metrics(95.0, 10));
+        assert!(engine.check("b", &metrics(95.0, 10)).is_empty());
+        assert_eq!(engine.check("a", &metrics(95.0, 10)).len(), 1);
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::Result;
+use std::{net::SocketAddr, path::PathBuf};
+use util::{
+    config::{check, non_blank, parse, parse_bool},
+    web::{client_ip::TrustedProxies, cors::CorsOptions},
+};
+
+/// Server settings, read once at startup from the environment (and `.env`).
+///
+/// - `DATABASE_URL`: required.
+/// - `CORS_ORIGINS`: comma separated or `*`, `http://localhost` by default.
+/// - `CORS_METHODS`, `CORS_HEADERS`: comma separated, `*` by default.
+/// - `CORS_ALLOW_CREDENTIALS`: `false` by default, needs explicit origins, methods and headers.
+/// - `TRUSTED_PROXIES`: comma separated addresses or CIDR ranges whose `X-Forwarded-For` and
+///   `X-Real-IP` headers are believed, none by default.
+/// - `ALERTS_CONFIG`: alert thresholds file, `alerts.json` by default.
+/// - `GRPC_ADDRESS`: where the gRPC metrics ingest listens, `127.0.0.1:50051` by default.
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub database_url: String,
+    pub cors: CorsOptions,
+    pub trusted_proxies: TrustedProxies,
+    pub alerts_config: PathBuf,
+    pub grpc_address: SocketAddr,
+}
+
+impl AppConfig {
+    pub fn from_env() -> Result<Self> {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    /// Collects every missing or invalid setting before failing, so they can all be fixed at once.
+    pub fn from_lookup<F: Fn(&str) -> Option<String>>(lookup: F) -> Result<Self> {
+        let mut errors = vec![];
+        let var = |name: &str| non_blank(lookup(name));
+
+        let database_url = var("DATABASE_URL").unwrap_or_else(|| {
+            errors.push("DATABASE_URL is required".to_string());
+            String::new()
+        });
+        let allow_credentials = parse_bool(
+            &mut errors,
+            "CORS_ALLOW_CREDENTIALS",
+            var("CORS_ALLOW_CREDENTIALS"),
+            false,
+        );
+        let cors = CorsOptions::parse(
+            &mut errors,
+            var("CORS_ORIGINS"),
+            var("CORS_METHODS"),
+            var("CORS_HEADERS"),
+            allow_credentials,
+        );
+        let trusted_proxies = TrustedProxies::parse(&mut errors, var("TRUSTED_PROXIES"));
+
+        let alerts_config =
+            PathBuf::from(var("ALERTS_CONFIG").unwrap_or_else(|| "alerts.json".into()));
+        let grpc_address = parse(
+            &mut errors,
+            "GRPC_ADDRESS",
+            var("GRPC_ADDRESS"),
+            SocketAddr::from(([127, 0, 0, 1], 50051)),
+        );
+        check(errors)?;
+
+        Ok(Self {
+            database_url,
+            cors,
+            trusted_proxies,
+            alerts_config,
+            grpc_address,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use util::web::cors::Allowed;
+
+    #[test]
+    fn reports_all_problems_together() {
+        let error = AppConfig::from_lookup(|name| match name {
+            "CORS_ORIGINS" => Some("http://ok.test,bad\norigin".to_string()),
+            "CORS_ALLOW_CREDENTIALS" => Some("maybe".to_string()),
+            _ => None,
+        })
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("DATABASE_URL"));
+        assert!(error.contains("CORS_ORIGINS"));
+        assert!(error.contains("CORS_ALLOW_CREDENTIALS"));
+
+        let config = AppConfig::from_lookup(|name| {
+            (name == "DATABASE_URL").then(|| "sqlite::memory:".to_string())
+        })
+        .unwrap();
+        assert_eq!(
+            config.cors.origins,
+            Allowed::Only(vec![HeaderValue::from_static("http://localhost")])
+        );
+        assert_eq!(config.alerts_config, PathBuf::from("alerts.json"));
+        assert_eq!(config.grpc_address.port(), 50051);
+    }
+}
@@ -0,0 +1,177 @@
+use shared_data::{CollectorCommand, Metrics};
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        mpsc::{SyncSender, TrySendError},
+    },
+};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tonic::{Request, Response, Status, transport::Server};
+use uuid::Uuid;
+
+use proto::{
+    SubmitDataRequest, SubmitDataResponse,
+    metrics_ingest_server::{MetricsIngest, MetricsIngestServer},
+};
+
+pub mod proto {
+    tonic::include_proto!("collector");
+}
+
+/// gRPC counterpart of the raw TCP receiver. Submitted metrics go into the same channel,
+/// so `watch_metrics` handles both the same way.
+#[derive(Debug, Clone)]
+pub struct MetricsIngestService {
+    sender: Arc<SyncSender<(u128, CollectorCommand)>>,
+}
+
+impl MetricsIngestService {
+    pub fn new(sender: Arc<SyncSender<(u128, CollectorCommand)>>) -> Self {
+        Self { sender }
+    }
+}
+
+#[tonic::async_trait]
+impl MetricsIngest for MetricsIngestService {
+    async fn submit_metrics(
+        &self,
+        request: Request<SubmitDataRequest>,
+    ) -> Result<Response<SubmitDataResponse>, Status> {
+        let request = request.into_inner();
+        let collector_id = Uuid::parse_str(&request.collector_id).map_err(|e| {
+            Status::invalid_argument(format!(
+                "collector_id '{}' is not a UUID: {e}",
+                request.collector_id
+            ))
+        })?;
+        let metrics = request
+            .metrics
+            .ok_or_else(|| Status::invalid_argument("metrics is required"))?;
+        let timestamp = request
+            .timestamp_micros
+            .map(u128::from)
+            .unwrap_or_else(util::datetime::unix::now_micros);
+        let command = CollectorCommand::SubmitData {
+            collector_id: collector_id.as_u128(),
+            metrics: Metrics {
+                total_memory: metrics.total_memory,
+                used_memory: metrics.used_memory,
+                cpus: metrics.cpus as usize,
+                cpu_usage: metrics.cpu_usage,
+                avg_cpu_usage: metrics.avg_cpu_usage,
+            },
+        };
+
+        // Don't block the runtime on a full channel, let the client retry instead
+        match self.sender.try_send((timestamp, command)) {
+            Ok(_) => Ok(Response::new(SubmitDataResponse {})),
+            Err(TrySendError::Full(_)) => Err(Status::resource_exhausted(
+                "Too many pending metrics, try again later",
+            )),
+            Err(TrySendError::Disconnected(_)) => {
+                Err(Status::unavailable("The server is shutting down"))
+            }
+        }
+    }
+}
+
+/// Serves the gRPC ingest service on `address` until `shutdown` is cancelled.
+pub fn serve(
+    address: SocketAddr,
+    sender: Arc<SyncSender<(u128, CollectorCommand)>>,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    let service = MetricsIngestServer::new(MetricsIngestService::new(sender));
+    tokio::spawn(async move {
+        tracing::info!("gRPC ingest listening on {address}");
+        let result = Server::builder()
+            .add_service(service)
+            .serve_with_shutdown(address, shutdown.cancelled_owned())
+            .await;
+
+        match result {
+            Ok(_) => tracing::info!("gRPC ingest stopped"),
+            Err(e) => tracing::error!("gRPC ingest failed: {e}"),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn request(collector_id: &str, metrics: Option<proto::Metrics>) -> Request<SubmitDataRequest> {
+        Request::new(SubmitDataRequest {
+            collector_id: collector_id.to_string(),
+            metrics,
+            timestamp_micros: Some(42),
+        })
+    }
+
+    #[tokio::test]
+    async fn submitted_metrics_reach_the_channel() {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let service = MetricsIngestService::new(Arc::new(tx));
+        let id = Uuid::new_v4();
+        let metrics = proto::Metrics {
+            total_memory: 200,
+            used_memory: 100,
+            cpus: 4,
+            cpu_usage: 12.5,
+            avg_cpu_usage: 10.0,
+        };
+
+        service
+            .submit_metrics(request(&id.to_string(), Some(metrics)))
+            .await
+            .unwrap();
+        let (timestamp, command) = rx.try_recv().unwrap();
+        assert_eq!(timestamp, 42);
+        assert_eq!(
+            command,
+            CollectorCommand::SubmitData {
+                collector_id: id.as_u128(),
+                metrics: Metrics {
+                    total_memory: 200,
+                    used_memory: 100,
+                    cpus: 4,
+                    cpu_usage: 12.5,
+                    avg_cpu_usage: 10.0,
+                },
+            }
+        );
+
+        // The channel holds one command, so the second one is turned away
+        let full = proto::Metrics::default();
+        service
+            .submit_metrics(request(&id.to_string(), Some(full)))
+            .await
+            .unwrap();
+        let status = service
+            .submit_metrics(request(&id.to_string(), Some(full)))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_are_rejected() {
+        let (tx, _rx) = mpsc::sync_channel(1);
+        let service = MetricsIngestService::new(Arc::new(tx));
+
+        let status = service
+            .submit_metrics(request("not a uuid", Some(proto::Metrics::default())))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = service
+            .submit_metrics(request(&Uuid::new_v4().to_string(), None))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
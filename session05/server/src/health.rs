@@ -0,0 +1,44 @@
+use axum::Router;
+use sqlx::sqlite::SqlitePool;
+use util::web::health;
+
+/// The shared probes and metrics, kept out of the CORS layer, with `/readyz` querying the
+/// database.
+pub fn routes() -> Router {
+    health::routes(|db: SqlitePool| async move {
+        sqlx::query("SELECT 1").execute(&db).await.map(|_| ())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Extension,
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    async fn status(db: &SqlitePool, uri: &str) -> StatusCode {
+        routes()
+            .layer(Extension(db.clone()))
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn readyz_fails_once_the_pool_is_closed() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        assert_eq!(status(&db, "/readyz").await, StatusCode::OK);
+
+        db.close().await;
+        assert_eq!(status(&db, "/healthz").await, StatusCode::OK);
+        assert_eq!(
+            status(&db, "/readyz").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+}
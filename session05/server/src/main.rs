@@ -1,15 +1,28 @@
+mod alerts;
+mod config;
+mod grpc;
+mod health;
+mod migration_lock;
 mod receiver;
 
+use alerts::{Alert, AlertEngine, AlertThresholds};
 use anyhow::Result;
 use axum::{
     Extension, Json, Router,
-    extract::Path as axum_path,
-    http::HeaderValue,
+    body::Body,
+    extract::{Path as axum_path, Query},
+    http::header,
+    middleware,
+    response::IntoResponse,
     routing::{delete, get},
 };
+use config::AppConfig;
 use dotenvy::dotenv;
+use migration_lock::{LockOutcome, with_migration_lock};
 use receiver::Receiver;
+use serde::Deserialize;
 use shared_data::{Collector, CollectorCommand, DataPoint, Metrics};
+use shutdown::InFlight;
 use sqlx::{
     Pool,
     migrate::MigrateDatabase,
@@ -17,21 +30,68 @@ use sqlx::{
 };
 use std::{
     fs,
+    net::SocketAddr,
     path::Path,
     sync::{Arc, mpsc},
+    time::Duration,
 };
 use tokio::task::JoinHandle;
-use tower_http::{
-    cors::{Any, CorsLayer},
-    services::ServeDir,
-};
+use tokio_util::sync::CancellationToken;
+use tower_http::{compression::CompressionLayer, services::ServeDir};
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_subscriber::{
     EnvFilter, filter::LevelFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt,
 };
-use util::datetime;
+use util::{
+    datetime::{self, unix},
+    web::{client_ip, request_log, shutdown},
+};
 use uuid::Uuid;
 
+/// How `received` is formatted in the metrics responses, chosen with `?time=`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TimeFormat {
+    /// Local time of day, e.g. `14:03:12.123456`.
+    #[default]
+    Short,
+    /// RFC 3339 in UTC, which the frontend can parse reliably.
+    Rfc3339,
+}
+
+impl TimeFormat {
+    fn format(self, micros: u128) -> String {
+        match self {
+            TimeFormat::Short => datetime::format_seconds_long(micros),
+            TimeFormat::Rfc3339 => datetime::format_rfc3339(micros),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct MetricsQuery {
+    time: TimeFormat,
+}
+
+/// Window `is_active` is computed with when `?active_within_secs=` is omitted.
+const DEFAULT_ACTIVE_WITHIN_SECS: u64 = 300;
+
+/// `?active_within_secs=` only lists the collectors that sent a sample within that many
+/// seconds. Without it every collector is listed.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct CollectorsQuery {
+    active_within_secs: Option<u64>,
+}
+
+/// `?collector=` limits the CSV export to one collector's samples.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ExportQuery {
+    collector: Option<String>,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
@@ -54,17 +114,29 @@ async fn main() -> Result<()> {
 
 async fn run() -> Result<()> {
     tracing::info!("Configuring database");
-    let db_url = std::env::var("DATABASE_URL")?;
-    let db = setup_database(&db_url).await?;
+    let config = AppConfig::from_env()?;
+    let db = setup_database(&config.database_url).await?;
     tracing::info!("Database configured successfully.");
 
-    let metrics_handle = watch_metrics(&db).await;
+    let thresholds = AlertThresholds::load(&config.alerts_config)?;
+    tracing::info!("Alert thresholds: {:?}", thresholds);
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn(shutdown::cancel_on_ctrl_c(shutdown.clone()));
+
+    let metrics_handle = watch_metrics(&db, thresholds, &config, shutdown.clone()).await;
 
     tracing::info!("Configuring application");
-    let app = setup_router().layer(Extension(db.clone()));
+    let in_flight = InFlight::default();
+    let app = setup_router(&config)
+        .layer(middleware::from_fn_with_state(
+            in_flight.clone(),
+            shutdown::track_in_flight,
+        ))
+        .layer(Extension(db.clone()));
     tracing::info!("Application configured successfully.");
 
-    let server_handle = run_server(app).await;
+    let server_handle = run_server(app, in_flight, shutdown).await;
 
     let (metrics_res, server_res) = tokio::join!(metrics_handle, server_handle);
 
@@ -152,27 +224,31 @@ async fn setup_database(db_url: &str) -> Result<Pool<Sqlite>> {
     let path = Path::new("./migrations");
 
     if path.exists() {
-        // Apply migrations
+        // Apply migrations, one instance at a time
         tracing::info!("Applying migrations...");
-        sqlx::migrate!("./migrations").run(&pool).await?;
-        tracing::info!("Migrations applied successfully.");
+        let ((), outcome) = with_migration_lock(&pool, || async {
+            Ok(sqlx::migrate!("./migrations").run(&pool).await?)
+        })
+        .await?;
+
+        match outcome {
+            LockOutcome::Acquired => tracing::info!("Migrations applied successfully."),
+            LockOutcome::Waited => {
+                tracing::info!("Migrations applied after waiting for another instance.")
+            }
+        }
     }
 
     Ok(pool)
 }
 
-fn setup_router() -> Router {
+fn setup_router(config: &AppConfig) -> Router {
     let curdir = std::env::current_dir().unwrap();
     let static_path = curdir.join("wwwroot");
-    let origins = std::env::var("CORS_ORIGINS")
-        .unwrap_or_else(|_| "http://localhost".to_string())
-        .split(',')
-        .map(|s| s.trim().parse::<HeaderValue>().unwrap())
-        .collect::<Vec<_>>();
-    let cors = CorsLayer::new()
-        .allow_origin(origins)
-        .allow_methods(Any)
-        .allow_headers(Any);
+    let cors = config
+        .cors
+        .layer()
+        .expose_headers([request_log::REQUEST_ID_HEADER]);
 
     tracing::info!("Configuring router");
     Router::new()
@@ -183,124 +259,350 @@ fn setup_router() -> Router {
         )
         .route("/api/metrics", get(web::show_metrics))
         .route("/api/metrics", delete(web::clear_metrics))
+        .route("/api/metrics/export.csv", get(web::export_metrics))
         .fallback_service(ServeDir::new(static_path).append_index_html_on_directories(true))
+        .layer(CompressionLayer::new())
         .layer(cors)
+        .merge(health::routes())
+        .layer(middleware::from_fn(request_log::request_log))
+        .layer(middleware::from_fn_with_state(
+            config.trusted_proxies.clone(),
+            client_ip::resolve_client_ip,
+        ))
 }
 
 // collector loop
-async fn watch_metrics(db: &Pool<Sqlite>) -> JoinHandle<()> {
+async fn watch_metrics(
+    db: &Pool<Sqlite>,
+    thresholds: AlertThresholds,
+    config: &AppConfig,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
     let (tx, rx) = mpsc::sync_channel::<(u128, CollectorCommand)>(10);
     let mut receiver = Receiver::new();
     let sender = Arc::new(tx);
-    let handle = receiver.start(sender).unwrap();
+    let handle = receiver.start(sender.clone()).unwrap();
+    // The gRPC ingest feeds the same channel and drops its sender once it has stopped. It
+    // stops with the server.
+    let grpc_handle = grpc::serve(config.grpc_address, sender, shutdown.child_token());
     let db = db.clone();
+    let mut alert_engine = AlertEngine::new(thresholds);
     tokio::spawn(async move {
-        'main_loop: loop {
-            match rx.recv() {
-                Ok((timestamp, command)) => match command {
-                    CollectorCommand::SubmitData {
-                        collector_id,
-                        metrics,
-                    } => {
-                        let collector_id = Uuid::from_u128(collector_id);
-                        let collector_id = collector_id.to_string();
-                        println!(
-                            "{} {} mem: {}/{} KB, CPUs: {}, CPU usage: {:.2}%, CPU usage (avg): {:.2}%",
-                            datetime::format_seconds_long(timestamp),
-                            collector_id,
-                            metrics.used_memory,
-                            metrics.total_memory,
-                            metrics.cpus,
-                            metrics.cpu_usage,
-                            metrics.avg_cpu_usage
-                        );
-                        let result =
-                            data::add_metrics(&db, &collector_id, timestamp, &metrics).await;
-
-                        if result.is_err() {
-                            println!("Error inserting metrics into the database. {result:?}")
-                        }
-                    }
-                    CollectorCommand::Exit { collector_id } => {
-                        println!("Closing connection to {collector_id}");
-                        break 'main_loop;
-                    }
-                },
-                Err(ex) => {
-                    println!("{}", ex);
-                    break 'main_loop;
-                }
-            }
-        }
+        ingest_metrics(&db, rx, &mut alert_engine, &shutdown).await;
 
         receiver.stop();
         let _ = handle.join();
+        let _ = grpc_handle.await;
     })
 }
 
+/// How long the ingest loop waits for a command before checking for shutdown again.
+const INGEST_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Handles the commands arriving on `rx` until the channel disconnects or `shutdown` is
+/// cancelled. On shutdown the commands already queued are handled first, so
+/// nothing accepted before it is lost.
+async fn ingest_metrics(
+    db: &Pool<Sqlite>,
+    mut rx: mpsc::Receiver<(u128, CollectorCommand)>,
+    alert_engine: &mut AlertEngine,
+    shutdown: &CancellationToken,
+) {
+    loop {
+        if shutdown.is_cancelled() {
+            let mut drained = 0;
+
+            while let Ok((timestamp, command)) = rx.try_recv() {
+                drained += 1;
+                handle_command(db, alert_engine, timestamp, command).await;
+            }
+
+            tracing::info!("Ingestion stopped, {drained} queued command(s) handled");
+            return;
+        }
+
+        // The channel is a std one, so it is polled to notice the cancellation, on the
+        // blocking pool to keep the runtime's workers free
+        let (returned, received) = tokio::task::spawn_blocking(move || {
+            let received = rx.recv_timeout(INGEST_POLL_INTERVAL);
+            (rx, received)
+        })
+        .await
+        .expect("ingest receiver panicked");
+        rx = returned;
+
+        match received {
+            Ok((timestamp, command)) => {
+                handle_command(db, alert_engine, timestamp, command).await;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                tracing::info!("Collector channel closed, metrics drained");
+                return;
+            }
+        }
+    }
+}
+
+/// Handles one collector command. A collector exiting only ends its own state; the others
+/// keep sending.
+async fn handle_command(
+    db: &Pool<Sqlite>,
+    alert_engine: &mut AlertEngine,
+    timestamp: u128,
+    command: CollectorCommand,
+) {
+    match command {
+        CollectorCommand::SubmitData {
+            collector_id,
+            metrics,
+        } => {
+            store_sample(db, alert_engine, timestamp, collector_id, &metrics).await;
+        }
+        CollectorCommand::Register {
+            collector_id,
+            hostname,
+            os,
+            cores,
+        } => {
+            let Some(collector_id) = collector_uuid(collector_id) else {
+                tracing::warn!(
+                    "Dropping a registration from invalid collector id {collector_id:#034x}"
+                );
+                return;
+            };
+
+            tracing::info!(
+                "Collector {collector_id} registered as {hostname} ({os}, {cores} cores)"
+            );
+            let result =
+                data::register_collector(db, &collector_id, &hostname, &os, cores, timestamp).await;
+
+            if result.is_err() {
+                println!("Error registering the collector. {result:?}")
+            }
+        }
+        CollectorCommand::Exit { collector_id } => {
+            alert_engine.forget(&Uuid::from_u128(collector_id).to_string());
+            println!("Closing connection to {collector_id}");
+        }
+    }
+}
+
+/// The collector id as a hyphenated UUID, or `None` unless it is a random (v4) UUID like
+/// the ones [`shared_data::new_collector_id`] generates. The nil id is rejected with the rest.
+fn collector_uuid(collector_id: u128) -> Option<String> {
+    let uuid = Uuid::from_u128(collector_id);
+
+    if uuid.get_version() != Some(uuid::Version::Random)
+        || uuid.get_variant() != uuid::Variant::RFC4122
+    {
+        return None;
+    }
+
+    Some(uuid.to_string())
+}
+
+/// Stores one sample and any alert it raises. Samples from an invalid collector id are
+/// logged and dropped.
+async fn store_sample(
+    db: &Pool<Sqlite>,
+    alert_engine: &mut AlertEngine,
+    timestamp: u128,
+    collector_id: u128,
+    metrics: &Metrics,
+) {
+    let Some(collector_id) = collector_uuid(collector_id) else {
+        tracing::warn!("Dropping a sample from invalid collector id {collector_id:#034x}");
+        return;
+    };
+
+    println!(
+        "{} {collector_id} {metrics}",
+        datetime::format_seconds_long(timestamp)
+    );
+    let result = data::add_metrics(db, &collector_id, timestamp, metrics).await;
+
+    if result.is_err() {
+        println!("Error inserting metrics into the database. {result:?}")
+    }
+
+    for alert in alert_engine.check(&collector_id, metrics) {
+        tracing::warn!("ALERT {alert}");
+        let result = data::add_alert(db, &alert, timestamp).await;
+
+        if result.is_err() {
+            println!("Error inserting alert into the database. {result:?}")
+        }
+    }
+}
+
 // server loop
-async fn run_server(app: Router) -> JoinHandle<()> {
+async fn run_server(
+    app: Router,
+    in_flight: InFlight,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
     tracing::info!("Starting server");
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     tracing::info!("Server listening on http://localhost:3000");
     tokio::spawn(async move {
-        axum::serve(listener, app).await.unwrap();
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(async move {
+            shutdown.cancelled().await;
+            tracing::info!(
+                "Shutting down gracefully, draining {} connection(s)",
+                in_flight.count()
+            );
+        })
+        .await
+        .unwrap();
+        tracing::info!("Server stopped");
     })
 }
 
 mod data {
     use super::*;
-
-    pub async fn get_collectors(db: &Pool<Sqlite>) -> Result<Vec<Collector>> {
-        const SQL: &str = "SELECT collector_id, 
-    MAX(received) AS last_seen 
-    FROM timeseries ts
-	GROUP BY collector_id
-	ORDER BY last_seen";
+    use futures::{SinkExt, StreamExt, channel::mpsc::Receiver as StreamReceiver};
+
+    const METRICS_SQL: &str = "SELECT * FROM TIMESERIES";
+    const METRICS_BY_COLLECTOR_SQL: &str =
+        "SELECT * FROM timeseries WHERE collector_id = ? ORDER BY received";
+    const CSV_HEADER: &str =
+        "id,collector_id,received,total_memory,used_memory,cpus,cpu_usage,avg_cpu_usage\n";
+
+    /// Lists the collectors with their latest sample. Collectors are active if that sample is
+    /// at most `active_within_secs` (or the default window) older than `now`; with
+    /// `active_within_secs` set, only the active ones are returned.
+    pub async fn get_collectors(
+        db: &Pool<Sqlite>,
+        active_within_secs: Option<u64>,
+        now: u128,
+    ) -> Result<Vec<Collector>> {
+        const SQL: &str = "SELECT samples.collector_id,
+    collectors.hostname,
+    collectors.os,
+    collectors.cores,
+    CAST(latest AS TEXT) AS last_seen,
+    latest >= ?1 AS is_active
+    FROM (
+        SELECT collector_id, MAX(CAST(received AS INTEGER)) AS latest
+        FROM timeseries
+        GROUP BY collector_id
+    ) AS samples
+    LEFT JOIN collectors ON collectors.collector_id = samples.collector_id
+    WHERE ?2 = 0 OR latest >= ?1
+    ORDER BY latest";
+        let window = active_within_secs.unwrap_or(DEFAULT_ACTIVE_WITHIN_SECS) as u128 * 1_000_000;
+        let cutoff = now.saturating_sub(window) as i64;
         let mut collectors = sqlx::query_as::<_, Collector>(SQL)
+            .bind(cutoff)
+            .bind(active_within_secs.is_some())
             .fetch_all(db)
-            .await
-            .unwrap();
+            .await?;
 
         for collector in &mut collectors {
-            let last_seen: u128 = collector.last_seen.parse().unwrap();
+            let last_seen = unix::parse_micros(&collector.last_seen)?;
             collector.last_seen = datetime::format_seconds_long(last_seen);
         }
 
         Ok(collectors)
     }
 
-    pub async fn get_metrics(db: &Pool<Sqlite>) -> Result<Vec<DataPoint>> {
-        let mut data_points = sqlx::query_as::<_, DataPoint>("SELECT * FROM TIMESERIES")
+    pub async fn get_metrics(db: &Pool<Sqlite>, time: TimeFormat) -> Result<Vec<DataPoint>> {
+        let mut data_points = sqlx::query_as::<_, DataPoint>(METRICS_SQL)
             .fetch_all(db)
             .await
             .unwrap();
 
         for data_point in &mut data_points {
-            let received: u128 = data_point.received.parse().unwrap();
-            data_point.received = datetime::format_seconds_long(received);
+            let received = unix::parse_micros(&data_point.received)?;
+            data_point.received = time.format(received);
         }
 
         Ok(data_points)
     }
 
-    pub async fn get_metrics_by_collector(db: &Pool<Sqlite>, uuid: &str) -> Result<Vec<DataPoint>> {
-        let mut data_points = sqlx::query_as::<_, DataPoint>(
-            "SELECT * FROM timeseries WHERE collector_id = ? ORDER BY received",
-        )
-        .bind(uuid)
-        .fetch_all(db)
-        .await
-        .unwrap();
+    pub async fn get_metrics_by_collector(
+        db: &Pool<Sqlite>,
+        uuid: &str,
+        time: TimeFormat,
+    ) -> Result<Vec<DataPoint>> {
+        let mut data_points = sqlx::query_as::<_, DataPoint>(METRICS_BY_COLLECTOR_SQL)
+            .bind(uuid)
+            .fetch_all(db)
+            .await
+            .unwrap();
 
         for data_point in &mut data_points {
-            let received: u128 = data_point.received.parse().unwrap();
-            data_point.received = datetime::format_seconds_long(received);
+            let received = unix::parse_micros(&data_point.received)?;
+            data_point.received = time.format(received);
         }
 
         Ok(data_points)
     }
 
+    /// Streams the samples as CSV lines, header first, with `received` in RFC 3339. Rows are
+    /// fetched one at a time by a background task so the table is never held in memory; the
+    /// stream ends after the first error.
+    pub fn export_csv(
+        db: Pool<Sqlite>,
+        collector_id: Option<String>,
+    ) -> StreamReceiver<Result<String>> {
+        let (mut tx, rx) = futures::channel::mpsc::channel(64);
+        tokio::spawn(async move {
+            if tx.send(Ok(CSV_HEADER.to_string())).await.is_err() {
+                return;
+            }
+
+            let query = match &collector_id {
+                Some(collector_id) => {
+                    sqlx::query_as::<_, DataPoint>(METRICS_BY_COLLECTOR_SQL).bind(collector_id)
+                }
+                None => sqlx::query_as::<_, DataPoint>(METRICS_SQL),
+            };
+            let mut rows = query.fetch(&db);
+
+            while let Some(row) = rows.next().await {
+                let line = row.map_err(|ex| ex.into()).and_then(|row| csv_line(&row));
+                let failed = line.is_err();
+
+                // The client went away or the export failed
+                if tx.send(line).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+
+    fn csv_line(data_point: &DataPoint) -> Result<String> {
+        let received = unix::parse_micros(&data_point.received)?;
+        Ok(format!(
+            "{},{},{},{},{},{},{},{}\n",
+            data_point.id,
+            csv_field(&data_point.collector_id),
+            datetime::format_rfc3339(received),
+            data_point.total_memory,
+            data_point.used_memory,
+            data_point.cpus,
+            data_point.cpu_usage,
+            data_point.avg_cpu_usage
+        ))
+    }
+
+    /// Quotes `value` if it would otherwise break the row.
+    fn csv_field(value: &str) -> String {
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }
+
     pub async fn add_metrics(
         db: &Pool<Sqlite>,
         collector_id: &str,
@@ -331,6 +633,61 @@ mod data {
         .map_err(|ex| ex.into())
     }
 
+    /// Stores what a collector registered with, replacing an earlier registration.
+    pub async fn register_collector(
+        db: &Pool<Sqlite>,
+        collector_id: &str,
+        hostname: &str,
+        os: &str,
+        cores: usize,
+        timestamp: u128,
+    ) -> Result<SqliteQueryResult> {
+        sqlx::query(
+            "INSERT INTO collectors (collector_id, hostname, os, cores, registered)
+						VALUES ($1, $2, $3, $4, $5)
+						ON CONFLICT (collector_id) DO UPDATE SET
+							hostname = excluded.hostname,
+							os = excluded.os,
+							cores = excluded.cores,
+							registered = excluded.registered",
+        )
+        .bind(collector_id)
+        .bind(hostname)
+        .bind(os)
+        .bind(cores as i32)
+        .bind(timestamp as i64)
+        .execute(db)
+        .await
+        .map_err(|ex| ex.into())
+    }
+
+    pub async fn add_alert(
+        db: &Pool<Sqlite>,
+        alert: &Alert,
+        timestamp: u128,
+    ) -> Result<SqliteQueryResult> {
+        sqlx::query(
+            "INSERT INTO alerts (
+							collector_id,
+							raised,
+							kind,
+							value,
+							threshold,
+							samples
+						)
+						VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&alert.collector_id)
+        .bind(timestamp as i64)
+        .bind(alert.kind.to_string())
+        .bind(alert.value)
+        .bind(alert.threshold)
+        .bind(alert.samples as i32)
+        .execute(db)
+        .await
+        .map_err(|ex| ex.into())
+    }
+
     pub async fn clear_metrics(db: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
         sqlx::query("DELETE FROM TIMESERIES")
             .execute(db)
@@ -342,25 +699,298 @@ mod data {
 mod web {
     use super::*;
 
-    pub async fn show_collectors(Extension(db): Extension<SqlitePool>) -> Json<Vec<Collector>> {
-        let rows = data::get_collectors(&db).await.unwrap();
+    pub async fn show_collectors(
+        Extension(db): Extension<SqlitePool>,
+        Query(query): Query<CollectorsQuery>,
+    ) -> Json<Vec<Collector>> {
+        let rows = data::get_collectors(&db, query.active_within_secs, unix::now_micros())
+            .await
+            .unwrap();
         Json(rows)
     }
 
-    pub async fn show_metrics(Extension(db): Extension<SqlitePool>) -> Json<Vec<DataPoint>> {
-        let rows = data::get_metrics(&db).await.unwrap();
+    pub async fn show_metrics(
+        Extension(db): Extension<SqlitePool>,
+        Query(query): Query<MetricsQuery>,
+    ) -> Json<Vec<DataPoint>> {
+        let rows = data::get_metrics(&db, query.time).await.unwrap();
         Json(rows)
     }
 
     pub async fn show_metrics_by_collector(
         Extension(db): Extension<SqlitePool>,
         uuid: axum_path<String>,
+        Query(query): Query<MetricsQuery>,
     ) -> Json<Vec<DataPoint>> {
-        let rows = data::get_metrics_by_collector(&db, &uuid).await.unwrap();
+        let rows = data::get_metrics_by_collector(&db, &uuid, query.time)
+            .await
+            .unwrap();
         Json(rows)
     }
 
+    pub async fn export_metrics(
+        Extension(db): Extension<SqlitePool>,
+        Query(query): Query<ExportQuery>,
+    ) -> impl IntoResponse {
+        let body = Body::from_stream(data::export_csv(db, query.collector));
+        (
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"metrics.csv\"",
+                ),
+            ],
+            body,
+        )
+    }
+
     pub async fn clear_metrics(Extension(db): Extension<SqlitePool>) {
         data::clear_metrics(&db).await.unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics() -> Metrics {
+        Metrics {
+            total_memory: 100,
+            used_memory: 10,
+            cpus: 4,
+            cpu_usage: 5.0,
+            avg_cpu_usage: 5.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn collectors_are_filtered_by_activity_window() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!("./migrations").run(&db).await.unwrap();
+        let now = 10_000 * 1_000_000;
+
+        for (collector_id, secs_ago) in [("stale", 3600), ("live", 10), ("stale", 7200)] {
+            data::add_metrics(&db, collector_id, now - secs_ago * 1_000_000, &metrics())
+                .await
+                .unwrap();
+        }
+
+        let all = data::get_collectors(&db, None, now).await.unwrap();
+        assert_eq!(
+            all.iter()
+                .map(|c| (c.collector_id.as_str(), c.is_active))
+                .collect::<Vec<_>>(),
+            [("stale", false), ("live", true)]
+        );
+
+        let active = data::get_collectors(&db, Some(60), now).await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].collector_id, "live");
+
+        let active = data::get_collectors(&db, Some(4000), now).await.unwrap();
+        assert_eq!(active.len(), 2);
+        assert!(active.iter().all(|c| c.is_active));
+    }
+
+    #[tokio::test]
+    async fn metrics_are_exported_as_csv() {
+        use futures::StreamExt;
+
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!("./migrations").run(&db).await.unwrap();
+
+        for (collector_id, secs) in [("a", 1), ("b", 2), ("a", 3)] {
+            data::add_metrics(&db, collector_id, secs * 1_000_000, &metrics())
+                .await
+                .unwrap();
+        }
+
+        let export = |collector: Option<&str>| {
+            data::export_csv(db.clone(), collector.map(str::to_string))
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        };
+
+        let lines = export(None).await;
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("id,collector_id,received,"));
+        assert_eq!(lines[1], "1,a,1970-01-01T00:00:01.000000Z,100,10,4,5,5\n");
+
+        let lines = export(Some("a")).await;
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1..].iter().all(|line| line.contains(",a,")));
+    }
+
+    #[tokio::test]
+    async fn samples_from_invalid_collector_ids_are_dropped() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!("./migrations").run(&db).await.unwrap();
+        let mut alert_engine = AlertEngine::new(AlertThresholds::default());
+        // A v1 UUID is well formed but not one a collector generates
+        let v1 = 0x6ba7b810_9dad_11d1_80b4_00c04fd430c8;
+
+        for collector_id in [0, u128::MAX, v1] {
+            store_sample(&db, &mut alert_engine, 1, collector_id, &metrics()).await;
+        }
+
+        let rows = data::get_metrics(&db, TimeFormat::Short).await.unwrap();
+        assert!(rows.is_empty());
+
+        let collector_id = shared_data::new_collector_id();
+        store_sample(&db, &mut alert_engine, 1, collector_id, &metrics()).await;
+        let rows = data::get_metrics(&db, TimeFormat::Short).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0].collector_id,
+            Uuid::from_u128(collector_id).to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn registered_collectors_are_listed_with_their_hostname() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!("./migrations").run(&db).await.unwrap();
+
+        for collector_id in ["named", "anonymous"] {
+            data::add_metrics(&db, collector_id, 1_000_000, &metrics())
+                .await
+                .unwrap();
+        }
+
+        data::register_collector(&db, "named", "old-host", "Linux", 2, 1)
+            .await
+            .unwrap();
+        // Registering again replaces the earlier registration
+        data::register_collector(&db, "named", "build-01", "Linux", 8, 2)
+            .await
+            .unwrap();
+
+        let collectors = data::get_collectors(&db, None, 1_000_000).await.unwrap();
+        let named = collectors
+            .iter()
+            .find(|c| c.collector_id == "named")
+            .unwrap();
+        assert_eq!(named.hostname.as_deref(), Some("build-01"));
+        assert_eq!(named.cores, Some(8));
+        let registered: (String, i64) = sqlx::query_as(
+            "SELECT typeof(registered), registered FROM collectors WHERE collector_id = 'named'",
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        assert_eq!(registered, ("integer".to_string(), 2));
+
+        let anonymous = collectors
+            .iter()
+            .find(|c| c.collector_id == "anonymous")
+            .unwrap();
+        assert_eq!(anonymous.hostname, None);
+    }
+
+    #[tokio::test]
+    async fn cancelling_stops_ingestion_after_the_queued_samples() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!("./migrations").run(&db).await.unwrap();
+        let (tx, rx) = mpsc::sync_channel(10);
+        let shutdown = CancellationToken::new();
+        let ingest = tokio::spawn({
+            let db = db.clone();
+            let shutdown = shutdown.clone();
+            async move {
+                let mut alert_engine = AlertEngine::new(AlertThresholds::default());
+                ingest_metrics(&db, rx, &mut alert_engine, &shutdown).await;
+            }
+        });
+
+        let collector_id = shared_data::new_collector_id();
+        let submit = || CollectorCommand::SubmitData {
+            collector_id,
+            metrics: metrics(),
+        };
+        tx.send((1, submit())).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // Queued while the loop waits; it is still stored after the cancellation
+        tx.send((2, submit())).unwrap();
+        shutdown.cancel();
+
+        // `tx` is still open, so only the cancellation can end the loop
+        tokio::time::timeout(INGEST_POLL_INTERVAL * 5, ingest)
+            .await
+            .expect("ingestion did not stop")
+            .unwrap();
+        let rows = data::get_metrics(&db, TimeFormat::Short).await.unwrap();
+        assert_eq!(rows.len(), 2);
+        drop(tx);
+    }
+
+    #[tokio::test]
+    async fn a_collector_exiting_leaves_ingestion_running() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!("./migrations").run(&db).await.unwrap();
+        let (tx, rx) = mpsc::sync_channel(10);
+        let shutdown = CancellationToken::new();
+        let ingest = tokio::spawn({
+            let db = db.clone();
+            let shutdown = shutdown.clone();
+            async move {
+                let mut alert_engine = AlertEngine::new(AlertThresholds::default());
+                ingest_metrics(&db, rx, &mut alert_engine, &shutdown).await;
+            }
+        });
+
+        let (leaving, staying) = (
+            shared_data::new_collector_id(),
+            shared_data::new_collector_id(),
+        );
+        tx.send((
+            1,
+            CollectorCommand::Exit {
+                collector_id: leaving,
+            },
+        ))
+        .unwrap();
+        tx.send((
+            2,
+            CollectorCommand::SubmitData {
+                collector_id: staying,
+                metrics: metrics(),
+            },
+        ))
+        .unwrap();
+        tokio::time::sleep(INGEST_POLL_INTERVAL * 2).await;
+        assert!(!ingest.is_finished());
+
+        shutdown.cancel();
+        tokio::time::timeout(INGEST_POLL_INTERVAL * 5, ingest)
+            .await
+            .expect("ingestion did not stop")
+            .unwrap();
+        let rows = data::get_metrics(&db, TimeFormat::Short).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        drop(tx);
+    }
+
+    #[tokio::test]
+    async fn request_durations_are_exposed_as_metrics() {
+        use axum::http::{Request, StatusCode};
+        use tower::ServiceExt;
+
+        let config = AppConfig::from_lookup(|name| match name {
+            "DATABASE_URL" => Some("sqlite::memory:".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        let app = setup_router(&config);
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(get("/healthz")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.oneshot(get("/metrics")).await.unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(text.contains("http_request_duration_seconds_count"));
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::Result;
+use sqlx::{Pool, Sqlite};
+use std::future::Future;
+use util::migration_lock::{self, LockStore, LockValue};
+
+pub use util::migration_lock::LockOutcome;
+
+struct SqlxStore<'a>(&'a Pool<Sqlite>);
+
+impl LockStore for SqlxStore<'_> {
+    async fn execute(&self, sql: &str, values: Vec<LockValue>) -> Result<u64> {
+        let mut query = sqlx::query(sql);
+
+        for value in values {
+            query = match value {
+                LockValue::Text(text) => query.bind(text),
+                LockValue::Int(int) => query.bind(int),
+            };
+        }
+
+        Ok(query.execute(self.0).await?.rows_affected())
+    }
+}
+
+/// Runs `migrate` while holding the lock of [`util::migration_lock`], so instances starting
+/// together migrate one at a time.
+pub async fn with_migration_lock<T, F, Fut>(
+    db: &Pool<Sqlite>,
+    migrate: F,
+) -> Result<(T, LockOutcome)>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    migration_lock::with_migration_lock(&SqlxStore(db), migrate).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePool;
+
+    #[tokio::test]
+    async fn execute_binds_the_values_and_counts_the_rows() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE locks (owner TEXT NOT NULL, acquired_at BIGINT)")
+            .execute(&db)
+            .await
+            .unwrap();
+        let store = SqlxStore(&db);
+
+        for owner in ["a", "b"] {
+            let values = vec![LockValue::Text(owner.to_string()), LockValue::Int(1)];
+            let inserted = store
+                .execute(
+                    "INSERT INTO locks (owner, acquired_at) VALUES (?, ?)",
+                    values,
+                )
+                .await
+                .unwrap();
+            assert_eq!(inserted, 1);
+        }
+
+        let updated = store
+            .execute(
+                "UPDATE locks SET acquired_at = ? WHERE owner = ?",
+                vec![LockValue::Int(2), LockValue::Text("a".to_string())],
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated, 1);
+        let removed = store
+            .execute(
+                "DELETE FROM locks WHERE acquired_at < ?",
+                vec![LockValue::Int(2)],
+            )
+            .await
+            .unwrap();
+        assert_eq!(removed, 1);
+    }
+}
@@ -2,7 +2,10 @@ use bincode::{Decode, Encode, config};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
-use std::io::{Cursor, Read};
+use std::{
+    fmt,
+    io::{Cursor, Read},
+};
 use util::{Result, error::RmxError};
 use uuid::Uuid;
 
@@ -19,10 +22,31 @@ pub struct Metrics {
     pub avg_cpu_usage: f32, // average across CPUs
 }
 
+/// One line summary, e.g. `mem: 512 MiB/1 GiB, CPUs: 4, CPU usage: 15.00%, CPU usage (avg): 1.50%`.
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mem: {}/{}, CPUs: {}, CPU usage: {:.2}%, CPU usage (avg): {:.2}%",
+            util::format_bytes(self.used_memory),
+            util::format_bytes(self.total_memory),
+            self.cpus,
+            self.cpu_usage,
+            self.avg_cpu_usage
+        )
+    }
+}
+
 #[derive(FromRow, Debug, Serialize)]
 pub struct Collector {
     pub collector_id: String,
+    /// What the collector registered with, unless it never did.
+    pub hostname: Option<String>,
+    pub os: Option<String>,
+    pub cores: Option<i32>,
     pub last_seen: String,
+    /// Whether the latest sample falls within the activity window of the query.
+    pub is_active: bool,
 }
 
 #[derive(FromRow, Debug, Serialize)]
@@ -46,6 +70,36 @@ pub enum CollectorCommand {
     Exit {
         collector_id: u128,
     },
+    /// Sent once at startup, before the first sample, so the dashboard can name the collector.
+    Register {
+        collector_id: u128,
+        hostname: String,
+        os: String,
+        cores: usize,
+    },
+}
+
+/// One line summary naming the collector by its hyphenated UUID.
+impl fmt::Display for CollectorCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SubmitData {
+                collector_id,
+                metrics,
+            } => write!(f, "{} {metrics}", Uuid::from_u128(*collector_id)),
+            Self::Exit { collector_id } => write!(f, "{} exit", Uuid::from_u128(*collector_id)),
+            Self::Register {
+                collector_id,
+                hostname,
+                os,
+                cores,
+            } => write!(
+                f,
+                "{} registered as {hostname} ({os}, {cores} cores)",
+                Uuid::from_u128(*collector_id)
+            ),
+        }
+    }
 }
 
 pub fn new_collector_id() -> u128 {
@@ -120,8 +174,33 @@ mod tests {
             metrics,
         };
         let encoded = encode(&command);
-        let (timestamp, decoded) = decode(&encoded).unwrap();
+        let (timestamp, decoded) =
+            decode(&encoded).unwrap_or_else(|e| panic!("{e}\n{}", util::hex_dump(&encoded)));
         assert!(timestamp > 0);
-        assert_eq!(command, decoded);
+        assert_eq!(command, decoded, "\n{}", util::hex_dump(&encoded));
+    }
+
+    #[test]
+    fn commands_display_as_one_line() {
+        let collector_id = 0x67e55044_10b1_426f_9247_bb680e5fe0c8;
+        let command = CollectorCommand::SubmitData {
+            collector_id,
+            metrics: Metrics {
+                total_memory: 1024 * 1024 * 1024,
+                used_memory: 512 * 1024 * 1024,
+                cpus: 4,
+                cpu_usage: 15.0,
+                avg_cpu_usage: 1.5,
+            },
+        };
+        assert_eq!(
+            command.to_string(),
+            "67e55044-10b1-426f-9247-bb680e5fe0c8 mem: 512 MiB/1 GiB, CPUs: 4, \
+             CPU usage: 15.00%, CPU usage (avg): 1.50%"
+        );
+        assert_eq!(
+            CollectorCommand::Exit { collector_id }.to_string(),
+            "67e55044-10b1-426f-9247-bb680e5fe0c8 exit"
+        );
     }
 }
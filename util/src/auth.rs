@@ -1,10 +1,11 @@
 use crate::{Result, error::RmxError};
 use fake::{
-    Dummy,
+    Dummy, Fake, Faker,
     faker::{
         internet::en::{Password as FakePassword, SafeEmail},
         name::en::Name,
     },
+    rand::{SeedableRng, rngs::StdRng},
 };
 use serde::{Deserialize, Serialize};
 use std::fmt;
@@ -191,6 +192,35 @@ impl User {
     }
 }
 
+/// Generates `count` fake users. The same `seed` always yields the same users, which keeps
+/// demos and tests reproducible; without one every call is random.
+pub fn gen_users(count: usize, seed: Option<u64>) -> Vec<User> {
+    match seed {
+        Some(seed) => {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..count).map(|_| Faker.fake_with_rng(&mut rng)).collect()
+        }
+        None => (0..count).map(|_| Faker.fake()).collect(),
+    }
+}
+
+/// Parses `--seed <u64>` from the command line, for [`gen_users`]. Without it the users are
+/// random.
+pub fn seed_arg() -> Option<u64> {
+    find_seed(std::env::args().skip(1))
+}
+
+fn find_seed(mut args: impl Iterator<Item = String>) -> Option<u64> {
+    while let Some(arg) = args.next() {
+        if arg == "--seed" {
+            let value = args.next().expect("--seed requires a value.");
+            return Some(value.parse().expect("--seed must be a number."));
+        }
+    }
+
+    None
+}
+
 #[derive(Debug)]
 pub struct Column {
     name: String,
@@ -311,3 +341,32 @@ impl UserFormatter {
         println!("{}", line);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_users_are_reproducible() {
+        let first = gen_users(5, Some(42));
+        let second = gen_users(5, Some(42));
+        assert_eq!(first.len(), 5);
+
+        for (a, b) in first.iter().zip(&second) {
+            assert_eq!(a.id(), b.id());
+            assert_eq!(a.username(), b.username());
+            assert_eq!(a.name(), b.name());
+        }
+
+        let other = gen_users(5, Some(7));
+        assert_ne!(first[0].id(), other[0].id());
+    }
+
+    #[test]
+    fn seed_is_read_after_its_flag() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(find_seed(args(&["--seed", "42"]).into_iter()), Some(42));
+        assert_eq!(find_seed(args(&["8", "--seed", "7"]).into_iter()), Some(7));
+        assert_eq!(find_seed(args(&["42"]).into_iter()), None);
+    }
+}
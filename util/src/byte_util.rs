@@ -1,6 +1,6 @@
 use crate::{Result, error::RmxError};
-use byteorder::{BigEndian, ReadBytesExt};
-use std::io::Cursor;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Cursor, Read, Write};
 
 pub trait ReadFromBytes: Sized {
     fn read_from(cursor: &mut Cursor<&[u8]>) -> Result<Self>;
@@ -34,6 +34,168 @@ pub fn read_slice<'a>(bytes: &'a [u8], offset: &mut usize, len: usize) -> Result
     Ok(slice)
 }
 
+/// Writes `value` as an unsigned LEB128 varint (7 bits per byte, low bits first)
+/// and returns the number of bytes written, 1 to 10.
+pub fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> Result<usize> {
+    let mut written = 0;
+
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        written += 1;
+
+        if value == 0 {
+            writer.write_u8(byte)?;
+            return Ok(written);
+        }
+
+        writer.write_u8(byte | 0x80)?;
+    }
+}
+
+/// Reads an unsigned LEB128 varint, rejecting encodings that don't fit in a `u64`.
+pub fn read_varint<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut value = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let byte = reader.read_u8()?;
+        let bits = (byte & 0x7f) as u64;
+
+        // The 10th byte only has room for the top bit
+        if shift == 63 && bits > 1 {
+            return Err(RmxError::Invalid("Varint overflows u64".to_string()));
+        }
+
+        value |= bits << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(RmxError::Invalid(
+        "Varint is longer than 10 bytes".to_string(),
+    ))
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ByteUnits {
+    /// Powers of 1024: KiB, MiB, GiB...
+    #[default]
+    Binary,
+    /// Powers of 1000: kB, MB, GB...
+    Decimal,
+}
+
+const BINARY_UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+const DECIMAL_UNITS: [&str; 7] = ["B", "kB", "MB", "GB", "TB", "PB", "EB"];
+
+/// Formats a byte count with binary units, e.g. "1.5 GiB" or "512 MiB".
+pub fn format_bytes(n: u64) -> String {
+    format_bytes_with(n, ByteUnits::Binary)
+}
+
+pub fn format_bytes_with(n: u64, units: ByteUnits) -> String {
+    let (base, names) = match units {
+        ByteUnits::Binary => (1024.0, BINARY_UNITS),
+        ByteUnits::Decimal => (1000.0, DECIMAL_UNITS),
+    };
+    let mut value = n as f64;
+    let mut unit = 0;
+
+    while value >= base && unit < names.len() - 1 {
+        value /= base;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        return format!("{} {}", n, names[0]);
+    }
+
+    let formatted = format!("{:.1}", value);
+    let formatted = formatted.strip_suffix(".0").unwrap_or(&formatted);
+    format!("{} {}", formatted, names[unit])
+}
+
+/// Parses sizes like "512", "10MB", "1.5 GiB" or "2k". Units are case-insensitive;
+/// "KiB"/"Ki" are powers of 1024 and "KB"/"K" powers of 1000.
+pub fn parse_bytes(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| RmxError::Invalid(format!("'{}' is not a byte size", s)))?;
+    let unit = unit.trim().to_ascii_lowercase();
+    let unit = unit.strip_suffix('b').unwrap_or(&unit);
+    let (prefix, base) = match unit.strip_suffix('i') {
+        Some(prefix) => (prefix, 1024u64),
+        None => (unit, 1000u64),
+    };
+    let exponent = match prefix {
+        "" if base == 1000 => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        "p" => 5,
+        "e" => 6,
+        _ => {
+            return Err(RmxError::Invalid(format!(
+                "'{}' has an unknown byte unit",
+                s
+            )));
+        }
+    };
+    let bytes = number * base.pow(exponent) as f64;
+
+    if !bytes.is_finite() || bytes > u64::MAX as f64 {
+        return Err(RmxError::Invalid(format!("'{}' is too large", s)));
+    }
+
+    Ok(bytes.round() as u64)
+}
+
+/// Formats bytes like `hexdump -C`: offset, 16 hex bytes split in two groups, then the ASCII.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut buf = Vec::new();
+    hex_dump_to(bytes, &mut buf).expect("Writing to a Vec can't fail");
+    String::from_utf8(buf).expect("hex dump is ASCII")
+}
+
+pub fn hex_dump_to<W: Write>(bytes: &[u8], writer: &mut W) -> std::io::Result<()> {
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        write!(writer, "{:08x}  ", line * 16)?;
+
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(b) => write!(writer, "{:02x} ", b)?,
+                None => write!(writer, "   ")?,
+            }
+
+            if i == 7 {
+                write!(writer, " ")?;
+            }
+        }
+
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        writeln!(writer, " |{}|", ascii)?;
+    }
+
+    Ok(())
+}
+
 // Unsigned integers
 impl ReadFromBytes for u8 {
     fn read_from(cursor: &mut Cursor<&[u8]>) -> Result<Self> {
@@ -132,3 +294,90 @@ impl ReadFromBytes for f64 {
             .map_err(|_| RmxError::Argument("Failed to read f64".to_string()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(512 * 1024 * 1024), "512 MiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024 / 2), "1.5 GiB");
+        assert_eq!(format_bytes(u64::MAX), "16 EiB");
+        assert_eq!(format_bytes_with(10_000_000, ByteUnits::Decimal), "10 MB");
+        assert_eq!(format_bytes_with(1_500, ByteUnits::Decimal), "1.5 kB");
+    }
+
+    #[test]
+    fn parses_bytes() {
+        assert_eq!(parse_bytes("512").unwrap(), 512);
+        assert_eq!(parse_bytes("10MB").unwrap(), 10_000_000);
+        assert_eq!(parse_bytes("2GiB").unwrap(), 2 << 30);
+        assert_eq!(parse_bytes(" 1.5 kib ").unwrap(), 1536);
+        assert_eq!(parse_bytes("4k").unwrap(), 4000);
+        assert!(parse_bytes("").is_err());
+        assert!(parse_bytes("10 XB").is_err());
+        assert!(parse_bytes("1i").is_err());
+        assert!(parse_bytes("100000EB").is_err());
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        let values = [
+            (0, 1),
+            (1, 1),
+            (127, 1),
+            (128, 2),
+            (16_383, 2),
+            (16_384, 3),
+            (u32::MAX as u64, 5),
+            (u64::MAX, 10),
+        ];
+
+        for (value, len) in values {
+            let mut buf = vec![];
+            assert_eq!(write_varint(&mut buf, value).unwrap(), len);
+            assert_eq!(buf.len(), len);
+            let mut cursor = Cursor::new(buf.as_slice());
+            assert_eq!(read_varint(&mut cursor).unwrap(), value);
+            assert_eq!(cursor.position() as usize, len);
+        }
+
+        assert_eq!(read_varint(&mut [0xac, 0x02].as_slice()).unwrap(), 300);
+    }
+
+    #[test]
+    fn varint_rejects_bad_input() {
+        // 2^64 doesn't fit
+        let too_big = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02];
+        assert!(read_varint(&mut too_big.as_slice()).is_err());
+        let too_long = [0x80; 11];
+        assert!(read_varint(&mut too_long.as_slice()).is_err());
+        // Truncated
+        assert!(read_varint(&mut [0x80].as_slice()).is_err());
+    }
+
+    #[test]
+    fn hex_dumps_bytes() {
+        assert_eq!(hex_dump(&[]), "");
+        assert_eq!(
+            hex_dump(b"Hello, world!\n\x00\x01\xffRust"),
+            "00000000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 01  |Hello, world!...|\n\
+             00000010  ff 52 75 73 74                                    |.Rust|\n"
+        );
+    }
+
+    #[test]
+    fn parse_and_format_round_trip() {
+        for n in [0, 1, 1023, 1024, 1536, 512 << 20, 3 << 29, 5 << 40] {
+            assert_eq!(parse_bytes(&format_bytes(n)).unwrap(), n);
+        }
+
+        for n in [0, 999, 1_000, 1_500, 10_000_000, 2_500_000_000] {
+            let formatted = format_bytes_with(n, ByteUnits::Decimal);
+            assert_eq!(parse_bytes(&formatted).unwrap(), n);
+        }
+    }
+}
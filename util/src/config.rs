@@ -0,0 +1,75 @@
+//! Helpers for reading settings from the environment that collect every problem instead of
+//! stopping at the first, so a misconfigured service can report them all at once.
+
+use anyhow::{Result, anyhow};
+use std::{fmt::Display, str::FromStr};
+
+/// Trims `value` and drops it when blank, so an empty variable counts as unset.
+pub fn non_blank(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_owned()).filter(|v| !v.is_empty())
+}
+
+/// Reads `true`/`false`, `1`/`0`, `yes`/`no` or `on`/`off`, in any case.
+pub fn parse_bool(
+    errors: &mut Vec<String>,
+    name: &str,
+    value: Option<String>,
+    default: bool,
+) -> bool {
+    match value.map(|v| v.to_lowercase()) {
+        None => default,
+        Some(v) if matches!(v.as_str(), "true" | "1" | "yes" | "on") => true,
+        Some(v) if matches!(v.as_str(), "false" | "0" | "no" | "off") => false,
+        Some(v) => {
+            errors.push(format!("{name}: '{v}' is not a boolean"));
+            default
+        }
+    }
+}
+
+pub fn parse<T>(errors: &mut Vec<String>, name: &str, value: Option<String>, default: T) -> T
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match value.map(|v| v.parse::<T>()) {
+        None => default,
+        Some(Ok(v)) => v,
+        Some(Err(e)) => {
+            errors.push(format!("{name}: {e}"));
+            default
+        }
+    }
+}
+
+/// Fails with every collected problem, one per line.
+pub fn check(errors: Vec<String>) -> Result<()> {
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(anyhow!("Invalid configuration:\n  {}", errors.join("\n  "))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn problems_are_collected() {
+        let mut errors = vec![];
+        assert!(parse_bool(&mut errors, "A", Some("On".into()), false));
+        assert!(!parse_bool(&mut errors, "B", Some("no".into()), true));
+        assert!(parse_bool(&mut errors, "C", None, true));
+        assert_eq!(parse(&mut errors, "D", Some("42".into()), 0u32), 42);
+        assert!(check(errors.clone()).is_ok());
+
+        assert!(!parse_bool(&mut errors, "E", Some("maybe".into()), false));
+        assert_eq!(parse(&mut errors, "F", Some("-1".into()), 7u32), 7);
+        assert_eq!(non_blank(Some("  ".into())), None);
+        assert_eq!(non_blank(Some(" x ".into())).as_deref(), Some("x"));
+
+        let error = check(errors).unwrap_err().to_string();
+        assert!(error.contains("E: 'maybe' is not a boolean"), "{error}");
+        assert!(error.contains("F: "), "{error}");
+    }
+}
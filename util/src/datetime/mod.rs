@@ -1,8 +1,10 @@
 pub mod unix;
 
-use chrono::{Local, TimeZone};
+use chrono::{DateTime, Local, SecondsFormat, TimeZone, Utc};
 use std::time::Duration;
 
+use crate::{Result, error::RmxError};
+
 pub fn format_duration(duration: Duration) -> String {
     format_seconds_long(duration.as_micros())
 }
@@ -25,3 +27,63 @@ pub fn format_seconds_long(time: u128) -> String {
         .map(|dt| dt.format("%H:%M:%S%.6f").to_string())
         .unwrap_or_else(|| "invalid time".to_string())
 }
+
+fn from_micros<Tz: TimeZone>(tz: &Tz, time: u128) -> Option<DateTime<Tz>> {
+    let secs = i64::try_from(time / 1_000_000).ok()?;
+    let nanos = (time % 1_000_000) as u32 * 1_000;
+    tz.timestamp_opt(secs, nanos).single()
+}
+
+/// Formats unix microseconds as RFC 3339 in UTC, e.g. `2023-11-14T22:13:20.123456Z`.
+pub fn format_rfc3339(time: u128) -> String {
+    from_micros(&Utc, time)
+        .map(|dt| dt.to_rfc3339_opts(SecondsFormat::Micros, true))
+        .unwrap_or_else(|| "invalid time".to_string())
+}
+
+/// Like `format_rfc3339` but in the system timezone, e.g. `2023-11-14T23:13:20.123456+01:00`.
+pub fn format_local(time: u128) -> String {
+    from_micros(&Local, time)
+        .map(|dt| dt.to_rfc3339_opts(SecondsFormat::Micros, false))
+        .unwrap_or_else(|| "invalid time".to_string())
+}
+
+/// Parses an RFC 3339 timestamp in any offset into unix microseconds.
+pub fn parse_rfc3339(value: &str) -> Result<u128> {
+    let dt = DateTime::parse_from_rfc3339(value.trim())
+        .map_err(|e| RmxError::Invalid(format!("'{}' is not an RFC 3339 time. {}", value, e)))?;
+    u128::try_from(dt.timestamp_micros())
+        .map_err(|_| RmxError::Invalid(format!("'{}' is before the unix epoch", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KNOWN: u128 = 1_700_000_000_123_456;
+
+    #[test]
+    fn formats_rfc3339() {
+        assert_eq!(format_rfc3339(0), "1970-01-01T00:00:00.000000Z");
+        assert_eq!(format_rfc3339(KNOWN), "2023-11-14T22:13:20.123456Z");
+    }
+
+    #[test]
+    fn parses_rfc3339() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z").unwrap(), 0);
+        assert_eq!(parse_rfc3339("2023-11-14T22:13:20.123456Z").unwrap(), KNOWN);
+        assert_eq!(
+            parse_rfc3339("2023-11-15T00:13:20.123456+02:00").unwrap(),
+            KNOWN
+        );
+        assert!(parse_rfc3339("1969-12-31T23:59:59Z").is_err());
+        assert!(parse_rfc3339("yesterday").is_err());
+    }
+
+    #[test]
+    fn local_time_round_trips() {
+        for time in [0, KNOWN] {
+            assert_eq!(parse_rfc3339(&format_local(time)).unwrap(), time);
+        }
+    }
+}
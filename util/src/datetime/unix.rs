@@ -1,5 +1,7 @@
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use crate::{Result, error::RmxError};
+
 pub fn now() -> u64 {
     unix_time().as_secs()
 }
@@ -12,13 +14,62 @@ pub fn now_millis() -> u128 {
     unix_time().as_millis()
 }
 
+pub fn now_nanos() -> u128 {
+    unix_time().as_nanos()
+}
+
 pub fn to_system_time(seconds: u64) -> SystemTime {
     UNIX_EPOCH + Duration::from_secs(seconds)
 }
 
+pub fn from_micros(micros: u128) -> SystemTime {
+    UNIX_EPOCH + Duration::from_micros(u64::try_from(micros).unwrap_or(u64::MAX))
+}
+
+pub fn to_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+/// Parses a unix microseconds timestamp stored as text, e.g. in a database column.
+pub fn parse_micros(value: &str) -> Result<u128> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| RmxError::Invalid(format!("'{}' is not a unix timestamp", value)))
+}
+
 fn unix_time() -> Duration {
     let start = SystemTime::now();
     start
         .duration_since(UNIX_EPOCH)
         .expect("Invalid time duration")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clocks_agree() {
+        let millis = now_millis();
+        let micros = now_micros();
+        let nanos = now_nanos();
+
+        assert!(millis <= micros / 1_000);
+        assert!(micros <= nanos / 1_000);
+        // Read at different moments, so only within a tolerance of each other
+        assert!(nanos / 1_000_000 - millis < 1_000);
+        assert!((now() as u128 * 1_000).abs_diff(millis) < 2_000);
+    }
+
+    #[test]
+    fn converts_micros() {
+        let micros = 1_700_000_000_123_456;
+        assert_eq!(to_millis(from_micros(micros)), 1_700_000_000_123);
+        assert_eq!(from_micros(0), UNIX_EPOCH);
+        assert_eq!(parse_micros(" 1700000000123456 ").unwrap(), micros);
+        assert!(parse_micros("-1").is_err());
+    }
+}
@@ -52,4 +52,7 @@ pub enum RmxError {
 
     #[error("Application exited with error {0}")]
     ExitCode(i32),
+
+    #[error("The last admin user can't be removed or demoted.")]
+    LastAdmin,
 }
@@ -1,10 +1,13 @@
-use crate::Result;
+use crate::{Result, error::RmxError};
 use crossterm::{
     event::{self, Event, KeyEvent},
     terminal::{disable_raw_mode, enable_raw_mode},
 };
-use std::thread;
-use tokio::sync::mpsc::{self, error::TryRecvError};
+use std::{ops::Deref, sync::mpsc as std_mpsc, thread, time::Duration};
+use tokio::{
+    sync::mpsc::{self, error::TryRecvError},
+    time::timeout,
+};
 
 #[derive(Debug)]
 pub struct KeyListener {
@@ -21,9 +24,9 @@ impl KeyListener {
         let (tx, rx) = mpsc::channel(buffer_size);
 
         let handle = thread::spawn(move || {
-            if enable_raw_mode().is_err() {
+            let Ok(_raw_mode) = RawModeGuard::new() else {
                 return;
-            }
+            };
 
             loop {
                 if let Ok(Event::Key(key)) = event::read() {
@@ -36,8 +39,6 @@ impl KeyListener {
                     }
                 }
             }
-
-            let _ = disable_raw_mode();
         });
 
         Ok(KeyListener {
@@ -57,6 +58,18 @@ impl KeyListener {
     pub fn try_recv(&mut self) -> std::result::Result<KeyEvent, TryRecvError> {
         self.rx.try_recv()
     }
+
+    /// Waits up to `dur` for a key press without spinning. Returns `Ok(None)` if no key
+    /// arrived in time and an error if the listener thread has stopped.
+    pub async fn try_recv_timeout(&mut self, dur: Duration) -> Result<Option<KeyEvent>> {
+        match timeout(dur, self.rx.recv()).await {
+            Ok(Some(key)) => Ok(Some(key)),
+            Ok(None) => Err(RmxError::InvalidOperation(
+                "The key listener is disconnected".to_string(),
+            )),
+            Err(_) => Ok(None),
+        }
+    }
 }
 
 impl Drop for KeyListener {
@@ -64,3 +77,112 @@ impl Drop for KeyListener {
         let _ = disable_raw_mode();
     }
 }
+
+/// Keeps the terminal in raw mode until dropped, so it is restored on every way out,
+/// panics included.
+#[derive(Debug)]
+pub struct RawModeGuard(());
+
+impl RawModeGuard {
+    pub fn new() -> Result<Self> {
+        enable_raw_mode()?;
+        Ok(Self(()))
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+    }
+}
+
+/// The key presses read by [`spawn_key_listener`]. It derefs to the channel's receiver and
+/// restores the terminal when dropped.
+#[derive(Debug)]
+pub struct KeyReceiver {
+    rx: std_mpsc::Receiver<KeyEvent>,
+    _raw_mode: RawModeGuard,
+}
+
+impl Deref for KeyReceiver {
+    type Target = std_mpsc::Receiver<KeyEvent>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.rx
+    }
+}
+
+/// Switches the terminal to raw mode and reads the key presses on a thread, for callers
+/// that don't run an async runtime. The thread stops at the first key read after the
+/// receiver is dropped.
+pub fn spawn_key_listener() -> Result<KeyReceiver> {
+    let raw_mode = RawModeGuard::new()?;
+    let (tx, rx) = std_mpsc::channel();
+
+    thread::spawn(move || {
+        loop {
+            match event::read() {
+                Ok(Event::Key(key)) if key.is_press() => {
+                    if tx.send(key).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+    });
+
+    Ok(KeyReceiver {
+        rx,
+        _raw_mode: raw_mode,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyCode;
+    use std::time::Instant;
+
+    // Builds a listener fed by the returned sender instead of the terminal
+    fn listener() -> (mpsc::Sender<KeyEvent>, KeyListener) {
+        let (tx, rx) = mpsc::channel(1);
+        let listener = KeyListener {
+            rx,
+            _handle: thread::spawn(|| {}),
+        };
+        (tx, listener)
+    }
+
+    #[tokio::test]
+    async fn try_recv_timeout_returns_none_when_idle() {
+        let (_tx, mut listener) = listener();
+        let start = Instant::now();
+        let key = listener
+            .try_recv_timeout(Duration::from_millis(20))
+            .await
+            .unwrap();
+        assert!(key.is_none());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn try_recv_timeout_returns_pending_key() {
+        let (tx, mut listener) = listener();
+        tx.send(KeyEvent::from(KeyCode::Enter)).await.unwrap();
+        let key = listener
+            .try_recv_timeout(Duration::from_millis(20))
+            .await
+            .unwrap();
+        assert_eq!(key.map(|k| k.code), Some(KeyCode::Enter));
+
+        drop(tx);
+        assert!(
+            listener
+                .try_recv_timeout(Duration::from_millis(20))
+                .await
+                .is_err()
+        );
+    }
+}
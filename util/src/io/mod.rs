@@ -3,14 +3,16 @@ pub use key_listener::*;
 
 use crate::{Result, error::RmxError};
 use crossterm::{
-    ExecutableCommand, cursor,
-    event::{self, Event, KeyCode, KeyEvent},
+    ExecutableCommand, QueueableCommand, cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    style::{Attribute, Print, SetAttribute},
     terminal::{Clear, ClearType, disable_raw_mode, enable_raw_mode},
 };
 use dialoguer::{Select, theme::ColorfulTheme};
 use rpassword::read_password;
 use std::{
-    io::{Write, stdin, stdout},
+    fmt::Display,
+    io::{BufRead, IsTerminal, Write, stdin, stdout},
     time::Duration,
 };
 
@@ -27,11 +29,83 @@ pub fn display_menu(items: &[&str], prompt: Option<&str>) -> Result<usize> {
         .default(0)
         .interact()
         .unwrap();
-    Ok(if selection == items.len() - 1 {
+    Ok(menu_choice(selection, items.len()))
+}
+
+/// Like `display_menu`, but draws the menu itself in raw mode: Up/Down move the highlight,
+/// Enter selects and Esc cancels, which returns 0 like choosing the last (exit) item.
+/// Falls back to `display_menu` when stdin isn't a terminal.
+pub fn display_menu_interactive(items: &[&str], title: Option<&str>) -> Result<usize> {
+    if items.is_empty() {
+        return Err(RmxError::Argument("The menu has no items".to_string()));
+    }
+
+    if !stdin().is_terminal() {
+        return display_menu(items, title);
+    }
+
+    let title = match title {
+        Some(s) if !s.is_empty() => s,
+        _ => "Please select an option",
+    };
+    let mut stdout = stdout();
+    let mut selected = 0;
+    enable_raw_mode()?;
+    clear_keys();
+
+    let result = loop {
+        if let Err(e) = render_menu(&mut stdout, items, title, selected) {
+            break Err(e);
+        }
+
+        match event::read() {
+            Ok(Event::Key(key)) if key.is_press() => match key.code {
+                KeyCode::Up => selected = selected.checked_sub(1).unwrap_or(items.len() - 1),
+                KeyCode::Down => selected = (selected + 1) % items.len(),
+                KeyCode::Enter => break Ok(menu_choice(selected, items.len())),
+                KeyCode::Esc => break Ok(0),
+                _ => {}
+            },
+            Ok(_) => {}
+            Err(e) => break Err(e.into()),
+        }
+    };
+
+    disable_raw_mode()?;
+    stdout.execute(cursor::Show)?;
+    result
+}
+
+fn render_menu<W: Write>(out: &mut W, items: &[&str], title: &str, selected: usize) -> Result<()> {
+    // Raw mode doesn't translate \n, so lines end with \r\n
+    out.queue(cursor::Hide)?
+        .queue(Clear(ClearType::All))?
+        .queue(cursor::MoveTo(0, 0))?
+        .queue(Print(format!("{}\r\n\r\n", title)))?;
+
+    for (i, item) in items.iter().enumerate() {
+        if i == selected {
+            out.queue(SetAttribute(Attribute::Reverse))?
+                .queue(Print(format!("> {}", item)))?
+                .queue(SetAttribute(Attribute::Reset))?
+                .queue(Print("\r\n"))?;
+        } else {
+            out.queue(Print(format!("  {}\r\n", item)))?;
+        }
+    }
+
+    out.queue(Print("\r\nUp/Down to move, Enter to select, Esc to exit"))?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Menu choices are 1-based, with the last item (exit) mapped to 0.
+fn menu_choice(selection: usize, len: usize) -> usize {
+    if selection == len - 1 {
         0
     } else {
         selection + 1
-    })
+    }
 }
 
 pub fn get(prompt: Option<&str>) -> Result<String> {
@@ -112,11 +186,125 @@ where
     }
 }
 
+/// Like `get_numeric`, but keeps prompting until the value parses and lies within
+/// `min..=max`. An empty line still returns `RmxError::NoInput` so callers can bail out.
+pub fn get_numeric_in_range<T>(prompt: Option<&str>, min: T, max: T) -> Result<T>
+where
+    T: std::str::FromStr + PartialOrd + Display,
+{
+    read_numeric_in_range(&mut stdin().lock(), &mut stdout(), prompt, min, max)
+}
+
+fn read_numeric_in_range<T, R, W>(
+    input: &mut R,
+    out: &mut W,
+    prompt: Option<&str>,
+    min: T,
+    max: T,
+) -> Result<T>
+where
+    T: std::str::FromStr + PartialOrd + Display,
+    R: BufRead,
+    W: Write,
+{
+    if let Some(p) = prompt.filter(|p| !p.is_empty()) {
+        write!(out, "{} ", p)?;
+    }
+
+    loop {
+        out.flush()?;
+        let mut buffer = String::new();
+
+        if input.read_line(&mut buffer)? == 0 {
+            return Err(RmxError::NoInput);
+        }
+
+        let line = buffer.trim();
+
+        if line.is_empty() {
+            return Err(RmxError::NoInput);
+        }
+
+        match line.parse::<T>() {
+            Ok(value) if value >= min && value <= max => return Ok(value),
+            _ => write!(out, "Please enter a number between {} and {}: ", min, max)?,
+        }
+    }
+}
+
+/// Asks a yes/no question, e.g. `confirm("Remove user 'bob'?")` prints
+/// `Remove user 'bob'? [y/N] `. Only `y` or `yes`, in any case, confirm; anything else,
+/// including an empty line, declines.
+pub fn confirm(prompt: &str) -> Result<bool> {
+    read_confirm(&mut stdin().lock(), &mut stdout(), prompt)
+}
+
+fn read_confirm<R: BufRead, W: Write>(input: &mut R, out: &mut W, prompt: &str) -> Result<bool> {
+    write!(out, "{} [y/N] ", prompt)?;
+    out.flush()?;
+    let mut buffer = String::new();
+    input.read_line(&mut buffer)?;
+    Ok(matches!(buffer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Reads a password echoing `*` per character. Backspace removes the last character,
+/// Enter finishes and Esc or Ctrl+C cancel. Falls back to `rpassword` when stdin isn't a terminal.
 pub fn get_password(prompt: Option<&str>) -> Result<String> {
     print_prompt(prompt);
 
-    let input = read_password()?;
-    Ok(input)
+    if !stdin().is_terminal() {
+        return Ok(read_password()?);
+    }
+
+    enable_raw_mode()?;
+    clear_keys();
+
+    let keys = std::iter::from_fn(|| {
+        loop {
+            match event::read() {
+                Ok(Event::Key(key)) if key.is_press() => return Some(Ok(key)),
+                Ok(_) => continue,
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    });
+    let result = read_masked(&mut stdout(), keys);
+
+    // Restore the terminal whether or not reading succeeded
+    disable_raw_mode()?;
+    println!();
+    result
+}
+
+fn read_masked<W, I>(out: &mut W, keys: I) -> Result<String>
+where
+    W: Write,
+    I: IntoIterator<Item = Result<KeyEvent>>,
+{
+    let mut buffer = String::new();
+
+    for key in keys {
+        let key = key?;
+
+        match key.code {
+            KeyCode::Enter => return Ok(buffer),
+            KeyCode::Esc => return Err(RmxError::Canceled),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Err(RmxError::Canceled);
+            }
+            KeyCode::Char(c) => {
+                buffer.push(c);
+                out.write_all(b"*")?;
+            }
+            // Backspace on an empty buffer must not erase the prompt
+            KeyCode::Backspace if buffer.pop().is_some() => out.write_all(b"\x08 \x08")?,
+            _ => continue,
+        }
+
+        out.flush()?;
+    }
+
+    Err(RmxError::NoInput)
 }
 
 pub fn get_password_str(prompt: Option<&str>) -> Result<String> {
@@ -156,3 +344,112 @@ fn print_prompt(prompt: Option<&str>) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numeric_in_range(input: &str) -> (Result<i32>, String) {
+        let mut out = vec![];
+        let value = read_numeric_in_range(&mut input.as_bytes(), &mut out, Some("n:"), 1, 10);
+        (value, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn numeric_in_range_accepts_bounds() {
+        assert_eq!(numeric_in_range("1\n").0.unwrap(), 1);
+        assert_eq!(numeric_in_range("10\n").0.unwrap(), 10);
+    }
+
+    #[test]
+    fn numeric_in_range_reprompts_until_valid() {
+        let (value, out) = numeric_in_range("0\n11\nabc\n5\n");
+        assert_eq!(value.unwrap(), 5);
+        assert_eq!(
+            out,
+            format!(
+                "n: {}",
+                "Please enter a number between 1 and 10: ".repeat(3)
+            )
+        );
+    }
+
+    #[test]
+    fn numeric_in_range_stops_on_empty_input() {
+        assert!(matches!(
+            numeric_in_range("42\n\n").0,
+            Err(RmxError::NoInput)
+        ));
+        assert!(matches!(numeric_in_range("").0, Err(RmxError::NoInput)));
+    }
+
+    #[test]
+    fn confirm_defaults_to_no() {
+        for (input, expected) in [
+            ("y\n", true),
+            (" YES\n", true),
+            ("\n", false),
+            ("n\n", false),
+            ("", false),
+        ] {
+            let mut out = vec![];
+            let confirmed = read_confirm(&mut input.as_bytes(), &mut out, "Sure?").unwrap();
+            assert_eq!(confirmed, expected, "{input:?}");
+            assert_eq!(out, b"Sure? [y/N] ");
+        }
+    }
+
+    fn keys(codes: &[KeyCode]) -> Vec<Result<KeyEvent>> {
+        codes.iter().map(|&code| Ok(KeyEvent::from(code))).collect()
+    }
+
+    #[test]
+    fn read_masked_echoes_stars_only() {
+        let mut out = vec![];
+        let input = keys(&[
+            KeyCode::Char('p'),
+            KeyCode::Char('w'),
+            KeyCode::Char('d'),
+            KeyCode::Backspace,
+            KeyCode::Char('!'),
+            KeyCode::Enter,
+        ]);
+        assert_eq!(read_masked(&mut out, input).unwrap(), "pw!");
+        assert_eq!(out, b"***\x08 \x08*");
+    }
+
+    #[test]
+    fn read_masked_ignores_backspace_on_empty() {
+        let mut out = vec![];
+        let input = keys(&[KeyCode::Backspace, KeyCode::Char('a'), KeyCode::Enter]);
+        assert_eq!(read_masked(&mut out, input).unwrap(), "a");
+        assert_eq!(out, b"*");
+    }
+
+    #[test]
+    fn read_masked_cancels_on_esc() {
+        let input = keys(&[KeyCode::Char('a'), KeyCode::Esc]);
+        assert!(matches!(
+            read_masked(&mut vec![], input),
+            Err(RmxError::Canceled)
+        ));
+    }
+
+    #[test]
+    fn menu_choice_maps_last_item_to_zero() {
+        assert_eq!(menu_choice(0, 3), 1);
+        assert_eq!(menu_choice(1, 3), 2);
+        assert_eq!(menu_choice(2, 3), 0);
+    }
+
+    #[test]
+    fn render_menu_highlights_selection() {
+        let mut out = vec![];
+        render_menu(&mut out, &["Login", "Exit"], "Menu", 1).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Menu\r\n\r\n"));
+        assert!(out.contains("  Login\r\n"));
+        assert!(out.contains("> Exit"));
+        assert!(!out.contains("> Login"));
+    }
+}
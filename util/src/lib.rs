@@ -2,10 +2,15 @@ pub use crossterm::*;
 pub use tokio::*;
 
 pub mod auth;
+pub mod config;
 pub mod datetime;
 pub mod error;
 pub mod io;
+pub mod metrics;
+pub mod migration_lock;
+pub mod retry;
 pub mod threading;
+pub mod web;
 
 mod byte_util;
 pub use byte_util::*;
@@ -0,0 +1,256 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Arc, OnceLock, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// Upper bounds in seconds used by [`Histogram::default`], the same as Prometheus' defaults.
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// A histogram of durations with fixed buckets. Observing is lock free, so it can be shared
+/// by every request.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    /// One counter per bound plus one for the values above the last bound.
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUCKETS)
+    }
+}
+
+impl Histogram {
+    /// Creates a histogram with the given bucket upper bounds in seconds. They are sorted
+    /// and deduplicated; non finite ones are dropped.
+    pub fn new(bounds: &[f64]) -> Self {
+        let mut bounds = bounds
+            .iter()
+            .copied()
+            .filter(|b| b.is_finite())
+            .collect::<Vec<_>>();
+        bounds.sort_by(f64::total_cmp);
+        bounds.dedup();
+        let buckets = (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect();
+        Self {
+            bounds,
+            buckets,
+            count: AtomicU64::new(0),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let index = self.bounds.partition_point(|b| *b < seconds);
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Starts a [`Timer`] that observes the elapsed time into this histogram when dropped.
+    pub fn start_timer(&self) -> Timer<'_> {
+        Timer {
+            histogram: self,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut cumulative = 0;
+        let buckets = self
+            .bounds
+            .iter()
+            .zip(&self.buckets)
+            .map(|(bound, bucket)| {
+                cumulative += bucket.load(Ordering::Relaxed);
+                (*bound, cumulative)
+            })
+            .collect();
+        HistogramSnapshot {
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)),
+            buckets,
+        }
+    }
+}
+
+/// Observes the time since it was started into its histogram when dropped.
+#[derive(Debug)]
+pub struct Timer<'a> {
+    histogram: &'a Histogram,
+    start: Instant,
+}
+
+impl Timer<'_> {
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        self.histogram.observe(self.start.elapsed());
+    }
+}
+
+/// The state of a [`Histogram`] at one point in time. Like Prometheus, `buckets` holds the
+/// cumulative count of observations less than or equal to each upper bound; the ones above
+/// the last bound are only in `count`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramSnapshot {
+    pub count: u64,
+    pub sum: Duration,
+    pub buckets: Vec<(f64, u64)>,
+}
+
+impl HistogramSnapshot {
+    /// Estimates the `q` quantile (0 to 1) in seconds by interpolating inside the bucket it
+    /// falls in, like Prometheus' `histogram_quantile`. Returns `None` without observations.
+    /// Values past the last bound are reported as that bound.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = q.clamp(0.0, 1.0) * self.count as f64;
+        let mut lower = (0.0, 0);
+
+        for &(bound, cumulative) in &self.buckets {
+            if cumulative as f64 >= rank {
+                let in_bucket = (cumulative - lower.1) as f64;
+
+                if in_bucket == 0.0 {
+                    return Some(bound);
+                }
+
+                let fraction = (rank - lower.1 as f64) / in_bucket;
+                return Some(lower.0 + (bound - lower.0) * fraction);
+            }
+
+            lower = (bound, cumulative);
+        }
+
+        Some(lower.0)
+    }
+}
+
+/// Named histograms, created on first use.
+#[derive(Debug, Default)]
+pub struct Registry {
+    histograms: RwLock<BTreeMap<String, Arc<Histogram>>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the histogram called `name`, creating it with [`DEFAULT_BUCKETS`] if needed.
+    pub fn histogram(&self, name: &str) -> Arc<Histogram> {
+        if let Some(histogram) = self.histograms.read().unwrap().get(name) {
+            return histogram.clone();
+        }
+
+        self.histograms
+            .write()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    pub fn snapshot(&self) -> Vec<(String, HistogramSnapshot)> {
+        self.histograms
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, histogram)| (name.clone(), histogram.snapshot()))
+            .collect()
+    }
+
+    /// Renders every histogram in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        for (name, snapshot) in self.snapshot() {
+            let _ = writeln!(out, "# TYPE {name} histogram");
+
+            for (bound, count) in &snapshot.buckets {
+                let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {count}");
+            }
+
+            let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", snapshot.count);
+            let _ = writeln!(out, "{name}_sum {}", snapshot.sum.as_secs_f64());
+            let _ = writeln!(out, "{name}_count {}", snapshot.count);
+        }
+
+        out
+    }
+}
+
+/// The process wide registry.
+pub fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observations_land_in_the_right_buckets() {
+        let histogram = Histogram::new(&[0.5, 0.1, 1.0, f64::INFINITY]);
+        for ms in [50, 100, 100, 300, 700, 2000] {
+            histogram.observe(Duration::from_millis(ms));
+        }
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 6);
+        assert_eq!(snapshot.sum, Duration::from_millis(3250));
+        assert_eq!(snapshot.buckets, [(0.1, 3), (0.5, 4), (1.0, 5)]);
+
+        // 3 of 6 are at most 0.1s and the 4th is in (0.1, 0.5]
+        assert_eq!(snapshot.quantile(0.5), Some(0.1));
+        assert!((snapshot.quantile(0.6).unwrap() - 0.34).abs() < 1e-9);
+        assert_eq!(snapshot.quantile(1.0), Some(1.0));
+        assert_eq!(Histogram::default().snapshot().quantile(0.5), None);
+    }
+
+    #[test]
+    fn observations_are_recorded_into_named_histograms() {
+        let registry = Registry::new();
+        registry
+            .histogram("work_seconds")
+            .observe(Duration::from_millis(1));
+        registry
+            .histogram("work_seconds")
+            .observe(Duration::from_secs(20));
+
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].1.count, 2);
+        assert_eq!(snapshot[0].1.buckets[0].1, 1);
+
+        let text = registry.render();
+        assert!(text.contains("work_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(text.contains("work_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("work_seconds_count 2\n"));
+
+        // However long the timer ran, it is observed once
+        drop(registry.histogram("work_seconds").start_timer());
+        assert_eq!(registry.snapshot()[0].1.count, 3);
+    }
+}
@@ -0,0 +1,273 @@
+//! A lock row in a `migration_lock` table that makes instances sharing a SQLite database
+//! run their migrations one at a time. The services run its statements through
+//! [`LockStore`] with whichever database library they use.
+
+use anyhow::Result;
+use std::{future::Future, time::Duration};
+use uuid::Uuid;
+
+use crate::datetime::unix;
+
+/// How often a waiting instance checks whether the lock was released.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How often the instance migrating refreshes the lock, so a long migration keeps it.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// A lock not refreshed for this long is taken to belong to an instance that died while
+/// migrating.
+pub const STALE_LOCK_AFTER: Duration = Duration::from_secs(600);
+
+const CREATE_LOCK_TABLE: &str = "CREATE TABLE IF NOT EXISTS migration_lock (
+    id INTEGER PRIMARY KEY,
+    owner TEXT NOT NULL,
+    acquired_at BIGINT NOT NULL
+)";
+
+/// A value bound to a `?` placeholder of the lock's statements.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockValue {
+    Text(String),
+    Int(i64),
+}
+
+/// Runs the lock's statements on the service's database.
+pub trait LockStore: Sync {
+    /// Runs `sql` with `values` bound to its placeholders in order and returns the number
+    /// of rows changed.
+    fn execute(
+        &self,
+        sql: &str,
+        values: Vec<LockValue>,
+    ) -> impl Future<Output = Result<u64>> + Send;
+}
+
+/// How [`with_migration_lock`] got the lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockOutcome {
+    /// The lock was free, so this instance did the migration.
+    Acquired,
+    /// Another instance was migrating; this one waited for it to finish, so usually there was
+    /// nothing left to apply.
+    Waited,
+}
+
+struct Timings {
+    poll: Duration,
+    refresh: Duration,
+    stale_after: Duration,
+}
+
+/// Runs `migrate` while holding the lock, refreshing it every [`REFRESH_INTERVAL`]. A lock
+/// not refreshed for [`STALE_LOCK_AFTER`] is removed. The lock is released whether
+/// `migrate` fails or not, and a failed release is only returned when `migrate` succeeded.
+pub async fn with_migration_lock<S, T, F, Fut>(store: &S, migrate: F) -> Result<(T, LockOutcome)>
+where
+    S: LockStore,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let timings = Timings {
+        poll: POLL_INTERVAL,
+        refresh: REFRESH_INTERVAL,
+        stale_after: STALE_LOCK_AFTER,
+    };
+    run_locked(store, &timings, migrate).await
+}
+
+async fn run_locked<S, T, F, Fut>(
+    store: &S,
+    timings: &Timings,
+    migrate: F,
+) -> Result<(T, LockOutcome)>
+where
+    S: LockStore,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    store.execute(CREATE_LOCK_TABLE, vec![]).await?;
+    let owner = Uuid::new_v4().to_string();
+    let mut outcome = LockOutcome::Acquired;
+
+    while !try_acquire(store, &owner).await? {
+        if outcome == LockOutcome::Acquired {
+            tracing::info!("Another instance is migrating the database, waiting for it");
+            outcome = LockOutcome::Waited;
+        }
+
+        let stale = unix::now().saturating_sub(timings.stale_after.as_secs()) as i64;
+        let removed = store
+            .execute(
+                "DELETE FROM migration_lock WHERE acquired_at < ?",
+                vec![LockValue::Int(stale)],
+            )
+            .await?;
+
+        if removed > 0 {
+            tracing::warn!(
+                "Removed a migration lock not refreshed for over {:?}",
+                timings.stale_after
+            );
+            continue;
+        }
+
+        tokio::time::sleep(timings.poll).await;
+    }
+
+    let result = tokio::select! {
+        result = migrate() => result,
+        () = keep_refreshed(store, &owner, timings.refresh) => unreachable!(),
+    };
+    let released = store
+        .execute(
+            "DELETE FROM migration_lock WHERE owner = ?",
+            vec![LockValue::Text(owner)],
+        )
+        .await;
+
+    match (result, released) {
+        (Ok(value), Ok(_)) => Ok((value, outcome)),
+        (Ok(_), Err(e)) => Err(e),
+        (Err(e), released) => {
+            if let Err(release) = released {
+                tracing::warn!("Failed to release the migration lock: {release}");
+            }
+
+            Err(e)
+        }
+    }
+}
+
+async fn try_acquire<S: LockStore>(store: &S, owner: &str) -> Result<bool> {
+    let acquired = store
+        .execute(
+            "INSERT INTO migration_lock (id, owner, acquired_at) VALUES (1, ?, ?) \
+             ON CONFLICT (id) DO NOTHING",
+            vec![
+                LockValue::Text(owner.to_owned()),
+                LockValue::Int(unix::now() as i64),
+            ],
+        )
+        .await?;
+    Ok(acquired == 1)
+}
+
+/// Moves the lock's `acquired_at` forward every `period`, until dropped.
+async fn keep_refreshed<S: LockStore>(store: &S, owner: &str, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    // The first tick is immediate, and the lock was just taken
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        let refreshed = store
+            .execute(
+                "UPDATE migration_lock SET acquired_at = ? WHERE owner = ?",
+                vec![
+                    LockValue::Int(unix::now() as i64),
+                    LockValue::Text(owner.to_owned()),
+                ],
+            )
+            .await;
+
+        match refreshed {
+            Ok(0) => tracing::warn!("The migration lock was taken over while migrating"),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to refresh the migration lock: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{SqlitePool, sqlite::SqliteQueryResult};
+    use std::sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    };
+
+    struct Store(SqlitePool);
+
+    impl LockStore for Store {
+        async fn execute(&self, sql: &str, values: Vec<LockValue>) -> Result<u64> {
+            let mut query = sqlx::query(sql);
+
+            for value in values {
+                query = match value {
+                    LockValue::Text(text) => query.bind(text),
+                    LockValue::Int(int) => query.bind(int),
+                };
+            }
+
+            let result: SqliteQueryResult = query.execute(&self.0).await?;
+            Ok(result.rows_affected())
+        }
+    }
+
+    async fn connect(path: &std::path::Path) -> Store {
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        Store(SqlitePool::connect(&url).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn a_refreshed_lock_isnt_taken_as_stale() {
+        let path = std::env::temp_dir().join(format!("util-{}.db", Uuid::new_v4()));
+        let timings = Arc::new(Timings {
+            poll: Duration::from_millis(50),
+            refresh: Duration::from_millis(100),
+            stale_after: Duration::from_secs(1),
+        });
+        let migrating = Arc::new(AtomicBool::new(false));
+        let mut tasks = vec![];
+
+        for _ in 0..2 {
+            let store = connect(&path).await;
+            let (timings, migrating) = (timings.clone(), migrating.clone());
+            tasks.push(tokio::spawn(async move {
+                let ((), outcome) = run_locked(&store, &timings, || async {
+                    assert!(!migrating.swap(true, Ordering::SeqCst));
+                    // Longer than the lock may go without a refresh
+                    tokio::time::sleep(Duration::from_millis(2500)).await;
+                    migrating.store(false, Ordering::SeqCst);
+                    Ok(())
+                })
+                .await
+                .unwrap();
+                outcome
+            }));
+        }
+
+        let mut outcomes = vec![];
+
+        for task in tasks {
+            outcomes.push(task.await.unwrap());
+        }
+
+        assert!(outcomes.contains(&LockOutcome::Acquired));
+        assert!(outcomes.contains(&LockOutcome::Waited));
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[tokio::test]
+    async fn a_failed_release_doesnt_hide_the_migration_error() {
+        let path = std::env::temp_dir().join(format!("util-{}.db", Uuid::new_v4()));
+        let store = connect(&path).await;
+        let drop_table = || async {
+            sqlx::query("DROP TABLE migration_lock")
+                .execute(&store.0)
+                .await?;
+            Ok(())
+        };
+
+        let error = with_migration_lock(&store, || async {
+            drop_table().await?;
+            Err::<(), _>(anyhow::anyhow!("broken"))
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(error.to_string(), "broken");
+
+        let error = with_migration_lock(&store, drop_table).await.unwrap_err();
+        assert!(error.to_string().contains("migration_lock"), "{error}");
+        std::fs::remove_file(&path).ok();
+    }
+}
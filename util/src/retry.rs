@@ -0,0 +1,156 @@
+use std::{future::Future, thread, time::Duration};
+
+/// How often and how long to wait between retries. The delay before retry `n` (0 based) is
+/// `base * multiplier^n`, capped at `max`. `jitter` (0 to 1) takes up to that fraction off
+/// each delay at random, so clients that failed together don't retry together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackoffPolicy {
+    pub base: Duration,
+    pub max: Duration,
+    pub multiplier: f64,
+    pub max_retries: u32,
+    pub jitter: f64,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_millis(500),
+            max: Duration::from_secs(8),
+            multiplier: 2.0,
+            max_retries: 5,
+            jitter: 0.1,
+        }
+    }
+}
+
+impl BackoffPolicy {
+    /// The delay before retry `attempt`, counting from 0.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .max(1.0)
+            .powi(attempt.min(i32::MAX as u32) as i32);
+        let delay = (self.base.as_secs_f64() * factor).min(self.max.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0) * rand::random::<f64>();
+        Duration::from_secs_f64(delay * (1.0 - jitter))
+    }
+}
+
+/// Runs `op` until it succeeds or failed `policy.max_retries` times after the first try,
+/// sleeping between attempts. Returns the last error.
+pub async fn retry_with_backoff<F, Fut, T, E>(
+    policy: &BackoffPolicy,
+    mut op: F,
+) -> std::result::Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<T, E>>,
+{
+    let mut attempt = 0;
+
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt >= policy.max_retries => return Err(e),
+            Err(_) => {
+                tokio::time::sleep(policy.delay(attempt)).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Same as [`retry_with_backoff`] for blocking code, sleeping the current thread.
+pub fn retry_with_backoff_blocking<F, T, E>(
+    policy: &BackoffPolicy,
+    mut op: F,
+) -> std::result::Result<T, E>
+where
+    F: FnMut() -> std::result::Result<T, E>,
+{
+    let mut attempt = 0;
+
+    loop {
+        match op() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt >= policy.max_retries => return Err(e),
+            Err(_) => {
+                thread::sleep(policy.delay(attempt));
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(max_retries: u32) -> BackoffPolicy {
+        BackoffPolicy {
+            base: Duration::from_millis(1),
+            max: Duration::from_millis(5),
+            multiplier: 2.0,
+            max_retries,
+            jitter: 0.0,
+        }
+    }
+
+    #[test]
+    fn delays_grow_up_to_the_max() {
+        let delays = (0..5).map(|n| policy(5).delay(n)).collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            [1, 2, 4, 5, 5].map(Duration::from_millis),
+            "{delays:?}"
+        );
+
+        let jittered = BackoffPolicy {
+            jitter: 0.5,
+            ..policy(5)
+        };
+
+        for n in 0..5 {
+            let delay = jittered.delay(n);
+            assert!(delay <= policy(5).delay(n) && delay >= policy(5).delay(n) / 2);
+        }
+    }
+
+    #[test]
+    fn blocking_retry_stops_after_max_retries() {
+        let mut calls = 0;
+        let result: std::result::Result<(), u32> = retry_with_backoff_blocking(&policy(3), || {
+            calls += 1;
+            Err(calls)
+        });
+        assert_eq!(result, Err(4));
+
+        let mut calls = 0;
+        let result = retry_with_backoff_blocking(&policy(3), || {
+            calls += 1;
+            if calls < 3 { Err(()) } else { Ok(calls) }
+        });
+        assert_eq!(result, Ok(3));
+    }
+
+    #[tokio::test]
+    async fn async_retry_stops_after_max_retries() {
+        let mut calls = 0;
+        let result: std::result::Result<(), u32> = retry_with_backoff(&policy(2), || {
+            calls += 1;
+            let attempt = calls;
+            async move { Err(attempt) }
+        })
+        .await;
+        assert_eq!(result, Err(3));
+
+        let mut calls = 0;
+        let result = retry_with_backoff(&policy(0), || {
+            calls += 1;
+            async { Ok::<_, ()>("done") }
+        })
+        .await;
+        assert_eq!((result, calls), (Ok("done"), 1));
+    }
+}
@@ -1,8 +1,16 @@
+use crossbeam::deque::{Injector, Steal, Stealer, Worker};
 use std::{
-    sync::{Arc, Condvar, Mutex},
+    sync::{
+        Arc, Condvar, LockResult, Mutex, MutexGuard, RwLock, TryLockError, TryLockResult,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread::{self, JoinHandle},
     time::{Duration, Instant},
 };
 
+use crate::{Result, error::RmxError};
+
 #[derive(Debug, Default, Clone)]
 pub struct Signal {
     inner: Arc<(Mutex<bool>, Condvar)>,
@@ -20,6 +28,7 @@ impl Signal {
         cvar.notify_all();
     }
 
+    /// Clears the flag so a `set` from a previous iteration isn't seen by the next wait.
     pub fn reset(&self) {
         let (lock, _) = &*self.inner;
         let mut signaled = lock.lock().unwrap();
@@ -38,6 +47,7 @@ impl Signal {
         *signaled = false;
     }
 
+    /// Returns whether the signal was set before `timeout` elapsed. A zero timeout waits forever.
     pub fn wait_timeout(&self, timeout: Duration) -> bool {
         if timeout.is_zero() {
             self.wait();
@@ -61,3 +71,590 @@ impl Signal {
         true
     }
 }
+
+/// A `Mutex` whose data stays usable after a thread panicked while holding the lock.
+/// `lock` and `try_lock` report poisoning like `Mutex`; the `_recover` variants log a
+/// warning and hand out the guard anyway, for data that can't be left inconsistent.
+#[derive(Debug, Default)]
+pub struct RecoverableMutex<T> {
+    inner: Mutex<T>,
+}
+
+impl<T> RecoverableMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: Mutex::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        self.inner.lock()
+    }
+
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        self.inner.try_lock()
+    }
+
+    pub fn lock_recover(&self) -> MutexGuard<'_, T> {
+        self.inner.lock().unwrap_or_else(|e| {
+            tracing::warn!("Recovering from a poisoned mutex");
+            e.into_inner()
+        })
+    }
+
+    /// Returns `None` only if the lock is held by another thread.
+    pub fn try_lock_recover(&self) -> Option<MutexGuard<'_, T>> {
+        match self.inner.try_lock() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(e)) => {
+                tracing::warn!("Recovering from a poisoned mutex");
+                Some(e.into_inner())
+            }
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Holds the most recent value of something shared, with a version that increases on
+/// every change. Readers remember the version they last saw and ask `changed_since` for
+/// anything newer instead of polling a "changed" flag that only one reader can reset.
+#[derive(Debug, Default)]
+pub struct LatestCache<T> {
+    inner: RwLock<(u64, T)>,
+}
+
+impl<T: Clone> LatestCache<T> {
+    /// Starts at version 0 with `value`.
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: RwLock::new((0, value)),
+        }
+    }
+
+    /// Replaces the value and returns its version.
+    pub fn set(&self, value: T) -> u64 {
+        self.update(|current| *current = value)
+    }
+
+    /// Changes the value in place and returns its version.
+    pub fn update<F: FnOnce(&mut T)>(&self, f: F) -> u64 {
+        let mut inner = self.inner.write().unwrap();
+        f(&mut inner.1);
+        inner.0 += 1;
+        inner.0
+    }
+
+    pub fn get(&self) -> T {
+        self.inner.read().unwrap().1.clone()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.inner.read().unwrap().0
+    }
+
+    /// Returns the value and its version if it changed after `version`.
+    pub fn changed_since(&self, version: u64) -> Option<(T, u64)> {
+        let inner = self.inner.read().unwrap();
+        (inner.0 > version).then(|| (inner.1.clone(), inner.0))
+    }
+}
+
+/// Lets threads wait until `count_down` was called `count` times, e.g. once by every worker.
+/// Clones share the same count.
+#[derive(Debug, Clone)]
+pub struct CountdownLatch {
+    inner: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl CountdownLatch {
+    pub fn new(count: usize) -> Self {
+        Self {
+            inner: Arc::new((Mutex::new(count), Condvar::new())),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        let (lock, _) = &*self.inner;
+        *lock.lock().unwrap()
+    }
+
+    pub fn count_down(&self) {
+        let (lock, cvar) = &*self.inner;
+        let mut count = lock.lock().unwrap();
+
+        if *count == 0 {
+            return;
+        }
+
+        *count -= 1;
+
+        if *count == 0 {
+            cvar.notify_all();
+        }
+    }
+
+    pub fn wait(&self) {
+        let (lock, cvar) = &*self.inner;
+        let mut count = lock.lock().unwrap();
+
+        while *count > 0 {
+            count = cvar.wait(count).unwrap();
+        }
+    }
+
+    /// Returns whether the count reached zero before `timeout` elapsed.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let (lock, cvar) = &*self.inner;
+        let count = lock.lock().unwrap();
+        let (count, _) = cvar
+            .wait_timeout_while(count, timeout, |count| *count > 0)
+            .unwrap();
+        *count == 0
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+enum Command {
+    Run(Job),
+    Exit,
+}
+
+/// A fixed number of worker threads taking jobs from one shared queue, so
+/// whichever worker is free picks up the next job.
+pub struct ThreadPool {
+    sender: mpsc::Sender<Command>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "ThreadPool needs at least one worker");
+
+        let (sender, receiver) = mpsc::channel::<Command>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || {
+                    loop {
+                        // The lock is released as soon as a command is taken, not held while it runs
+                        let command = receiver.lock().unwrap().recv();
+
+                        match command {
+                            Ok(Command::Run(job)) => job(),
+                            Ok(Command::Exit) | Err(_) => break,
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        Self { sender, workers }
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.sender.send(Command::Run(Box::new(f))).unwrap();
+    }
+}
+
+impl Drop for ThreadPool {
+    /// Lets the queued jobs finish, then stops and joins every worker.
+    fn drop(&mut self) {
+        for _ in &self.workers {
+            let _ = self.sender.send(Command::Exit);
+        }
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// How long an idle worker sleeps before looking for tasks again.
+const IDLE_WAIT: Duration = Duration::from_millis(5);
+
+/// Workers with their own deques. Pushed tasks go to a shared injector; an idle
+/// worker takes a batch from it or steals from its peers. Dropping the pool (or
+/// `shutdown`) lets the workers drain all the remaining tasks, then joins them.
+pub struct WorkStealingPool<T: Send + 'static> {
+    injector: Arc<Injector<T>>,
+    shutdown: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> WorkStealingPool<T> {
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn new<F>(size: usize, handler: F) -> Self
+    where
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        assert!(size > 0, "WorkStealingPool needs at least one worker");
+
+        let injector = Arc::new(Injector::new());
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let handler = Arc::new(handler);
+        let locals: Vec<Worker<T>> = (0..size).map(|_| Worker::new_fifo()).collect();
+        let stealers: Arc<Vec<Stealer<T>>> = Arc::new(locals.iter().map(|w| w.stealer()).collect());
+        let workers = locals
+            .into_iter()
+            .enumerate()
+            .map(|(i, local)| {
+                let injector = Arc::clone(&injector);
+                let shutdown = Arc::clone(&shutdown);
+                let stealers = Arc::clone(&stealers);
+                let handler = Arc::clone(&handler);
+                thread::Builder::new()
+                    .name(format!("worker-{}", i + 1))
+                    .spawn(move || {
+                        loop {
+                            if let Some(task) = find_task(&local, &stealers, &injector) {
+                                handler(task);
+                                continue;
+                            }
+
+                            // Only leave once there was nothing left to find
+                            if shutdown.load(Ordering::SeqCst) {
+                                break;
+                            }
+
+                            thread::sleep(IDLE_WAIT);
+                        }
+                    })
+                    .expect("Failed to spawn a worker thread")
+            })
+            .collect();
+
+        Self {
+            injector,
+            shutdown,
+            workers,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn push(&self, task: T) {
+        self.injector.push(task);
+    }
+
+    /// Waits for every pushed task to be handled and stops the workers.
+    pub fn shutdown(self) {
+        // Drop does the work
+    }
+}
+
+impl<T: Send + 'static> Drop for WorkStealingPool<T> {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Takes the next task from the local deque, then from the peers, then a batch from the injector.
+fn find_task<T>(local: &Worker<T>, stealers: &[Stealer<T>], injector: &Injector<T>) -> Option<T> {
+    local.pop().or_else(|| {
+        // Retry means another thread raced us, so it's not proof the queues are empty
+        std::iter::repeat_with(|| {
+            stealers
+                .iter()
+                .map(|s| s.steal())
+                .collect::<Steal<T>>()
+                .or_else(|| injector.steal_batch_and_pop(local))
+        })
+        .find(|s| !s.is_retry())
+        .and_then(|s| s.success())
+    })
+}
+
+type Request<Req, Resp> = (Req, mpsc::SyncSender<Resp>);
+
+/// A thread owning `handler` that answers requests one at a time. `call` sends the
+/// request along with its own reply channel and blocks until the handler returns, so
+/// callers don't need a [`Signal`] to wait for the answer. Dropping the actor (or
+/// `shutdown`) lets the queued requests finish, then joins the thread.
+pub struct Actor<Req: Send + 'static, Resp: Send + 'static> {
+    sender: Option<mpsc::Sender<Request<Req, Resp>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<Req: Send + 'static, Resp: Send + 'static> Actor<Req, Resp> {
+    pub fn new<F>(mut handler: F) -> Self
+    where
+        F: FnMut(Req) -> Resp + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel::<Request<Req, Resp>>();
+        let handle = thread::spawn(move || {
+            for (request, reply) in receiver {
+                // The caller may have given up, there's no one to tell then
+                let _ = reply.send(handler(request));
+            }
+        });
+
+        Self {
+            sender: Some(sender),
+            handle: Some(handle),
+        }
+    }
+
+    /// Sends `request` and waits for the handler's response. Fails if the handler thread
+    /// stopped, e.g. because the handler panicked.
+    pub fn call(&self, request: Req) -> Result<Resp> {
+        let stopped = || RmxError::InvalidOperation("The actor has stopped".to_string());
+        let (reply, response) = mpsc::sync_channel(1);
+        self.sender
+            .as_ref()
+            .ok_or_else(stopped)?
+            .send((request, reply))
+            .map_err(|_| stopped())?;
+        response.recv().map_err(|_| stopped())
+    }
+
+    /// Waits for the queued requests to be handled and stops the thread.
+    pub fn shutdown(self) {
+        // Drop does the work
+    }
+}
+
+impl<Req: Send + 'static, Resp: Send + 'static> Drop for Actor<Req, Resp> {
+    fn drop(&mut self) {
+        // Closing the channel ends the handler's loop once it is empty
+        self.sender.take();
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::HashSet,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    #[test]
+    fn wait_timeout_returns_true_when_signaled() {
+        let signal = Signal::new();
+        let setter = signal.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            setter.set();
+        });
+
+        assert!(signal.wait_timeout(Duration::from_secs(5)));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn wait_timeout_returns_false_when_timed_out() {
+        let signal = Signal::new();
+        let start = Instant::now();
+        assert!(!signal.wait_timeout(Duration::from_millis(50)));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn reset_clears_a_pending_signal() {
+        let signal = Signal::new();
+        signal.set();
+        signal.reset();
+        assert!(!signal.wait_timeout(Duration::from_millis(10)));
+
+        // A consumed signal doesn't satisfy the next wait either
+        signal.set();
+        assert!(signal.wait_timeout(Duration::from_millis(10)));
+        assert!(!signal.wait_timeout(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn latch_waits_for_every_thread() {
+        let latch = CountdownLatch::new(5);
+        let finished = Arc::new(AtomicUsize::new(0));
+        let handles = (0..5)
+            .map(|i| {
+                let latch = latch.clone();
+                let finished = Arc::clone(&finished);
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(10 * i));
+                    finished.fetch_add(1, Ordering::SeqCst);
+                    latch.count_down();
+                })
+            })
+            .collect::<Vec<_>>();
+
+        latch.wait();
+        assert_eq!(finished.load(Ordering::SeqCst), 5);
+        assert_eq!(latch.count(), 0);
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn latch_wait_timeout() {
+        let latch = CountdownLatch::new(2);
+        latch.count_down();
+        assert!(!latch.wait_timeout(Duration::from_millis(20)));
+        latch.count_down();
+        assert!(latch.wait_timeout(Duration::from_millis(20)));
+        // Counting down past zero is a no-op
+        latch.count_down();
+        assert_eq!(latch.count(), 0);
+    }
+
+    #[test]
+    fn runs_every_job_across_workers() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+
+        {
+            let pool = ThreadPool::new(4);
+            assert_eq!(pool.size(), 4);
+
+            for _ in 0..100 {
+                let counter = Arc::clone(&counter);
+                let threads = Arc::clone(&threads);
+                pool.execute(move || {
+                    threads.lock().unwrap().insert(thread::current().id());
+                    thread::sleep(Duration::from_millis(1));
+                    counter.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+            // Dropping the pool waits for the queued jobs
+        }
+
+        assert_eq!(counter.load(Ordering::SeqCst), 100);
+        assert!(threads.lock().unwrap().len() > 1);
+    }
+
+    #[test]
+    fn work_stealing_pool_handles_each_item_once() {
+        let seen = Arc::new(Mutex::new(vec![0u32; 1000]));
+        let pool = {
+            let seen = Arc::clone(&seen);
+            WorkStealingPool::new(4, move |i: usize| {
+                seen.lock().unwrap()[i] += 1;
+            })
+        };
+        assert_eq!(pool.size(), 4);
+
+        for i in 0..1000 {
+            pool.push(i);
+        }
+
+        // Shutting down right away must still drain the queue
+        pool.shutdown();
+        assert!(seen.lock().unwrap().iter().all(|&n| n == 1));
+    }
+
+    #[test]
+    fn lock_recover_survives_a_poisoning_panic() {
+        let mutex = Arc::new(RecoverableMutex::new(41));
+        let poisoner = mutex.clone();
+        let result = thread::spawn(move || {
+            let mut value = poisoner.lock().unwrap();
+            *value += 1;
+            panic!("poison the lock");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(mutex.is_poisoned());
+        assert!(mutex.lock().is_err());
+
+        assert_eq!(*mutex.lock_recover(), 42);
+        assert_eq!(mutex.try_lock_recover().map(|v| *v), Some(42));
+        assert!(matches!(mutex.try_lock(), Err(TryLockError::Poisoned(_))));
+    }
+
+    #[test]
+    fn latest_cache_reports_newer_versions_only() {
+        let cache = LatestCache::new(vec![]);
+        assert_eq!(cache.version(), 0);
+        assert!(cache.changed_since(0).is_none());
+
+        assert_eq!(cache.update(|v| v.push("alice")), 1);
+        assert_eq!(cache.set(vec!["bob"]), 2);
+
+        // A reader that saw version 1 gets the latest value, not every change in between
+        let (value, seen) = cache.changed_since(1).unwrap();
+        assert_eq!((value, seen), (vec!["bob"], 2));
+        assert!(cache.changed_since(seen).is_none());
+
+        // Other readers keep their own position
+        assert_eq!(cache.changed_since(0).map(|(_, v)| v), Some(2));
+        assert_eq!(cache.get(), ["bob"]);
+    }
+
+    #[test]
+    fn actor_answers_each_call_in_order() {
+        let handled = Arc::new(AtomicUsize::new(0));
+        let actor = {
+            let handled = Arc::clone(&handled);
+            let mut total = 0;
+            Actor::new(move |n: i32| {
+                handled.fetch_add(1, Ordering::SeqCst);
+                total += n;
+                total
+            })
+        };
+
+        for (n, total) in [(1, 1), (2, 3), (3, 6), (-6, 0)] {
+            assert_eq!(actor.call(n).unwrap(), total);
+        }
+
+        // Concurrent callers each get their own reply
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..10 {
+                        actor.call(1).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(actor.call(0).unwrap(), 40);
+
+        actor.shutdown();
+        assert_eq!(handled.load(Ordering::SeqCst), 45);
+    }
+
+    #[test]
+    fn actor_call_fails_once_the_handler_panicked() {
+        let actor = Actor::new(|n: u32| {
+            assert!(n > 0, "zero");
+            n
+        });
+        assert_eq!(actor.call(1).unwrap(), 1);
+        assert!(actor.call(0).is_err());
+        assert!(actor.call(2).is_err());
+    }
+}
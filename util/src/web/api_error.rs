@@ -0,0 +1,113 @@
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+use std::fmt;
+
+/// Error returned by the handlers, rendered as
+/// `{ "error": { "code": "not_found", "message": "Image not found." } }` with `status`.
+///
+/// Anything convertible to `anyhow::Error` (repository, database, IO errors) converts into a
+/// 500, so handlers can use `?` for unexpected failures and the constructors for the rest.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl fmt::Display) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn bad_request(message: impl fmt::Display) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn not_found(message: impl fmt::Display) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    pub fn internal(message: impl fmt::Display) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+
+    /// Machine readable form of the status, e.g. `payload_too_large`.
+    pub fn code(&self) -> String {
+        self.status
+            .canonical_reason()
+            .unwrap_or("error")
+            .chars()
+            .filter_map(|c| match c {
+                ' ' | '-' => Some('_'),
+                c if c.is_ascii_alphanumeric() => Some(c.to_ascii_lowercase()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// No std::error::Error impl on purpose, it would conflict with this blanket conversion
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(error: E) -> Self {
+        Self::internal(error.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if self.status.is_server_error() {
+            tracing::error!("{}", self.message);
+        }
+
+        let body = json!({
+            "error": {
+                "code": self.code(),
+                "message": self.message,
+            }
+        });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn render(error: ApiError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn renders_json_with_status() {
+        let (status, body) = render(ApiError::not_found("Image not found.")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            body,
+            json!({ "error": { "code": "not_found", "message": "Image not found." } })
+        );
+
+        let (status, body) = render(ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "Too big")).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["error"]["code"], "payload_too_large");
+    }
+
+    #[tokio::test]
+    async fn other_errors_are_internal() {
+        let error: ApiError = std::io::Error::other("disk full").into();
+        let (status, body) = render(error).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["error"]["code"], "internal_server_error");
+        assert_eq!(body["error"]["message"], "disk full");
+    }
+}
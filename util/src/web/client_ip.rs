@@ -0,0 +1,268 @@
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Request, State, connect_info::MockConnectInfo},
+    http::{Extensions, HeaderMap, HeaderName, request::Parts},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+pub const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+pub const X_REAL_IP: HeaderName = HeaderName::from_static("x-real-ip");
+
+/// An address or a CIDR range, e.g. `10.0.0.1` or `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Network {
+    address: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    fn parse(value: &str) -> Result<Self, String> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let address = address
+            .trim()
+            .parse::<IpAddr>()
+            .map_err(|_| format!("'{value}' is not an IP address or range"))?;
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            None => bits,
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|prefix| *prefix <= bits)
+                .ok_or_else(|| format!("'{value}' has an invalid prefix length"))?,
+        };
+
+        Ok(Self { address, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The reverse proxies whose `X-Forwarded-For` and `X-Real-IP` headers are believed. The
+/// headers of any other peer are ignored, since a client connecting directly could put
+/// anything in them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies(Arc<Vec<Network>>);
+
+impl TrustedProxies {
+    /// Reads a comma separated list of addresses and CIDR ranges. Invalid entries are added
+    /// to `errors`, as the config does.
+    pub fn parse(errors: &mut Vec<String>, value: Option<String>) -> Self {
+        let mut networks = vec![];
+
+        for item in value
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+        {
+            if item.is_empty() {
+                continue;
+            }
+
+            match Network::parse(item) {
+                Ok(network) => networks.push(network),
+                Err(e) => errors.push(format!("TRUSTED_PROXIES: {e}")),
+            }
+        }
+
+        Self(Arc::new(networks))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|network| network.contains(ip))
+    }
+
+    /// The client behind `peer`. For a trusted peer that is the last `X-Forwarded-For` hop
+    /// not added by a trusted proxy, or `X-Real-IP` without it; otherwise `peer` itself.
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.contains(peer) {
+            return peer;
+        }
+
+        let hops = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|hop| hop.trim().parse::<IpAddr>())
+            .collect::<Vec<_>>();
+
+        if !hops.is_empty() {
+            // Walk back from the nearest hop; whatever an untrusted hop forwarded may be forged
+            let mut client = peer;
+
+            for hop in hops.into_iter().rev() {
+                let Ok(hop) = hop else {
+                    break;
+                };
+
+                client = hop;
+
+                if !self.contains(hop) {
+                    break;
+                }
+            }
+
+            return client;
+        }
+
+        headers
+            .get(X_REAL_IP)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(peer)
+    }
+}
+
+/// The address of the client that sent the request, as resolved by [`resolve_client_ip`].
+/// Without that middleware it is the peer address, or `0.0.0.0` when the router isn't
+/// served with connection info.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+impl ClientIp {
+    pub fn from_extensions(extensions: &Extensions) -> Self {
+        extensions.get::<ClientIp>().copied().unwrap_or_else(|| {
+            // Like the `ConnectInfo` extractor, fall back to the address tests mock
+            let peer = extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|c| c.0)
+                .or_else(|| extensions.get::<MockConnectInfo<SocketAddr>>().map(|m| m.0));
+            Self(peer.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |peer| peer.ip()))
+        })
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_extensions(&parts.extensions))
+    }
+}
+
+/// Middleware storing the request's [`ClientIp`], trusting the forwarding headers only from
+/// `proxies`. It has to wrap the layers reading it, such as the request log.
+pub async fn resolve_client_ip(
+    State(proxies): State<TrustedProxies>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = ClientIp::from_extensions(request.extensions()).0;
+    let client = proxies.resolve(peer, request.headers());
+    request.extensions_mut().insert(ClientIp(client));
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, middleware, routing::get};
+    use tower::ServiceExt;
+
+    fn proxies(value: &str) -> TrustedProxies {
+        let mut errors = vec![];
+        let proxies = TrustedProxies::parse(&mut errors, Some(value.to_string()));
+        assert!(errors.is_empty(), "{errors:?}");
+        proxies
+    }
+
+    async fn client_ip(peer: [u8; 4], headers: &[(HeaderName, &str)]) -> String {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|ClientIp(ip): ClientIp| async move { ip.to_string() }),
+            )
+            .layer(middleware::from_fn_with_state(
+                proxies("10.0.0.0/8, ::1"),
+                resolve_client_ip,
+            ))
+            .layer(MockConnectInfo(SocketAddr::from((peer, 1234))));
+        let mut request = Request::get("/");
+
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+
+        let response = app
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn direct_clients_cant_spoof_their_address() {
+        assert_eq!(client_ip([203, 0, 113, 7], &[]).await, "203.0.113.7");
+        let forged = [
+            (X_FORWARDED_FOR, "198.51.100.1"),
+            (X_REAL_IP, "198.51.100.2"),
+        ];
+        assert_eq!(client_ip([203, 0, 113, 7], &forged).await, "203.0.113.7");
+    }
+
+    #[tokio::test]
+    async fn proxied_clients_are_taken_from_the_headers() {
+        let peer = [10, 0, 0, 2];
+        assert_eq!(client_ip(peer, &[]).await, "10.0.0.2");
+        assert_eq!(
+            client_ip(peer, &[(X_REAL_IP, "198.51.100.2")]).await,
+            "198.51.100.2"
+        );
+
+        // The client may prepend anything; only the hops added by trusted proxies count
+        let chain = [(X_FORWARDED_FOR, "1.2.3.4, 198.51.100.1, 10.0.0.9")];
+        assert_eq!(client_ip(peer, &chain).await, "198.51.100.1");
+        let chain = [
+            (X_FORWARDED_FOR, "198.51.100.1"),
+            (X_FORWARDED_FOR, "10.0.0.9"),
+            (X_REAL_IP, "1.2.3.4"),
+        ];
+        assert_eq!(client_ip(peer, &chain).await, "198.51.100.1");
+    }
+
+    #[test]
+    fn ranges_are_parsed_and_matched() {
+        let trusted = proxies("192.168.1.0/24, 127.0.0.1, fd00::/8");
+        assert!(trusted.contains("192.168.1.200".parse().unwrap()));
+        assert!(!trusted.contains("192.168.2.1".parse().unwrap()));
+        assert!(trusted.contains("127.0.0.1".parse().unwrap()));
+        assert!(trusted.contains("::ffff:127.0.0.1".parse().unwrap()));
+        assert!(trusted.contains("fd12::1".parse().unwrap()));
+        assert!(proxies("").is_empty());
+        assert!(proxies("0.0.0.0/0").contains("8.8.8.8".parse().unwrap()));
+
+        let mut errors = vec![];
+        TrustedProxies::parse(&mut errors, Some("10.0.0.0/33, proxy.local".into()));
+        assert_eq!(errors.len(), 2);
+    }
+}
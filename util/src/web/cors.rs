@@ -0,0 +1,194 @@
+use axum::http::{HeaderName, HeaderValue, Method};
+use std::str::FromStr;
+use tower_http::cors::{Any, CorsLayer};
+
+/// Origins, methods or headers the CORS layer allows: `*` for any, or a comma separated list.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Allowed<T> {
+    Any,
+    Only(Vec<T>),
+}
+
+/// The CORS settings, by default the given origins with any method and header and no
+/// credentials.
+#[derive(Debug, Clone)]
+pub struct CorsOptions {
+    pub origins: Allowed<HeaderValue>,
+    pub methods: Allowed<Method>,
+    pub headers: Allowed<HeaderName>,
+    pub allow_credentials: bool,
+}
+
+impl CorsOptions {
+    /// Parses the `CORS_ORIGINS`, `CORS_METHODS` and `CORS_HEADERS` values, adding every
+    /// problem to `errors`. Browsers ignore credentials when anything is `*`, so that
+    /// combination is an error rather than a silently broken setup.
+    pub fn parse(
+        errors: &mut Vec<String>,
+        origins: Option<String>,
+        methods: Option<String>,
+        headers: Option<String>,
+        allow_credentials: bool,
+    ) -> Self {
+        let origins = parse_list(
+            errors,
+            "CORS_ORIGINS",
+            origins.as_deref().unwrap_or("http://localhost"),
+            |v| v.parse::<HeaderValue>().ok(),
+        );
+        let methods = parse_list(
+            errors,
+            "CORS_METHODS",
+            methods.as_deref().unwrap_or("*"),
+            |v| Method::from_str(&v.to_uppercase()).ok(),
+        );
+        let headers = parse_list(
+            errors,
+            "CORS_HEADERS",
+            headers.as_deref().unwrap_or("*"),
+            |v| v.parse::<HeaderName>().ok(),
+        );
+
+        if allow_credentials {
+            let wildcards = [
+                ("CORS_ORIGINS", origins == Allowed::Any),
+                ("CORS_METHODS", methods == Allowed::Any),
+                ("CORS_HEADERS", headers == Allowed::Any),
+            ];
+
+            for (name, _) in wildcards.iter().filter(|(_, any)| *any) {
+                errors.push(format!(
+                    "CORS_ALLOW_CREDENTIALS needs an explicit {name}, not '*'"
+                ));
+            }
+        }
+
+        Self {
+            origins,
+            methods,
+            headers,
+            allow_credentials,
+        }
+    }
+
+    pub fn layer(&self) -> CorsLayer {
+        let layer = match &self.origins {
+            Allowed::Any => CorsLayer::new().allow_origin(Any),
+            Allowed::Only(origins) => CorsLayer::new().allow_origin(origins.clone()),
+        };
+        let layer = match &self.methods {
+            Allowed::Any => layer.allow_methods(Any),
+            Allowed::Only(methods) => layer.allow_methods(methods.clone()),
+        };
+        let layer = match &self.headers {
+            Allowed::Any => layer.allow_headers(Any),
+            Allowed::Only(headers) => layer.allow_headers(headers.clone()),
+        };
+        layer.allow_credentials(self.allow_credentials)
+    }
+}
+
+fn parse_list<T, F>(errors: &mut Vec<String>, name: &str, value: &str, parse: F) -> Allowed<T>
+where
+    F: Fn(&str) -> Option<T>,
+{
+    if value.trim() == "*" {
+        return Allowed::Any;
+    }
+
+    let mut values = vec![];
+
+    for v in value.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        match parse(v) {
+            Some(parsed) => values.push(parsed),
+            None => errors.push(format!("{name}: '{v}' is not valid")),
+        }
+    }
+
+    Allowed::Only(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, header},
+        routing::get,
+    };
+    use tower::ServiceExt;
+
+    fn parse(vars: [Option<&str>; 3], allow_credentials: bool) -> (CorsOptions, Vec<String>) {
+        let mut errors = vec![];
+        let [origins, methods, headers] = vars.map(|v| v.map(str::to_string));
+        let options = CorsOptions::parse(&mut errors, origins, methods, headers, allow_credentials);
+        (options, errors)
+    }
+
+    #[test]
+    fn defaults_allow_any_method_and_header() {
+        let (options, errors) = parse([None, None, None], false);
+        assert!(errors.is_empty());
+        assert_eq!(
+            options.origins,
+            Allowed::Only(vec![HeaderValue::from_static("http://localhost")])
+        );
+        assert_eq!(options.methods, Allowed::Any);
+        assert_eq!(options.headers, Allowed::Any);
+    }
+
+    #[test]
+    fn credentials_need_explicit_lists() {
+        let (_, errors) = parse([Some("*"), None, Some("content-type")], true);
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert!(errors[0].contains("CORS_ORIGINS"));
+        assert!(errors[1].contains("CORS_METHODS"));
+
+        let (options, errors) = parse(
+            [
+                Some("http://a.test"),
+                Some("get, Post"),
+                Some("Content-Type"),
+            ],
+            true,
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(
+            options.methods,
+            Allowed::Only(vec![Method::GET, Method::POST])
+        );
+        assert_eq!(options.headers, Allowed::Only(vec![header::CONTENT_TYPE]));
+
+        let (_, errors) = parse([None, Some("GET,bad method"), None], false);
+        assert_eq!(errors, ["CORS_METHODS: 'bad method' is not valid"]);
+    }
+
+    #[tokio::test]
+    async fn preflight_reports_the_configured_lists() {
+        let (options, _) = parse(
+            [Some("http://a.test"), Some("GET"), Some("content-type")],
+            true,
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(options.layer());
+        let response = app
+            .oneshot(
+                Request::options("/")
+                    .header(header::ORIGIN, "http://a.test")
+                    .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let headers = response.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "GET");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+    }
+}
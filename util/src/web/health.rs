@@ -0,0 +1,92 @@
+use axum::{
+    Extension, Router,
+    http::{StatusCode, header},
+    response::IntoResponse,
+    routing::get,
+};
+use std::{fmt, future::Future};
+
+use crate::{metrics, web::api_error::ApiError};
+
+/// Liveness and readiness probes and the metrics. They are merged after the CORS layer
+/// since only the orchestrator and the scraper call them.
+///
+/// `/readyz` passes the `T` request extension, usually the database pool, to `probe` and
+/// answers 503 with its error when it fails.
+pub fn routes<T, E, F, Fut>(probe: F) -> Router
+where
+    T: Clone + Send + Sync + 'static,
+    E: fmt::Display,
+    F: Fn(T) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), E>> + Send,
+{
+    let readyz = move |Extension(dependency): Extension<T>| async move {
+        probe(dependency).await.map_err(|e| {
+            ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("Database is unreachable: {e}"),
+            )
+        })?;
+        Ok::<_, ApiError>("ok")
+    };
+
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(render_metrics))
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// The histograms in the Prometheus text format.
+async fn render_metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::registry().render(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use std::sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    };
+    use tower::ServiceExt;
+
+    #[derive(Clone, Default)]
+    struct Pool(Arc<AtomicBool>);
+
+    async fn status(pool: &Pool, uri: &str) -> StatusCode {
+        routes(|pool: Pool| async move {
+            match pool.0.load(Ordering::Acquire) {
+                true => Err("pool closed"),
+                false => Ok(()),
+            }
+        })
+        .layer(Extension(pool.clone()))
+        .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+        .status()
+    }
+
+    #[tokio::test]
+    async fn readyz_reports_a_failed_probe() {
+        let pool = Pool::default();
+        assert_eq!(status(&pool, "/healthz").await, StatusCode::OK);
+        assert_eq!(status(&pool, "/readyz").await, StatusCode::OK);
+        assert_eq!(status(&pool, "/metrics").await, StatusCode::OK);
+
+        pool.0.store(true, Ordering::Release);
+        assert_eq!(status(&pool, "/healthz").await, StatusCode::OK);
+        assert_eq!(
+            status(&pool, "/readyz").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+}
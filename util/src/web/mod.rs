@@ -0,0 +1,8 @@
+//! Pieces shared by the axum servers of the workspace.
+
+pub mod api_error;
+pub mod client_ip;
+pub mod cors;
+pub mod health;
+pub mod request_log;
+pub mod shutdown;
@@ -0,0 +1,96 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::time::Instant;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{metrics, web::client_ip::ClientIp};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+/// Histogram of the time taken by every request.
+pub const REQUEST_DURATION_METRIC: &str = "http_request_duration_seconds";
+
+/// Gives each request a UUID, runs it inside a `request` span carrying that id and the
+/// [`ClientIp`] so every log line it produces can be correlated, and logs the outcome with the
+/// elapsed time. The id is echoed back in the `x-request-id` response header. The elapsed time
+/// is also recorded in the `http_request_duration_seconds` histogram.
+pub async fn request_log(request: Request, next: Next) -> Response {
+    let id = Uuid::new_v4();
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let ClientIp(client) = ClientIp::from_extensions(request.extensions());
+    let span = tracing::info_span!("request", %id, %client);
+    let start = Instant::now();
+    let mut response = next.run(request).instrument(span.clone()).await;
+    let elapsed = start.elapsed();
+    metrics::registry()
+        .histogram(REQUEST_DURATION_METRIC)
+        .observe(elapsed);
+
+    span.in_scope(|| {
+        tracing::info!(
+            "{method} {path} {} in {:?}",
+            response.status().as_u16(),
+            elapsed
+        );
+    });
+
+    if let Ok(value) = HeaderValue::from_str(&id.to_string()) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, middleware, routing::get};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn echoes_a_unique_request_id() {
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn(request_log));
+        let request_id = |response: Response| {
+            let value = response.headers().get(REQUEST_ID_HEADER).unwrap();
+            Uuid::parse_str(value.to_str().unwrap()).unwrap()
+        };
+
+        let first = app
+            .clone()
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let second = app
+            .oneshot(Request::get("/missing").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_ne!(request_id(first), request_id(second));
+    }
+
+    #[tokio::test]
+    async fn durations_are_recorded() {
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn(request_log));
+        let histogram = metrics::registry().histogram(REQUEST_DURATION_METRIC);
+        let before = histogram.snapshot().count;
+
+        app.oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        // Other tests may run requests at the same time
+        assert!(histogram.snapshot().count > before);
+        assert!(
+            metrics::registry()
+                .render()
+                .contains(REQUEST_DURATION_METRIC)
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+use tokio_util::sync::CancellationToken;
+
+/// Number of requests currently being handled, so shutdown can report what it waits for.
+#[derive(Debug, Clone, Default)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Middleware counting the request for as long as it runs. The guard also releases the
+/// count when the client disconnects and the handler future is dropped.
+pub async fn track_in_flight(
+    State(in_flight): State<InFlight>,
+    request: Request,
+    next: Next,
+) -> Response {
+    in_flight.0.fetch_add(1, Ordering::AcqRel);
+    let _guard = InFlightGuard(in_flight.0.clone());
+    next.run(request).await
+}
+
+/// Resolves on Ctrl-C. `axum::serve` then stops accepting connections and waits for the
+/// in-flight requests to finish.
+pub async fn ctrl_c(in_flight: InFlight) {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        tracing::error!("Failed to listen for Ctrl-C: {e}");
+        // Without the signal the server can only be killed, so never resolve
+        std::future::pending::<()>().await;
+    }
+
+    tracing::info!(
+        "Shutting down gracefully, draining {} connection(s)",
+        in_flight.count()
+    );
+}
+
+/// Cancels `token` on Ctrl-C, for servers running more than one task that has to stop.
+pub async fn cancel_on_ctrl_c(token: CancellationToken) {
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            if let Err(e) = result {
+                tracing::error!("Failed to listen for Ctrl-C: {e}");
+                return;
+            }
+
+            tracing::info!("Ctrl-C received, shutting down gracefully");
+            token.cancel();
+        }
+        // Already cancelled elsewhere, e.g. the server failed
+        _ = token.cancelled() => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, middleware, routing::get};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn counts_requests_while_they_run() {
+        let in_flight = InFlight::default();
+        let seen = in_flight.clone();
+        let app = Router::new()
+            .route("/", get(move || async move { seen.count().to_string() }))
+            .layer(middleware::from_fn_with_state(
+                in_flight.clone(),
+                track_in_flight,
+            ));
+
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"1");
+        assert_eq!(in_flight.count(), 0);
+    }
+}